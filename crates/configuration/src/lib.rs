@@ -1,4 +1,6 @@
 use config::{Config, ConfigError, Environment as ConfigEnvironment, File};
+use protocol::channel::{MediaContainer, MediaTrim, DIRECT_CHANNEL};
+use regex::Regex;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::env;
@@ -19,6 +21,225 @@ pub struct AppConfiguration {
   pub gateway_addr: String,
 }
 
+#[derive(Deserialize, Default, Clone)]
+pub struct HttpClientConfig {
+  // Proxy URL used for every request of the channel, e.g. `socks5://127.0.0.1:1080`
+  pub proxy: Option<String>,
+  #[serde(rename = "user-agent")]
+  pub user_agent: Option<String>,
+  pub referer: Option<String>,
+  // Extra headers sent with API, playlist and segment requests
+  #[serde(default)]
+  pub headers: HashMap<String, String>,
+  // Initial cookies of the cookie jar, in `Set-Cookie` format (e.g. `token=abc; Path=/`). They are
+  // only sent to the host of the base URL (and its sub domains with a `Domain` attribute), not to
+  // CDNs on other domains, which get a `Cookie` entry of `headers` instead
+  #[serde(default)]
+  pub cookies: Vec<String>,
+  // Timeout of the whole request, in seconds
+  pub timeout: Option<u64>,
+  // Timeout of the connect phase, in seconds
+  #[serde(rename = "connect-timeout")]
+  pub connect_timeout: Option<u64>,
+  // Accept self-signed or otherwise invalid TLS certificates
+  #[serde(rename = "accept-invalid-certs", default)]
+  pub accept_invalid_certs: bool,
+  // Path of an extra PEM encoded root certificate to trust
+  #[serde(rename = "ca-certificate")]
+  pub ca_certificate: Option<PathBuf>,
+  // Number of parallel range requests of progressive (e.g. MP4) downloads, defaults to 1
+  #[serde(rename = "progressive-connections")]
+  pub progressive_connections: Option<usize>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RetryConfig {
+  // Maximum number of attempts of a request, including the first one
+  #[serde(rename = "max-attempts")]
+  pub max_attempts: u32,
+  // Backoff before the first retry, in milliseconds
+  #[serde(rename = "initial-backoff")]
+  pub initial_backoff: u64,
+  // Upper bound of the backoff, in milliseconds
+  #[serde(rename = "max-backoff")]
+  pub max_backoff: u64,
+  // Factor the backoff is multiplied by after each retry
+  pub multiplier: f64,
+  // Random deviation of the backoff, as a fraction of it (0.0 - 1.0)
+  pub jitter: f64,
+  // HTTP status codes that are worth retrying
+  #[serde(rename = "retry-status-codes")]
+  pub retry_status_codes: Vec<u16>,
+}
+
+impl Default for RetryConfig {
+  fn default() -> Self {
+    Self {
+      max_attempts: 3,
+      initial_backoff: 500,
+      max_backoff: 10_000,
+      multiplier: 2.0,
+      jitter: 0.2,
+      retry_status_codes: vec![408, 429, 500, 502, 503, 504],
+    }
+  }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RateLimitConfig {
+  // Number of requests the channel accepts per second on average
+  #[serde(rename = "requests-per-second")]
+  pub requests_per_second: f64,
+  // Number of requests that may be sent at once after being idle, defaults to 1
+  pub burst: Option<u32>,
+}
+
+// Maps the categories of a channel to a media kind. Exactly one of `type-id`, `name` and
+// `pattern` should be given.
+#[derive(Deserialize, Clone)]
+pub struct CategoryMappingConfig {
+  #[serde(rename = "type-id")]
+  pub type_id: Option<u32>,
+  pub name: Option<String>,
+  // Regular expression matched against the category name
+  pub pattern: Option<String>,
+  // One of `movie`, `tv`, `variety`, `anime`, `documentary`, `short-drama` and `other`
+  pub kind: String,
+}
+
+// Which variant of an HLS master playlist or a DASH manifest is downloaded
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantPreference {
+  // The one with the highest bandwidth
+  #[default]
+  Highest,
+  // The one with the lowest bandwidth
+  Lowest,
+}
+
+// Format of the subtitles saved next to downloaded media
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+  #[default]
+  Srt,
+  Vtt,
+}
+
+// Removes ads spliced into HLS playlists between `#EXT-X-DISCONTINUITY` markers
+#[derive(Deserialize, Default, Clone)]
+pub struct AdFilterConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  // Regular expressions of segment URIs that are always ads, e.g. `/adjump/`
+  #[serde(default)]
+  pub patterns: Vec<AdPattern>,
+  // Longest run of segments between discontinuities that may be taken as an ad, in seconds,
+  // defaults to 90
+  #[serde(rename = "max-duration")]
+  pub max_duration: Option<f64>,
+}
+
+// Regular expression of an ad segment URI, compiled when the configuration is loaded so that an
+// invalid one fails at startup
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct AdPattern(Regex);
+
+impl AdPattern {
+  pub fn is_match(&self, uri: &str) -> bool {
+    self.0.is_match(uri)
+  }
+}
+
+impl TryFrom<String> for AdPattern {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    Regex::new(&value)
+      .map(Self)
+      .map_err(|e| format!("Invalid ad pattern {}: {}", value, e))
+  }
+}
+
+// Checks downloaded media with ffprobe before reporting them as done
+#[derive(Deserialize, Default, Clone)]
+pub struct VerificationConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  // Largest difference between the probed duration and the one of the playlist, in seconds,
+  // defaults to 10
+  #[serde(rename = "duration-tolerance")]
+  pub duration_tolerance: Option<f64>,
+  // Longest gap between the packets of a stream, in seconds, defaults to 10
+  #[serde(rename = "max-gap")]
+  pub max_gap: Option<f64>,
+  // Whether media without an audio stream fail the verification, defaults to true
+  #[serde(rename = "require-audio")]
+  pub require_audio: Option<bool>,
+  // Times a media failing the verification is downloaded again before failing its task
+  #[serde(default)]
+  pub retries: u32,
+}
+
+// Recordings of live streams
+#[derive(Deserialize, Default, Clone)]
+pub struct LiveConfig {
+  // Longest recording, in seconds, whatever its requested duration, defaults to 4 hours
+  #[serde(rename = "max-duration")]
+  pub max_duration: Option<u64>,
+  // Polls of the playlist without new segments before the stream is taken as over, defaults
+  // to 10
+  #[serde(rename = "max-idle-polls")]
+  pub max_idle_polls: Option<u32>,
+}
+
+// Choice between the sources of a media, e.g. the lines (`$$$`-separated playlists) of unified
+// channels
+#[derive(Deserialize, Default, Clone)]
+pub struct SourceSelectionConfig {
+  // Segments downloaded from each source to measure its speed, 0 ranks the sources by their
+  // history alone, defaults to 2
+  #[serde(rename = "probe-segments")]
+  pub probe_segments: Option<usize>,
+  // Sources probed before a download, best ranked first, defaults to 4
+  #[serde(rename = "max-probed")]
+  pub max_probed: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct DownloadConfig {
+  #[serde(default)]
+  pub variant: VariantPreference,
+  // Variants taller than this (e.g. 1080) are skipped, unless there are no others
+  #[serde(rename = "max-height")]
+  pub max_height: Option<u32>,
+  #[serde(rename = "ad-filter", default)]
+  pub ad_filter: AdFilterConfig,
+  #[serde(rename = "subtitle-format", default)]
+  pub subtitle_format: SubtitleFormat,
+  // Container of the media remuxed from HLS playlists and DASH manifests, defaults to mp4
+  #[serde(default)]
+  pub container: MediaContainer,
+  #[serde(default)]
+  pub verification: VerificationConfig,
+  #[serde(default)]
+  pub live: LiveConfig,
+  #[serde(rename = "source-selection", default)]
+  pub source_selection: SourceSelectionConfig,
+}
+
+// Format of the responses of the MacCMS collection API, `at/xml` mirrors serve XML
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiFormat {
+  #[default]
+  Json,
+  Xml,
+}
+
 #[derive(Deserialize, Clone)]
 pub struct UnifiedItemConfig {
   pub name: String,
@@ -26,15 +247,268 @@ pub struct UnifiedItemConfig {
   pub base_url: String,
   #[serde(rename = "http-version")]
   pub http_version: Option<u8>,
+  #[serde(default)]
+  pub format: ApiFormat,
+  #[serde(default)]
+  pub http: HttpClientConfig,
+  #[serde(default)]
+  pub retry: RetryConfig,
+  #[serde(rename = "rate-limit")]
+  pub rate_limit: Option<RateLimitConfig>,
+  #[serde(default)]
+  pub categories: Vec<CategoryMappingConfig>,
+  #[serde(default)]
+  pub download: DownloadConfig,
+}
+
+// Endpoint of a JSON channel. `url` is a template, whose placeholders are replaced with the
+// values of the request, e.g. `{base_url}/search?wd={keyword}&page={page}`.
+#[derive(Deserialize, Clone)]
+pub struct JsonEndpointConfig {
+  pub url: String,
+  // Path of the media list (search) or the media (detail) in the response, defaults to `$`
+  pub items: Option<String>,
+  // Path of the total number of search results
+  pub total: Option<String>,
+}
+
+// JSONPath-style paths (e.g. `$.data.list[*].title`) of the metadata fields of a media,
+// relative to the media object.
+#[derive(Deserialize, Clone)]
+pub struct JsonMetadataMappingConfig {
+  pub id: String,
+  pub name: String,
+  #[serde(rename = "poster-url")]
+  pub poster_url: Option<String>,
+  #[serde(rename = "release-year")]
+  pub release_year: Option<String>,
+  pub description: Option<String>,
+  // Paths of the category, which is mapped to a media kind like categories of unified channels
+  #[serde(rename = "category-id")]
+  pub category_id: Option<String>,
+  #[serde(rename = "category-name")]
+  pub category_name: Option<String>,
+  pub cast: Option<String>,
+  pub directors: Option<String>,
+  pub region: Option<String>,
+  pub language: Option<String>,
+  pub remarks: Option<String>,
+  #[serde(rename = "total-episodes")]
+  pub total_episodes: Option<String>,
+  #[serde(rename = "updated-at")]
+  pub updated_at: Option<String>,
+  pub score: Option<String>,
+}
+
+// Playlist of a media. Without `url`, the playlist is read from the detail response.
+#[derive(Deserialize, Clone)]
+pub struct JsonPlaylistConfig {
+  pub url: Option<String>,
+  // Path of the playlist items in the response
+  pub items: String,
+  // Paths of the fields of a playlist item, relative to the item
+  pub text: String,
+  #[serde(rename = "media-url")]
+  pub media_url: String,
+  // Defaults to the position of the item in the playlist
+  pub number: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct JsonChannelConfig {
+  pub name: String,
+  #[serde(rename = "url")]
+  pub base_url: String,
+  #[serde(rename = "http-version")]
+  pub http_version: Option<u8>,
+  #[serde(default)]
+  pub http: HttpClientConfig,
+  #[serde(default)]
+  pub retry: RetryConfig,
+  #[serde(rename = "rate-limit")]
+  pub rate_limit: Option<RateLimitConfig>,
+  #[serde(default)]
+  pub categories: Vec<CategoryMappingConfig>,
+  #[serde(default)]
+  pub download: DownloadConfig,
+  pub search: JsonEndpointConfig,
+  pub detail: JsonEndpointConfig,
+  pub playlist: JsonPlaylistConfig,
+  pub fields: JsonMetadataMappingConfig,
+}
+
+// Value scraped from an HTML page: the text of the first element matched by `selector`, or its
+// `attribute` if given. `pattern` is a regular expression whose first group is kept.
+#[derive(Deserialize, Clone)]
+pub struct HtmlFieldConfig {
+  pub selector: String,
+  pub attribute: Option<String>,
+  pub pattern: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HtmlSearchConfig {
+  // Template of the search page URL, with `{base_url}`, `{keyword}` and `{page}`
+  pub url: String,
+  // Selector of the search results, the fields are selected inside each result
+  pub items: String,
+  pub id: HtmlFieldConfig,
+  pub name: HtmlFieldConfig,
+  #[serde(rename = "poster-url")]
+  pub poster_url: Option<HtmlFieldConfig>,
+  pub total: Option<HtmlFieldConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HtmlDetailConfig {
+  // Template of the detail page URL, with `{base_url}` and `{media_id}`
+  pub url: String,
+  pub name: HtmlFieldConfig,
+  #[serde(rename = "poster-url")]
+  pub poster_url: Option<HtmlFieldConfig>,
+  #[serde(rename = "release-year")]
+  pub release_year: Option<HtmlFieldConfig>,
+  pub description: Option<HtmlFieldConfig>,
+  // Name of the category, mapped to a media kind like categories of unified channels
+  pub category: Option<HtmlFieldConfig>,
+  // Selected elements are listed one by one, e.g. `.actors a`
+  pub cast: Option<HtmlFieldConfig>,
+  pub directors: Option<HtmlFieldConfig>,
+  pub region: Option<HtmlFieldConfig>,
+  pub language: Option<HtmlFieldConfig>,
+  pub remarks: Option<HtmlFieldConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HtmlPlaylistConfig {
+  // Template of the playlist page URL, defaults to the detail page
+  pub url: Option<String>,
+  // Selector of the episode links, whose text is the episode name and `href` the player page
+  pub episodes: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HtmlPlayerConfig {
+  // Regular expression of the m3u8 URL in the player page, whose first group is the URL
+  pub pattern: Option<String>,
+  // Frame of the player page holding the video, followed when the page has no m3u8 URL
+  pub frame: Option<HtmlFieldConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HtmlChannelConfig {
+  pub name: String,
+  #[serde(rename = "url")]
+  pub base_url: String,
+  #[serde(rename = "http-version")]
+  pub http_version: Option<u8>,
+  #[serde(default)]
+  pub http: HttpClientConfig,
+  #[serde(default)]
+  pub retry: RetryConfig,
+  #[serde(rename = "rate-limit")]
+  pub rate_limit: Option<RateLimitConfig>,
+  #[serde(default)]
+  pub categories: Vec<CategoryMappingConfig>,
+  #[serde(default)]
+  pub download: DownloadConfig,
+  pub search: HtmlSearchConfig,
+  pub detail: HtmlDetailConfig,
+  pub playlist: HtmlPlaylistConfig,
+  pub player: Option<HtmlPlayerConfig>,
+}
+
+// HTTP settings of the built-in `direct` channel. Cookies are ignored, since the URLs may be
+// of any site.
+#[derive(Deserialize, Default, Clone)]
+pub struct DirectChannelConfig {
+  #[serde(default)]
+  pub http: HttpClientConfig,
+  #[serde(default)]
+  pub retry: RetryConfig,
+  #[serde(rename = "rate-limit")]
+  pub rate_limit: Option<RateLimitConfig>,
+  #[serde(default)]
+  pub download: DownloadConfig,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct ChannelConfig {
   #[serde(rename = "unified-channels")]
   pub unified_channels: HashMap<String, UnifiedItemConfig>,
+  // Channels with JSON APIs other than the MacCMS one
+  #[serde(rename = "json-channels", default)]
+  pub json_channels: HashMap<String, JsonChannelConfig>,
+  // Channels scraped from HTML pages
+  #[serde(rename = "html-channels", default)]
+  pub html_channels: HashMap<String, HtmlChannelConfig>,
+  #[serde(default)]
+  pub direct: DirectChannelConfig,
   pub default: String,
 }
 
+impl ChannelConfig {
+  // The id of the direct channel is reserved, `download_url` would send any URL to a channel
+  // configured with it
+  fn validate(&self) -> anyhow::Result<()> {
+    let configured = self.unified_channels.contains_key(DIRECT_CHANNEL)
+      || self.json_channels.contains_key(DIRECT_CHANNEL)
+      || self.html_channels.contains_key(DIRECT_CHANNEL);
+
+    anyhow::ensure!(
+      !configured,
+      "Channel id {:?} is reserved for the direct channel, use [channel.direct] to configure it",
+      DIRECT_CHANNEL
+    );
+
+    Ok(())
+  }
+}
+
+// ffmpeg settings of a named post-processing profile, e.g. `h265-archive`. Options go between
+// the input and the output, e.g. `["-c:v", "libx265", "-crf", "26", "-c:a", "copy"]`.
+#[derive(Deserialize, Clone)]
+pub struct PostProcessingProfileConfig {
+  pub args: Vec<String>,
+  // Container of the output (e.g. `mkv`), defaults to the one of the download
+  pub extension: Option<String>,
+}
+
+// Post-processing run on downloaded media before they are moved to the library. Profiles given
+// here are added to the built-in ones, or replace them.
+#[derive(Deserialize, Default, Clone)]
+pub struct PostProcessingConfig {
+  #[serde(default)]
+  pub profiles: HashMap<String, PostProcessingProfileConfig>,
+  // Profiles of media kinds (e.g. `movie = "h265-archive"`), used unless a request picks one
+  #[serde(default)]
+  pub kinds: HashMap<String, String>,
+  // Profile of the media of other kinds
+  pub default: Option<String>,
+}
+
+// Settings of the episodes of a show, e.g. the seconds of its opening and ending trimmed from each
+// of them
+#[derive(Deserialize, Clone)]
+pub struct SeriesConfig {
+  pub channel: String,
+  #[serde(rename = "media-id")]
+  pub media_id: String,
+  #[serde(default)]
+  pub intro: f64,
+  #[serde(default)]
+  pub outro: f64,
+}
+
+impl SeriesConfig {
+  pub fn trim(&self) -> MediaTrim {
+    MediaTrim {
+      intro: self.intro,
+      outro: self.outro,
+    }
+  }
+}
+
 #[derive(Deserialize, Clone)]
 pub struct DatabaseConfig {
   pub url: String,
@@ -46,6 +520,10 @@ pub struct UserConfiguration {
   pub app: AppConfiguration,
   pub database: DatabaseConfig,
   pub channel: ChannelConfig,
+  #[serde(rename = "post-processing", default)]
+  pub post_processing: PostProcessingConfig,
+  #[serde(default)]
+  pub series: Vec<SeriesConfig>,
 }
 
 // Configuration is a structure composed of user configuration and environment configuration.
@@ -55,6 +533,8 @@ pub struct Configuration {
   pub app: AppConfiguration,
   pub database: DatabaseConfig,
   pub channel: ChannelConfig,
+  pub post_processing: PostProcessingConfig,
+  pub series: Vec<SeriesConfig>,
 }
 
 impl Configuration {
@@ -88,12 +568,27 @@ impl Configuration {
 
     let user_config: UserConfiguration = config.try_deserialize()?;
 
+    user_config.channel.validate()?;
+
+    for series in &user_config.series {
+      series.trim().validate().map_err(|err| {
+        anyhow::anyhow!(
+          "Invalid series {} of channel {}: {}",
+          series.media_id,
+          series.channel,
+          err
+        )
+      })?;
+    }
+
     Ok(Self {
       // Return the environment
       environment: env,
       app: user_config.app,
       database: user_config.database,
       channel: user_config.channel,
+      post_processing: user_config.post_processing,
+      series: user_config.series,
     })
   }
 }
@@ -0,0 +1,179 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+/// How far an ffmpeg run is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+  // Time of the output written so far, in seconds
+  pub time: f64,
+  // Duration of the input, once ffmpeg has read it
+  pub duration: Option<f64>,
+}
+
+impl Progress {
+  /// Processed fraction of the input (0.0 - 1.0), if its duration is known.
+  pub fn fraction(&self) -> Option<f64> {
+    self
+      .duration
+      .map(|duration| (self.time / duration).clamp(0.0, 1.0))
+  }
+}
+
+/// Runs ffmpeg with `args`, calling `on_progress` as the output is written. The last line of its
+/// output is part of the error when it fails.
+pub async fn run<I, S, F>(args: I, mut on_progress: F) -> anyhow::Result<()>
+where
+  I: IntoIterator<Item = S>,
+  S: AsRef<OsStr>,
+  F: FnMut(Progress),
+{
+  let mut child = Command::new("ffmpeg")
+    .args(["-nostdin", "-progress", "pipe:1", "-nostats"])
+    .args(args)
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+
+  let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+    anyhow::bail!("Failed to read the output of ffmpeg");
+  };
+
+  let (duration_sender, mut duration_receiver) = tokio::sync::watch::channel(None);
+
+  let stderr_handle = tokio::spawn(async move {
+    let mut lines = BufReader::new(stderr).lines();
+    let mut last_line = String::new();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+      if let Some(duration) = parse_duration_line(&line) {
+        duration_sender.send_if_modified(|current| current.replace(duration).is_none());
+      }
+
+      last_line = line;
+    }
+
+    last_line
+  });
+
+  let mut lines = BufReader::new(stdout).lines();
+
+  while let Ok(Some(line)) = lines.next_line().await {
+    if let Some(time) = parse_progress_line(&line) {
+      on_progress(Progress {
+        time,
+        duration: *duration_receiver.borrow_and_update(),
+      });
+    }
+  }
+
+  let status = child.wait().await?;
+  let last_line = stderr_handle.await?;
+
+  if !status.success() {
+    anyhow::bail!(
+      "ffmpeg command failed with status: {:?} ({})",
+      status,
+      last_line
+    );
+  }
+
+  Ok(())
+}
+
+/// Codec of the first stream matching `stream` (e.g. `v:0`) of the media at `path`, as named by
+/// ffprobe, e.g. `h264`. `input_args` are the options of the input.
+pub async fn probe_codec(
+  path: &Path,
+  input_args: &[&str],
+  stream: &str,
+) -> anyhow::Result<Option<String>> {
+  let output = Command::new("ffprobe")
+    .args(["-v", "error"])
+    .args(input_args)
+    .args([
+      "-select_streams",
+      stream,
+      "-show_entries",
+      "stream=codec_name",
+    ])
+    .args(["-of", "csv=p=0"])
+    .arg(path)
+    .stdin(Stdio::null())
+    .output()
+    .await?;
+
+  if !output.status.success() {
+    anyhow::bail!(
+      "ffprobe failed with status: {:?} ({})",
+      output.status,
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+
+  let output = String::from_utf8_lossy(&output.stdout);
+  let codec = output.lines().next().unwrap_or_default().trim();
+
+  Ok(Some(codec.to_string()).filter(|codec| !codec.is_empty()))
+}
+
+// e.g. "  Duration: 00:42:13.28, start: 1.400000, bitrate: 2136 kb/s", in seconds
+fn parse_duration_line(line: &str) -> Option<f64> {
+  let duration = line.trim().strip_prefix("Duration: ")?.split(',').next()?;
+
+  parse_time(duration).filter(|duration| *duration > 0.0)
+}
+
+// Processed time of `-progress` output, e.g. "out_time_us=1234567", in seconds. `out_time_ms` is
+// in microseconds as well.
+fn parse_progress_line(line: &str) -> Option<f64> {
+  let (key, value) = line.split_once('=')?;
+
+  match key {
+    "out_time_us" | "out_time_ms" => value
+      .trim()
+      .parse::<i64>()
+      .ok()
+      .map(|micros| micros as f64 / 1_000_000.0),
+    _ => None,
+  }
+}
+
+fn parse_time(time: &str) -> Option<f64> {
+  let mut seconds = 0.0;
+
+  for part in time.split(':') {
+    seconds = seconds * 60.0 + part.trim().parse::<f64>().ok()?;
+  }
+
+  Some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_duration_line, parse_progress_line, Progress};
+
+  #[test]
+  fn test_parse_ffmpeg_output() {
+    assert_eq!(
+      parse_duration_line("  Duration: 00:42:13.28, start: 1.400000, bitrate: 2136 kb/s"),
+      Some(2533.28)
+    );
+    assert_eq!(parse_duration_line("  Duration: N/A, bitrate: N/A"), None);
+    assert_eq!(parse_progress_line("out_time_us=1500000"), Some(1.5));
+    assert_eq!(parse_progress_line("out_time_ms=2500000"), Some(2.5));
+    assert_eq!(parse_progress_line("out_time=00:00:01.500000"), None);
+    assert_eq!(parse_progress_line("progress=continue"), None);
+  }
+
+  #[test]
+  fn test_progress_fraction() {
+    let progress = |time, duration| Progress { time, duration };
+
+    assert_eq!(progress(30.0, Some(120.0)).fraction(), Some(0.25));
+    assert_eq!(progress(130.0, Some(120.0)).fraction(), Some(1.0));
+    assert_eq!(progress(30.0, None).fraction(), None);
+  }
+}
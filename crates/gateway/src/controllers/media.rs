@@ -3,10 +3,19 @@ use axum::extract::{Json, Path, Query};
 use axum::http::StatusCode;
 use protocol::media::BatchDownloadMediaRequest;
 use protocol::media::DownloadMediaRequest;
+use protocol::media::DownloadUrlRequest;
 use protocol::media::{GetMediaMetadataRequest, MediaMetadata};
 use protocol::media::{GetMediaPlaylistRequest, MediaPlaylist};
+use protocol::media::{GetMediaRenditionsRequest, MediaRenditions};
 use protocol::media::{SearchMediaRequest, SearchMediaResponse};
 
+#[derive(serde::Deserialize)]
+pub struct MediaPlaylistQuery {
+  // Whether the media of each item is probed
+  #[serde(default)]
+  pub probe: bool,
+}
+
 #[derive(serde::Deserialize)]
 pub struct SearchMediaQuery {
   pub channel: Option<String>,
@@ -39,6 +48,18 @@ pub async fn batch_download_media(
   Ok(StatusCode::CREATED)
 }
 
+/// Handler for `POST /api/v1/media/download_url`
+pub async fn download_url(
+  RpcClient(rpc_client): RpcClient,
+  JsonBody(request): JsonBody<DownloadUrlRequest>,
+) -> crate::Result<StatusCode> {
+  let mut media_client = rpc_client.media.clone();
+
+  media_client.download_url(request).await?;
+
+  Ok(StatusCode::CREATED)
+}
+
 /// Handler for `GET /api/v1/channels/:channel_name/media/:media_id`
 pub async fn get_media_metadata(
   RpcClient(rpc_client): RpcClient,
@@ -73,15 +94,39 @@ pub async fn search_media(
   Ok(Json(res))
 }
 
-/// Handler for `GET /api/v1/channels/:channel_name/media/:media_id/playlist`
+/// Handler for `GET /api/v1/channels/:channel_name/media/:media_id/playlist?probe=true`
 pub async fn get_media_playlist(
   RpcClient(rpc_client): RpcClient,
   Path((channel, media_id)): Path<(String, String)>,
+  Query(query): Query<MediaPlaylistQuery>,
 ) -> crate::Result<Json<MediaPlaylist>> {
   let mut media_client = rpc_client.media.clone();
 
   let res = media_client
-    .get_media_playlist(GetMediaPlaylistRequest { channel, media_id })
+    .get_media_playlist(GetMediaPlaylistRequest {
+      channel,
+      media_id,
+      probe: query.probe,
+    })
+    .await?
+    .into_inner();
+
+  Ok(Json(res))
+}
+
+/// Handler for `GET /api/v1/channels/:channel_name/media/:media_id/playlist/:number/renditions`
+pub async fn get_media_renditions(
+  RpcClient(rpc_client): RpcClient,
+  Path((channel, media_id, number)): Path<(String, String, u32)>,
+) -> crate::Result<Json<MediaRenditions>> {
+  let mut media_client = rpc_client.media.clone();
+
+  let res = media_client
+    .get_media_renditions(GetMediaRenditionsRequest {
+      channel,
+      media_id,
+      number: Some(number),
+    })
     .await?
     .into_inner();
 
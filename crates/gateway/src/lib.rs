@@ -63,8 +63,13 @@ impl Gateway {
         "/channels/:channel_name/media/:media_id/playlist",
         get(media::get_media_playlist),
       )
+      .route(
+        "/channels/:channel_name/media/:media_id/playlist/:number/renditions",
+        get(media::get_media_renditions),
+      )
       .route("/media/download", post(media::download_media))
       .route("/media/batch_download", post(media::batch_download_media))
+      .route("/media/download_url", post(media::download_url))
       .route("/media/search", get(media::search_media))
       .route("/downloads", get(downloads::list_downloads))
       .route("/downloads/events", get(downloads::download_events_sse))
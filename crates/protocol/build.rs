@@ -11,6 +11,7 @@ fn main() {
       rpc GetMediaMetadata(crate::channel::GetMediaMetadataRequest) returns (crate::channel::MediaMetadata) {}
       rpc SearchMedia(crate::channel::SearchMediaRequest) returns (crate::channel::SearchMediaResponse) {}
       rpc GetMediaPlaylist(crate::channel::GetMediaPlaylistRequest) returns (crate::channel::MediaPlaylist) {}
+      rpc GetMediaRenditions(crate::channel::GetMediaRenditionsRequest) returns (crate::channel::MediaRenditions) {}
     }
   };
 
@@ -24,6 +25,8 @@ fn main() {
       rpc SearchMedia(crate::media::SearchMediaRequest) returns (crate::media::SearchMediaResponse) {}
       rpc GetMediaPlaylist(crate::media::GetMediaPlaylistRequest) returns (crate::media::MediaPlaylist) {}
       rpc BatchDownloadMedia(crate::media::BatchDownloadMediaRequest) returns (crate::Empty) {}
+      rpc DownloadUrl(crate::media::DownloadUrlRequest) returns (crate::Empty) {}
+      rpc GetMediaRenditions(crate::media::GetMediaRenditionsRequest) returns (crate::media::MediaRenditions) {}
     }
   };
 
@@ -1,5 +1,12 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// ID of the built-in channel downloading stream URLs given by users, whose media IDs are the
+/// URLs themselves.
+pub const DIRECT_CHANNEL: &str = "direct";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetChannelsRequest {}
@@ -17,11 +24,187 @@ pub struct GetChannelsResponse {
   pub channels: Vec<ChannelInfo>,
 }
 
+/// Alternate renditions of a media downloaded along with it, matched against the language or
+/// the name of the renditions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenditionSelection {
+  // Audio muxed into the media instead of the default one
+  #[serde(default)]
+  pub audio: Option<String>,
+  // Subtitles saved next to the media
+  #[serde(default)]
+  pub subtitles: Vec<String>,
+}
+
+/// Container of a downloaded media, configured for a channel and overridden by requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaContainer {
+  #[default]
+  Mp4,
+  Mkv,
+  Ts,
+}
+
+/// Format of the audio extracted by audio-only downloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+  M4a,
+  Opus,
+}
+
+/// Time range of a media to download, in seconds from its start. Clips without an end run to
+/// the end of the media. Times are those of the media once its ads are removed, which is what
+/// players of the downloaded media show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaClip {
+  #[serde(default)]
+  pub start: f64,
+  #[serde(default)]
+  pub end: Option<f64>,
+}
+
+impl MediaClip {
+  pub fn validate(&self) -> Result<(), String> {
+    if !self.start.is_finite() || self.start < 0.0 {
+      return Err(format!("Invalid start of clip: {}", self.start));
+    }
+
+    match self.end {
+      Some(end) if !end.is_finite() || end <= self.start => Err(format!(
+        "End of clip {} isn't after its start {}",
+        end, self.start
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  pub fn duration(&self) -> Option<f64> {
+    self.end.map(|end| end - self.start)
+  }
+}
+
+/// Seconds cut from the start and the end of a media, e.g. the opening and the ending repeated
+/// by every episode of a show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaTrim {
+  #[serde(default)]
+  pub intro: f64,
+  #[serde(default)]
+  pub outro: f64,
+}
+
+impl MediaTrim {
+  pub fn validate(&self) -> Result<(), String> {
+    for (name, seconds) in [("intro", self.intro), ("outro", self.outro)] {
+      if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!("Invalid {} to trim: {}", name, seconds));
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Part of a media lasting `duration` seconds that is kept, unless nothing would be.
+  pub fn clip_of(&self, duration: f64) -> Option<MediaClip> {
+    let end = duration - self.outro;
+
+    (end > self.intro).then(|| MediaClip {
+      start: self.intro,
+      end: (self.outro > 0.0).then_some(end),
+    })
+  }
+}
+
+/// Recording of a live HLS stream, which stops once `duration` seconds of it are recorded or at
+/// `until`, whichever comes first, and at the latest after the maximum duration configured for
+/// recordings. Times are RFC 3339, e.g. `2024-05-01T20:00:00+08:00`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveRecording {
+  #[serde(default)]
+  pub duration: Option<u64>,
+  #[serde(default)]
+  pub until: Option<String>,
+  // The recording is scheduled for then, it starts right away without it
+  #[serde(default)]
+  pub start_at: Option<String>,
+}
+
+impl LiveRecording {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.duration == Some(0) {
+      return Err("Duration of recording must be positive".to_string());
+    }
+
+    let start_at = parse_time(self.start_at.as_deref(), "start")?;
+    let until = parse_time(self.until.as_deref(), "end")?;
+
+    match (start_at, until) {
+      (_, Some(until)) if until <= Utc::now() => {
+        Err(format!("End of recording {} is in the past", until))
+      }
+      (Some(start_at), Some(until)) if until <= start_at => Err(format!(
+        "End of recording {} isn't after its start {}",
+        until, start_at
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  pub fn start_at(&self) -> Option<DateTime<Utc>> {
+    parse_time(self.start_at.as_deref(), "start").ok().flatten()
+  }
+
+  pub fn until(&self) -> Option<DateTime<Utc>> {
+    parse_time(self.until.as_deref(), "end").ok().flatten()
+  }
+
+  /// How long the recording waits before starting, if it is scheduled for later.
+  pub fn start_delay(&self) -> Option<Duration> {
+    self
+      .start_at()
+      .and_then(|start_at| (start_at - Utc::now()).to_std().ok())
+  }
+}
+
+fn parse_time(time: Option<&str>, name: &str) -> Result<Option<DateTime<Utc>>, String> {
+  time
+    .map(|time| {
+      DateTime::parse_from_rfc3339(time.trim())
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|err| format!("Invalid {} of recording {}: {}", name, time, err))
+    })
+    .transpose()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadMediaRequest {
   pub channel: String,
   pub media_id: String,
   pub number: Option<u32>,
+  #[serde(default)]
+  pub renditions: RenditionSelection,
+  // Post-processing profile run by the media service, instead of the one of the media kind
+  #[serde(default)]
+  pub post_processing: Option<String>,
+  #[serde(default)]
+  pub container: Option<MediaContainer>,
+  // Only this part of the media is downloaded, timed once its ads are removed
+  #[serde(default)]
+  pub clip: Option<MediaClip>,
+  // Only the audio of the media is kept, encoded into this format
+  #[serde(default)]
+  pub audio_only: Option<AudioFormat>,
+  // The media is a live stream recorded until these limits
+  #[serde(default)]
+  pub recording: Option<LiveRecording>,
+  // Cut from the media unless a clip of it is downloaded
+  #[serde(default)]
+  pub trim: Option<MediaTrim>,
+  // Written into the downloaded media
+  #[serde(default)]
+  pub tags: Option<MediaTags>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,9 +224,28 @@ pub enum MediaKind {
   TV,
   Variety,
   Anime,
+  Documentary,
+  ShortDrama,
   Other,
 }
 
+impl FromStr for MediaKind {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "movie" => Ok(Self::Movie),
+      "tv" => Ok(Self::TV),
+      "variety" => Ok(Self::Variety),
+      "anime" => Ok(Self::Anime),
+      "documentary" => Ok(Self::Documentary),
+      "shortdrama" | "short-drama" | "short_drama" => Ok(Self::ShortDrama),
+      "other" => Ok(Self::Other),
+      _ => Err(format!("Unknown media kind: {}", s)),
+    }
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MediaMetadata {
   pub channel: String,
@@ -53,6 +255,24 @@ pub struct MediaMetadata {
   pub release_year: u32,
   pub description: String,
   pub kind: MediaKind,
+  #[serde(default)]
+  pub cast: Vec<String>,
+  #[serde(default)]
+  pub directors: Vec<String>,
+  #[serde(default)]
+  pub region: String,
+  #[serde(default)]
+  pub language: String,
+  // Status of the media given by the channel, e.g. "更新至20集"
+  #[serde(default)]
+  pub remarks: String,
+  #[serde(default)]
+  pub total_episodes: Option<u32>,
+  // Last time the media was updated on the channel
+  #[serde(default)]
+  pub updated_at: Option<String>,
+  #[serde(default)]
+  pub score: Option<f32>,
 }
 
 impl MediaMetadata {
@@ -61,10 +281,69 @@ impl MediaMetadata {
   }
 }
 
+/// Container metadata written into downloaded media, so that players show more than their file
+/// name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaTags {
+  pub title: String,
+  pub year: Option<u32>,
+  pub description: String,
+  // Attached as cover art by containers supporting it
+  pub poster_url: Option<String>,
+  pub episode: Option<EpisodeTags>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpisodeTags {
+  pub show: String,
+  pub season: u8,
+  pub episode: u32,
+}
+
+impl MediaTags {
+  pub fn movie(metadata: &MediaMetadata) -> Self {
+    Self {
+      title: metadata.name.clone(),
+      year: Some(metadata.release_year).filter(|year| *year > 0),
+      description: metadata.description.trim().to_string(),
+      poster_url: Some(metadata.poster_url.trim().to_string()).filter(|url| !url.is_empty()),
+      episode: None,
+    }
+  }
+
+  pub fn episode(metadata: &MediaMetadata, show: String, season: u8, episode: u32) -> Self {
+    Self {
+      title: format!("{} S{:02}E{:02}", show, season, episode),
+      episode: Some(EpisodeTags {
+        show,
+        season,
+        episode,
+      }),
+      ..Self::movie(metadata)
+    }
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetMediaPlaylistRequest {
   pub channel: String,
   pub media_id: String,
+  // Whether the media of each item is probed, which takes a request or more per item
+  #[serde(default)]
+  pub probe: bool,
+}
+
+/// What the playlist of an item tells about its media, without downloading it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaItemProbe {
+  pub reachable: bool,
+  // Total duration of the segments, in seconds
+  pub duration: Option<f64>,
+  pub segments: Option<usize>,
+  // Resolutions of the variants, e.g. `1920x1080`, highest first
+  #[serde(default)]
+  pub resolutions: Vec<String>,
+  pub error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -72,6 +351,9 @@ pub struct MediaPlaylistItem {
   pub number: u32,
   pub text: String,
   pub url: String,
+  // Set when the playlist was probed
+  #[serde(default)]
+  pub probe: Option<MediaItemProbe>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,6 +363,29 @@ pub struct MediaPlaylist {
   pub items: Vec<MediaPlaylistItem>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetMediaRenditionsRequest {
+  pub channel: String,
+  pub media_id: String,
+  pub number: Option<u32>,
+}
+
+/// Alternate audio or subtitles of a media (`#EXT-X-MEDIA` of HLS master playlists).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaRendition {
+  pub group_id: String,
+  pub name: String,
+  pub language: Option<String>,
+  pub default: bool,
+  pub forced: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaRenditions {
+  pub audio: Vec<MediaRendition>,
+  pub subtitles: Vec<MediaRendition>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchMediaRequest {
   pub channel: Option<String>,
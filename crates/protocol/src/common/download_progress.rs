@@ -18,6 +18,29 @@ pub enum DownloadProgressItem {
     message: String,
     started_at: String,
   },
+  Retrying {
+    message: String,
+    started_at: String,
+  },
+  // Progress of downloads that aren't split into segments, e.g. MP4 files
+  BytesDownloaded {
+    downloaded_bytes: u64,
+    total_bytes: Option<u64>,
+    started_at: String,
+  },
+  // Segments of ads left out of the download
+  AdsRemoved {
+    removed_segments: usize,
+    removed_seconds: f64,
+    started_at: String,
+  },
+  // Progress of live recordings, which stop once `limit_seconds` are recorded
+  Recording {
+    recorded_seconds: f64,
+    recorded_bytes: u64,
+    limit_seconds: f64,
+    started_at: String,
+  },
   TransformingVideo {
     started_at: String,
   },
@@ -34,6 +57,10 @@ pub enum DownloadProgressItem {
 pub trait DownloadProgressExt {
   fn start(&self, total_segments: usize);
   fn segment_downloaded(&self, msg: &str);
+  fn retrying(&self, msg: &str);
+  fn bytes_downloaded(&self, downloaded_bytes: u64, total_bytes: Option<u64>);
+  fn ads_removed(&self, removed_segments: usize, removed_seconds: f64);
+  fn recording(&self, recorded_seconds: f64, recorded_bytes: u64, limit_seconds: f64);
   fn transforming_video(&self);
   fn done(&self, local_path: &str);
   fn failed(&self, reason: &str);
@@ -54,6 +81,38 @@ impl DownloadProgressExt for Stream<DownloadProgressItem, DownloadProgressRespon
     });
   }
 
+  fn retrying(&self, msg: &str) {
+    self.send(DownloadProgressItem::Retrying {
+      message: msg.to_string(),
+      started_at: now(),
+    });
+  }
+
+  fn bytes_downloaded(&self, downloaded_bytes: u64, total_bytes: Option<u64>) {
+    self.send(DownloadProgressItem::BytesDownloaded {
+      downloaded_bytes,
+      total_bytes,
+      started_at: now(),
+    });
+  }
+
+  fn ads_removed(&self, removed_segments: usize, removed_seconds: f64) {
+    self.send(DownloadProgressItem::AdsRemoved {
+      removed_segments,
+      removed_seconds,
+      started_at: now(),
+    });
+  }
+
+  fn recording(&self, recorded_seconds: f64, recorded_bytes: u64, limit_seconds: f64) {
+    self.send(DownloadProgressItem::Recording {
+      recorded_seconds,
+      recorded_bytes,
+      limit_seconds,
+      started_at: now(),
+    });
+  }
+
   fn transforming_video(&self) {
     self.send(DownloadProgressItem::TransformingVideo { started_at: now() })
   }
@@ -8,18 +8,71 @@ pub type GetMediaPlaylistRequest = crate::channel::GetMediaPlaylistRequest;
 
 pub type MediaPlaylist = crate::channel::MediaPlaylist;
 
+pub type GetMediaRenditionsRequest = crate::channel::GetMediaRenditionsRequest;
+
+pub type MediaRenditions = crate::channel::MediaRenditions;
+
+pub type RenditionSelection = crate::channel::RenditionSelection;
+
+pub type MediaContainer = crate::channel::MediaContainer;
+
 pub type SearchMediaRequest = crate::channel::SearchMediaRequest;
 
 pub type SearchMediaResponse = crate::channel::SearchMediaResponse;
 
 pub type MediaMetadata = crate::channel::MediaMetadata;
 
+pub type MediaKind = crate::channel::MediaKind;
+
+pub type AudioFormat = crate::channel::AudioFormat;
+
+pub type LiveRecording = crate::channel::LiveRecording;
+
+pub type MediaTrim = crate::channel::MediaTrim;
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct BatchDownloadMediaRequest {
   pub channel: String,
   pub media_id: String,
   pub start_number: u32,
   pub count: u8,
+  #[serde(default)]
+  pub renditions: RenditionSelection,
+  #[serde(default)]
+  pub post_processing: Option<String>,
+  #[serde(default)]
+  pub container: Option<MediaContainer>,
+  #[serde(default)]
+  pub audio_only: Option<AudioFormat>,
+  // Trimmed from each episode instead of the trim configured for the series
+  #[serde(default)]
+  pub trim: Option<MediaTrim>,
+}
+
+/// Download of a stream URL that is not listed by any channel, the metadata used to rename the
+/// downloaded file is given by the user.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DownloadUrlRequest {
+  pub url: String,
+  pub title: String,
+  pub kind: MediaKind,
+  #[serde(default)]
+  pub release_year: Option<u32>,
+  // Season and episode of TV shows, default to 1
+  #[serde(default)]
+  pub season: Option<u8>,
+  #[serde(default)]
+  pub episode: Option<u32>,
+  #[serde(default)]
+  pub renditions: RenditionSelection,
+  #[serde(default)]
+  pub post_processing: Option<String>,
+  #[serde(default)]
+  pub container: Option<MediaContainer>,
+  #[serde(default)]
+  pub audio_only: Option<AudioFormat>,
+  #[serde(default)]
+  pub recording: Option<LiveRecording>,
 }
 
 mod media_inner {
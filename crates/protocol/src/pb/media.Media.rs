@@ -198,6 +198,50 @@ pub mod media_client {
                 .insert(GrpcMethod::new("media.Media", "BatchDownloadMedia"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn download_url(
+            &mut self,
+            request: impl tonic::IntoRequest<crate::media::DownloadUrlRequest>,
+        ) -> std::result::Result<tonic::Response<crate::Empty>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = crate::json_codec::JsonCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/media.Media/DownloadUrl");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new("media.Media", "DownloadUrl"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_media_renditions(
+            &mut self,
+            request: impl tonic::IntoRequest<crate::media::GetMediaRenditionsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<crate::media::MediaRenditions>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = crate::json_codec::JsonCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/media.Media/GetMediaRenditions",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("media.Media", "GetMediaRenditions"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -236,6 +280,17 @@ pub mod media_server {
             &self,
             request: tonic::Request<crate::media::BatchDownloadMediaRequest>,
         ) -> std::result::Result<tonic::Response<crate::Empty>, tonic::Status>;
+        async fn download_url(
+            &self,
+            request: tonic::Request<crate::media::DownloadUrlRequest>,
+        ) -> std::result::Result<tonic::Response<crate::Empty>, tonic::Status>;
+        async fn get_media_renditions(
+            &self,
+            request: tonic::Request<crate::media::GetMediaRenditionsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<crate::media::MediaRenditions>,
+            tonic::Status,
+        >;
     }
     #[derive(Debug)]
     pub struct MediaServer<T: Media> {
@@ -553,6 +608,101 @@ pub mod media_server {
                     };
                     Box::pin(fut)
                 }
+                "/media.Media/DownloadUrl" => {
+                    #[allow(non_camel_case_types)]
+                    struct DownloadUrlSvc<T: Media>(pub Arc<T>);
+                    impl<
+                        T: Media,
+                    > tonic::server::UnaryService<crate::media::DownloadUrlRequest>
+                    for DownloadUrlSvc<T> {
+                        type Response = crate::Empty;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<crate::media::DownloadUrlRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Media>::download_url(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = DownloadUrlSvc(inner);
+                        let codec = crate::json_codec::JsonCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/media.Media/GetMediaRenditions" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetMediaRenditionsSvc<T: Media>(pub Arc<T>);
+                    impl<
+                        T: Media,
+                    > tonic::server::UnaryService<
+                        crate::media::GetMediaRenditionsRequest,
+                    > for GetMediaRenditionsSvc<T> {
+                        type Response = crate::media::MediaRenditions;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                crate::media::GetMediaRenditionsRequest,
+                            >,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Media>::get_media_renditions(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetMediaRenditionsSvc(inner);
+                        let codec = crate::json_codec::JsonCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
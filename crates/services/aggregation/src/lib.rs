@@ -50,7 +50,12 @@ pub fn create_aggregation_service(
   task_manager: Arc<TaskManager>,
 ) -> AggregationService {
   let channel = ChannelService::new(configuration);
-  let media = MediaService::new(rpc_client, task_manager);
+  let media = MediaService::new(
+    rpc_client,
+    task_manager,
+    &configuration.post_processing,
+    &configuration.series,
+  );
 
   AggregationService { channel, media }
 }
@@ -1,3 +1,47 @@
+mod ad_filter;
+mod category;
+mod clip;
+mod container;
+mod dash;
 mod download_media;
+mod ffmpeg;
+mod http_client;
+mod live;
+mod names;
+mod probe;
+mod progressive;
+mod rate_limiter;
+mod renditions;
+mod retry;
+mod segments;
+mod sources;
+mod subtitles;
+mod tags;
+#[cfg(test)]
+mod test_server;
+mod url_template;
+mod variant;
+mod verify;
 
+pub use ad_filter::*;
+pub use category::*;
+pub use clip::*;
+pub use container::*;
+pub use dash::*;
 pub use download_media::*;
+pub use ffmpeg::*;
+pub use http_client::*;
+pub use live::*;
+pub use names::*;
+pub use probe::*;
+pub use progressive::*;
+pub use rate_limiter::*;
+pub use renditions::*;
+pub use retry::*;
+pub use segments::*;
+pub use sources::*;
+pub use subtitles::*;
+pub use tags::*;
+pub use url_template::*;
+pub use variant::*;
+pub use verify::*;
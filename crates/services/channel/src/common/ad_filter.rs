@@ -0,0 +1,599 @@
+use configuration::{AdFilterConfig, AdPattern};
+use m3u8_rs::{MediaPlaylist, MediaSegment};
+use std::collections::HashMap;
+use std::ops::Range;
+use url::Url;
+
+const DEFAULT_MAX_AD_DURATION: f64 = 90.0;
+
+// Share of the duration of the media the main host or directory needs to be taken as the
+// signature of the media
+const SIGNATURE_SHARE: f64 = 0.8;
+
+// More is removed only when the heuristics are obviously wrong
+const MAX_REMOVED_SHARE: f64 = 0.5;
+
+/// What was left out of a playlist.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdFilterReport {
+  pub removed_segments: usize,
+  pub removed_seconds: f64,
+  // Time ranges of the removed segments, in seconds from the start of the playlist
+  pub removed_ranges: Vec<Range<f64>>,
+}
+
+/// Finds ads spliced into an HLS playlist. Ads are segments whose URI matches one of the
+/// configured patterns, and short runs of segments between discontinuities that don't look
+/// like the rest of the media: they are on another host, in another directory, or none of
+/// their segments has the usual segment duration.
+pub struct AdFilter {
+  patterns: Vec<AdPattern>,
+  max_duration: f64,
+}
+
+impl AdFilter {
+  pub fn new(config: &AdFilterConfig) -> Self {
+    Self {
+      patterns: config.patterns.clone(),
+      max_duration: config.max_duration.unwrap_or(DEFAULT_MAX_AD_DURATION),
+    }
+  }
+
+  /// Removes the ads of `playlist`, which should have absolute segment URIs.
+  pub fn filter(&self, playlist: &mut MediaPlaylist) -> AdFilterReport {
+    let is_ad = self.detect_ads(&playlist.segments);
+
+    let total_seconds = total_duration(&playlist.segments);
+    let removed_seconds: f64 = playlist
+      .segments
+      .iter()
+      .zip(&is_ad)
+      .filter(|(_, is_ad)| **is_ad)
+      .map(|(segment, _)| segment.duration as f64)
+      .sum();
+    let removed_segments = is_ad.iter().filter(|is_ad| **is_ad).count();
+
+    if removed_segments == 0 {
+      return AdFilterReport::default();
+    }
+
+    if removed_seconds > total_seconds * MAX_REMOVED_SHARE {
+      log::warn!(
+        "Keep {} segments ({:.1}s of {:.1}s) taken as ads, too much of the media would be removed",
+        removed_segments,
+        removed_seconds,
+        total_seconds
+      );
+
+      return AdFilterReport::default();
+    }
+
+    let removed_ranges = flagged_ranges(&playlist.segments, &is_ad);
+
+    remove_segments(playlist, &is_ad);
+
+    AdFilterReport {
+      removed_segments,
+      removed_seconds,
+      removed_ranges,
+    }
+  }
+
+  fn detect_ads(&self, segments: &[MediaSegment]) -> Vec<bool> {
+    let mut is_ad: Vec<bool> = segments
+      .iter()
+      .map(|segment| {
+        self
+          .patterns
+          .iter()
+          .any(|pattern| pattern.is_match(&segment.uri))
+      })
+      .collect();
+
+    let blocks = discontinuity_blocks(segments);
+
+    // Without discontinuities, there's nothing to tell the ads apart from
+    if blocks.len() < 2 {
+      return is_ad;
+    }
+
+    let main_host = main_signature(segments, |segment| uri_host(&segment.uri));
+    let main_directory = main_signature(segments, |segment| uri_directory(&segment.uri));
+    let usual_duration = usual_segment_duration(segments);
+
+    for block in blocks {
+      let block_segments = &segments[block.clone()];
+
+      if total_duration(block_segments) > self.max_duration {
+        continue;
+      }
+
+      let differs = |main: &Option<String>, signature: fn(&str) -> Option<String>| {
+        main.as_ref().is_some_and(|main| {
+          block_segments
+            .iter()
+            .all(|segment| signature(&segment.uri).as_ref() != Some(main))
+        })
+      };
+
+      // The last segment of a run is usually cut short. The end of the media, e.g. its credits,
+      // is often made of short segments, only its host or directory tells an ad apart.
+      let durations = match block_segments.len() {
+        _ if block.end == segments.len() => &[],
+        1 => block_segments,
+        len => &block_segments[..len - 1],
+      };
+      let has_unusual_durations = !durations.is_empty()
+        && usual_duration.is_some_and(|usual_duration| {
+          durations
+            .iter()
+            .all(|segment| duration_key(segment.duration) != usual_duration)
+        });
+
+      if differs(&main_host, uri_host)
+        || differs(&main_directory, uri_directory)
+        || has_unusual_durations
+      {
+        is_ad[block].fill(true);
+      }
+    }
+
+    is_ad
+  }
+}
+
+// Runs of segments between discontinuities
+fn discontinuity_blocks(segments: &[MediaSegment]) -> Vec<Range<usize>> {
+  let mut blocks = vec![];
+  let mut start = 0;
+
+  for (index, segment) in segments.iter().enumerate() {
+    if segment.discontinuity && index > start {
+      blocks.push(start..index);
+      start = index;
+    }
+  }
+
+  if start < segments.len() {
+    blocks.push(start..segments.len());
+  }
+
+  blocks
+}
+
+// Value of `signature` shared by most of the duration of the media, if it's shared enough
+fn main_signature(
+  segments: &[MediaSegment],
+  signature: impl Fn(&MediaSegment) -> Option<String>,
+) -> Option<String> {
+  let mut durations: HashMap<String, f64> = HashMap::new();
+
+  for segment in segments {
+    if let Some(value) = signature(segment) {
+      *durations.entry(value).or_default() += segment.duration as f64;
+    }
+  }
+
+  let total = total_duration(segments);
+  let (value, duration) = durations
+    .into_iter()
+    .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+  (duration >= total * SIGNATURE_SHARE).then_some(value)
+}
+
+// Most common segment duration, in milliseconds
+fn usual_segment_duration(segments: &[MediaSegment]) -> Option<u64> {
+  let mut counts: HashMap<u64, usize> = HashMap::new();
+
+  for segment in segments {
+    *counts.entry(duration_key(segment.duration)).or_default() += 1;
+  }
+
+  let (duration, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+
+  // A duration shared by few segments says nothing
+  (count * 2 >= segments.len()).then_some(duration)
+}
+
+fn duration_key(duration: f32) -> u64 {
+  (duration as f64 * 1000.0).round() as u64
+}
+
+fn total_duration(segments: &[MediaSegment]) -> f64 {
+  segments.iter().map(|segment| segment.duration as f64).sum()
+}
+
+fn uri_host(uri: &str) -> Option<String> {
+  Url::parse(uri).ok()?.host_str().map(String::from)
+}
+
+fn uri_directory(uri: &str) -> Option<String> {
+  let url = Url::parse(uri).ok()?;
+  let path = url.path();
+
+  Some(path[..path.rfind('/')? + 1].to_string())
+}
+
+/// Removes the segments of `playlist` in the time `ranges` of another playlist of the same media,
+/// e.g. the ads found in the video from its alternate audio, so that both stay in sync. A
+/// segment is in a range when its middle is.
+pub fn remove_time_ranges(playlist: &mut MediaPlaylist, ranges: &[Range<f64>]) -> usize {
+  let mut start = 0.0;
+
+  let remove: Vec<bool> = playlist
+    .segments
+    .iter()
+    .map(|segment| {
+      let middle = start + segment.duration as f64 / 2.0;
+      start += segment.duration as f64;
+
+      ranges.iter().any(|range| range.contains(&middle))
+    })
+    .collect();
+
+  let removed_segments = remove.iter().filter(|remove| **remove).count();
+
+  if removed_segments > 0 {
+    remove_segments(playlist, &remove);
+  }
+
+  removed_segments
+}
+
+// Time ranges of the runs of segments flagged in `flags`
+fn flagged_ranges(segments: &[MediaSegment], flags: &[bool]) -> Vec<Range<f64>> {
+  let mut ranges: Vec<Range<f64>> = vec![];
+  let mut start = 0.0;
+
+  for (segment, flag) in segments.iter().zip(flags) {
+    let end = start + segment.duration as f64;
+
+    if *flag {
+      match ranges.last_mut() {
+        Some(range) if range.end == start => range.end = end,
+        _ => ranges.push(start..end),
+      }
+    }
+
+    start = end;
+  }
+
+  ranges
+}
+
+// Removes the segments flagged in `remove`. Keys and init sections apply to the following
+// segments too, so those of removed segments are moved to the next kept one, and the segment
+// after a gap starts a new discontinuity.
+fn remove_segments(playlist: &mut MediaPlaylist, remove: &[bool]) {
+  let segments = std::mem::take(&mut playlist.segments);
+
+  let mut key = None;
+  let mut map = None;
+  let mut after_gap = false;
+
+  for (segment, remove) in segments.into_iter().zip(remove) {
+    if *remove {
+      key = segment.key.or(key);
+      map = segment.map.or(map);
+      after_gap = true;
+      continue;
+    }
+
+    let mut segment = segment;
+
+    if segment.key.is_none() {
+      segment.key = key.take();
+    }
+
+    if segment.map.is_none() {
+      segment.map = map.take();
+    }
+
+    key = None;
+    map = None;
+
+    if after_gap && !playlist.segments.is_empty() {
+      segment.discontinuity = true;
+    }
+
+    after_gap = false;
+    playlist.segments.push(segment);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{remove_time_ranges, AdFilter, AdFilterReport};
+  use configuration::{AdFilterConfig, AdPattern};
+  use m3u8_rs::{parse_media_playlist_res, MediaPlaylist};
+
+  fn parse_playlist(text: &str) -> MediaPlaylist {
+    parse_media_playlist_res(text.as_bytes()).unwrap()
+  }
+
+  fn filter(config: AdFilterConfig, playlist: &mut MediaPlaylist) -> AdFilterReport {
+    AdFilter::new(&config).filter(playlist)
+  }
+
+  fn pattern(pattern: &str) -> AdPattern {
+    AdPattern::try_from(pattern.to_string()).unwrap()
+  }
+
+  fn uris(playlist: &MediaPlaylist) -> Vec<&str> {
+    playlist
+      .segments
+      .iter()
+      .map(|segment| segment.uri.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn test_remove_ads_of_other_hosts() {
+    let mut playlist = parse_playlist(
+      "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-KEY:METHOD=AES-128,URI=\"https://cdn.example.com/vod/key.bin\"
+#EXTINF:6.0,
+https://cdn.example.com/vod/0.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/1.ts
+#EXT-X-DISCONTINUITY
+#EXT-X-KEY:METHOD=NONE
+#EXTINF:6.0,
+https://ads.example.net/spot/0.ts
+#EXTINF:6.0,
+https://ads.example.net/spot/1.ts
+#EXT-X-DISCONTINUITY
+#EXT-X-KEY:METHOD=AES-128,URI=\"https://cdn.example.com/vod/key.bin\"
+#EXTINF:6.0,
+https://cdn.example.com/vod/2.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/3.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/4.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/5.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/6.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/7.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/8.ts
+#EXTINF:3.5,
+https://cdn.example.com/vod/9.ts
+#EXT-X-ENDLIST
+",
+    );
+
+    let config = AdFilterConfig {
+      enabled: true,
+      ..Default::default()
+    };
+
+    assert_eq!(
+      filter(config, &mut playlist),
+      AdFilterReport {
+        removed_segments: 2,
+        removed_seconds: 12.0,
+        removed_ranges: vec![12.0..24.0],
+      }
+    );
+    assert_eq!(playlist.segments.len(), 10);
+    assert!(uris(&playlist)
+      .iter()
+      .all(|uri| uri.starts_with("https://cdn.example.com/vod/")));
+
+    let resumed = &playlist.segments[2];
+
+    assert!(resumed.discontinuity);
+    assert!(resumed.key.as_ref().unwrap().uri.is_some());
+  }
+
+  #[test]
+  fn test_remove_ads_of_other_directories_and_durations() {
+    // MacCMS style ads on the same host, in another directory or with unusual durations
+    let mut playlist = parse_playlist(
+      "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXTINF:4.000000,
+https://cdn.example.com/20230714/abc/hls/0.ts
+#EXTINF:4.000000,
+https://cdn.example.com/20230714/abc/hls/1.ts
+#EXTINF:4.000000,
+https://cdn.example.com/20230714/abc/hls/2.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:3.000000,
+https://cdn.example.com/adjump/20230701/0.ts
+#EXTINF:3.000000,
+https://cdn.example.com/adjump/20230701/1.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:4.000000,
+https://cdn.example.com/20230714/abc/hls/3.ts
+#EXTINF:4.000000,
+https://cdn.example.com/20230714/abc/hls/4.ts
+#EXTINF:4.000000,
+https://cdn.example.com/20230714/abc/hls/5.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:3.333333,
+https://cdn.example.com/20230714/abc/hls/6.ts
+#EXTINF:2.000000,
+https://cdn.example.com/20230714/abc/hls/7.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:4.000000,
+https://cdn.example.com/20230714/abc/hls/8.ts
+#EXTINF:4.000000,
+https://cdn.example.com/20230714/abc/hls/9.ts
+#EXT-X-ENDLIST
+",
+    );
+
+    let config = AdFilterConfig {
+      enabled: true,
+      ..Default::default()
+    };
+
+    let report = filter(config, &mut playlist);
+
+    assert_eq!(report.removed_segments, 4);
+    assert!((report.removed_seconds - 11.333333).abs() < 0.001);
+    assert_eq!(playlist.segments.len(), 8);
+    assert!(playlist
+      .segments
+      .iter()
+      .all(|segment| segment.duration == 4.0));
+  }
+
+  #[test]
+  fn test_remove_ads_matching_patterns() {
+    let mut playlist = parse_playlist(
+      "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXTINF:6.0,
+https://cdn.example.com/vod/ad-0.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/0.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/1.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/2.ts
+#EXT-X-ENDLIST
+",
+    );
+
+    let config = AdFilterConfig {
+      enabled: true,
+      patterns: vec![pattern("/ad-\\d+\\.ts$")],
+      max_duration: None,
+    };
+
+    assert_eq!(filter(config, &mut playlist).removed_segments, 1);
+    assert_eq!(playlist.segments[0].uri, "https://cdn.example.com/vod/0.ts");
+    // Nothing was before the removed segment
+    assert!(!playlist.segments[0].discontinuity);
+  }
+
+  #[test]
+  fn test_keep_media_that_looks_like_ads() {
+    // Discontinuities of the media itself, e.g. between the intro and the episode
+    let text = "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXTINF:6.0,
+https://cdn.example.com/vod/0.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/1.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:6.0,
+https://cdn.example.com/vod/2.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/3.ts
+#EXTINF:2.0,
+https://cdn.example.com/vod/4.ts
+#EXT-X-ENDLIST
+";
+
+    let config = AdFilterConfig {
+      enabled: true,
+      ..Default::default()
+    };
+
+    let mut playlist = parse_playlist(text);
+
+    assert_eq!(
+      filter(config.clone(), &mut playlist),
+      AdFilterReport::default()
+    );
+    assert_eq!(playlist.segments.len(), 5);
+
+    // Removing most of the media is a sign of wrong heuristics
+    let config = AdFilterConfig {
+      patterns: vec![pattern("/vod/")],
+      ..config
+    };
+
+    let mut playlist = parse_playlist(text);
+
+    assert_eq!(filter(config, &mut playlist), AdFilterReport::default());
+    assert_eq!(playlist.segments.len(), 5);
+  }
+
+  #[test]
+  fn test_keep_short_trailing_run() {
+    // The last run of the media, e.g. its credits, ends with a short segment
+    let mut playlist = parse_playlist(
+      "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXTINF:6.0,
+https://cdn.example.com/vod/0.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/1.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/2.ts
+#EXTINF:6.0,
+https://cdn.example.com/vod/3.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:6.0,
+https://cdn.example.com/vod/4.ts
+#EXTINF:2.5,
+https://cdn.example.com/vod/5.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:3.0,
+https://cdn.example.com/vod/6.ts
+#EXTINF:1.5,
+https://cdn.example.com/vod/7.ts
+#EXT-X-ENDLIST
+",
+    );
+
+    let config = AdFilterConfig {
+      enabled: true,
+      ..Default::default()
+    };
+
+    assert_eq!(filter(config, &mut playlist), AdFilterReport::default());
+    assert_eq!(playlist.segments.len(), 8);
+  }
+
+  #[test]
+  fn test_remove_time_ranges_of_alternate_audio() {
+    // Audio segments don't line up with the video ones
+    let mut audio = parse_playlist(
+      "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXTINF:4.0,
+https://cdn.example.com/vod/audio/0.aac
+#EXTINF:4.0,
+https://cdn.example.com/vod/audio/1.aac
+#EXTINF:4.0,
+https://cdn.example.com/vod/audio/2.aac
+#EXT-X-DISCONTINUITY
+#EXTINF:4.0,
+https://ads.example.net/spot/audio/0.aac
+#EXTINF:4.0,
+https://ads.example.net/spot/audio/1.aac
+#EXTINF:4.0,
+https://ads.example.net/spot/audio/2.aac
+#EXT-X-DISCONTINUITY
+#EXTINF:4.0,
+https://cdn.example.com/vod/audio/3.aac
+#EXTINF:4.0,
+https://cdn.example.com/vod/audio/4.aac
+#EXT-X-ENDLIST
+",
+    );
+
+    assert_eq!(remove_time_ranges(&mut audio, &[12.0..24.0]), 3);
+    assert!(uris(&audio)
+      .iter()
+      .all(|uri| uri.starts_with("https://cdn.example.com/vod/audio/")));
+    assert!(audio.segments[3].discontinuity);
+
+    assert_eq!(remove_time_ranges(&mut audio, &[]), 0);
+    assert_eq!(audio.segments.len(), 5);
+  }
+
+  #[test]
+  fn test_invalid_pattern() {
+    assert!(AdPattern::try_from("(".to_string()).is_err());
+  }
+}
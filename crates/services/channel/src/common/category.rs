@@ -0,0 +1,94 @@
+use configuration::CategoryMappingConfig;
+use protocol::channel::MediaKind;
+use regex::Regex;
+
+/// A category of a channel, either the category of a media or one of its ancestors.
+#[derive(Debug, Clone, Copy)]
+pub struct Category<'a> {
+  pub id: Option<u32>,
+  pub name: &'a str,
+}
+
+enum CategoryMatcher {
+  TypeId(u32),
+  Name(String),
+  Pattern(Regex),
+}
+
+/// Maps categories of a channel to media kinds, using the mapping configured for the channel
+/// before the category names most channels share.
+pub struct CategoryMapper {
+  rules: Vec<(CategoryMatcher, MediaKind)>,
+}
+
+impl CategoryMapper {
+  pub fn new(configs: &[CategoryMappingConfig]) -> anyhow::Result<Self> {
+    let mut rules = Vec::with_capacity(configs.len());
+
+    for config in configs {
+      let kind: MediaKind = config.kind.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+
+      let matcher = match (config.type_id, &config.name, &config.pattern) {
+        (Some(type_id), None, None) => CategoryMatcher::TypeId(type_id),
+        (None, Some(name), None) => CategoryMatcher::Name(name.trim().to_string()),
+        (None, None, Some(pattern)) => CategoryMatcher::Pattern(
+          Regex::new(pattern)
+            .map_err(|e| anyhow::anyhow!("Invalid category pattern {}: {}", pattern, e))?,
+        ),
+        _ => anyhow::bail!(
+          "Category mapping to {} should have exactly one of `type-id`, `name` and `pattern`",
+          config.kind
+        ),
+      };
+
+      rules.push((matcher, kind));
+    }
+
+    Ok(Self { rules })
+  }
+
+  /// Finds the media kind of a category. `lineage` is the category followed by its ancestors up
+  /// to the root category. The most specific category matching a configured rule decides the
+  /// kind, and only without any does the most specific category with a default name.
+  pub fn media_kind<'a>(&self, lineage: impl IntoIterator<Item = Category<'a>>) -> MediaKind {
+    let lineage: Vec<Category> = lineage.into_iter().collect();
+
+    lineage
+      .iter()
+      .find_map(|category| self.configured_kind(category))
+      .or_else(|| {
+        lineage
+          .iter()
+          .find_map(|category| default_kind(category.name))
+      })
+      .unwrap_or(MediaKind::Other)
+  }
+
+  fn configured_kind(&self, category: &Category) -> Option<MediaKind> {
+    let name = category.name.trim();
+
+    self
+      .rules
+      .iter()
+      .find(|(matcher, _)| match matcher {
+        CategoryMatcher::TypeId(type_id) => category.id == Some(*type_id),
+        CategoryMatcher::Name(expected) => expected == name,
+        CategoryMatcher::Pattern(pattern) => pattern.is_match(name),
+      })
+      .map(|(_, kind)| *kind)
+  }
+}
+
+fn default_kind(name: &str) -> Option<MediaKind> {
+  let kind = match name.trim() {
+    "电影" | "电影片" | "影片" => MediaKind::Movie,
+    "电视剧" | "连续剧" | "剧集" => MediaKind::TV,
+    "综艺" | "综艺片" | "综艺节目" => MediaKind::Variety,
+    "动漫" | "动漫片" | "动画" | "动画片" | "番剧" => MediaKind::Anime,
+    "纪录片" | "记录片" | "纪录" => MediaKind::Documentary,
+    "短剧" | "微短剧" | "短剧大全" => MediaKind::ShortDrama,
+    _ => return None,
+  };
+
+  Some(kind)
+}
@@ -0,0 +1,132 @@
+use m3u8_rs::MediaPlaylist;
+use protocol::channel::MediaClip;
+
+/// Keeps the segments of `playlist` covering `clip`, returning where the clip starts in the
+/// first kept one, in seconds. Keys and init sections of the dropped segments are moved to the
+/// first kept one, whose media sequence number, from which the IV of keys without one is
+/// derived, is kept as well.
+pub fn clip_playlist(playlist: &mut MediaPlaylist, clip: &MediaClip) -> anyhow::Result<f64> {
+  let segments = std::mem::take(&mut playlist.segments);
+
+  let mut start = 0.0;
+  let mut offset = None;
+  let mut skipped = 0;
+  let mut key = None;
+  let mut map = None;
+
+  for mut segment in segments {
+    let end = start + segment.duration as f64;
+    let is_covered = end > clip.start && clip.end.is_none_or(|clip_end| start < clip_end);
+
+    if !is_covered {
+      if offset.is_none() {
+        key = segment.key.take().or(key);
+        map = segment.map.take().or(map);
+        skipped += 1;
+      }
+
+      start = end;
+      continue;
+    }
+
+    if offset.is_none() {
+      offset = Some(clip.start - start);
+
+      if segment.key.is_none() {
+        segment.key = key.take();
+      }
+
+      if segment.map.is_none() {
+        segment.map = map.take();
+      }
+
+      // The clip doesn't start after a discontinuity of the media
+      segment.discontinuity = false;
+    }
+
+    playlist.segments.push(segment);
+    start = end;
+  }
+
+  let Some(offset) = offset else {
+    anyhow::bail!(
+      "Clip starting at {}s is past the end of the media ({:.1}s)",
+      clip.start,
+      start
+    );
+  };
+
+  playlist.media_sequence += skipped;
+
+  Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::clip_playlist;
+  use m3u8_rs::{parse_media_playlist_res, MediaPlaylist};
+  use protocol::channel::MediaClip;
+
+  const PLAYLIST: &str = "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:100
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"
+#EXTINF:10.0,
+0.m4s
+#EXTINF:10.0,
+1.m4s
+#EXT-X-DISCONTINUITY
+#EXTINF:10.0,
+2.m4s
+#EXTINF:10.0,
+3.m4s
+#EXTINF:5.0,
+4.m4s
+#EXT-X-ENDLIST
+";
+
+  fn clip(start: f64, end: Option<f64>) -> (MediaPlaylist, anyhow::Result<f64>) {
+    let mut playlist = parse_media_playlist_res(PLAYLIST.as_bytes()).unwrap();
+    let offset = clip_playlist(&mut playlist, &MediaClip { start, end });
+
+    (playlist, offset)
+  }
+
+  fn uris(playlist: &MediaPlaylist) -> Vec<&str> {
+    playlist
+      .segments
+      .iter()
+      .map(|segment| segment.uri.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn test_clip_playlist() {
+    let (playlist, offset) = clip(25.0, Some(32.0));
+
+    assert_eq!(offset.unwrap(), 5.0);
+    assert_eq!(uris(&playlist), vec!["2.m4s", "3.m4s"]);
+    assert_eq!(playlist.media_sequence, 102);
+
+    let first = &playlist.segments[0];
+    assert_eq!(first.map.as_ref().unwrap().uri, "init.mp4");
+    assert_eq!(first.key.as_ref().unwrap().uri.as_deref(), Some("key.bin"));
+    assert!(!first.discontinuity);
+
+    // Clips ending on the boundary of a segment don't need the next one
+    let (playlist, offset) = clip(0.0, Some(20.0));
+
+    assert_eq!(offset.unwrap(), 0.0);
+    assert_eq!(uris(&playlist), vec!["0.m4s", "1.m4s"]);
+    assert_eq!(playlist.media_sequence, 100);
+
+    let (playlist, offset) = clip(40.0, None);
+
+    assert_eq!(offset.unwrap(), 0.0);
+    assert_eq!(uris(&playlist), vec!["4.m4s"]);
+
+    assert!(clip(45.0, None).1.is_err());
+  }
+}
@@ -0,0 +1,74 @@
+use protocol::channel::MediaContainer;
+
+pub fn container_extension(container: MediaContainer) -> &'static str {
+  match container {
+    MediaContainer::Mp4 => "mp4",
+    MediaContainer::Mkv => "mkv",
+    MediaContainer::Ts => "ts",
+  }
+}
+
+/// ffmpeg options of the output of a remux into `container`.
+pub fn container_args(container: MediaContainer) -> &'static [&'static str] {
+  match container {
+    // Moves the index to the front of the file, so that it plays before being fully loaded
+    MediaContainer::Mp4 => &["-movflags", "+faststart"],
+    MediaContainer::Mkv => &[],
+    MediaContainer::Ts => &["-f", "mpegts"],
+  }
+}
+
+/// ffmpeg options encoding the video of a clip into `container`, so that it's cut exactly
+/// instead of on a keyframe. The video is encoded with the codec it had, `video_codec` as named
+/// by ffprobe, and the audio is copied, its packets are short enough to cut on.
+pub fn clip_encoding_args(
+  container: MediaContainer,
+  video_codec: Option<&str>,
+) -> Vec<&'static str> {
+  let mut args = match video_codec {
+    Some("hevc") => vec!["-c:v", "libx265", "-crf", "20", "-preset", "veryfast"],
+    Some("vp9") => vec![
+      "-c:v",
+      "libvpx-vp9",
+      "-crf",
+      "31",
+      "-b:v",
+      "0",
+      "-row-mt",
+      "1",
+    ],
+    Some("av1") => vec!["-c:v", "libsvtav1", "-crf", "30", "-preset", "8"],
+    _ => vec!["-c:v", "libx264", "-crf", "18", "-preset", "veryfast"],
+  };
+
+  // Players of Apple devices only play HEVC in MP4 tagged as such
+  if video_codec == Some("hevc") && container == MediaContainer::Mp4 {
+    args.extend(["-tag:v", "hvc1"]);
+  }
+
+  args.extend(["-c:a", "copy"]);
+  args
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{clip_encoding_args, container_args, container_extension};
+  use protocol::channel::MediaContainer;
+
+  #[test]
+  fn test_container() {
+    assert_eq!(container_extension(MediaContainer::default()), "mp4");
+    assert_eq!(container_extension(MediaContainer::Ts), "ts");
+    assert!(container_args(MediaContainer::Mp4).contains(&"+faststart"));
+
+    let args = clip_encoding_args(MediaContainer::Mp4, Some("hevc"));
+
+    assert_eq!(&args[..2], ["-c:v", "libx265"]);
+    assert!(args.ends_with(&["-tag:v", "hvc1", "-c:a", "copy"]));
+    assert!(!clip_encoding_args(MediaContainer::Mkv, Some("hevc")).contains(&"hvc1"));
+    assert_eq!(
+      &clip_encoding_args(MediaContainer::Ts, None)[..2],
+      ["-c:v", "libx264"]
+    );
+  }
+}
@@ -0,0 +1,1226 @@
+use super::segments::download_file;
+use super::{container_args, container_extension, download_verified, is_language, run_ffmpeg};
+use super::{DownloadMediaOptions, HttpClient, MediaTagging, Variant, VariantPolicy};
+use futures::{StreamExt, TryStreamExt};
+use protocol::channel::MediaContainer;
+use protocol::{DownloadProgressExt, DownloadProgressReceiver, DownloadProgressStream};
+use roxmltree::{Document, Node};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+const SEGMENT_CONCURRENCY: usize = 4;
+
+/// Static (on demand) MPEG-DASH manifest, with the segment URLs of every representation resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DashManifest {
+  pub periods: Vec<DashPeriod>,
+  // Total duration in seconds, if known
+  pub duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DashPeriod {
+  pub representations: Vec<DashRepresentation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DashContentKind {
+  Video,
+  Audio,
+  Text,
+  Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DashRepresentation {
+  pub id: String,
+  pub kind: DashContentKind,
+  pub bandwidth: u64,
+  pub width: Option<u32>,
+  pub height: Option<u32>,
+  pub codecs: Option<String>,
+  pub language: Option<String>,
+  // URL of the initialization segment, which the media segments are appended to
+  pub initialization: Option<String>,
+  pub segments: Vec<String>,
+}
+
+impl Variant for DashRepresentation {
+  fn bandwidth(&self) -> u64 {
+    self.bandwidth
+  }
+
+  fn height(&self) -> Option<u32> {
+    self.height
+  }
+}
+
+/// Downloads the video and audio representations of a DASH manifest picked by the variant
+/// policy of the channel, and muxes them into `options.destination_path`.
+pub async fn download_dash_media(
+  options: DownloadMediaOptions<'_>,
+) -> anyhow::Result<DownloadProgressReceiver> {
+  let stream = stream::Stream::new(Ok);
+  let receiver = stream.recv();
+  let variant_policy = VariantPolicy::new(options.download_config);
+
+  let manifest = options
+    .http_client
+    .retry_policy()
+    .run_with_notify(
+      options.download_url,
+      || fetch_dash_manifest(options.http_client, options.download_url),
+      |message| stream.retrying(message),
+    )
+    .await?;
+
+  let mut tracks = select_tracks(
+    &manifest,
+    &variant_policy,
+    options.renditions.audio.as_deref(),
+  )?;
+
+  // Audio-only downloads skip the video, unless its audio is muxed into it
+  if options.audio_only
+    && tracks
+      .iter()
+      .any(|track| track.representation.kind == DashContentKind::Audio)
+  {
+    tracks.retain(|track| track.representation.kind != DashContentKind::Video);
+  }
+
+  let total_segments = tracks.iter().map(DashTrack::total_files).sum();
+
+  stream.start(total_segments);
+
+  let container = options.container();
+
+  tokio::spawn({
+    let download = DashDownload {
+      http_client: options.http_client.clone(),
+      tracks,
+      container,
+    };
+    let download_url = options.download_url.to_string();
+    let destination_path = options
+      .destination_path
+      .with_extension(container_extension(container));
+    let expected_duration = manifest.duration;
+    let audio_only = options.audio_only;
+    let verification = options.download_config.verification.clone();
+    let tags = options.tags.cloned();
+
+    async move {
+      log::info!(
+        "Downloading DASH media: {} (total segments: {})",
+        download_url,
+        total_segments
+      );
+
+      let tagging = match &tags {
+        Some(tags) => {
+          Some(MediaTagging::prepare(&download.http_client, tags, &destination_path).await)
+        }
+        None => None,
+      };
+
+      let downloaded = download_verified(
+        || download.run(tagging.as_ref(), &destination_path, &stream),
+        &destination_path,
+        expected_duration,
+        !audio_only,
+        &verification,
+        total_segments,
+        &stream,
+      )
+      .await;
+
+      if let Some(tagging) = &tagging {
+        tagging.remove_cover().await;
+      }
+
+      if let Err(err) = downloaded {
+        log::error!("Failed to download DASH media: {:#}", err);
+        stream.failed(&format!("{:#}", err));
+        return Err(err);
+      }
+
+      log::info!("Done. {:?}", destination_path);
+      stream.done(&destination_path.to_string_lossy());
+
+      Ok(()) as anyhow::Result<()>
+    }
+  });
+
+  Ok(receiver)
+}
+
+async fn fetch_dash_manifest(http_client: &HttpClient, url: &str) -> anyhow::Result<DashManifest> {
+  log::info!("Starting fetch DASH manifest: {}", url);
+
+  let request = http_client.media_client().get(url);
+  let res = http_client.send(request).await?.error_for_status()?;
+
+  // Relative URLs of the manifest are relative to where it was actually served from
+  let manifest_url = res.url().clone();
+  let text = res.text().await?;
+
+  parse_dash_manifest(&text, &manifest_url)
+}
+
+/// Representation downloaded for a period of the manifest.
+#[derive(Debug, Clone)]
+struct DashTrack {
+  period: usize,
+  representation: DashRepresentation,
+}
+
+impl DashTrack {
+  fn name(&self) -> String {
+    let kind = match self.representation.kind {
+      DashContentKind::Video => "video",
+      DashContentKind::Audio => "audio",
+      DashContentKind::Text => "text",
+      DashContentKind::Other => "other",
+    };
+
+    format!("{}-{}", self.period, kind)
+  }
+
+  fn total_files(&self) -> usize {
+    self.representation.segments.len() + usize::from(self.representation.initialization.is_some())
+  }
+}
+
+// `audio_language` picks among audio representations of several languages, the variant policy
+// applies to those of the language
+fn select_tracks(
+  manifest: &DashManifest,
+  variant_policy: &VariantPolicy,
+  audio_language: Option<&str>,
+) -> anyhow::Result<Vec<DashTrack>> {
+  let mut tracks = vec![];
+
+  for (index, period) in manifest.periods.iter().enumerate() {
+    let of_kind = |kind: DashContentKind| {
+      period
+        .representations
+        .iter()
+        .filter(move |representation| representation.kind == kind)
+    };
+
+    let of_language = |representation: &&DashRepresentation| match (
+      audio_language,
+      representation.language.as_deref(),
+    ) {
+      (Some(wanted), Some(language)) => is_language(language, wanted),
+      _ => false,
+    };
+
+    let video = variant_policy.select(of_kind(DashContentKind::Video));
+    let audio = if of_kind(DashContentKind::Audio).any(|r| of_language(&r)) {
+      variant_policy.select(of_kind(DashContentKind::Audio).filter(of_language))
+    } else {
+      variant_policy.select(of_kind(DashContentKind::Audio))
+    };
+
+    if video.is_none() && audio.is_none() {
+      anyhow::bail!(
+        "No video or audio representation in period {} of the manifest",
+        index
+      );
+    }
+
+    for representation in video.into_iter().chain(audio) {
+      tracks.push(DashTrack {
+        period: index,
+        representation: representation.clone(),
+      });
+    }
+  }
+
+  if tracks.is_empty() {
+    anyhow::bail!("No period in the manifest");
+  }
+
+  Ok(tracks)
+}
+
+struct DashDownload {
+  http_client: HttpClient,
+  tracks: Vec<DashTrack>,
+  container: MediaContainer,
+}
+
+impl DashDownload {
+  async fn run(
+    &self,
+    tagging: Option<&MediaTagging>,
+    destination_path: &Path,
+    stream: &DownloadProgressStream,
+  ) -> anyhow::Result<()> {
+    fs::create_dir_all(destination_path.parent().unwrap()).await?;
+
+    // Segments are kept next to the destination until they are muxed, so that a failed download
+    // can be resumed
+    let segments_dir = destination_path.with_extension("dash");
+
+    let track_files = self.download_tracks(&segments_dir, stream).await?;
+
+    mux_tracks(
+      &track_files,
+      &segments_dir,
+      destination_path,
+      container_args(self.container),
+      tagging,
+    )
+    .await?;
+
+    fs::remove_dir_all(&segments_dir).await?;
+
+    Ok(())
+  }
+
+  /// Downloads the segments of every track into `segments_dir`, and joins the segments of each
+  /// of them into a single file. Returns the period and the file of each track.
+  async fn download_tracks(
+    &self,
+    segments_dir: &Path,
+    stream: &DownloadProgressStream,
+  ) -> anyhow::Result<Vec<(usize, PathBuf)>> {
+    fs::create_dir_all(segments_dir).await?;
+
+    let mut downloads = vec![];
+    let mut track_files = vec![];
+
+    for track in &self.tracks {
+      let name = track.name();
+      let mut parts = vec![];
+
+      if let Some(url) = &track.representation.initialization {
+        parts.push((url.clone(), segments_dir.join(format!("{}-init.mp4", name))));
+      }
+
+      for (index, url) in track.representation.segments.iter().enumerate() {
+        parts.push((
+          url.clone(),
+          segments_dir.join(format!("{}-{:05}.m4s", name, index)),
+        ));
+      }
+
+      downloads.extend(parts.iter().cloned());
+      track_files.push((
+        track.period,
+        segments_dir.join(format!("{}.mp4", name)),
+        parts,
+      ));
+    }
+
+    let total_segments = downloads.len();
+    let downloaded_segments = AtomicUsize::new(0);
+    let downloaded_segments = &downloaded_segments;
+    let http_client = &self.http_client;
+
+    futures::stream::iter(downloads)
+      .map(|(url, path)| async move {
+        download_file(http_client, &url, None, &path, stream).await?;
+
+        let downloaded = downloaded_segments.fetch_add(1, Ordering::SeqCst) + 1;
+
+        stream.segment_downloaded(&format!(
+          "Downloaded segment {}/{}",
+          downloaded, total_segments
+        ));
+
+        Ok::<_, anyhow::Error>(())
+      })
+      .buffer_unordered(SEGMENT_CONCURRENCY)
+      .try_collect::<Vec<_>>()
+      .await?;
+
+    let mut joined_files = vec![];
+
+    // Fragmented MP4 segments are playable once appended to their initialization segment
+    for (period, path, parts) in track_files {
+      let mut file = fs::File::create(&path).await?;
+
+      for (_, part_path) in &parts {
+        let mut part = fs::File::open(part_path).await?;
+        tokio::io::copy(&mut part, &mut file).await?;
+      }
+
+      file.flush().await?;
+      joined_files.push((period, path));
+    }
+
+    Ok(joined_files)
+  }
+}
+
+// `output_args` are the ffmpeg options of the container of the destination, which `tagging` is
+// written into as well
+async fn mux_tracks(
+  track_files: &[(usize, PathBuf)],
+  segments_dir: &Path,
+  destination_path: &Path,
+  output_args: &[&str],
+  tagging: Option<&MediaTagging>,
+) -> anyhow::Result<()> {
+  let total_periods = track_files
+    .iter()
+    .map(|(period, _)| period + 1)
+    .max()
+    .unwrap_or_default();
+
+  let files_of_period = |period: usize| {
+    track_files
+      .iter()
+      .filter(move |(track_period, _)| *track_period == period)
+      .map(|(_, path)| path.as_path())
+  };
+
+  if total_periods == 1 {
+    return mux_files(files_of_period(0), destination_path, output_args, tagging).await;
+  }
+
+  // Periods are muxed one by one and then concatenated
+  let extension = destination_path
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .unwrap_or("mp4");
+  let mut concat_list = String::new();
+
+  for period in 0..total_periods {
+    let period_path = segments_dir.join(format!("{}.{}", period, extension));
+
+    mux_files(files_of_period(period), &period_path, &[], None).await?;
+
+    concat_list.push_str(&format!(
+      "file '{}'\n",
+      period_path.to_string_lossy().replace('\'', "'\\''")
+    ));
+  }
+
+  let concat_list_path = segments_dir.join("concat.txt");
+
+  fs::write(&concat_list_path, concat_list).await?;
+
+  let mut args: Vec<OsString> = vec![
+    "-f".into(),
+    "concat".into(),
+    "-safe".into(),
+    "0".into(),
+    "-i".into(),
+    concat_list_path.into(),
+  ];
+
+  if let Some(tagging) = tagging {
+    if let Some(cover_path) = tagging.cover_path() {
+      args.extend(["-i".into(), cover_path.into()]);
+      args.extend(tagging.map_args(1));
+      args.extend(["-map", "0"].map(OsString::from));
+    }
+  }
+
+  args.extend(["-c", "copy"].map(OsString::from));
+
+  if let Some(tagging) = tagging {
+    args.extend(tagging.output_args());
+  }
+
+  args.extend(output_args.iter().map(OsString::from));
+  args.extend(["-y".into(), destination_path.into()]);
+
+  run_ffmpeg(args).await
+}
+
+async fn mux_files(
+  inputs: impl Iterator<Item = &Path>,
+  destination_path: &Path,
+  output_args: &[&str],
+  tagging: Option<&MediaTagging>,
+) -> anyhow::Result<()> {
+  let mut args: Vec<OsString> = vec![];
+  let mut maps: Vec<OsString> = vec![];
+
+  for (index, input) in inputs.enumerate() {
+    args.extend(["-i".into(), input.into()]);
+    maps.extend(["-map".into(), index.to_string().into()]);
+  }
+
+  // The cover is the input following the tracks, and the first stream of the output
+  if let Some(tagging) = tagging {
+    if let Some(cover_path) = tagging.cover_path() {
+      args.extend(["-i".into(), cover_path.into()]);
+    }
+
+    args.extend(tagging.map_args(maps.len() / 2));
+  }
+
+  args.extend(maps);
+  args.extend(["-c".into(), "copy".into()]);
+
+  if let Some(tagging) = tagging {
+    args.extend(tagging.output_args());
+  }
+
+  args.extend(output_args.iter().map(OsString::from));
+  args.extend(["-y".into(), destination_path.into()]);
+
+  run_ffmpeg(args).await
+}
+
+/// Parses a DASH manifest served from `manifest_url`. Segments may be addressed with a
+/// `SegmentTemplate` (with or without a `SegmentTimeline`), a `SegmentList`, or be the whole
+/// `BaseURL` of the representation.
+pub fn parse_dash_manifest(text: &str, manifest_url: &Url) -> anyhow::Result<DashManifest> {
+  let document =
+    Document::parse(text).map_err(|e| anyhow::anyhow!("Failed to parse DASH manifest: {}", e))?;
+
+  let mpd = document.root_element();
+
+  if !mpd.has_tag_name("MPD") {
+    anyhow::bail!("Not a DASH manifest: {}", manifest_url);
+  }
+
+  if mpd.attribute("type") == Some("dynamic") {
+    anyhow::bail!("Live DASH manifests are not supported: {}", manifest_url);
+  }
+
+  let base_url = resolve_base_url(mpd, manifest_url)?;
+  let total_duration = optional_duration(mpd, "mediaPresentationDuration")?;
+  let period_nodes: Vec<Node> = children(mpd, "Period").collect();
+
+  let mut periods = vec![];
+  let mut start = 0.0;
+  let mut known_durations = true;
+
+  for (index, period) in period_nodes.iter().enumerate() {
+    let period_start = optional_duration(*period, "start")?.unwrap_or(start);
+
+    let duration = match optional_duration(*period, "duration")? {
+      Some(duration) => Some(duration),
+      None => {
+        let end = match period_nodes.get(index + 1) {
+          Some(next) => optional_duration(*next, "start")?,
+          None => total_duration,
+        };
+
+        end.map(|end| end - period_start)
+      }
+    };
+
+    periods.push(parse_period(*period, &base_url, duration)?);
+    known_durations &= duration.is_some();
+    start = period_start + duration.unwrap_or_default();
+  }
+
+  Ok(DashManifest {
+    periods,
+    duration: total_duration.or(Some(start).filter(|_| known_durations)),
+  })
+}
+
+fn parse_period(period: Node, base_url: &Url, duration: Option<f64>) -> anyhow::Result<DashPeriod> {
+  let period_base_url = resolve_base_url(period, base_url)?;
+  let period_template = SegmentTemplate::default().inherit(period)?;
+
+  let mut representations = vec![];
+
+  for adaptation_set in children(period, "AdaptationSet") {
+    let set_base_url = resolve_base_url(adaptation_set, &period_base_url)?;
+    let set_template = period_template.clone().inherit(adaptation_set)?;
+
+    for representation in children(adaptation_set, "Representation") {
+      let base_url = resolve_base_url(representation, &set_base_url)?;
+      let template = set_template.clone().inherit(representation)?;
+
+      // Most attributes of representations may be given by their adaptation set instead
+      let attribute = |name: &str| {
+        representation
+          .attribute(name)
+          .or(adaptation_set.attribute(name))
+      };
+
+      let id = representation
+        .attribute("id")
+        .unwrap_or_default()
+        .to_string();
+      let bandwidth = attribute("bandwidth")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default();
+
+      let (initialization, segments) = if template.media.is_some() {
+        template.segments(&base_url, &id, bandwidth, duration)?
+      } else if let Some(list) =
+        child(representation, "SegmentList").or(child(adaptation_set, "SegmentList"))
+      {
+        list_segments(list, &base_url)?
+      } else {
+        let segment_base =
+          child(representation, "SegmentBase").or(child(adaptation_set, "SegmentBase"));
+
+        // The whole media is at the base URL
+        let initialization = segment_base
+          .and_then(|segment_base| child(segment_base, "Initialization"))
+          .and_then(|initialization| initialization.attribute("sourceURL"))
+          .map(|url| base_url.join(url).map(String::from))
+          .transpose()?;
+
+        (initialization, vec![base_url.to_string()])
+      };
+
+      representations.push(DashRepresentation {
+        id,
+        kind: content_kind(
+          adaptation_set.attribute("contentType"),
+          attribute("mimeType"),
+          attribute("codecs"),
+        ),
+        bandwidth,
+        width: attribute("width").and_then(|value| value.parse().ok()),
+        height: attribute("height").and_then(|value| value.parse().ok()),
+        codecs: attribute("codecs").map(String::from),
+        language: attribute("lang").map(String::from),
+        initialization,
+        segments,
+      });
+    }
+  }
+
+  Ok(DashPeriod { representations })
+}
+
+fn content_kind(
+  content_type: Option<&str>,
+  mime_type: Option<&str>,
+  codecs: Option<&str>,
+) -> DashContentKind {
+  let kind = content_type.or(mime_type.and_then(|mime_type| mime_type.split('/').next()));
+
+  match kind {
+    Some("video") => return DashContentKind::Video,
+    Some("audio") => return DashContentKind::Audio,
+    Some("text") => return DashContentKind::Text,
+    _ => {}
+  }
+
+  if mime_type.is_some_and(|mime_type| mime_type.contains("ttml") || mime_type.contains("vtt")) {
+    return DashContentKind::Text;
+  }
+
+  let codecs = codecs.unwrap_or_default();
+
+  if ["avc", "hvc", "hev", "vp0", "vp8", "vp9", "av01"]
+    .iter()
+    .any(|prefix| codecs.starts_with(prefix))
+  {
+    DashContentKind::Video
+  } else if ["mp4a", "opus", "ac-3", "ec-3", "flac"]
+    .iter()
+    .any(|prefix| codecs.starts_with(prefix))
+  {
+    DashContentKind::Audio
+  } else if ["stpp", "wvtt"]
+    .iter()
+    .any(|prefix| codecs.starts_with(prefix))
+  {
+    DashContentKind::Text
+  } else {
+    DashContentKind::Other
+  }
+}
+
+// `SegmentTemplate` of a representation, whose attributes may be given at the period,
+// adaptation set or representation level
+#[derive(Debug, Clone, Default)]
+struct SegmentTemplate {
+  media: Option<String>,
+  initialization: Option<String>,
+  start_number: Option<u64>,
+  timescale: Option<u64>,
+  duration: Option<u64>,
+  presentation_time_offset: Option<u64>,
+  timeline: Option<Vec<TimelineEntry>>,
+}
+
+// `<S t="0" d="4000" r="2"/>` of a `SegmentTimeline`
+#[derive(Debug, Clone, Copy)]
+struct TimelineEntry {
+  time: Option<u64>,
+  duration: u64,
+  repeat: i64,
+}
+
+impl SegmentTemplate {
+  fn inherit(self, node: Node) -> anyhow::Result<Self> {
+    let Some(template) = child(node, "SegmentTemplate") else {
+      return Ok(self);
+    };
+
+    let timeline = child(template, "SegmentTimeline")
+      .map(|timeline| {
+        children(timeline, "S")
+          .map(|entry| {
+            Ok(TimelineEntry {
+              time: optional_number(entry, "t")?,
+              duration: optional_number(entry, "d")?
+                .ok_or_else(|| anyhow::anyhow!("Segment timeline entry without duration"))?,
+              repeat: optional_number(entry, "r")?.unwrap_or_default(),
+            })
+          })
+          .collect::<anyhow::Result<Vec<_>>>()
+      })
+      .transpose()?;
+
+    Ok(Self {
+      media: template.attribute("media").map(String::from).or(self.media),
+      initialization: template
+        .attribute("initialization")
+        .map(String::from)
+        .or(self.initialization),
+      start_number: optional_number(template, "startNumber")?.or(self.start_number),
+      timescale: optional_number(template, "timescale")?.or(self.timescale),
+      duration: optional_number(template, "duration")?.or(self.duration),
+      presentation_time_offset: optional_number(template, "presentationTimeOffset")?
+        .or(self.presentation_time_offset),
+      timeline: timeline.or(self.timeline),
+    })
+  }
+
+  fn segments(
+    &self,
+    base_url: &Url,
+    representation_id: &str,
+    bandwidth: u64,
+    period_duration: Option<f64>,
+  ) -> anyhow::Result<(Option<String>, Vec<String>)> {
+    let media = self.media.as_deref().unwrap_or_default();
+    let start_number = self.start_number.unwrap_or(1);
+    let timescale = self.timescale.unwrap_or(1).max(1);
+    let offset = self.presentation_time_offset.unwrap_or_default();
+
+    let period_end =
+      period_duration.map(|duration| offset + (duration * timescale as f64).round() as u64);
+
+    // Start time of every segment, in the timescale
+    let times: Vec<u64> = match (&self.timeline, self.duration) {
+      (Some(timeline), _) => {
+        let mut times = vec![];
+        let mut time = offset;
+
+        for (index, entry) in timeline.iter().enumerate() {
+          time = entry.time.unwrap_or(time);
+
+          let repeat = if entry.repeat >= 0 {
+            entry.repeat as u64
+          } else {
+            // Repeated until the next entry or the end of the period
+            let end = timeline
+              .get(index + 1)
+              .and_then(|next| next.time)
+              .or(period_end)
+              .ok_or_else(|| anyhow::anyhow!("Unknown end of open-ended segment timeline"))?;
+
+            end
+              .saturating_sub(time)
+              .div_ceil(entry.duration.max(1))
+              .saturating_sub(1)
+          };
+
+          for _ in 0..=repeat {
+            times.push(time);
+            time += entry.duration;
+          }
+        }
+
+        times
+      }
+      (None, Some(duration)) => {
+        let period_duration = period_duration
+          .ok_or_else(|| anyhow::anyhow!("Unknown duration of period of {}", representation_id))?;
+
+        let total = (period_duration * timescale as f64 / duration.max(1) as f64 - 1e-9).ceil();
+
+        (0..total.max(0.0) as u64)
+          .map(|index| offset + index * duration)
+          .collect()
+      }
+      (None, None) => anyhow::bail!(
+        "Segment template of {} has neither a duration nor a timeline",
+        representation_id
+      ),
+    };
+
+    let initialization = self
+      .initialization
+      .as_deref()
+      .map(|template| {
+        let path = expand_template(template, representation_id, bandwidth, start_number, 0)?;
+
+        Ok::<_, anyhow::Error>(base_url.join(&path)?.to_string())
+      })
+      .transpose()?;
+
+    let segments = times
+      .into_iter()
+      .enumerate()
+      .map(|(index, time)| {
+        let number = start_number + index as u64;
+        let path = expand_template(media, representation_id, bandwidth, number, time)?;
+
+        Ok(base_url.join(&path)?.to_string())
+      })
+      .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok((initialization, segments))
+  }
+}
+
+fn list_segments(list: Node, base_url: &Url) -> anyhow::Result<(Option<String>, Vec<String>)> {
+  let initialization = child(list, "Initialization")
+    .and_then(|initialization| initialization.attribute("sourceURL"))
+    .map(|url| base_url.join(url).map(String::from))
+    .transpose()?;
+
+  let segments = children(list, "SegmentURL")
+    .map(|segment| {
+      let media = segment
+        .attribute("media")
+        .ok_or_else(|| anyhow::anyhow!("Byte range segments are not supported"))?;
+
+      Ok(base_url.join(media)?.to_string())
+    })
+    .collect::<anyhow::Result<Vec<_>>>()?;
+
+  Ok((initialization, segments))
+}
+
+// Replaces the `$RepresentationID$`, `$Number$`, `$Bandwidth$` and `$Time$` identifiers of a
+// template, which may have a width such as `$Number%05d$`. `$$` is an escaped `$`.
+fn expand_template(
+  template: &str,
+  representation_id: &str,
+  bandwidth: u64,
+  number: u64,
+  time: u64,
+) -> anyhow::Result<String> {
+  let mut parts = template.split('$');
+  let mut expanded = parts.next().unwrap_or_default().to_string();
+
+  while let Some(identifier) = parts.next() {
+    let Some(text) = parts.next() else {
+      anyhow::bail!("Unterminated identifier in segment template {}", template);
+    };
+
+    let (name, format) = match identifier.split_once('%') {
+      Some((name, format)) => (name, Some(format)),
+      None => (identifier, None),
+    };
+
+    let value = match name {
+      "" => "$".to_string(),
+      "RepresentationID" => representation_id.to_string(),
+      "Number" => number.to_string(),
+      "Bandwidth" => bandwidth.to_string(),
+      "Time" => time.to_string(),
+      _ => anyhow::bail!(
+        "Unknown identifier {} in segment template {}",
+        name,
+        template
+      ),
+    };
+
+    // Only widths of decimals (`%0<width>d`) are allowed
+    let width = format
+      .and_then(|format| format.strip_suffix('d'))
+      .and_then(|width| width.trim_start_matches('0').parse().ok())
+      .unwrap_or(0);
+
+    expanded.push_str(&format!("{:0>width$}", value, width = width));
+    expanded.push_str(text);
+  }
+
+  Ok(expanded)
+}
+
+fn resolve_base_url(node: Node, base_url: &Url) -> anyhow::Result<Url> {
+  match child(node, "BaseURL").and_then(|base| base.text()) {
+    Some(url) => Ok(base_url.join(url.trim())?),
+    None => Ok(base_url.clone()),
+  }
+}
+
+// Duration in the `xs:duration` format, e.g. `PT1H2M3.5S`, in seconds
+fn parse_duration(value: &str) -> anyhow::Result<f64> {
+  let invalid = || anyhow::anyhow!("Invalid duration {:?}", value);
+
+  let rest = value.trim().strip_prefix('P').ok_or_else(invalid)?;
+  let mut seconds = 0.0;
+  let mut in_time = false;
+  let mut number = String::new();
+
+  for c in rest.chars() {
+    match c {
+      'T' => in_time = true,
+      '0'..='9' | '.' => number.push(c),
+      _ => {
+        let value: f64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+
+        let unit = match (c, in_time) {
+          ('Y', false) => 365.0 * 86400.0,
+          ('M', false) => 30.0 * 86400.0,
+          ('W', false) => 7.0 * 86400.0,
+          ('D', false) => 86400.0,
+          ('H', true) => 3600.0,
+          ('M', true) => 60.0,
+          ('S', true) => 1.0,
+          _ => return Err(invalid()),
+        };
+
+        seconds += value * unit;
+      }
+    }
+  }
+
+  if !number.is_empty() {
+    return Err(invalid());
+  }
+
+  Ok(seconds)
+}
+
+fn optional_duration(node: Node, name: &str) -> anyhow::Result<Option<f64>> {
+  node.attribute(name).map(parse_duration).transpose()
+}
+
+fn optional_number<T: std::str::FromStr>(node: Node, name: &str) -> anyhow::Result<Option<T>> {
+  node
+    .attribute(name)
+    .map(|value| {
+      value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid {} {:?} of {}", name, value, node.tag_name().name()))
+    })
+    .transpose()
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+  node.children().find(|child| child.has_tag_name(name))
+}
+
+fn children<'a, 'input: 'a>(
+  node: Node<'a, 'input>,
+  name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+  node
+    .children()
+    .filter(move |child| child.has_tag_name(name))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{expand_template, parse_dash_manifest, parse_duration, select_tracks};
+  use super::{fetch_dash_manifest, DashContentKind, DashDownload};
+  use crate::common::test_server::{http_client, serve_dir};
+  use crate::common::VariantPolicy;
+  use configuration::VariantPreference;
+  use protocol::channel::MediaContainer;
+  use url::Url;
+
+  // On demand manifest with a numbered template for video and a timeline for audio
+  const TEMPLATE_MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT9.6S" minBufferTime="PT2S" profiles="urn:mpeg:dash:profile:isoff-live:2011">
+  <BaseURL>media/</BaseURL>
+  <Period id="0">
+    <AdaptationSet contentType="video" mimeType="video/mp4" segmentAlignment="true">
+      <SegmentTemplate timescale="1000" duration="4000" startNumber="1" initialization="$RepresentationID$/init.mp4" media="$RepresentationID$/seg-$Number%03d$.m4s"/>
+      <Representation id="v480" bandwidth="800000" width="854" height="480" codecs="avc1.64001e"/>
+      <Representation id="v720" bandwidth="2500000" width="1280" height="720" codecs="avc1.64001f"/>
+      <Representation id="v1080" bandwidth="5000000" width="1920" height="1080" codecs="avc1.640028"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4" lang="en">
+      <SegmentTemplate timescale="48000" initialization="audio/$Bandwidth$/init.mp4" media="audio/$Bandwidth$/$Time$.m4s">
+        <SegmentTimeline>
+          <S t="0" d="192000" r="1"/>
+          <S d="76800"/>
+        </SegmentTimeline>
+      </SegmentTemplate>
+      <Representation id="a64" bandwidth="64000" codecs="mp4a.40.2"/>
+      <Representation id="a128" bandwidth="128000" codecs="mp4a.40.2"/>
+    </AdaptationSet>
+    <AdaptationSet contentType="text" mimeType="application/ttml+xml" lang="en">
+      <Representation id="sub" bandwidth="1000">
+        <BaseURL>subtitles/en.ttml</BaseURL>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+  // Two periods, addressed with a segment list and a single file
+  const MULTI_PERIOD_MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT1M">
+  <BaseURL>https://cdn.example.com/vod/</BaseURL>
+  <Period id="intro" duration="PT20S">
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="1" bandwidth="1000000" height="720">
+        <SegmentList>
+          <Initialization sourceURL="intro/init.mp4"/>
+          <SegmentURL media="intro/1.m4s"/>
+          <SegmentURL media="intro/2.m4s"/>
+        </SegmentList>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+  <Period id="main">
+    <BaseURL>/main/</BaseURL>
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="1" bandwidth="1000000" height="720">
+        <BaseURL>video.mp4</BaseURL>
+        <SegmentBase indexRange="800-1200"/>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4">
+      <SegmentTemplate timescale="10" media="audio-$Number$.m4s" startNumber="0">
+        <SegmentTimeline>
+          <S t="0" d="100" r="-1"/>
+        </SegmentTimeline>
+      </SegmentTemplate>
+      <Representation id="2" bandwidth="96000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+  #[test]
+  fn test_parse_template_manifest() {
+    let manifest_url = Url::parse("https://cdn.example.com/vod/1/manifest.mpd").unwrap();
+    let manifest = parse_dash_manifest(TEMPLATE_MANIFEST, &manifest_url).unwrap();
+
+    assert_eq!(manifest.periods.len(), 1);
+
+    let representations = &manifest.periods[0].representations;
+
+    assert_eq!(representations.len(), 6);
+
+    let video = &representations[1];
+
+    assert_eq!(video.id, "v720");
+    assert_eq!(video.kind, DashContentKind::Video);
+    assert_eq!(video.bandwidth, 2_500_000);
+    assert_eq!(video.width, Some(1280));
+    assert_eq!(video.height, Some(720));
+    assert_eq!(video.codecs.as_deref(), Some("avc1.64001f"));
+    assert_eq!(
+      video.initialization.as_deref(),
+      Some("https://cdn.example.com/vod/1/media/v720/init.mp4")
+    );
+    assert_eq!(
+      video.segments,
+      vec![
+        "https://cdn.example.com/vod/1/media/v720/seg-001.m4s",
+        "https://cdn.example.com/vod/1/media/v720/seg-002.m4s",
+        "https://cdn.example.com/vod/1/media/v720/seg-003.m4s",
+      ]
+    );
+
+    let audio = &representations[4];
+
+    assert_eq!(audio.id, "a128");
+    assert_eq!(audio.kind, DashContentKind::Audio);
+    assert_eq!(audio.language.as_deref(), Some("en"));
+    assert_eq!(
+      audio.initialization.as_deref(),
+      Some("https://cdn.example.com/vod/1/media/audio/128000/init.mp4")
+    );
+    assert_eq!(
+      audio.segments,
+      vec![
+        "https://cdn.example.com/vod/1/media/audio/128000/0.m4s",
+        "https://cdn.example.com/vod/1/media/audio/128000/192000.m4s",
+        "https://cdn.example.com/vod/1/media/audio/128000/384000.m4s",
+      ]
+    );
+
+    let subtitles = &representations[5];
+
+    assert_eq!(subtitles.kind, DashContentKind::Text);
+    assert_eq!(
+      subtitles.segments,
+      vec!["https://cdn.example.com/vod/1/media/subtitles/en.ttml"]
+    );
+  }
+
+  #[test]
+  fn test_parse_multi_period_manifest() {
+    let manifest_url = Url::parse("https://origin.example.com/manifest.mpd").unwrap();
+    let manifest = parse_dash_manifest(MULTI_PERIOD_MANIFEST, &manifest_url).unwrap();
+
+    assert_eq!(manifest.periods.len(), 2);
+
+    let intro = &manifest.periods[0].representations[0];
+
+    assert_eq!(
+      intro.initialization.as_deref(),
+      Some("https://cdn.example.com/vod/intro/init.mp4")
+    );
+    assert_eq!(
+      intro.segments,
+      vec![
+        "https://cdn.example.com/vod/intro/1.m4s",
+        "https://cdn.example.com/vod/intro/2.m4s",
+      ]
+    );
+
+    let main = &manifest.periods[1].representations;
+
+    assert_eq!(main[0].initialization, None);
+    assert_eq!(
+      main[0].segments,
+      vec!["https://cdn.example.com/main/video.mp4"]
+    );
+
+    // The open-ended timeline lasts until the end of the period, 40 seconds
+    assert_eq!(main[1].kind, DashContentKind::Audio);
+    assert_eq!(main[1].segments.len(), 4);
+    assert_eq!(
+      main[1].segments[0],
+      "https://cdn.example.com/main/audio-0.m4s"
+    );
+    assert_eq!(
+      main[1].segments[3],
+      "https://cdn.example.com/main/audio-3.m4s"
+    );
+  }
+
+  #[test]
+  fn test_select_audio_language() {
+    let manifest_url = Url::parse("https://cdn.example.com/manifest.mpd").unwrap();
+    let manifest = parse_dash_manifest(
+      r#"<MPD type="static" mediaPresentationDuration="PT4S">
+  <Period>
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="v" bandwidth="1000000" height="720"><BaseURL>v.mp4</BaseURL></Representation>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4" lang="en">
+      <Representation id="en-hi" bandwidth="256000"><BaseURL>en-hi.mp4</BaseURL></Representation>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4" lang="ja-JP">
+      <Representation id="ja-lo" bandwidth="64000"><BaseURL>ja-lo.mp4</BaseURL></Representation>
+      <Representation id="ja-hi" bandwidth="128000"><BaseURL>ja-hi.mp4</BaseURL></Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+      &manifest_url,
+    )
+    .unwrap();
+
+    let audio_of = |language: Option<&str>| {
+      select_tracks(&manifest, &VariantPolicy::default(), language).unwrap()[1]
+        .representation
+        .id
+        .clone()
+    };
+
+    assert_eq!(audio_of(None), "en-hi");
+    assert_eq!(audio_of(Some("ja")), "ja-hi");
+    // Unknown languages fall back to the variant policy
+    assert_eq!(audio_of(Some("fr")), "en-hi");
+  }
+
+  #[test]
+  fn test_parse_invalid_manifest() {
+    let manifest_url = Url::parse("https://cdn.example.com/manifest.mpd").unwrap();
+
+    assert!(parse_dash_manifest("#EXTM3U", &manifest_url).is_err());
+    assert!(parse_dash_manifest("<html></html>", &manifest_url).is_err());
+    assert!(parse_dash_manifest(r#"<MPD type="dynamic"></MPD>"#, &manifest_url).is_err());
+  }
+
+  #[test]
+  fn test_expand_template() {
+    assert_eq!(
+      expand_template("$RepresentationID$/$Number%05d$.m4s", "v1", 0, 42, 0).unwrap(),
+      "v1/00042.m4s"
+    );
+    assert_eq!(
+      expand_template("$Bandwidth$-$Time$-$$.m4s", "v1", 128000, 1, 90000).unwrap(),
+      "128000-90000-$.m4s"
+    );
+    assert!(expand_template("$Number.m4s", "v1", 0, 1, 0).is_err());
+    assert!(expand_template("$SubNumber$.m4s", "v1", 0, 1, 0).is_err());
+  }
+
+  #[test]
+  fn test_parse_duration() {
+    assert_eq!(parse_duration("PT9.6S").unwrap(), 9.6);
+    assert_eq!(parse_duration("PT1H2M3S").unwrap(), 3723.0);
+    assert_eq!(parse_duration("P1DT1S").unwrap(), 86401.0);
+    assert!(parse_duration("1H").is_err());
+    assert!(parse_duration("PT5").is_err());
+  }
+
+  #[tokio::test]
+  async fn test_download_selected_tracks() {
+    let files: Vec<(String, Vec<u8>)> = vec![
+      ("manifest.mpd".into(), TEMPLATE_MANIFEST.as_bytes().to_vec()),
+      ("media/v720/init.mp4".into(), b"v-init;".to_vec()),
+      ("media/v720/seg-001.m4s".into(), b"v-1;".to_vec()),
+      ("media/v720/seg-002.m4s".into(), b"v-2;".to_vec()),
+      ("media/v720/seg-003.m4s".into(), b"v-3;".to_vec()),
+      ("media/audio/128000/init.mp4".into(), b"a-init;".to_vec()),
+      ("media/audio/128000/0.m4s".into(), b"a-1;".to_vec()),
+      ("media/audio/128000/192000.m4s".into(), b"a-2;".to_vec()),
+      ("media/audio/128000/384000.m4s".into(), b"a-3;".to_vec()),
+    ];
+    let files: Vec<(&str, &[u8])> = files
+      .iter()
+      .map(|(name, content)| (name.as_str(), content.as_slice()))
+      .collect();
+
+    let (base_url, dir) = serve_dir(&files).await;
+    let http_client = http_client(1);
+
+    let manifest = fetch_dash_manifest(&http_client, &format!("{}/manifest.mpd", base_url))
+      .await
+      .unwrap();
+
+    let policy = VariantPolicy {
+      preference: VariantPreference::Highest,
+      max_height: Some(720),
+    };
+    let tracks = select_tracks(&manifest, &policy, None).unwrap();
+
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0].representation.id, "v720");
+    assert_eq!(tracks[1].representation.id, "a128");
+
+    let download = DashDownload {
+      http_client,
+      tracks,
+      container: MediaContainer::Mp4,
+    };
+
+    let stream = stream::Stream::new(Ok);
+    let track_files = download
+      .download_tracks(&dir.join("out.dash"), &stream)
+      .await
+      .unwrap();
+
+    assert_eq!(track_files.len(), 2);
+    assert_eq!(track_files[0].0, 0);
+    assert_eq!(
+      std::fs::read(&track_files[0].1).unwrap(),
+      b"v-init;v-1;v-2;v-3;"
+    );
+    assert_eq!(
+      std::fs::read(&track_files[1].1).unwrap(),
+      b"a-init;a-1;a-2;a-3;"
+    );
+  }
+}
@@ -1,32 +1,224 @@
-use m3u8_rs::{parse_playlist_res, MasterPlaylist, MediaPlaylist, Playlist};
+use super::VariantPolicy;
+use super::{clip_encoding_args, clip_playlist, container_args, container_extension};
+use super::{download_dash_media, download_progressive_media, download_segments, run_ffmpeg};
+use super::{download_verified, probe_keyframes};
+use super::{is_growing_live, record_live, verify_media, LiveTrack, RecordingLimits};
+use super::{remove_time_ranges, save_subtitles, select_audio, select_subtitles};
+use super::{sniff_media_source, AdFilter, AdFilterReport, HttpClient, MediaSource, MediaTagging};
+use configuration::DownloadConfig;
+use m3u8_rs::{parse_playlist_res, AlternativeMedia, MasterPlaylist, MediaPlaylist, Playlist};
+use protocol::channel::RenditionSelection;
+use protocol::channel::{LiveRecording, MediaClip, MediaContainer, MediaTags, MediaTrim};
 use protocol::{DownloadProgressExt, DownloadProgressReceiver, DownloadProgressStream};
-use reqwest::Client;
-use std::path::Path;
-use std::process::Stdio;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use tokio::fs;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use url::{Origin, Url};
+use url::Url;
 
 pub struct DownloadMediaOptions<'a> {
   pub download_url: &'a str,
   pub destination_path: &'a Path,
+  pub http_client: &'a HttpClient,
+  pub download_config: &'a DownloadConfig,
+  pub renditions: &'a RenditionSelection,
+  pub container: Option<MediaContainer>,
+  pub clip: Option<MediaClip>,
+  pub audio_only: bool,
+  // Set for live streams, which are recorded until its limits
+  pub recording: Option<&'a LiveRecording>,
+  // Cut from the media, unless a clip of it is downloaded
+  pub trim: Option<MediaTrim>,
+  // Written into the media by the pass producing it
+  pub tags: Option<&'a MediaTags>,
 }
 
-pub async fn download_media_using_ffmpeg(
+impl DownloadMediaOptions<'_> {
+  /// Container of the output, the one requested or else the one of the channel.
+  pub fn container(&self) -> MediaContainer {
+    self.container.unwrap_or(self.download_config.container)
+  }
+}
+
+/// Downloads the media of `options.download_url`, which is either an HLS playlist, a DASH
+/// manifest or a single media file such as an MP4.
+pub async fn download_media(
   options: DownloadMediaOptions<'_>,
 ) -> anyhow::Result<DownloadProgressReceiver> {
-  let playlist = fetch_media_playlist(options.download_url).await?;
+  let source = sniff_media_source(options.http_client, options.download_url).await;
+
+  // Segments of other sources aren't listed with their durations
+  if options.clip.is_some() && source != MediaSource::Playlist {
+    anyhow::bail!("Clips can only be downloaded from HLS playlists");
+  }
+
+  if options.trim.is_some() && source != MediaSource::Playlist {
+    log::warn!(
+      "Intros and outros are only trimmed from HLS playlists, {} is downloaded in full",
+      options.download_url
+    );
+  }
+
+  match source {
+    MediaSource::Playlist => download_media_using_ffmpeg(options).await,
+    MediaSource::Dash => download_dash_media(options).await,
+    MediaSource::Progressive(media) => download_progressive_media(options, media).await,
+  }
+}
+
+/// Media playlists of the variant picked from an HLS master playlist, along with those of its
+/// selected alternate renditions (`#EXT-X-MEDIA`).
+struct HlsMedia {
+  video: MediaPlaylist,
+  audio: Option<MediaPlaylist>,
+  // Where the playlists were fetched from, to poll those of live streams
+  video_url: String,
+  audio_url: Option<String>,
+  subtitles: Vec<(AlternativeMedia, MediaPlaylist)>,
+}
+
+const KEYFRAME_SEEK_MARGIN: f64 = 0.01;
+
+// Options of the local playlists, whose segments are saved as `.ts` whatever their original
+// extension was
+const LOCAL_PLAYLIST_ARGS: [&str; 4] = [
+  "-allowed_extensions",
+  "ALL",
+  "-protocol_whitelist",
+  "file,crypto,data",
+];
+
+/// How the downloaded segments are remuxed into the output.
+#[derive(Debug, Clone, Copy)]
+struct RemuxOptions {
+  container: MediaContainer,
+  trim: Option<ClipTrim>,
+  // The video, if any, is left out
+  audio_only: bool,
+}
 
-  let total_segments = playlist.segments.len();
+/// What ffmpeg trims from the segments covering a clip, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ClipTrim {
+  // Where the clip starts in the media, which its subtitles are timed against
+  start: f64,
+  // Where the clip starts in the first segment of the video, and in that of the alternate audio
+  video_offset: f64,
+  audio_offset: f64,
+  duration: f64,
+  // Whether the clip is encoded to be cut exactly, instead of on the keyframes of the media
+  exact: bool,
+}
+
+pub async fn download_media_using_ffmpeg(
+  options: DownloadMediaOptions<'_>,
+) -> anyhow::Result<DownloadProgressReceiver> {
   let stream = stream::Stream::new(Ok);
+  let receiver = stream.recv();
+  let variant_policy = VariantPolicy::new(options.download_config);
+
+  let mut media = options
+    .http_client
+    .retry_policy()
+    .run_with_notify(
+      options.download_url,
+      || {
+        fetch_media_playlist(
+          options.http_client,
+          options.download_url,
+          &variant_policy,
+          options.renditions,
+        )
+      },
+      |message| stream.retrying(message),
+    )
+    .await?;
+
+  if options.audio_only {
+    // The alternate audio is all there is to download, without it the audio is muxed into the
+    // video and stripped from it by the remux
+    if let (Some(audio), Some(audio_url)) = (media.audio.take(), media.audio_url.take()) {
+      media.video = audio;
+      media.video_url = audio_url;
+    }
+
+    media.subtitles.clear();
+  }
+
+  // Live streams are recorded as their segments are appended, instead of downloaded at once
+  let is_live = match options.recording {
+    Some(_) => true,
+    None => is_growing_live(options.http_client, &media.video_url, &media.video).await,
+  };
+
+  if is_live {
+    if options.clip.is_some() {
+      anyhow::bail!("Clips can't be downloaded from live streams");
+    }
+
+    spawn_live_recording(&options, media, stream);
+
+    return Ok(receiver);
+  }
+
+  let ad_filter_report = if options.download_config.ad_filter.enabled {
+    let report = AdFilter::new(&options.download_config.ad_filter).filter(&mut media.video);
+
+    // Ads are spliced into the alternate audio as well, the same time ranges are removed from it
+    // so that it stays in sync with the video
+    if let Some(audio) = media.audio.as_mut() {
+      remove_time_ranges(audio, &report.removed_ranges);
+    }
+
+    report
+  } else {
+    AdFilterReport::default()
+  };
+
+  // Clips are cut from the media once its ads are removed
+  let trim = match (&options.clip, &options.trim) {
+    (Some(clip), _) => Some(clip_media(&mut media, clip)?),
+    (None, Some(trim)) => trim_media(&mut media, trim)?,
+    (None, None) => None,
+  };
+
+  let total_segments =
+    media.video.segments.len() + media.audio.as_ref().map_or(0, |audio| audio.segments.len());
 
   stream.start(total_segments);
-  let receiver = stream.recv();
+
+  if ad_filter_report.removed_segments > 0 {
+    log::info!(
+      "Removed {} ad segments ({:.1}s) of {}",
+      ad_filter_report.removed_segments,
+      ad_filter_report.removed_seconds,
+      options.download_url
+    );
+
+    stream.ads_removed(
+      ad_filter_report.removed_segments,
+      ad_filter_report.removed_seconds,
+    );
+  }
+
+  let remux = RemuxOptions {
+    container: options.container(),
+    trim,
+    audio_only: options.audio_only,
+  };
 
   tokio::spawn({
     let download_url = options.download_url.to_string();
-    let destination_path = options.destination_path.to_path_buf();
+    let destination_path = options
+      .destination_path
+      .with_extension(container_extension(remux.container));
+    let http_client = options.http_client.clone();
+    let subtitle_format = options.download_config.subtitle_format;
+    let verification = options.download_config.verification.clone();
+    let tags = options.tags.cloned();
+    let expected_duration = match trim {
+      Some(trim) => trim.duration,
+      None => total_duration(&media.video),
+    };
 
     async move {
       log::info!(
@@ -35,13 +227,56 @@ pub async fn download_media_using_ffmpeg(
         total_segments
       );
 
-      if let Err(err) =
-        download_with_ffmpeg_progress(&download_url, &destination_path, &stream, total_segments)
-          .await
-      {
-        log::error!("Failed to download with ffmpeg: {}", err);
-        stream.failed(&err.to_string());
-        return Err(err);
+      let tagging = match &tags {
+        Some(tags) => Some(MediaTagging::prepare(&http_client, tags, &destination_path).await),
+        None => None,
+      };
+
+      let download = || {
+        download_with_ffmpeg_progress(
+          &http_client,
+          &media,
+          remux,
+          tagging.as_ref(),
+          &destination_path,
+          &stream,
+        )
+      };
+
+      let downloaded = download_verified(
+        download,
+        &destination_path,
+        Some(expected_duration),
+        !remux.audio_only,
+        &verification,
+        total_segments,
+        &stream,
+      )
+      .await;
+
+      if let Some(tagging) = &tagging {
+        tagging.remove_cover().await;
+      }
+
+      // The trim actually applied, once moved to a keyframe
+      let trim = match downloaded {
+        Ok(trim) => trim,
+        Err(err) => {
+          log::error!("Failed to download with ffmpeg: {:#}", err);
+          stream.failed(&format!("{:#}", err));
+          return Err(err);
+        }
+      };
+
+      if !media.subtitles.is_empty() {
+        save_subtitles(
+          &http_client,
+          &media.subtitles,
+          &destination_path,
+          subtitle_format,
+          trim.map(|trim| trim.start..trim.start + trim.duration),
+        )
+        .await;
       }
 
       log::info!("Done. {:?}", destination_path);
@@ -54,201 +289,1097 @@ pub async fn download_media_using_ffmpeg(
   Ok(receiver)
 }
 
+// Records the live playlists of `media` into the destination in the background. Neither ads nor
+// subtitles of live streams are handled.
+fn spawn_live_recording(
+  options: &DownloadMediaOptions<'_>,
+  media: HlsMedia,
+  stream: DownloadProgressStream,
+) {
+  let limits = RecordingLimits::new(options.recording, &options.download_config.live);
+  let remux = RemuxOptions {
+    container: options.container(),
+    trim: None,
+    audio_only: options.audio_only,
+  };
+
+  let download_url = options.download_url.to_string();
+  let destination_path = options
+    .destination_path
+    .with_extension(container_extension(remux.container));
+  let http_client = options.http_client.clone();
+  let verification = options.download_config.verification.clone();
+  let tags = options.tags.cloned();
+
+  tokio::spawn(async move {
+    log::info!(
+      "Recording live media: {} (up to {:.0}s)",
+      download_url,
+      limits.max_seconds
+    );
+
+    stream.start(0);
+
+    let tagging = match &tags {
+      Some(tags) => Some(MediaTagging::prepare(&http_client, tags, &destination_path).await),
+      None => None,
+    };
+
+    let recorded = record_live_media(
+      &http_client,
+      media,
+      limits,
+      remux,
+      tagging.as_ref(),
+      &destination_path,
+      &stream,
+    )
+    .await;
+
+    if let Some(tagging) = &tagging {
+      tagging.remove_cover().await;
+    }
+
+    // Recordings can't be downloaded again, they are only verified
+    let verified = match recorded {
+      Ok(duration) if verification.enabled => {
+        verify_media(
+          &destination_path,
+          Some(duration),
+          !remux.audio_only,
+          &verification,
+        )
+        .await
+      }
+      Ok(_) => Ok(()),
+      Err(err) => Err(err),
+    };
+
+    if let Err(err) = verified {
+      log::error!("Failed to record live media: {:#}", err);
+      stream.failed(&format!("{:#}", err));
+      return Err(err);
+    }
+
+    log::info!("Done. {:?}", destination_path);
+    stream.done(&destination_path.to_string_lossy());
+
+    Ok(()) as anyhow::Result<()>
+  });
+}
+
+// Records the playlists of `media` and remuxes them, returning the recorded duration
+async fn record_live_media(
+  http_client: &HttpClient,
+  media: HlsMedia,
+  limits: RecordingLimits,
+  remux: RemuxOptions,
+  tagging: Option<&MediaTagging>,
+  destination_path: &Path,
+  stream: &DownloadProgressStream,
+) -> anyhow::Result<f64> {
+  fs::create_dir_all(destination_path.parent().unwrap()).await?;
+
+  let mut tracks = vec![LiveTrack::new(
+    media.video_url,
+    media.video,
+    destination_path.with_extension("segments"),
+  )];
+
+  if let (Some(audio), Some(audio_url)) = (media.audio, media.audio_url) {
+    tracks.push(LiveTrack::new(
+      audio_url,
+      audio,
+      destination_path.with_extension("audio"),
+    ));
+  }
+
+  record_live(http_client, &mut tracks, limits, stream).await?;
+
+  let mut playlist_paths = vec![];
+
+  for track in &tracks {
+    playlist_paths.push(track.write_playlist().await?);
+  }
+
+  remux_with_ffmpeg(
+    &playlist_paths[0],
+    playlist_paths.get(1).map(PathBuf::as_path),
+    remux,
+    tagging,
+    destination_path,
+  )
+  .await?;
+
+  for track in &tracks {
+    fs::remove_dir_all(track.dir()).await?;
+  }
+
+  Ok(tracks[0].recorded_seconds())
+}
+
+// Keeps the segments of `media` covering `clip`. Subtitles are kept whole, their cues are cut
+// once downloaded.
+fn clip_media(media: &mut HlsMedia, clip: &MediaClip) -> anyhow::Result<ClipTrim> {
+  let video_offset = clip_playlist(&mut media.video, clip)?;
+  let audio_offset = match media.audio.as_mut() {
+    Some(audio) => clip_playlist(audio, clip)?,
+    None => 0.0,
+  };
+
+  let covered = total_duration(&media.video) - video_offset;
+
+  Ok(ClipTrim {
+    start: clip.start,
+    video_offset,
+    audio_offset,
+    duration: clip
+      .duration()
+      .map_or(covered, |duration| duration.min(covered)),
+    exact: true,
+  })
+}
+
+// Cuts the intro and the outro of `trim` from `media`. Whole episodes take too long to encode,
+// their streams are copied and cut on the keyframes before the cuts.
+fn trim_media(media: &mut HlsMedia, trim: &MediaTrim) -> anyhow::Result<Option<ClipTrim>> {
+  let duration = total_duration(&media.video);
+
+  let Some(clip) = trim.clip_of(duration) else {
+    log::warn!(
+      "Trimming {:?} would leave nothing of {:.1}s, keeping the whole media",
+      trim,
+      duration
+    );
+    return Ok(None);
+  };
+
+  let clip_trim = clip_media(media, &clip)?;
+
+  Ok(Some(ClipTrim {
+    exact: false,
+    ..clip_trim
+  }))
+}
+
+fn total_duration(playlist: &MediaPlaylist) -> f64 {
+  playlist
+    .segments
+    .iter()
+    .map(|segment| segment.duration as f64)
+    .sum()
+}
+
+// Downloads and remuxes `media`, returning how it was trimmed
 async fn download_with_ffmpeg_progress(
-  download_url: &str,
+  http_client: &HttpClient,
+  media: &HlsMedia,
+  mut remux: RemuxOptions,
+  tagging: Option<&MediaTagging>,
   destination_path: &Path,
   stream: &DownloadProgressStream,
-  total_segments: usize,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<ClipTrim>> {
   fs::create_dir_all(destination_path.parent().unwrap()).await?;
 
-  let mut child = Command::new("ffmpeg")
-    .arg("-i")
-    .arg(download_url)
-    .arg("-c")
-    .arg("copy")
-    .arg("-y")
-    .arg(destination_path)
-    .stdout(Stdio::piped())
-    .stderr(Stdio::piped())
-    .spawn()?;
-
-  let stdout = child.stdout.take().unwrap();
-  let stderr = child.stderr.take().unwrap();
-
-  // Capture stdout (usually empty for this use case)
-  let stdout_handle = tokio::spawn(async move {
-    let reader = BufReader::new(stdout);
-    let mut lines = reader.lines();
-    while let Ok(Some(_line)) = lines.next_line().await {
-      // stdout is usually empty when not using -progress
-    }
-  });
+  // Segments are kept next to the destination until they are remuxed, so that a failed
+  // download can be resumed
+  let segments_dir = destination_path.with_extension("segments");
+  let playlist_path =
+    download_local_playlist(http_client, &media.video, &segments_dir, stream).await?;
 
-  let stream_clone = stream.clone();
-  let stderr_handle = tokio::spawn(async move {
-    let reader = BufReader::new(stderr);
-    let mut lines = reader.lines();
-    let mut last_reported_time = 0.0;
-    let mut segment_count = 0;
-
-    while let Ok(Some(line)) = lines.next_line().await {
-      // Count HLS segments being opened
-      // Example: "[hls @ 0xaaaabc34aab0] Opening 'crypto+https://...' for reading"
-      if line.contains("[hls @") && line.contains("Opening") {
-        segment_count += 1;
-      }
+  let audio_dir = destination_path.with_extension("audio");
+  let audio_playlist_path = match &media.audio {
+    Some(audio) => Some(download_local_playlist(http_client, audio, &audio_dir, stream).await?),
+    None => None,
+  };
 
-      // Parse progress from frame output
-      // Example: "frame=25200 fps= 56 q=-1.0 size=  262912kB time=00:16:48.04 bitrate=2136.6kbits/s speed=2.23x"
-      if line.contains("frame=") && line.contains("time=") {
-        if let Some(current_time) = parse_time_from_frame_line(&line) {
-          // Only report if time changed significantly (more than 3 seconds)
-          if current_time - last_reported_time >= 3.0 {
-            let progress_pct = if total_segments > 0 {
-              (segment_count as f64 / total_segments as f64 * 100.0).min(99.0) as usize
-            } else {
-              0
-            };
-            let msg = format!(
-              "Downloading: {:.0}m {:.0}s - {}/{} segments ({}%)",
-              current_time / 60.0,
-              current_time % 60.0,
-              segment_count,
-              total_segments,
-              progress_pct
-            );
-
-            stream_clone.segment_downloaded(&msg);
-            log::info!("{}", msg);
-            last_reported_time = current_time;
-          }
-        }
-      }
+  if let Some(trim) = remux.trim.filter(|trim| !trim.exact) {
+    remux.trim = Some(align_to_keyframe(&playlist_path, trim).await);
+  }
 
-      // Detect completion - look for final summary line
-      // Example: "frame=30423 fps= 56 q=-1.0 Lsize=  313424kB time=00:20:16.93 bitrate=2109.9kbits/s speed=2.25x"
-      if line.contains("Lsize=") && line.contains("time=") {
-        if let Some(final_time) = parse_time_from_frame_line(&line) {
-          stream_clone.segment_downloaded(&format!(
-            "Completed: {:.0}m {:.0}s - {}/{} segments",
-            final_time / 60.0,
-            final_time % 60.0,
-            segment_count,
-            total_segments
-          ));
-        }
-      }
+  remux_with_ffmpeg(
+    &playlist_path,
+    audio_playlist_path.as_deref(),
+    remux,
+    tagging,
+    destination_path,
+  )
+  .await?;
+
+  fs::remove_dir_all(&segments_dir).await?;
+
+  if audio_playlist_path.is_some() {
+    fs::remove_dir_all(&audio_dir).await?;
+  }
+
+  Ok(remux.trim)
+}
+
+// Copied streams are cut on the keyframe before the cut, which the cut is moved to so that the
+// subtitles are cut where the media actually starts
+async fn align_to_keyframe(playlist_path: &Path, trim: ClipTrim) -> ClipTrim {
+  // Keyframes are at most a segment apart, the cut is in the first one
+  match probe_keyframes(playlist_path, &LOCAL_PLAYLIST_ARGS, trim.video_offset + 0.5).await {
+    Ok(keyframes) => keyframe_trim(trim, &keyframes),
+    Err(err) => {
+      log::warn!(
+        "Failed to probe the keyframes of {:?}: {:#}",
+        playlist_path,
+        err
+      );
+      trim
     }
-  });
+  }
+}
 
-  let status = child.wait().await?;
+fn keyframe_trim(trim: ClipTrim, keyframes: &[f64]) -> ClipTrim {
+  let Some(keyframe) = keyframes
+    .iter()
+    .copied()
+    .filter(|keyframe| *keyframe <= trim.video_offset)
+    .reduce(f64::max)
+  else {
+    return trim;
+  };
 
-  stdout_handle.await?;
-  stderr_handle.await?;
+  let shift = trim.video_offset - keyframe;
 
-  if !status.success() {
-    anyhow::bail!("ffmpeg command failed with status: {:?}", status);
+  ClipTrim {
+    start: trim.start - shift,
+    // Seeking a bit after the keyframe lands on it whatever the rounding of its time
+    video_offset: (keyframe + KEYFRAME_SEEK_MARGIN).min(trim.video_offset),
+    audio_offset: (trim.audio_offset - shift).max(0.0),
+    duration: trim.duration + shift,
+    exact: false,
   }
+}
+
+// Downloads the segments of `playlist` into `segments_dir`, returning the path of the local
+// playlist referring to them
+async fn download_local_playlist(
+  http_client: &HttpClient,
+  playlist: &MediaPlaylist,
+  segments_dir: &Path,
+  stream: &DownloadProgressStream,
+) -> anyhow::Result<PathBuf> {
+  let local_playlist = download_segments(http_client, playlist, segments_dir, stream).await?;
 
-  Ok(())
+  let playlist_path = segments_dir.join("index.m3u8");
+  let mut playlist_bytes = Vec::new();
+
+  local_playlist.write_to(&mut playlist_bytes)?;
+  fs::write(&playlist_path, playlist_bytes).await?;
+
+  Ok(playlist_path)
 }
 
-fn parse_time_from_frame_line(line: &str) -> Option<f64> {
-  // Parse line like "frame=25200 fps= 56 q=-1.0 size=  262912kB time=00:16:48.04 bitrate=2136.6kbits/s speed=2.23x"
-  if let Some(time_part) = line.split("time=").nth(1) {
-    if let Some(time_str) = time_part.split_whitespace().next() {
-      return parse_time_string(time_str);
+async fn remux_with_ffmpeg(
+  playlist_path: &Path,
+  audio_playlist_path: Option<&Path>,
+  remux: RemuxOptions,
+  tagging: Option<&MediaTagging>,
+  destination_path: &Path,
+) -> anyhow::Result<()> {
+  let mut args: Vec<OsString> = vec![];
+  let trim = remux.trim;
+
+  let inputs = [
+    (Some(playlist_path), trim.map(|trim| trim.video_offset)),
+    (audio_playlist_path, trim.map(|trim| trim.audio_offset)),
+  ];
+
+  for (input, offset) in inputs {
+    let Some(input) = input else {
+      continue;
+    };
+
+    if let Some(offset) = offset {
+      args.extend(["-ss".into(), format!("{:.3}", offset).into()]);
     }
+
+    args.extend(LOCAL_PLAYLIST_ARGS.map(OsString::from));
+    args.extend(["-i".into(), input.into()]);
   }
-  None
-}
 
-fn parse_time_string(time_str: &str) -> Option<f64> {
-  // Parse time string like "00:16:48.04"
-  let parts: Vec<&str> = time_str.split(':').collect();
-  if parts.len() == 3 {
-    if let (Ok(hours), Ok(minutes), Ok(seconds)) = (
-      parts[0].parse::<f64>(),
-      parts[1].parse::<f64>(),
-      parts[2].parse::<f64>(),
-    ) {
-      return Some(hours * 3600.0 + minutes * 60.0 + seconds);
+  let cover_path = tagging.and_then(MediaTagging::cover_path);
+
+  if let Some(cover_path) = cover_path {
+    args.extend(["-i".into(), cover_path.into()]);
+  }
+
+  if audio_playlist_path.is_some() || cover_path.is_some() {
+    let media_inputs = if audio_playlist_path.is_some() { 2 } else { 1 };
+
+    // The cover comes first, then the alternate audio replaces whatever audio is muxed into
+    // the video
+    if let Some(tagging) = tagging {
+      args.extend(tagging.map_args(media_inputs));
+    }
+
+    if !remux.audio_only {
+      args.extend(["-map", "0:v?"].map(OsString::from));
+    }
+
+    args.extend(["-map".into(), format!("{}:a?", media_inputs - 1).into()]);
+  } else if remux.audio_only {
+    args.push("-vn".into());
+  }
+
+  match trim {
+    // Cuts of copied videos can only fall on keyframes, clips are short enough to encode
+    Some(trim) if trim.exact => {
+      let video_codec = match ffmpeg::probe_codec(playlist_path, &LOCAL_PLAYLIST_ARGS, "v:0").await
+      {
+        Ok(video_codec) => video_codec,
+        Err(err) => {
+          log::warn!("Failed to probe the video codec of the clip: {:#}", err);
+          None
+        }
+      };
+
+      args.extend(
+        clip_encoding_args(remux.container, video_codec.as_deref())
+          .into_iter()
+          .map(OsString::from),
+      );
     }
+    _ => args.extend(["-c", "copy"].map(OsString::from)),
   }
-  None
+
+  if let Some(trim) = trim {
+    args.extend(["-t".into(), format!("{:.3}", trim.duration).into()]);
+  }
+
+  if let Some(tagging) = tagging {
+    args.extend(tagging.output_args());
+  }
+
+  args.extend(container_args(remux.container).iter().map(OsString::from));
+  args.extend(["-y".into(), destination_path.into()]);
+
+  run_ffmpeg(args).await
 }
 
-#[async_recursion::async_recursion]
-async fn fetch_media_playlist(download_url: &str) -> anyhow::Result<MediaPlaylist> {
-  log::info!("Starting fetch media playlist: {}", download_url);
-  let client = Client::new();
+/// Fetches the HLS playlist at `url`, URIs of which are resolved against the URL it was served
+/// from.
+pub(super) async fn fetch_playlist(
+  http_client: &HttpClient,
+  url: &str,
+) -> anyhow::Result<Playlist> {
+  log::info!("Starting fetch media playlist: {}", url);
 
-  let res = client.get(download_url).send().await?;
+  let request = http_client.media_client().get(url);
+  let res = http_client.send(request).await?;
 
-  let status = res.status();
-  if !status.is_success() {
+  if !res.status().is_success() {
     log::info!("{:#?}", res.headers());
-    anyhow::bail!("Request failed with code {}", status);
   }
 
+  let res = res.error_for_status()?;
+
+  // URIs of the playlist are relative to where it was actually served from
+  let base_url = res.url().clone();
   let bytes = res.bytes().await?.to_vec();
-  let parsed = parse_playlist_res(&bytes);
-  let origin = extract_origin_from_url(download_url)?;
 
-  let playlist = match parsed {
+  match parse_playlist_res(&bytes) {
     Ok(Playlist::MediaPlaylist(mut playlist)) => {
-      normalize_media_playlist(&mut playlist, &origin);
+      normalize_media_playlist(&mut playlist, &base_url);
 
-      playlist
+      Ok(Playlist::MediaPlaylist(playlist))
     }
     Ok(Playlist::MasterPlaylist(mut master_playlist)) => {
-      log::info!("Got master playlist: {:#?}", master_playlist);
-      normalize_master_playlist(&mut master_playlist, &origin);
+      normalize_master_playlist(&mut master_playlist, &base_url);
 
-      parse_master_playlist(&mut master_playlist).await?
+      Ok(Playlist::MasterPlaylist(master_playlist))
     }
     Err(err) => anyhow::bail!("Fetch media playlist error: {}", err),
-  };
+  }
+}
 
-  Ok(playlist)
+#[async_recursion::async_recursion]
+async fn fetch_media_playlist(
+  http_client: &HttpClient,
+  download_url: &str,
+  variant_policy: &VariantPolicy,
+  renditions: &RenditionSelection,
+) -> anyhow::Result<HlsMedia> {
+  match fetch_playlist(http_client, download_url).await? {
+    Playlist::MediaPlaylist(playlist) => Ok(HlsMedia {
+      video: playlist,
+      audio: None,
+      video_url: download_url.to_string(),
+      audio_url: None,
+      subtitles: vec![],
+    }),
+    Playlist::MasterPlaylist(master_playlist) => {
+      log::info!("Got master playlist: {:#?}", master_playlist);
+
+      parse_master_playlist(http_client, &master_playlist, variant_policy, renditions).await
+    }
+  }
 }
 
 async fn parse_master_playlist(
-  master_playlist: &mut MasterPlaylist,
-) -> anyhow::Result<MediaPlaylist> {
-  if let Some(new_variant_stream) = master_playlist.get_newest_variant() {
-    fetch_media_playlist(&new_variant_stream.uri).await
-  } else {
+  http_client: &HttpClient,
+  master_playlist: &MasterPlaylist,
+  variant_policy: &VariantPolicy,
+  renditions: &RenditionSelection,
+) -> anyhow::Result<HlsMedia> {
+  let variants = master_playlist
+    .variants
+    .iter()
+    .filter(|variant| !variant.is_i_frame);
+
+  let Some(variant_stream) = variant_policy.select(variants) else {
     anyhow::bail!("Unsupported format")
+  };
+
+  let mut media =
+    fetch_media_playlist(http_client, &variant_stream.uri, variant_policy, renditions).await?;
+
+  if let Some(alternative) = select_audio(master_playlist, variant_stream, renditions) {
+    log::info!("Downloading audio rendition {}", alternative.name);
+
+    media.audio = Some(fetch_rendition_playlist(http_client, alternative).await?);
+    media.audio_url = alternative.uri.clone();
+  }
+
+  for alternative in select_subtitles(master_playlist, variant_stream, renditions) {
+    match fetch_rendition_playlist(http_client, alternative).await {
+      Ok(playlist) => media.subtitles.push((alternative.clone(), playlist)),
+      Err(err) => log::warn!("Skipping subtitles {}: {:#}", alternative.name, err),
+    }
   }
+
+  Ok(media)
 }
 
-fn normalize_media_playlist(media_playlist: &mut MediaPlaylist, origin: &str) {
+async fn fetch_rendition_playlist(
+  http_client: &HttpClient,
+  alternative: &AlternativeMedia,
+) -> anyhow::Result<MediaPlaylist> {
+  let uri = alternative.uri.as_deref().unwrap_or_default();
+
+  match fetch_playlist(http_client, uri).await? {
+    Playlist::MediaPlaylist(playlist) => Ok(playlist),
+    Playlist::MasterPlaylist(_) => {
+      anyhow::bail!("Rendition {} is not a media playlist", alternative.name)
+    }
+  }
+}
+
+fn normalize_media_playlist(media_playlist: &mut MediaPlaylist, base_url: &Url) {
   for segment in &mut media_playlist.segments {
-    segment.uri = normalize_url(&segment.uri, origin);
+    segment.uri = normalize_url(&segment.uri, base_url);
+
+    if let Some(uri) = segment.key.as_mut().and_then(|key| key.uri.as_mut()) {
+      *uri = normalize_url(uri, base_url);
+    }
+
+    if let Some(map) = segment.map.as_mut() {
+      map.uri = normalize_url(&map.uri, base_url);
+    }
   }
 }
 
-fn normalize_master_playlist(master_playlist: &mut MasterPlaylist, origin: &str) {
+fn normalize_master_playlist(master_playlist: &mut MasterPlaylist, base_url: &Url) {
   for variant_stream in &mut master_playlist.variants {
-    variant_stream.uri = normalize_url(&variant_stream.uri, origin);
+    variant_stream.uri = normalize_url(&variant_stream.uri, base_url);
+  }
+
+  for alternative in &mut master_playlist.alternatives {
+    if let Some(uri) = alternative.uri.as_mut() {
+      *uri = normalize_url(uri, base_url);
+    }
+  }
+
+  for session_key in &mut master_playlist.session_key {
+    if let Some(uri) = session_key.0.uri.as_mut() {
+      *uri = normalize_url(uri, base_url);
+    }
   }
 }
 
-fn normalize_url(path_or_url: &str, origin: &str) -> String {
-  if path_or_url.starts_with("http") {
-    path_or_url.to_string()
-  } else {
-    format!("{}{}", origin, path_or_url)
+/// Resolves a URI of a playlist against the URL of the playlist (RFC 3986). Absolute URIs,
+/// including those of other schemes such as `data:` or `skd:`, are kept as they are.
+fn normalize_url(path_or_url: &str, base_url: &Url) -> String {
+  match base_url.join(path_or_url.trim()) {
+    Ok(url) => url.to_string(),
+    Err(err) => {
+      log::warn!(
+        "Failed to resolve {} against {}: {}",
+        path_or_url,
+        base_url,
+        err
+      );
+      path_or_url.to_string()
+    }
   }
 }
 
-fn extract_origin_from_url(url: &str) -> anyhow::Result<String> {
-  let parsed_url = Url::parse(url)?;
-  let Origin::Tuple(protocol, host, _port) = parsed_url.origin() else {
-    anyhow::bail!("Unsupported origin");
-  };
+#[cfg(test)]
+mod tests {
+  use super::HlsMedia;
+  use super::{download_local_playlist, fetch_media_playlist, normalize_master_playlist};
+  use super::{keyframe_trim, normalize_media_playlist, normalize_url, trim_media, ClipTrim};
+  use crate::common::test_server::{http_client, serve_dir, serve_dir_with_routes};
+  use crate::common::{is_growing_live, VariantPolicy};
+  use axum::response::Redirect;
+  use m3u8_rs::{parse_master_playlist_res, parse_media_playlist_res};
+  use protocol::channel::{MediaTrim, RenditionSelection};
+  use std::sync::atomic::{AtomicU64, Ordering};
+  use std::sync::Arc;
+  use url::Url;
+
+  #[test]
+  fn test_normalize_url() {
+    // (URL of the playlist, URI in the playlist, resolved URL)
+    let corpus = [
+      // Siblings and sub directories of the playlist
+      (
+        "https://cdn.example.com/vod/movie/index.m3u8",
+        "seg-001.ts",
+        "https://cdn.example.com/vod/movie/seg-001.ts",
+      ),
+      (
+        "https://cdn.example.com/vod/movie/index.m3u8",
+        "seg/001.ts",
+        "https://cdn.example.com/vod/movie/seg/001.ts",
+      ),
+      (
+        "https://cdn.example.com/vod/movie/index.m3u8",
+        "./1080p/index.m3u8",
+        "https://cdn.example.com/vod/movie/1080p/index.m3u8",
+      ),
+      // Parent directories, e.g. `index.m3u8` -> `../hls/index.m3u8` of MacCMS sources
+      (
+        "https://cdn.example.com/20230714/abc/index.m3u8",
+        "../hls/index.m3u8",
+        "https://cdn.example.com/20230714/hls/index.m3u8",
+      ),
+      (
+        "https://cdn.example.com/a/b/c/index.m3u8",
+        "../../x/1.ts",
+        "https://cdn.example.com/a/x/1.ts",
+      ),
+      (
+        "https://cdn.example.com/a/index.m3u8",
+        "../../../1.ts",
+        "https://cdn.example.com/1.ts",
+      ),
+      // Relative to the root of the host
+      (
+        "https://cdn.example.com/20230714/abc/index.m3u8",
+        "/20230714/abc/1000kb/hls/index.m3u8",
+        "https://cdn.example.com/20230714/abc/1000kb/hls/index.m3u8",
+      ),
+      // Relative to the scheme, ports are kept for relative URIs only
+      (
+        "https://cdn.example.com:8443/vod/index.m3u8",
+        "//media.example.net/vod/1.ts",
+        "https://media.example.net/vod/1.ts",
+      ),
+      (
+        "http://cdn.example.com:8080/vod/index.m3u8",
+        "1.ts",
+        "http://cdn.example.com:8080/vod/1.ts",
+      ),
+      // Absolute URLs
+      (
+        "https://cdn.example.com/vod/index.m3u8",
+        "https://media.example.net/vod/1.ts?sign=abc",
+        "https://media.example.net/vod/1.ts?sign=abc",
+      ),
+      (
+        "https://cdn.example.com/vod/index.m3u8",
+        "http://media.example.net/1.ts",
+        "http://media.example.net/1.ts",
+      ),
+      // Queries of the playlist don't apply to relative URIs, which may have their own
+      (
+        "https://cdn.example.com/vod/index.m3u8?token=abc&expires=1700000000",
+        "1.ts",
+        "https://cdn.example.com/vod/1.ts",
+      ),
+      (
+        "https://cdn.example.com/vod/index.m3u8?token=abc",
+        "1.ts?token=def",
+        "https://cdn.example.com/vod/1.ts?token=def",
+      ),
+      (
+        "https://cdn.example.com/vod/index.m3u8?token=abc",
+        "?part=2",
+        "https://cdn.example.com/vod/index.m3u8?part=2",
+      ),
+      // Playlists served from directories or without extensions
+      (
+        "https://cdn.example.com/play/abc/",
+        "1.ts",
+        "https://cdn.example.com/play/abc/1.ts",
+      ),
+      (
+        "https://cdn.example.com/play?id=abc",
+        "segments/1.ts",
+        "https://cdn.example.com/segments/1.ts",
+      ),
+      // Characters that must be escaped in URLs
+      (
+        "https://cdn.example.com/vod/index.m3u8",
+        "第01集/1 .ts",
+        "https://cdn.example.com/vod/%E7%AC%AC01%E9%9B%86/1%20.ts",
+      ),
+      (
+        "https://cdn.example.com/vod/index.m3u8",
+        " 1.ts\r",
+        "https://cdn.example.com/vod/1.ts",
+      ),
+      // Keys of other schemes
+      (
+        "https://cdn.example.com/vod/index.m3u8",
+        "skd://key-id",
+        "skd://key-id",
+      ),
+      (
+        "https://cdn.example.com/vod/index.m3u8",
+        "data:text/plain;base64,AAECAwQFBgcICQoLDA0ODw==",
+        "data:text/plain;base64,AAECAwQFBgcICQoLDA0ODw==",
+      ),
+    ];
+
+    for (base_url, uri, expected) in corpus {
+      let base_url = Url::parse(base_url).unwrap();
+
+      assert_eq!(
+        normalize_url(uri, &base_url),
+        expected,
+        "{} against {}",
+        uri,
+        base_url
+      );
+    }
+  }
+
+  #[test]
+  fn test_normalize_media_playlist() {
+    let mut playlist = parse_media_playlist_res(
+      br#"#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:6
+#EXT-X-KEY:METHOD=AES-128,URI="../keys/key.bin",IV=0x00000000000000000000000000000001
+#EXT-X-MAP:URI="init.mp4"
+#EXTINF:6.0,
+seg/0.m4s
+#EXTINF:6.0,
+/vod/other/seg/1.m4s
+#EXT-X-ENDLIST
+"#,
+    )
+    .unwrap();
+
+    let base_url = Url::parse("https://cdn.example.com/vod/movie/720p/index.m3u8").unwrap();
+
+    normalize_media_playlist(&mut playlist, &base_url);
+
+    let segment = &playlist.segments[0];
+
+    assert_eq!(
+      segment.uri,
+      "https://cdn.example.com/vod/movie/720p/seg/0.m4s"
+    );
+    assert_eq!(
+      segment.key.as_ref().unwrap().uri.as_deref(),
+      Some("https://cdn.example.com/vod/movie/keys/key.bin")
+    );
+    assert_eq!(
+      segment.map.as_ref().unwrap().uri,
+      "https://cdn.example.com/vod/movie/720p/init.mp4"
+    );
+    assert_eq!(
+      playlist.segments[1].uri,
+      "https://cdn.example.com/vod/other/seg/1.m4s"
+    );
+  }
+
+  #[test]
+  fn test_normalize_master_playlist() {
+    let mut playlist = parse_master_playlist_res(
+      r#"#EXTM3U
+#EXT-X-SESSION-KEY:METHOD=AES-128,URI="keys/session.bin"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac",NAME="English",LANGUAGE="en",DEFAULT=YES,URI="audio/en/index.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="中文",LANGUAGE="zh",URI="../subs/zh.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,RESOLUTION=1280x720,AUDIO="aac",SUBTITLES="subs"
+720p/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080,AUDIO="aac",SUBTITLES="subs"
+https://backup.example.com/1080p/index.m3u8
+"#
+      .as_bytes(),
+    )
+    .unwrap();
+
+    let base_url = Url::parse("https://cdn.example.com/vod/movie/master.m3u8?token=abc").unwrap();
+
+    normalize_master_playlist(&mut playlist, &base_url);
+
+    assert_eq!(
+      playlist.variants[0].uri,
+      "https://cdn.example.com/vod/movie/720p/index.m3u8"
+    );
+    assert_eq!(
+      playlist.variants[1].uri,
+      "https://backup.example.com/1080p/index.m3u8"
+    );
+    assert_eq!(
+      playlist.alternatives[0].uri.as_deref(),
+      Some("https://cdn.example.com/vod/movie/audio/en/index.m3u8")
+    );
+    assert_eq!(
+      playlist.alternatives[1].uri.as_deref(),
+      Some("https://cdn.example.com/vod/subs/zh.m3u8")
+    );
+    assert_eq!(
+      playlist.session_key[0].0.uri.as_deref(),
+      Some("https://cdn.example.com/vod/movie/keys/session.bin")
+    );
+  }
+
+  #[tokio::test]
+  async fn test_resolve_against_redirected_url() {
+    let routes = axum::Router::new().route(
+      "/share/abc",
+      axum::routing::get(|| async { Redirect::temporary("/real/movie/master.m3u8") }),
+    );
+
+    let master: &[u8] = b"#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=854x480
+480p/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,RESOLUTION=1280x720
+720p/index.m3u8
+";
+    let media: &[u8] = b"#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-KEY:METHOD=AES-128,URI=\"../key.bin\"
+#EXTINF:6.0,
+seg-0.ts
+#EXT-X-ENDLIST
+";
+
+    let (base_url, _dir) = serve_dir_with_routes(
+      &[
+        ("real/movie/master.m3u8", master),
+        ("real/movie/720p/index.m3u8", media),
+      ],
+      routes,
+    )
+    .await;
+
+    let media = fetch_media_playlist(
+      &http_client(1),
+      &format!("{}/share/abc", base_url),
+      &VariantPolicy::default(),
+      &RenditionSelection::default(),
+    )
+    .await
+    .unwrap();
+
+    let segment = &media.video.segments[0];
+
+    assert_eq!(
+      segment.uri,
+      format!("{}/real/movie/720p/seg-0.ts", base_url)
+    );
+    assert_eq!(
+      segment.key.as_ref().unwrap().uri,
+      Some(format!("{}/real/movie/key.bin", base_url))
+    );
+  }
+
+  #[tokio::test]
+  async fn test_fetch_alternate_renditions() {
+    let master: &[u8] = br#"#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac",NAME="English",LANGUAGE="en",DEFAULT=YES,URI="audio/en.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac",NAME="Japanese",LANGUAGE="ja",URI="audio/ja.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="English",LANGUAGE="en",URI="subs/en.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="Chinese",LANGUAGE="zh",URI="subs/missing.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,RESOLUTION=1280x720,AUDIO="aac",SUBTITLES="subs"
+video/720p.m3u8
+"#;
+    let media_of = |segment: &str| {
+      format!(
+        "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\n{}\n#EXT-X-ENDLIST\n",
+        segment
+      )
+    };
+    let (video, audio, subtitles) = (media_of("0.ts"), media_of("0.aac"), media_of("0.vtt"));
+
+    let (base_url, _dir) = serve_dir_with_routes(
+      &[
+        ("renditions/master.m3u8", master),
+        ("renditions/video/720p.m3u8", video.as_bytes()),
+        ("renditions/audio/ja.m3u8", audio.as_bytes()),
+        ("renditions/subs/en.m3u8", subtitles.as_bytes()),
+      ],
+      axum::Router::new(),
+    )
+    .await;
+
+    let selection = RenditionSelection {
+      audio: Some("ja".to_string()),
+      subtitles: vec!["en".to_string(), "zh".to_string()],
+    };
+
+    let media = fetch_media_playlist(
+      &http_client(1),
+      &format!("{}/renditions/master.m3u8", base_url),
+      &VariantPolicy::default(),
+      &selection,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+      media.video.segments[0].uri,
+      format!("{}/renditions/video/0.ts", base_url)
+    );
+    assert_eq!(
+      media.audio.unwrap().segments[0].uri,
+      format!("{}/renditions/audio/0.aac", base_url)
+    );
+    // Subtitles which fail to be fetched are skipped
+    assert_eq!(media.subtitles.len(), 1);
+    assert_eq!(media.subtitles[0].0.name, "English");
+    assert_eq!(
+      media.subtitles[0].1.segments[0].uri,
+      format!("{}/renditions/subs/0.vtt", base_url)
+    );
+  }
+
+  #[tokio::test]
+  async fn test_download_vod_without_endlist() {
+    // Served without `#EXT-X-ENDLIST` by some VOD servers
+    let vod: &[u8] = b"#EXTM3U
+#EXT-X-TARGETDURATION:1
+#EXTINF:1.0,
+0.ts
+#EXTINF:1.0,
+1.ts
+#EXTINF:1.0,
+2.ts
+";
+    let event: &[u8] = b"#EXTM3U
+#EXT-X-PLAYLIST-TYPE:EVENT
+#EXT-X-TARGETDURATION:1
+#EXTINF:1.0,
+0.ts
+";
+    let segment = vec![0x47; 188];
+
+    // Each fetch of a live playlist slides it by a segment
+    let fetches = Arc::new(AtomicU64::new(0));
+    let routes = axum::Router::new().route(
+      "/endless/live.m3u8",
+      axum::routing::get(move || async move {
+        let sequence = fetches.fetch_add(1, Ordering::SeqCst);
 
-  Ok(format!("{}://{}", protocol, host))
+        format!(
+          "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:{}\n#EXTINF:1.0,\n{}.ts\n",
+          sequence, sequence
+        )
+      }),
+    );
+
+    let (base_url, dir) = serve_dir_with_routes(
+      &[
+        ("endless/vod.m3u8", vod),
+        ("endless/event.m3u8", event),
+        ("endless/0.ts", &segment),
+        ("endless/1.ts", &segment),
+        ("endless/2.ts", &segment),
+      ],
+      routes,
+    )
+    .await;
+
+    let fetch = |name: &str| {
+      let url = format!("{}/endless/{}", base_url, name);
+
+      async move {
+        let media = fetch_media_playlist(
+          &http_client(1),
+          &url,
+          &VariantPolicy::default(),
+          &RenditionSelection::default(),
+        )
+        .await
+        .unwrap();
+
+        let is_live = is_growing_live(&http_client(1), &url, &media.video).await;
+
+        (media, is_live)
+      }
+    };
+
+    let (media, is_live) = fetch("vod.m3u8").await;
+    assert!(!is_live);
+
+    let segments_dir = dir.join("vod.segments");
+    let stream = stream::Stream::new(Ok);
+    download_local_playlist(&http_client(1), &media.video, &segments_dir, &stream)
+      .await
+      .unwrap();
+
+    for index in 0..3 {
+      assert!(segments_dir.join(format!("{:05}.ts", index)).exists());
+    }
+
+    assert!(fetch("event.m3u8").await.1);
+    assert!(fetch("live.m3u8").await.1);
+  }
+
+  #[test]
+  fn test_trim_media() {
+    let playlist = parse_media_playlist_res(
+      b"#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXTINF:10.0,
+0.ts
+#EXTINF:10.0,
+1.ts
+#EXTINF:10.0,
+2.ts
+#EXTINF:10.0,
+3.ts
+#EXTINF:10.0,
+4.ts
+#EXT-X-ENDLIST
+",
+    )
+    .unwrap();
+
+    let media_of = |playlist| HlsMedia {
+      video: playlist,
+      audio: None,
+      video_url: "https://cdn.example.com/index.m3u8".to_string(),
+      audio_url: None,
+      subtitles: vec![],
+    };
+
+    let mut media = media_of(playlist.clone());
+    let trim = MediaTrim {
+      intro: 15.0,
+      outro: 12.0,
+    };
+
+    assert_eq!(
+      trim_media(&mut media, &trim).unwrap(),
+      Some(ClipTrim {
+        start: 15.0,
+        video_offset: 5.0,
+        audio_offset: 0.0,
+        duration: 23.0,
+        exact: false,
+      })
+    );
+    assert_eq!(media.video.segments.len(), 3);
+    assert_eq!(media.video.segments[0].uri, "1.ts");
+
+    // Media shorter than their intro and outro are kept whole
+    let mut media = media_of(playlist);
+    let trim = MediaTrim {
+      intro: 30.0,
+      outro: 20.0,
+    };
+
+    assert_eq!(trim_media(&mut media, &trim).unwrap(), None);
+    assert_eq!(media.video.segments.len(), 5);
+  }
+
+  #[tokio::test]
+  async fn test_retry_with_another_trim() {
+    let segments: Vec<(String, Vec<u8>)> = (0..5)
+      .map(|index| {
+        (
+          format!("{}.ts", index),
+          format!("segment-{}", index).into_bytes(),
+        )
+      })
+      .collect();
+    let files: Vec<(&str, &[u8])> = segments
+      .iter()
+      .map(|(name, content)| (name.as_str(), content.as_slice()))
+      .collect();
+    let (base_url, dir) = serve_dir(&files).await;
+
+    let segment_list: String = (0..5)
+      .map(|index| format!("#EXTINF:10.0,\n{}/{}.ts\n", base_url, index))
+      .collect();
+    let playlist = parse_media_playlist_res(
+      format!(
+        "#EXTM3U\n#EXT-X-TARGETDURATION:10\n{}#EXT-X-ENDLIST\n",
+        segment_list
+      )
+      .as_bytes(),
+    )
+    .unwrap();
+
+    let media_of = |playlist| HlsMedia {
+      video: playlist,
+      audio: None,
+      video_url: format!("{}/index.m3u8", base_url),
+      audio_url: None,
+      subtitles: vec![],
+    };
+
+    let segments_dir = dir.join("episode.segments");
+    let stream = stream::Stream::new(Ok);
+    let read = |name: &str| std::fs::read(segments_dir.join(name)).unwrap();
+
+    // The first attempt downloads the whole episode
+    let media = media_of(playlist.clone());
+    download_local_playlist(&http_client(1), &media.video, &segments_dir, &stream)
+      .await
+      .unwrap();
+
+    assert_eq!(read("00000.ts"), b"segment-0");
+
+    // The series trim changed before the retry, the first segment is now another one
+    let mut media = media_of(playlist);
+    let trim = MediaTrim {
+      intro: 15.0,
+      outro: 0.0,
+    };
+    trim_media(&mut media, &trim).unwrap();
+
+    download_local_playlist(&http_client(1), &media.video, &segments_dir, &stream)
+      .await
+      .unwrap();
+
+    assert_eq!(read("00000.ts"), b"segment-1");
+    assert_eq!(read("00003.ts"), b"segment-4");
+  }
+
+  #[test]
+  fn test_keyframe_trim() {
+    let trim = ClipTrim {
+      start: 15.0,
+      video_offset: 5.0,
+      audio_offset: 5.0,
+      duration: 23.0,
+      exact: false,
+    };
+
+    // The cut moves back to the keyframe before it, and the subtitles with it
+    assert_eq!(
+      keyframe_trim(trim, &[0.0, 4.0, 8.0]),
+      ClipTrim {
+        start: 14.0,
+        video_offset: 4.01,
+        audio_offset: 4.0,
+        duration: 24.0,
+        exact: false,
+      }
+    );
+    assert_eq!(keyframe_trim(trim, &[5.0]).start, 15.0);
+    // Media without keyframes before the cut, e.g. without video, are cut where they were
+    assert_eq!(keyframe_trim(trim, &[6.0]), trim);
+  }
 }
@@ -0,0 +1,26 @@
+use std::ffi::OsStr;
+
+// Seconds of output between progress logs
+const PROGRESS_LOG_INTERVAL: f64 = 60.0;
+
+/// Runs ffmpeg with `args`, logging its progress. The last line of its output is part of the
+/// error when it fails.
+pub async fn run_ffmpeg<I, S>(args: I) -> anyhow::Result<()>
+where
+  I: IntoIterator<Item = S>,
+  S: AsRef<OsStr>,
+{
+  let mut last_reported_time = 0.0;
+
+  ffmpeg::run(args, |progress| {
+    if progress.time - last_reported_time >= PROGRESS_LOG_INTERVAL {
+      log::info!(
+        "Remuxing: {:.0}m {:.0}s",
+        (progress.time / 60.0).floor(),
+        progress.time % 60.0
+      );
+      last_reported_time = progress.time;
+    }
+  })
+  .await
+}
@@ -0,0 +1,272 @@
+use super::{RateLimiter, RetryPolicy};
+use configuration::{HttpClientConfig, RateLimitConfig, RetryConfig};
+use reqwest::cookie::Jar;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, REFERER, USER_AGENT};
+use reqwest::{Certificate, Client, Proxy, RequestBuilder, Response};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+const DEFAULT_USER_AGENT: &str =
+  "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/127.0.0.0 Safari/537.36";
+
+/// The HTTP clients of a channel, built once from its `HttpClientConfig` and shared by the
+/// channel API, playlist and segment requests.
+#[derive(Clone)]
+pub struct HttpClient {
+  // Client for the channel API, honours the configured HTTP version
+  client: Client,
+  // Client for playlists and segments, which are usually served by CDNs that negotiate
+  // the HTTP version on their own
+  media_client: Client,
+  http_version: http::Version,
+  retry_policy: RetryPolicy,
+  rate_limiter: Option<Arc<RateLimiter>>,
+  progressive_connections: usize,
+}
+
+impl HttpClient {
+  pub fn new(
+    base_url: &str,
+    http_version: http::Version,
+    config: &HttpClientConfig,
+    retry_config: &RetryConfig,
+    rate_limit_config: Option<&RateLimitConfig>,
+  ) -> anyhow::Result<Self> {
+    let headers = default_headers(config)?;
+
+    let cookie_jar = Arc::new(Jar::default());
+    let base_url = Url::parse(base_url)?;
+
+    // Seeded for the base URL only, playlists and segments on other domains don't get them
+    for cookie in &config.cookies {
+      cookie_jar.add_cookie_str(cookie, &base_url);
+    }
+
+    let http2_prior_knowledge = http_version == http::Version::HTTP_2;
+
+    let client = build_client(config, &headers, &cookie_jar, http2_prior_knowledge)?;
+    let media_client = build_client(config, &headers, &cookie_jar, false)?;
+
+    let rate_limiter = rate_limit_config
+      .map(RateLimiter::new)
+      .transpose()?
+      .map(Arc::new);
+
+    Ok(Self {
+      client,
+      media_client,
+      http_version,
+      retry_policy: RetryPolicy::new(retry_config),
+      rate_limiter,
+      progressive_connections: config.progressive_connections.unwrap_or(1).max(1),
+    })
+  }
+
+  pub fn client(&self) -> &Client {
+    &self.client
+  }
+
+  pub fn media_client(&self) -> &Client {
+    &self.media_client
+  }
+
+  pub fn http_version(&self) -> http::Version {
+    self.http_version
+  }
+
+  pub fn retry_policy(&self) -> &RetryPolicy {
+    &self.retry_policy
+  }
+
+  pub fn progressive_connections(&self) -> usize {
+    self.progressive_connections
+  }
+
+  /// Sends `request` once the rate limit of the channel allows it.
+  pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+    if let Some(rate_limiter) = &self.rate_limiter {
+      rate_limiter.acquire().await;
+    }
+
+    request.send().await
+  }
+}
+
+fn default_headers(config: &HttpClientConfig) -> anyhow::Result<HeaderMap> {
+  let mut headers = HeaderMap::new();
+
+  let user_agent = config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+  headers.insert(USER_AGENT, HeaderValue::from_str(user_agent)?);
+
+  if let Some(referer) = &config.referer {
+    headers.insert(REFERER, HeaderValue::from_str(referer)?);
+  }
+
+  for (name, value) in &config.headers {
+    let name = HeaderName::from_bytes(name.as_bytes())
+      .map_err(|e| anyhow::anyhow!("Invalid header name {}: {}", name, e))?;
+    let value = HeaderValue::from_str(value)
+      .map_err(|e| anyhow::anyhow!("Invalid value of header {}: {}", name, e))?;
+
+    headers.insert(name, value);
+  }
+
+  Ok(headers)
+}
+
+fn build_client(
+  config: &HttpClientConfig,
+  headers: &HeaderMap,
+  cookie_jar: &Arc<Jar>,
+  http2_prior_knowledge: bool,
+) -> anyhow::Result<Client> {
+  let mut builder = Client::builder()
+    .default_headers(headers.clone())
+    .cookie_provider(cookie_jar.clone())
+    .danger_accept_invalid_certs(config.accept_invalid_certs);
+
+  if http2_prior_knowledge {
+    builder = builder.http2_prior_knowledge().use_rustls_tls();
+  }
+
+  if let Some(proxy) = &config.proxy {
+    let proxy = Proxy::all(proxy).map_err(|e| anyhow::anyhow!("Invalid proxy {}: {}", proxy, e))?;
+    builder = builder.proxy(proxy);
+  }
+
+  if let Some(timeout) = config.timeout {
+    builder = builder.timeout(Duration::from_secs(timeout));
+  }
+
+  if let Some(connect_timeout) = config.connect_timeout {
+    builder = builder.connect_timeout(Duration::from_secs(connect_timeout));
+  }
+
+  if let Some(path) = &config.ca_certificate {
+    let pem = std::fs::read(path)
+      .map_err(|e| anyhow::anyhow!("Failed to read CA certificate {:?}: {}", path, e))?;
+    builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+  }
+
+  builder
+    .build()
+    .map_err(|e| anyhow::anyhow!("Failed to create new request client: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::HttpClient;
+  use crate::common::test_server::serve_dir_with_routes;
+  use axum::http::{HeaderMap, Uri};
+  use axum::routing::get;
+  use configuration::{HttpClientConfig, RetryConfig};
+  use std::collections::HashMap;
+  use std::time::Duration;
+
+  async fn echo_headers(headers: HeaderMap) -> String {
+    ["user-agent", "referer", "x-token", "cookie"]
+      .iter()
+      .map(|name| {
+        let value = headers.get(*name).and_then(|value| value.to_str().ok());
+        format!("{}={}", name, value.unwrap_or_default())
+      })
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  fn new_client(base_url: &str, config: &HttpClientConfig) -> HttpClient {
+    HttpClient::new(
+      base_url,
+      http::Version::HTTP_11,
+      config,
+      &RetryConfig::default(),
+      None,
+    )
+    .unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_headers_and_cookies() {
+    let routes = axum::Router::new().route("/headers", get(echo_headers));
+    let (base_url, _dir) = serve_dir_with_routes(&[], routes).await;
+
+    let config = HttpClientConfig {
+      user_agent: Some("media-downloader".to_string()),
+      referer: Some("https://www.example.com/".to_string()),
+      headers: HashMap::from([("X-Token".to_string(), "abc".to_string())]),
+      cookies: vec!["session=123; Path=/".to_string()],
+      ..Default::default()
+    };
+    let http_client = new_client(&base_url, &config);
+
+    // Both the API and the media clients send them
+    for client in [http_client.client(), http_client.media_client()] {
+      let request = client.get(format!("{}/headers", base_url));
+      let text = http_client
+        .send(request)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+      assert_eq!(
+        text,
+        "user-agent=media-downloader\nreferer=https://www.example.com/\nx-token=abc\ncookie=session=123"
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_proxy() {
+    // Requests through an HTTP proxy carry the absolute URL of the proxied resource
+    let routes = axum::Router::new().route(
+      "/1/index.m3u8",
+      get(|uri: Uri| async move { uri.to_string() }),
+    );
+    let (proxy_url, _dir) = serve_dir_with_routes(&[], routes).await;
+
+    let config = HttpClientConfig {
+      proxy: Some(proxy_url),
+      ..Default::default()
+    };
+    let http_client = new_client("http://media.invalid", &config);
+
+    let request = http_client
+      .media_client()
+      .get("http://media.invalid/1/index.m3u8");
+    let text = http_client
+      .send(request)
+      .await
+      .unwrap()
+      .text()
+      .await
+      .unwrap();
+
+    assert_eq!(text, "http://media.invalid/1/index.m3u8");
+  }
+
+  #[tokio::test]
+  async fn test_timeout() {
+    let routes = axum::Router::new().route(
+      "/slow",
+      get(|| async {
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        "slow"
+      }),
+    );
+    let (base_url, _dir) = serve_dir_with_routes(&[], routes).await;
+
+    let config = HttpClientConfig {
+      timeout: Some(1),
+      ..Default::default()
+    };
+    let http_client = new_client(&base_url, &config);
+
+    let request = http_client.media_client().get(format!("{}/slow", base_url));
+    let err = http_client.send(request).await.unwrap_err();
+
+    assert!(err.is_timeout());
+  }
+}
@@ -0,0 +1,539 @@
+use super::{download_segments, fetch_playlist, HttpClient};
+use configuration::LiveConfig;
+use m3u8_rs::{Key, KeyMethod, Map, MediaPlaylist, MediaPlaylistType, Playlist};
+use protocol::channel::LiveRecording;
+use protocol::{DownloadProgressExt, DownloadProgressStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs;
+
+const DEFAULT_MAX_DURATION: u64 = 4 * 60 * 60;
+const DEFAULT_MAX_IDLE_POLLS: u32 = 10;
+
+// Segments from the end of a live playlist that recordings start at, as players do
+const LIVE_EDGE_SEGMENTS: usize = 3;
+
+/// Whether segments are still appended to `playlist`, which is then polled until it ends.
+pub fn is_live(playlist: &MediaPlaylist) -> bool {
+  !playlist.end_list && playlist.playlist_type != Some(MediaPlaylistType::Vod)
+}
+
+/// Whether `playlist`, fetched from `url`, is recorded as its segments are appended instead of
+/// downloaded at once. Events are, while playlists of no type lacking `#EXT-X-ENDLIST`, which
+/// some VOD servers serve, only are if they grew when fetched again a target duration later.
+pub async fn is_growing_live(
+  http_client: &HttpClient,
+  url: &str,
+  playlist: &MediaPlaylist,
+) -> bool {
+  if !is_live(playlist) {
+    return false;
+  }
+
+  if playlist.playlist_type == Some(MediaPlaylistType::Event) {
+    return true;
+  }
+
+  tokio::time::sleep(Duration::from_secs(playlist.target_duration.max(1))).await;
+
+  match fetch_playlist(http_client, url).await {
+    Ok(Playlist::MediaPlaylist(refetched)) => end_sequence(&refetched) > end_sequence(playlist),
+    Ok(Playlist::MasterPlaylist(_)) => false,
+    Err(err) => {
+      log::warn!(
+        "Failed to fetch {} again, downloading it at once: {:#}",
+        url,
+        err
+      );
+      false
+    }
+  }
+}
+
+/// When a recording stops: once `max_seconds` are recorded or have passed, or after as many polls
+/// of the playlist without new segments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingLimits {
+  pub max_seconds: f64,
+  pub max_idle_polls: u32,
+}
+
+impl RecordingLimits {
+  /// Limits of `recording` starting now, none of which exceeds the configured maximum duration.
+  pub fn new(recording: Option<&LiveRecording>, config: &LiveConfig) -> Self {
+    let max_duration = config.max_duration.unwrap_or(DEFAULT_MAX_DURATION);
+    let duration = recording
+      .and_then(|recording| recording.duration)
+      .map_or(max_duration, |duration| duration.min(max_duration));
+
+    let mut max_seconds = duration as f64;
+
+    if let Some(until) = recording.and_then(LiveRecording::until) {
+      let left = (until - chrono::Utc::now()).num_milliseconds().max(0) as f64 / 1000.0;
+      max_seconds = max_seconds.min(left);
+    }
+
+    Self {
+      max_seconds,
+      max_idle_polls: config.max_idle_polls.unwrap_or(DEFAULT_MAX_IDLE_POLLS),
+    }
+  }
+}
+
+/// A media playlist of a live stream, the new segments of which are downloaded each time it is
+/// polled. Segments are downloaded in batches, one directory each, and listed by a single local
+/// playlist once the recording is over.
+pub struct LiveTrack {
+  url: String,
+  dir: PathBuf,
+  latest: MediaPlaylist,
+  // Segments recorded so far, referring to the files of the batches
+  recorded: MediaPlaylist,
+  // Media sequence number of the next segment to record
+  next_sequence: u64,
+  // Key and init section in effect at the next segment
+  key: Option<Key>,
+  map: Option<Map>,
+  // Whether segments were missed since the last recorded one
+  gap: bool,
+  batches: usize,
+  recorded_seconds: f64,
+  recorded_bytes: u64,
+}
+
+impl LiveTrack {
+  pub fn new(url: String, playlist: MediaPlaylist, dir: PathBuf) -> Self {
+    let recorded = MediaPlaylist {
+      segments: vec![],
+      media_sequence: 0,
+      discontinuity_sequence: 0,
+      end_list: true,
+      playlist_type: Some(MediaPlaylistType::Vod),
+      ..playlist.clone()
+    };
+
+    Self {
+      url,
+      dir,
+      next_sequence: playlist.media_sequence,
+      latest: playlist,
+      recorded,
+      key: None,
+      map: None,
+      gap: false,
+      batches: 0,
+      recorded_seconds: 0.0,
+      recorded_bytes: 0,
+    }
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  pub fn recorded_seconds(&self) -> f64 {
+    self.recorded_seconds
+  }
+
+  fn is_ended(&self) -> bool {
+    !is_live(&self.latest) && self.next_sequence >= end_sequence(&self.latest)
+  }
+
+  // Skips the segments of the latest playlist before its last `seconds`
+  fn skip_to_last(&mut self, seconds: f64) {
+    let mut kept = 0.0;
+    let mut sequence = end_sequence(&self.latest);
+
+    for segment in self.latest.segments.iter().rev() {
+      if kept >= seconds {
+        break;
+      }
+
+      kept += segment.duration as f64;
+      sequence -= 1;
+    }
+
+    self.next_sequence = sequence;
+  }
+
+  /// Takes the segments of the latest playlist that weren't recorded yet, as a playlist of their
+  /// own, up to `max_seconds` of them. Keys and init sections of earlier segments are carried to
+  /// them, and IVs derived from media sequence numbers are made explicit, so that batches can be
+  /// listed one after the other.
+  fn take_new_segments(&mut self, max_seconds: f64) -> MediaPlaylist {
+    let playlist = &self.latest;
+    let mut batch = MediaPlaylist {
+      segments: vec![],
+      ..playlist.clone()
+    };
+
+    if playlist.media_sequence > self.next_sequence && self.batches > 0 {
+      log::warn!(
+        "Missed {} segments of {}",
+        playlist.media_sequence - self.next_sequence,
+        self.url
+      );
+      self.gap = true;
+    }
+
+    let mut seconds = 0.0;
+
+    for (sequence, segment) in (playlist.media_sequence..).zip(&playlist.segments) {
+      if segment.key.is_some() {
+        self.key = segment.key.clone();
+      }
+
+      if segment.map.is_some() {
+        self.map = segment.map.clone();
+      }
+
+      if sequence < self.next_sequence {
+        continue;
+      }
+
+      if seconds >= max_seconds {
+        break;
+      }
+
+      let mut segment = segment.clone();
+
+      segment.key = self.key.clone().map(|mut key| {
+        if key.method == KeyMethod::AES128 && key.iv.is_none() {
+          key.iv = Some(format!("0x{:032X}", sequence));
+        }
+
+        key
+      });
+
+      if batch.segments.is_empty() {
+        segment.map = self.map.clone();
+        segment.discontinuity |= std::mem::take(&mut self.gap);
+      }
+
+      seconds += segment.duration as f64;
+      batch.segments.push(segment);
+      self.next_sequence = sequence + 1;
+    }
+
+    self.next_sequence = self.next_sequence.max(playlist.media_sequence);
+
+    batch
+  }
+
+  // Downloads the new segments of the latest playlist, returning how many were recorded
+  async fn record(
+    &mut self,
+    http_client: &HttpClient,
+    max_seconds: f64,
+    stream: &DownloadProgressStream,
+  ) -> usize {
+    let batch = self.take_new_segments(max_seconds);
+
+    if batch.segments.is_empty() {
+      return 0;
+    }
+
+    let batch_name = format!("{:05}", self.batches);
+    let batch_dir = self.dir.join(&batch_name);
+    self.batches += 1;
+
+    let local_batch = match download_segments(http_client, &batch, &batch_dir, stream).await {
+      Ok(local_batch) => local_batch,
+      Err(err) => {
+        // The recording goes on without them, after a discontinuity
+        log::warn!(
+          "Skipping {} segments of {}: {:#}",
+          batch.segments.len(),
+          self.url,
+          err
+        );
+        fs::remove_dir_all(&batch_dir).await.ok();
+        self.gap = true;
+
+        return 0;
+      }
+    };
+
+    for (segment, mut local_segment) in batch.segments.iter().zip(local_batch.segments) {
+      local_segment.uri = format!("{}/{}", batch_name, local_segment.uri);
+
+      // Keys of other schemes are left to ffmpeg as they are
+      if let Some(key) = local_segment.key.as_mut() {
+        let is_downloaded = segment
+          .key
+          .as_ref()
+          .and_then(|key| key.uri.as_deref())
+          .is_some_and(|uri| uri.starts_with("http"));
+
+        if let (true, Some(uri)) = (is_downloaded, key.uri.as_mut()) {
+          *uri = format!("{}/{}", batch_name, uri);
+        }
+      }
+
+      if let Some(map) = local_segment.map.as_mut() {
+        map.uri = format!("{}/{}", batch_name, map.uri);
+      }
+
+      self.recorded.target_duration = self
+        .recorded
+        .target_duration
+        .max(local_segment.duration.ceil() as u64);
+      self.recorded_seconds += local_segment.duration as f64;
+      self.recorded.segments.push(local_segment);
+    }
+
+    self.recorded_bytes += dir_size(&batch_dir).await;
+
+    batch.segments.len()
+  }
+
+  async fn poll(&mut self, http_client: &HttpClient, stream: &DownloadProgressStream) {
+    let playlist = http_client
+      .retry_policy()
+      .run_with_notify(
+        &self.url,
+        || fetch_playlist(http_client, &self.url),
+        |message| stream.retrying(message),
+      )
+      .await;
+
+    match playlist {
+      Ok(Playlist::MediaPlaylist(playlist)) => self.latest = playlist,
+      Ok(Playlist::MasterPlaylist(_)) => log::warn!("{} is no longer a media playlist", self.url),
+      Err(err) => log::warn!("Failed to poll {}: {:#}", self.url, err),
+    }
+  }
+
+  /// Writes the playlist of the recorded segments into the directory of the track.
+  pub async fn write_playlist(&self) -> anyhow::Result<PathBuf> {
+    let playlist_path = self.dir.join("index.m3u8");
+    let mut playlist_bytes = Vec::new();
+
+    self.recorded.write_to(&mut playlist_bytes)?;
+    fs::write(&playlist_path, playlist_bytes).await?;
+
+    Ok(playlist_path)
+  }
+}
+
+// Media sequence number following the last segment of `playlist`
+fn end_sequence(playlist: &MediaPlaylist) -> u64 {
+  playlist.media_sequence + playlist.segments.len() as u64
+}
+
+async fn dir_size(dir: &Path) -> u64 {
+  let mut size = 0;
+
+  if let Ok(mut entries) = fs::read_dir(dir).await {
+    while let Ok(Some(entry)) = entries.next_entry().await {
+      if let Ok(metadata) = entry.metadata().await {
+        size += metadata.len();
+      }
+    }
+  }
+
+  size
+}
+
+/// Records the live playlists of `tracks`, the first of which is the video, by polling them for
+/// new segments until one of `limits` is reached or the stream ends (`#EXT-X-ENDLIST`).
+/// Recordings of live playlists start at their live edge, those of ended ones at their start.
+pub async fn record_live(
+  http_client: &HttpClient,
+  tracks: &mut [LiveTrack],
+  limits: RecordingLimits,
+  stream: &DownloadProgressStream,
+) -> anyhow::Result<()> {
+  let Some(video) = tracks.first_mut() else {
+    anyhow::bail!("No playlist to record");
+  };
+
+  if is_live(&video.latest) {
+    let edge = video
+      .latest
+      .segments
+      .len()
+      .saturating_sub(LIVE_EDGE_SEGMENTS);
+    let seconds: f64 = video.latest.segments[edge..]
+      .iter()
+      .map(|segment| segment.duration as f64)
+      .sum();
+
+    // Alternate renditions start at the same time as the video
+    for track in tracks.iter_mut() {
+      track.skip_to_last(seconds);
+    }
+  }
+
+  let started_at = Instant::now();
+  let mut idle_polls = 0;
+
+  loop {
+    let mut recorded_segments = 0;
+
+    for track in tracks.iter_mut() {
+      let max_seconds = limits.max_seconds - track.recorded_seconds;
+      recorded_segments += track.record(http_client, max_seconds, stream).await;
+    }
+
+    let video = &tracks[0];
+    let recorded_bytes = tracks.iter().map(|track| track.recorded_bytes).sum();
+
+    stream.recording(video.recorded_seconds, recorded_bytes, limits.max_seconds);
+
+    if recorded_segments == 0 {
+      idle_polls += 1;
+    } else {
+      idle_polls = 0;
+    }
+
+    if video.is_ended() {
+      log::info!("Live stream {} ended", video.url);
+      break;
+    }
+
+    let elapsed = started_at.elapsed().as_secs_f64();
+
+    if video.recorded_seconds >= limits.max_seconds || elapsed >= limits.max_seconds {
+      log::info!(
+        "Recorded {:.1}s of {} in {:.1}s",
+        video.recorded_seconds,
+        video.url,
+        elapsed
+      );
+      break;
+    }
+
+    if idle_polls > limits.max_idle_polls {
+      log::warn!("No new segments of {} for {} polls", video.url, idle_polls);
+      break;
+    }
+
+    // Playlists are reloaded after a target duration, or half of it while unchanged (RFC 8216)
+    let mut interval = Duration::from_secs(video.latest.target_duration.max(1));
+
+    if recorded_segments == 0 {
+      interval /= 2;
+    }
+
+    tokio::time::sleep(interval.min(Duration::from_secs_f64(limits.max_seconds - elapsed))).await;
+
+    for track in tracks.iter_mut() {
+      track.poll(http_client, stream).await;
+    }
+  }
+
+  if tracks[0].recorded.segments.is_empty() {
+    anyhow::bail!("No segments of {} were recorded", tracks[0].url);
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{LiveTrack, RecordingLimits};
+  use configuration::LiveConfig;
+  use m3u8_rs::{parse_media_playlist_res, MediaPlaylist};
+  use protocol::channel::LiveRecording;
+  use std::path::PathBuf;
+
+  fn live_playlist(media_sequence: u64, count: u64) -> MediaPlaylist {
+    let mut playlist = format!(
+      "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-MEDIA-SEQUENCE:{}\n\
+       #EXT-X-KEY:METHOD=AES-128,URI=\"https://live.example.com/key\"\n",
+      media_sequence
+    );
+
+    for sequence in media_sequence..media_sequence + count {
+      playlist.push_str(&format!(
+        "#EXTINF:6.0,\nhttps://live.example.com/{}.ts\n",
+        sequence
+      ));
+    }
+
+    parse_media_playlist_res(playlist.as_bytes()).unwrap()
+  }
+
+  fn uris(playlist: &MediaPlaylist) -> Vec<&str> {
+    playlist
+      .segments
+      .iter()
+      .map(|segment| segment.uri.rsplit('/').next().unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn test_take_new_segments() {
+    let mut track = LiveTrack::new(
+      "https://live.example.com/index.m3u8".to_string(),
+      live_playlist(100, 6),
+      PathBuf::from("/media/live.segments"),
+    );
+
+    track.skip_to_last(18.0);
+    let batch = track.take_new_segments(f64::MAX);
+
+    assert_eq!(uris(&batch), vec!["103.ts", "104.ts", "105.ts"]);
+
+    // The key of the window is carried to each segment, with the IV it had in the live playlist
+    let key = batch.segments[1].key.as_ref().unwrap();
+    assert_eq!(key.uri.as_deref(), Some("https://live.example.com/key"));
+    assert_eq!(
+      key.iv.as_deref(),
+      Some("0x00000000000000000000000000000068")
+    );
+    assert!(!batch.segments[0].discontinuity);
+
+    // Only the segments appended since are taken, up to the seconds left
+    track.batches += 1;
+    track.latest = live_playlist(102, 6);
+    assert_eq!(
+      uris(&track.take_new_segments(10.0)),
+      vec!["106.ts", "107.ts"]
+    );
+    assert!(track.take_new_segments(f64::MAX).segments.is_empty());
+
+    // Segments that were dropped from the playlist before being polled leave a gap
+    track.latest = live_playlist(110, 2);
+    let batch = track.take_new_segments(f64::MAX);
+
+    assert_eq!(uris(&batch), vec!["110.ts", "111.ts"]);
+    assert!(batch.segments[0].discontinuity);
+    assert!(!batch.segments[1].discontinuity);
+  }
+
+  #[test]
+  fn test_recording_limits() {
+    let config = LiveConfig::default();
+
+    assert_eq!(RecordingLimits::new(None, &config).max_seconds, 14400.0);
+
+    let recording = LiveRecording {
+      duration: Some(1800),
+      ..Default::default()
+    };
+    assert_eq!(
+      RecordingLimits::new(Some(&recording), &config).max_seconds,
+      1800.0
+    );
+
+    let config = LiveConfig {
+      max_duration: Some(600),
+      ..Default::default()
+    };
+    assert_eq!(
+      RecordingLimits::new(Some(&recording), &config).max_seconds,
+      600.0
+    );
+
+    let until = chrono::Utc::now() + chrono::Duration::seconds(300);
+    let recording = LiveRecording {
+      until: Some(until.to_rfc3339()),
+      ..recording
+    };
+    let max_seconds = RecordingLimits::new(Some(&recording), &config).max_seconds;
+
+    assert!(max_seconds > 290.0 && max_seconds <= 300.0);
+  }
+}
@@ -0,0 +1,9 @@
+/// Splits a list of people (e.g. cast or directors) given as a single string by a channel.
+pub fn split_names(names: &str) -> Vec<String> {
+  names
+    .split([',', '，', '/', '、', '|'])
+    .map(|name| name.trim())
+    .filter(|name| !name.is_empty())
+    .map(|name| name.to_string())
+    .collect()
+}
@@ -0,0 +1,277 @@
+use super::{fetch_playlist, segment_ranges, HttpClient, VariantPolicy};
+use futures::StreamExt;
+use m3u8_rs::{MasterPlaylist, MediaPlaylist, Playlist};
+use protocol::channel::{MediaItemProbe, MediaPlaylistItem};
+use reqwest::header::RANGE;
+use std::future::Future;
+
+const PROBE_CONCURRENCY: usize = 4;
+
+/// Probes the media of `items`, a few at a time. `media_url` resolves the URL of an item to that
+/// of its media, e.g. the playlist linked by a player page.
+pub async fn probe_playlist_items<F, Fut>(
+  http_client: &HttpClient,
+  variant_policy: &VariantPolicy,
+  items: &mut [MediaPlaylistItem],
+  media_url: F,
+) where
+  F: Fn(String) -> Fut,
+  Fut: Future<Output = anyhow::Result<String>>,
+{
+  let urls: Vec<String> = items.iter().map(|item| item.url.clone()).collect();
+
+  let probes: Vec<MediaItemProbe> = futures::stream::iter(urls)
+    .map(|url| {
+      let url = media_url(url);
+
+      async move {
+        match url.await {
+          Ok(url) => probe_media(http_client, variant_policy, &url).await,
+          Err(err) => failed_probe(&err),
+        }
+      }
+    })
+    .buffered(PROBE_CONCURRENCY)
+    .collect()
+    .await;
+
+  for (item, probe) in items.iter_mut().zip(probes) {
+    item.probe = Some(probe);
+  }
+}
+
+/// Probes the HLS playlist at `url`, along with the media playlist of the variant that would be
+/// downloaded and its first segment.
+pub async fn probe_media(
+  http_client: &HttpClient,
+  variant_policy: &VariantPolicy,
+  url: &str,
+) -> MediaItemProbe {
+  let fetch = |url: String| async move {
+    http_client
+      .retry_policy()
+      .run(&url, || fetch_playlist(http_client, &url))
+      .await
+  };
+
+  let (playlist, resolutions) = match fetch(url.to_string()).await {
+    Ok(Playlist::MediaPlaylist(playlist)) => (playlist, vec![]),
+    Ok(Playlist::MasterPlaylist(master_playlist)) => {
+      let resolutions = list_resolutions(&master_playlist);
+      let variants = master_playlist
+        .variants
+        .iter()
+        .filter(|variant| !variant.is_i_frame);
+
+      let Some(variant) = variant_policy.select(variants) else {
+        return MediaItemProbe {
+          reachable: true,
+          resolutions,
+          error: Some("No variant to download".to_string()),
+          ..Default::default()
+        };
+      };
+
+      match fetch(variant.uri.clone()).await {
+        Ok(Playlist::MediaPlaylist(playlist)) => (playlist, resolutions),
+        Ok(Playlist::MasterPlaylist(_)) => {
+          return MediaItemProbe {
+            reachable: true,
+            resolutions,
+            error: Some(format!("Variant {} is not a media playlist", variant.uri)),
+            ..Default::default()
+          }
+        }
+        Err(err) => return failed_probe(&err),
+      }
+    }
+    Err(err) => return failed_probe(&err),
+  };
+
+  let mut probe = media_probe(&playlist, resolutions);
+
+  // Playlists are often still served once their segments are gone
+  if let Err(err) = probe_first_segment(http_client, &playlist).await {
+    probe.reachable = false;
+    probe.error = Some(format!("First segment is unavailable: {:#}", err));
+  }
+
+  probe
+}
+
+// Requests the first byte of the first segment of `playlist`
+async fn probe_first_segment(
+  http_client: &HttpClient,
+  playlist: &MediaPlaylist,
+) -> anyhow::Result<()> {
+  let Some(segment) = playlist.segments.first() else {
+    return Ok(());
+  };
+
+  let offset = match segment_ranges(playlist)?.first() {
+    Some(Some((offset, _))) => *offset,
+    _ => 0,
+  };
+
+  http_client
+    .retry_policy()
+    .run(&segment.uri, || async {
+      let request = http_client
+        .media_client()
+        .get(&segment.uri)
+        .header(RANGE, format!("bytes={}-{}", offset, offset));
+
+      http_client.send(request).await?.error_for_status()?;
+
+      Ok(())
+    })
+    .await
+}
+
+fn media_probe(playlist: &MediaPlaylist, resolutions: Vec<String>) -> MediaItemProbe {
+  MediaItemProbe {
+    reachable: true,
+    duration: Some(
+      playlist
+        .segments
+        .iter()
+        .map(|segment| segment.duration as f64)
+        .sum(),
+    ),
+    segments: Some(playlist.segments.len()),
+    resolutions,
+    error: None,
+  }
+}
+
+// Only links answered with an HTTP status are reachable, those that can't be connected to or
+// don't answer with a playlist (e.g. an HTML error page) can't be downloaded
+fn failed_probe(err: &anyhow::Error) -> MediaItemProbe {
+  let reachable = err.chain().any(|cause| {
+    cause
+      .downcast_ref::<reqwest::Error>()
+      .is_some_and(|err| err.status().is_some())
+  });
+
+  MediaItemProbe {
+    reachable,
+    error: Some(format!("{:#}", err)),
+    ..Default::default()
+  }
+}
+
+// Resolutions of the variants of `master_playlist`, highest first
+fn list_resolutions(master_playlist: &MasterPlaylist) -> Vec<String> {
+  let mut resolutions: Vec<(u64, u64)> = master_playlist
+    .variants
+    .iter()
+    .filter(|variant| !variant.is_i_frame)
+    .filter_map(|variant| variant.resolution)
+    .map(|resolution| (resolution.height, resolution.width))
+    .collect();
+
+  resolutions.sort_unstable_by(|a, b| b.cmp(a));
+  resolutions.dedup();
+
+  resolutions
+    .into_iter()
+    .map(|(height, width)| format!("{}x{}", width, height))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::probe_playlist_items;
+  use crate::common::test_server::{http_client, serve_dir};
+  use crate::common::VariantPolicy;
+  use configuration::DownloadConfig;
+  use protocol::channel::{MediaItemProbe, MediaPlaylistItem};
+
+  const MASTER: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=854x480
+480p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080
+1080p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2800000,RESOLUTION=1280x720
+720p.m3u8
+";
+
+  const MEDIA: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXTINF:10.0,
+0.ts
+#EXTINF:10.0,
+1.ts
+#EXTINF:4.5,
+2.ts
+#EXT-X-ENDLIST
+";
+
+  #[tokio::test]
+  async fn test_probe_playlist_items() {
+    let (base_url, _dir) = serve_dir(&[
+      ("1/index.m3u8", MASTER.as_bytes()),
+      ("1/1080p.m3u8", MEDIA.as_bytes()),
+      ("1/0.ts", &[0x47; 188]),
+      ("2/index.m3u8", MEDIA.as_bytes()),
+      ("2/0.ts", &[0x47; 188]),
+      ("3/index.mpd", b"<MPD></MPD>".as_slice()),
+      // Its segments are gone
+      ("4/index.m3u8", MEDIA.as_bytes()),
+    ])
+    .await;
+
+    let item = |number: u32, path: &str| MediaPlaylistItem {
+      number,
+      text: format!("第{}集", number),
+      url: format!("{}/{}", base_url, path),
+      probe: None,
+    };
+
+    let mut items = vec![
+      item(1, "1/index.m3u8"),
+      item(2, "2/index.m3u8"),
+      item(3, "missing/index.m3u8"),
+      item(4, "3/index.mpd"),
+      item(5, "4/index.m3u8"),
+    ];
+
+    let variant_policy = VariantPolicy::new(&DownloadConfig::default());
+
+    probe_playlist_items(&http_client(1), &variant_policy, &mut items, |url| async {
+      Ok(url)
+    })
+    .await;
+
+    // The variant downloaded by default is the best one
+    assert_eq!(
+      items[0].probe,
+      Some(MediaItemProbe {
+        reachable: true,
+        duration: Some(24.5),
+        segments: Some(3),
+        resolutions: vec![
+          "1920x1080".to_string(),
+          "1280x720".to_string(),
+          "854x480".to_string()
+        ],
+        error: None,
+      })
+    );
+
+    let probe = items[1].probe.as_ref().unwrap();
+    assert_eq!((probe.segments, probe.resolutions.len()), (Some(3), 0));
+
+    // The server answers with an error status
+    let probe = items[2].probe.as_ref().unwrap();
+    assert!(probe.reachable && probe.error.is_some());
+
+    // A DASH manifest doesn't parse as a playlist
+    let probe = items[3].probe.as_ref().unwrap();
+    assert!(!probe.reachable && probe.duration.is_none());
+
+    let probe = items[4].probe.as_ref().unwrap();
+    assert!(!probe.reachable && probe.error.is_some());
+    assert_eq!(probe.segments, Some(3));
+  }
+}
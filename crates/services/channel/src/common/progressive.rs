@@ -0,0 +1,680 @@
+use super::MediaTagging;
+use super::{container_extension, download_verified, DownloadMediaOptions, HttpClient};
+use futures::{StreamExt, TryStreamExt};
+use protocol::{DownloadProgressExt, DownloadProgressReceiver, DownloadProgressStream};
+use reqwest::header::{HeaderMap, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::StatusCode;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+const PROGRESSIVE_EXTENSIONS: &[&str] = &["mp4", "m4v", "mkv", "webm", "mov", "flv", "avi"];
+
+const PLAYLIST_EXTENSIONS: &[&str] = &["m3u8", "m3u"];
+
+const DASH_EXTENSIONS: &[&str] = &["mpd"];
+
+// Size of the chunks downloaded in parallel
+const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+// Minimum number of bytes between progress events
+const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// What a media URL links to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MediaSource {
+  Playlist,
+  Dash,
+  Progressive(ProgressiveMedia),
+}
+
+/// Single media file, e.g. an MP4, downloaded as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressiveMedia {
+  pub total_bytes: Option<u64>,
+  pub accepts_ranges: bool,
+  pub extension: Option<&'static str>,
+}
+
+/// Finds out whether `url` links to a playlist, a DASH manifest or a single media file. Apart
+/// from playlist and manifest URLs, the headers of the URL are requested to read its content
+/// type, size and range support.
+pub async fn sniff_media_source(http_client: &HttpClient, url: &str) -> MediaSource {
+  match url_extension(url) {
+    Some(extension) if PLAYLIST_EXTENSIONS.contains(&extension.as_str()) => {
+      return MediaSource::Playlist
+    }
+    Some(extension) if DASH_EXTENSIONS.contains(&extension.as_str()) => return MediaSource::Dash,
+    _ => {}
+  }
+
+  let request = http_client.media_client().head(url);
+
+  match http_client.send(request).await {
+    Ok(res) if res.status().is_success() => {
+      return media_source_of_response(url, res.status(), res.headers())
+    }
+    Ok(res) => log::debug!(
+      "HEAD {} answered {}, requesting its first byte",
+      url,
+      res.status()
+    ),
+    Err(err) => log::debug!("HEAD {} failed, requesting its first byte: {}", url, err),
+  }
+
+  // Some servers reject HEAD requests, e.g. with 405 Method Not Allowed
+  let headers = http_client
+    .retry_policy()
+    .run(url, || async {
+      let request = http_client
+        .media_client()
+        .get(url)
+        .header(RANGE, "bytes=0-0");
+      let res = http_client.send(request).await?.error_for_status()?;
+
+      // The body is dropped unread, servers ignoring the range don't send the whole media
+      Ok((res.status(), res.headers().clone()))
+    })
+    .await;
+
+  match headers {
+    Ok((status, headers)) => media_source_of_response(url, status, &headers),
+    Err(err) => {
+      // Leave the error to the actual download
+      log::warn!("Failed to sniff media source of {}: {:#}", url, err);
+
+      match progressive_extension_of_url(url) {
+        Some(extension) => MediaSource::Progressive(ProgressiveMedia {
+          total_bytes: None,
+          accepts_ranges: false,
+          extension: Some(extension),
+        }),
+        None => MediaSource::Playlist,
+      }
+    }
+  }
+}
+
+fn media_source_of_response(url: &str, status: StatusCode, headers: &HeaderMap) -> MediaSource {
+  let content_type = headers
+    .get(CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.split(';').next())
+    .map(|value| value.trim().to_lowercase())
+    .unwrap_or_default();
+
+  let extension = progressive_extension_of_url(url).or(extension_of_content_type(&content_type));
+
+  if content_type == "application/dash+xml" {
+    return MediaSource::Dash;
+  }
+
+  let is_progressive = if content_type.contains("mpegurl") {
+    false
+  } else if content_type.starts_with("video/")
+    || content_type.starts_with("audio/")
+    || content_type == "application/mp4"
+  {
+    true
+  } else {
+    // e.g. `application/octet-stream`, trust the URL
+    progressive_extension_of_url(url).is_some()
+  };
+
+  if !is_progressive {
+    return MediaSource::Playlist;
+  }
+
+  let (total_bytes, accepts_ranges) = if status == StatusCode::PARTIAL_CONTENT {
+    // e.g. `bytes 0-0/1048576`
+    let total_bytes = headers
+      .get(CONTENT_RANGE)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.rsplit('/').next())
+      .and_then(|total| total.trim().parse().ok());
+
+    (total_bytes, true)
+  } else {
+    let total_bytes = headers
+      .get(reqwest::header::CONTENT_LENGTH)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.parse().ok());
+    let accepts_ranges = headers
+      .get(ACCEPT_RANGES)
+      .is_some_and(|value| value.as_bytes() == b"bytes");
+
+    (total_bytes, accepts_ranges)
+  };
+
+  MediaSource::Progressive(ProgressiveMedia {
+    total_bytes,
+    accepts_ranges,
+    extension,
+  })
+}
+
+/// Downloads a single media file. The extension of the destination follows the media, which is
+/// saved as it is whatever the output container, and an interrupted download is resumed if the
+/// server supports range requests.
+pub async fn download_progressive_media(
+  options: DownloadMediaOptions<'_>,
+  media: ProgressiveMedia,
+) -> anyhow::Result<DownloadProgressReceiver> {
+  let stream = stream::Stream::new(Ok);
+  let receiver = stream.recv();
+
+  let extension = media
+    .extension
+    .unwrap_or_else(|| container_extension(options.container()));
+  let destination_path = options.destination_path.with_extension(extension);
+
+  // There are no segments, the progress is reported in bytes
+  stream.start(0);
+
+  tokio::spawn({
+    let download = ProgressiveDownload {
+      url: options.download_url.to_string(),
+      http_client: options.http_client.clone(),
+      media,
+      chunk_size: CHUNK_SIZE,
+    };
+    let verification = options.download_config.verification.clone();
+    // Single files can't be downloaded without their video, which their tag pass or the media
+    // service strips
+    let audio_only = options.audio_only;
+    let tags = options.tags.cloned();
+
+    async move {
+      log::info!(
+        "Downloading progressive media: {} ({:?})",
+        download.url,
+        download.media
+      );
+
+      // Single files have no playlist telling their duration
+      if let Err(err) = download_verified(
+        || download.run(&destination_path, &stream),
+        &destination_path,
+        None,
+        !audio_only,
+        &verification,
+        0,
+        &stream,
+      )
+      .await
+      {
+        log::error!("Failed to download progressive media: {:#}", err);
+        stream.failed(&format!("{:#}", err));
+        return Err(err);
+      }
+
+      // Files are saved as they are served, their tags take a pass of their own
+      if let Some(tags) = &tags {
+        let tagging = MediaTagging::prepare(&download.http_client, tags, &destination_path).await;
+
+        if let Err(err) = tagging.write_into(&destination_path, audio_only).await {
+          log::warn!("Failed to tag {:?}: {:#}", destination_path, err);
+        }
+
+        tagging.remove_cover().await;
+      }
+
+      log::info!("Done. {:?}", destination_path);
+      stream.done(&destination_path.to_string_lossy());
+
+      Ok(()) as anyhow::Result<()>
+    }
+  });
+
+  Ok(receiver)
+}
+
+struct ProgressiveDownload {
+  url: String,
+  http_client: HttpClient,
+  media: ProgressiveMedia,
+  chunk_size: u64,
+}
+
+impl ProgressiveDownload {
+  async fn run(
+    &self,
+    destination_path: &Path,
+    stream: &DownloadProgressStream,
+  ) -> anyhow::Result<()> {
+    fs::create_dir_all(destination_path.parent().unwrap()).await?;
+
+    let connections = self.http_client.progressive_connections();
+
+    match self.media.total_bytes {
+      Some(total_bytes)
+        if self.media.accepts_ranges && connections > 1 && total_bytes > self.chunk_size =>
+      {
+        self
+          .download_chunks(total_bytes, connections, destination_path, stream)
+          .await
+      }
+      _ => self.download_whole(destination_path, stream).await,
+    }
+  }
+
+  async fn download_whole(
+    &self,
+    destination_path: &Path,
+    stream: &DownloadProgressStream,
+  ) -> anyhow::Result<()> {
+    let part_path = with_suffix(destination_path, ".part");
+    let progress = ByteProgress::new(stream, self.media.total_bytes);
+
+    progress.add(file_len(&part_path).await);
+
+    self
+      .download_range(&part_path, 0, self.media.total_bytes, &progress)
+      .await?;
+
+    progress.report();
+
+    if let Some(total_bytes) = self.media.total_bytes {
+      let downloaded = file_len(&part_path).await;
+
+      if downloaded != total_bytes {
+        anyhow::bail!("Incomplete download: {}/{} bytes", downloaded, total_bytes);
+      }
+    }
+
+    fs::rename(&part_path, destination_path).await?;
+
+    Ok(())
+  }
+
+  async fn download_chunks(
+    &self,
+    total_bytes: u64,
+    connections: usize,
+    destination_path: &Path,
+    stream: &DownloadProgressStream,
+  ) -> anyhow::Result<()> {
+    // Chunks are kept next to the destination until they are joined, so that a failed download
+    // can be resumed
+    let chunks_dir = with_suffix(destination_path, ".chunks");
+
+    fs::create_dir_all(&chunks_dir).await?;
+
+    let chunks: Vec<(PathBuf, u64, u64)> = (0..total_bytes)
+      .step_by(self.chunk_size as usize)
+      .enumerate()
+      .map(|(index, offset)| {
+        let length = self.chunk_size.min(total_bytes - offset);
+
+        (
+          chunks_dir.join(format!("{:05}.part", index)),
+          offset,
+          length,
+        )
+      })
+      .collect();
+
+    let progress = ByteProgress::new(stream, Some(total_bytes));
+
+    for (path, _, length) in &chunks {
+      progress.add(file_len(path).await.min(*length));
+    }
+
+    let progress = &progress;
+
+    futures::stream::iter(chunks.clone())
+      .map(|(path, offset, length)| async move {
+        self
+          .download_range(&path, offset, Some(length), progress)
+          .await?;
+
+        let downloaded = file_len(&path).await;
+
+        if downloaded != length {
+          anyhow::bail!(
+            "Incomplete chunk {:?}: {}/{} bytes",
+            path,
+            downloaded,
+            length
+          );
+        }
+
+        Ok(())
+      })
+      .buffer_unordered(connections)
+      .try_collect::<Vec<_>>()
+      .await?;
+
+    progress.report();
+
+    let part_path = with_suffix(destination_path, ".part");
+    let mut file = fs::File::create(&part_path).await?;
+
+    for (path, _, _) in &chunks {
+      let mut chunk = fs::File::open(path).await?;
+      tokio::io::copy(&mut chunk, &mut file).await?;
+    }
+
+    file.flush().await?;
+    fs::rename(&part_path, destination_path).await?;
+    fs::remove_dir_all(&chunks_dir).await?;
+
+    Ok(())
+  }
+
+  /// Downloads `length` bytes (up to the end without it) of the media from `offset` into `path`,
+  /// continuing from the bytes already in `path`.
+  async fn download_range(
+    &self,
+    path: &Path,
+    offset: u64,
+    length: Option<u64>,
+    progress: &ByteProgress<'_>,
+  ) -> anyhow::Result<()> {
+    let http_client = &self.http_client;
+    let url = self.url.as_str();
+
+    http_client
+      .retry_policy()
+      .run_with_notify(
+        url,
+        || async {
+          let existing = file_len(path).await;
+
+          if length.is_some_and(|length| existing >= length) {
+            return Ok(());
+          }
+
+          let start = offset + existing;
+          let mut request = http_client.media_client().get(url);
+
+          if self.media.accepts_ranges && (start > 0 || length.is_some()) {
+            let range = match length {
+              Some(length) => format!("bytes={}-{}", start, offset + length - 1),
+              None => format!("bytes={}-", start),
+            };
+
+            request = request.header(RANGE, range);
+          }
+
+          let mut res = http_client.send(request).await?.error_for_status()?;
+
+          let mut file = if res.status() == StatusCode::PARTIAL_CONTENT {
+            fs::OpenOptions::new()
+              .create(true)
+              .append(true)
+              .open(path)
+              .await?
+          } else if offset == 0 {
+            // The whole media is sent, start over
+            progress.sub(existing);
+            fs::File::create(path).await?
+          } else {
+            anyhow::bail!("Range request of {} is not supported by the server", url);
+          };
+
+          while let Some(chunk) = res.chunk().await? {
+            file.write_all(&chunk).await?;
+            progress.add(chunk.len() as u64);
+          }
+
+          file.flush().await?;
+
+          Ok(())
+        },
+        |message| progress.stream.retrying(message),
+      )
+      .await
+  }
+}
+
+struct ByteProgress<'a> {
+  stream: &'a DownloadProgressStream,
+  total_bytes: Option<u64>,
+  downloaded_bytes: AtomicU64,
+  reported_bytes: AtomicU64,
+}
+
+impl<'a> ByteProgress<'a> {
+  fn new(stream: &'a DownloadProgressStream, total_bytes: Option<u64>) -> Self {
+    Self {
+      stream,
+      total_bytes,
+      downloaded_bytes: AtomicU64::new(0),
+      reported_bytes: AtomicU64::new(0),
+    }
+  }
+
+  fn add(&self, bytes: u64) {
+    let downloaded = self.downloaded_bytes.fetch_add(bytes, Ordering::SeqCst) + bytes;
+    let reported = self.reported_bytes.load(Ordering::SeqCst);
+
+    if downloaded >= reported + PROGRESS_INTERVAL
+      && self
+        .reported_bytes
+        .compare_exchange(reported, downloaded, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+    {
+      self.stream.bytes_downloaded(downloaded, self.total_bytes);
+    }
+  }
+
+  fn sub(&self, bytes: u64) {
+    self.downloaded_bytes.fetch_sub(bytes, Ordering::SeqCst);
+    self.reported_bytes.store(0, Ordering::SeqCst);
+  }
+
+  fn report(&self) {
+    let downloaded = self.downloaded_bytes.load(Ordering::SeqCst);
+
+    self.reported_bytes.store(downloaded, Ordering::SeqCst);
+    self.stream.bytes_downloaded(downloaded, self.total_bytes);
+  }
+}
+
+async fn file_len(path: &Path) -> u64 {
+  fs::metadata(path)
+    .await
+    .map(|metadata| metadata.len())
+    .unwrap_or(0)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut path = path.as_os_str().to_owned();
+  path.push(suffix);
+
+  PathBuf::from(path)
+}
+
+fn url_extension(url: &str) -> Option<String> {
+  let url = Url::parse(url).ok()?;
+  let extension = Path::new(url.path()).extension()?.to_str()?;
+
+  Some(extension.to_lowercase())
+}
+
+fn progressive_extension_of_url(url: &str) -> Option<&'static str> {
+  let extension = url_extension(url)?;
+
+  PROGRESSIVE_EXTENSIONS
+    .iter()
+    .find(|known| **known == extension)
+    .copied()
+}
+
+fn extension_of_content_type(content_type: &str) -> Option<&'static str> {
+  let extension = match content_type {
+    "video/mp4" | "application/mp4" => "mp4",
+    "video/x-m4v" => "m4v",
+    "video/x-matroska" => "mkv",
+    "video/webm" => "webm",
+    "video/quicktime" => "mov",
+    "video/x-flv" => "flv",
+    "video/x-msvideo" => "avi",
+    _ => return None,
+  };
+
+  Some(extension)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{sniff_media_source, MediaSource, ProgressiveDownload, ProgressiveMedia};
+  use crate::common::test_server::{http_client, serve_dir, serve_dir_with_routes};
+  use axum::response::IntoResponse;
+
+  fn media_bytes(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+  }
+
+  #[tokio::test]
+  async fn test_sniff_media_source() {
+    let (base_url, _dir) = serve_dir(&[
+      ("sniff.mp4", &media_bytes(4096)),
+      ("sniff.m3u8", b"#EXTM3U\n"),
+      ("sniff.bin", b"unknown"),
+      ("sniff.mpd", b"<MPD/>"),
+    ])
+    .await;
+
+    let http_client = http_client(1);
+
+    assert_eq!(
+      sniff_media_source(&http_client, &format!("{}/sniff.mp4", base_url)).await,
+      MediaSource::Progressive(ProgressiveMedia {
+        total_bytes: Some(4096),
+        accepts_ranges: true,
+        extension: Some("mp4"),
+      })
+    );
+    assert_eq!(
+      sniff_media_source(&http_client, &format!("{}/sniff.m3u8", base_url)).await,
+      MediaSource::Playlist
+    );
+    assert_eq!(
+      sniff_media_source(&http_client, &format!("{}/sniff.bin", base_url)).await,
+      MediaSource::Playlist
+    );
+    assert_eq!(
+      sniff_media_source(&http_client, &format!("{}/sniff.mpd", base_url)).await,
+      MediaSource::Dash
+    );
+  }
+
+  #[tokio::test]
+  async fn test_sniff_media_source_without_head() {
+    let routes = axum::Router::new().route(
+      "/no-head.mp4",
+      axum::routing::get(|method: http::Method| async move {
+        if method == http::Method::HEAD {
+          return (http::StatusCode::METHOD_NOT_ALLOWED, vec![]).into_response();
+        }
+
+        let headers = [
+          (http::header::CONTENT_TYPE, "video/mp4"),
+          (http::header::CONTENT_RANGE, "bytes 0-0/4096"),
+        ];
+
+        (http::StatusCode::PARTIAL_CONTENT, headers, vec![0]).into_response()
+      }),
+    );
+    let (base_url, _dir) = serve_dir_with_routes(&[], routes).await;
+
+    assert_eq!(
+      sniff_media_source(&http_client(1), &format!("{}/no-head.mp4", base_url)).await,
+      MediaSource::Progressive(ProgressiveMedia {
+        total_bytes: Some(4096),
+        accepts_ranges: true,
+        extension: Some("mp4"),
+      })
+    );
+  }
+
+  #[tokio::test]
+  async fn test_download_in_chunks_and_resume() {
+    let content = media_bytes(10_000);
+    let (base_url, dir) = serve_dir(&[("chunks.mp4", &content)]).await;
+
+    let download = ProgressiveDownload {
+      url: format!("{}/chunks.mp4", base_url),
+      http_client: http_client(3),
+      media: ProgressiveMedia {
+        total_bytes: Some(content.len() as u64),
+        accepts_ranges: true,
+        extension: Some("mp4"),
+      },
+      chunk_size: 1024,
+    };
+
+    let destination_path = dir.join("out").join("chunks.mp4");
+
+    // A complete and a partial chunk of an interrupted download
+    let chunks_dir = dir.join("out").join("chunks.mp4.chunks");
+    std::fs::create_dir_all(&chunks_dir).unwrap();
+    std::fs::write(chunks_dir.join("00000.part"), &content[..1024]).unwrap();
+    std::fs::write(chunks_dir.join("00003.part"), &content[3072..3500]).unwrap();
+
+    let stream = stream::Stream::new(Ok);
+    download.run(&destination_path, &stream).await.unwrap();
+
+    assert_eq!(std::fs::read(&destination_path).unwrap(), content);
+    assert!(!chunks_dir.exists());
+  }
+
+  #[tokio::test]
+  async fn test_download_whole_and_resume() {
+    let content = media_bytes(5000);
+    let (base_url, dir) = serve_dir(&[("whole.mp4", &content)]).await;
+
+    let download = ProgressiveDownload {
+      url: format!("{}/whole.mp4", base_url),
+      http_client: http_client(1),
+      media: ProgressiveMedia {
+        total_bytes: Some(content.len() as u64),
+        accepts_ranges: true,
+        extension: Some("mp4"),
+      },
+      chunk_size: 1024,
+    };
+
+    let destination_path = dir.join("out").join("whole.mp4");
+
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    std::fs::write(dir.join("out").join("whole.mp4.part"), &content[..1234]).unwrap();
+
+    let stream = stream::Stream::new(Ok);
+    download.run(&destination_path, &stream).await.unwrap();
+
+    assert_eq!(std::fs::read(&destination_path).unwrap(), content);
+  }
+
+  #[tokio::test]
+  async fn test_download_without_range_support() {
+    let content = media_bytes(3000);
+    let (base_url, dir) = serve_dir(&[("norange.mp4", &content)]).await;
+
+    // Without range support, a stale partial file is discarded
+    let download = ProgressiveDownload {
+      url: format!("{}/norange.mp4", base_url),
+      http_client: http_client(4),
+      media: ProgressiveMedia {
+        total_bytes: None,
+        accepts_ranges: false,
+        extension: Some("mp4"),
+      },
+      chunk_size: 1024,
+    };
+
+    let destination_path = dir.join("out").join("norange.mp4");
+
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    std::fs::write(dir.join("out").join("norange.mp4.part"), b"stale").unwrap();
+
+    let stream = stream::Stream::new(Ok);
+    download.run(&destination_path, &stream).await.unwrap();
+
+    assert_eq!(std::fs::read(&destination_path).unwrap(), content);
+  }
+}
@@ -0,0 +1,133 @@
+use configuration::RateLimitConfig;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Token bucket limiting the requests sent to a channel. Callers that exceed the limit wait in
+/// line for the next token instead of failing.
+pub struct RateLimiter {
+  requests_per_second: f64,
+  burst: f64,
+  bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+  tokens: f64,
+  refilled_at: Instant,
+}
+
+impl RateLimiter {
+  pub fn new(config: &RateLimitConfig) -> anyhow::Result<Self> {
+    if config.requests_per_second.is_nan() || config.requests_per_second <= 0.0 {
+      anyhow::bail!(
+        "Invalid rate limit of {} requests per second",
+        config.requests_per_second
+      );
+    }
+
+    let burst = config.burst.unwrap_or(1).max(1) as f64;
+
+    Ok(Self {
+      requests_per_second: config.requests_per_second,
+      burst,
+      bucket: Mutex::new(Bucket {
+        tokens: burst,
+        refilled_at: Instant::now(),
+      }),
+    })
+  }
+
+  pub async fn acquire(&self) {
+    // The lock is held while waiting, so waiting requests are served in FIFO order
+    let mut bucket = self.bucket.lock().await;
+
+    loop {
+      let now = Instant::now();
+      let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+
+      bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(self.burst);
+      bucket.refilled_at = now;
+
+      if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        return;
+      }
+
+      let wait = (1.0 - bucket.tokens) / self.requests_per_second;
+
+      tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::RateLimiter;
+  use configuration::RateLimitConfig;
+  use std::sync::Arc;
+  use std::time::{Duration, Instant};
+
+  fn rate_limiter(requests_per_second: f64, burst: Option<u32>) -> RateLimiter {
+    RateLimiter::new(&RateLimitConfig {
+      requests_per_second,
+      burst,
+    })
+    .unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_burst_then_pace() {
+    let rate_limiter = rate_limiter(20.0, Some(3));
+    let started_at = Instant::now();
+    let mut acquired_at = vec![];
+
+    for _ in 0..7 {
+      rate_limiter.acquire().await;
+      acquired_at.push(started_at.elapsed());
+    }
+
+    // The burst is served at once
+    assert!(acquired_at[2] < Duration::from_millis(20));
+
+    // Then one request every 50ms
+    for pair in acquired_at[2..].windows(2) {
+      let interval = pair[1] - pair[0];
+      assert!(interval >= Duration::from_millis(45), "{:?}", interval);
+    }
+
+    assert!(acquired_at[6] >= Duration::from_millis(190));
+    assert!(acquired_at[6] < Duration::from_millis(400));
+  }
+
+  #[tokio::test]
+  async fn test_concurrent_requests_share_the_limit() {
+    let rate_limiter = Arc::new(rate_limiter(10.0, None));
+    let started_at = Instant::now();
+
+    let handles: Vec<_> = (0..4)
+      .map(|_| {
+        let rate_limiter = rate_limiter.clone();
+        tokio::spawn(async move { rate_limiter.acquire().await })
+      })
+      .collect();
+
+    for handle in handles {
+      handle.await.unwrap();
+    }
+
+    // One at once, the three others 100ms apart
+    assert!(started_at.elapsed() >= Duration::from_millis(290));
+  }
+
+  #[test]
+  fn test_invalid_rate() {
+    for requests_per_second in [0.0, -1.0, f64::NAN] {
+      let config = RateLimitConfig {
+        requests_per_second,
+        burst: None,
+      };
+
+      assert!(RateLimiter::new(&config).is_err());
+    }
+  }
+}
@@ -0,0 +1,253 @@
+use super::{fetch_playlist, HttpClient};
+use m3u8_rs::{AlternativeMedia, AlternativeMediaType, MasterPlaylist, Playlist, VariantStream};
+use protocol::channel::{MediaRendition, MediaRenditions, RenditionSelection};
+
+/// Lists the alternate audio and subtitles of the media at `url`. Media other than HLS master
+/// playlists have none.
+pub async fn get_media_renditions(
+  http_client: &HttpClient,
+  url: &str,
+) -> anyhow::Result<MediaRenditions> {
+  let playlist = http_client
+    .retry_policy()
+    .run(url, || fetch_playlist(http_client, url))
+    .await;
+
+  match playlist {
+    Ok(Playlist::MasterPlaylist(master_playlist)) => Ok(list_renditions(&master_playlist)),
+    Ok(Playlist::MediaPlaylist(_)) => Ok(MediaRenditions::default()),
+    Err(err) => {
+      // DASH manifests and single files aren't playlists
+      log::info!("No renditions found in {}: {:#}", url, err);
+      Ok(MediaRenditions::default())
+    }
+  }
+}
+
+/// Renditions of `master_playlist` that can be downloaded, i.e. those with their own playlist.
+pub fn list_renditions(master_playlist: &MasterPlaylist) -> MediaRenditions {
+  let of_type = |media_type: AlternativeMediaType| {
+    let mut renditions: Vec<MediaRendition> = vec![];
+
+    let alternatives = master_playlist
+      .alternatives
+      .iter()
+      .filter(|alternative| alternative.media_type == media_type && alternative.uri.is_some());
+
+    for alternative in alternatives {
+      // Groups of different variants usually repeat the same renditions
+      let is_listed = renditions.iter().any(|rendition| {
+        rendition.name == alternative.name && rendition.language == alternative.language
+      });
+
+      if !is_listed {
+        renditions.push(MediaRendition {
+          group_id: alternative.group_id.clone(),
+          name: alternative.name.clone(),
+          language: alternative.language.clone(),
+          default: alternative.default,
+          forced: alternative.forced,
+        });
+      }
+    }
+
+    renditions
+  };
+
+  MediaRenditions {
+    audio: of_type(AlternativeMediaType::Audio),
+    subtitles: of_type(AlternativeMediaType::Subtitles),
+  }
+}
+
+/// Picks the audio rendition muxed with `variant` when one is selected: the selected one, else the
+/// default one of its group. Without a selection, the variant is downloaded as it is. Variants
+/// without an audio group, or whose audio is muxed into the video, have none.
+pub fn select_audio<'a>(
+  master_playlist: &'a MasterPlaylist,
+  variant: &VariantStream,
+  selection: &RenditionSelection,
+) -> Option<&'a AlternativeMedia> {
+  let wanted = selection.audio.as_ref()?;
+  let group_id = variant.audio.as_ref()?;
+
+  let candidates: Vec<&AlternativeMedia> = master_playlist
+    .alternatives
+    .iter()
+    .filter(|alternative| {
+      alternative.media_type == AlternativeMediaType::Audio
+        && &alternative.group_id == group_id
+        && alternative.uri.is_some()
+    })
+    .collect();
+
+  let selected = candidates
+    .iter()
+    .find(|alternative| is_rendition_of(alternative, wanted));
+
+  if selected.is_none() {
+    log::warn!(
+      "No audio rendition {} in group {}, using the default one",
+      wanted,
+      group_id
+    );
+  }
+
+  selected
+    .or_else(|| candidates.iter().find(|alternative| alternative.default))
+    .or_else(|| candidates.first())
+    .copied()
+}
+
+/// Picks the selected subtitles of `variant`, at most one rendition per wanted language or name.
+pub fn select_subtitles<'a>(
+  master_playlist: &'a MasterPlaylist,
+  variant: &VariantStream,
+  selection: &RenditionSelection,
+) -> Vec<&'a AlternativeMedia> {
+  let candidates: Vec<&AlternativeMedia> = master_playlist
+    .alternatives
+    .iter()
+    .filter(|alternative| {
+      alternative.media_type == AlternativeMediaType::Subtitles
+        && alternative.uri.is_some()
+        && variant
+          .subtitles
+          .as_ref()
+          .is_none_or(|group_id| &alternative.group_id == group_id)
+    })
+    .collect();
+
+  let mut selected: Vec<&AlternativeMedia> = vec![];
+
+  for wanted in &selection.subtitles {
+    match candidates
+      .iter()
+      .find(|alternative| is_rendition_of(alternative, wanted))
+    {
+      Some(alternative) if !selected.iter().any(|s| std::ptr::eq(*s, *alternative)) => {
+        selected.push(alternative)
+      }
+      Some(_) => {}
+      None => log::warn!("No subtitles {:?} found", wanted),
+    }
+  }
+
+  selected
+}
+
+fn is_rendition_of(alternative: &AlternativeMedia, wanted: &str) -> bool {
+  alternative.name.eq_ignore_ascii_case(wanted.trim())
+    || alternative
+      .language
+      .as_deref()
+      .is_some_and(|language| is_language(language, wanted))
+}
+
+/// Whether the language tag `language` (e.g. `en-US`) is the `wanted` one, either the same tag
+/// or its primary language (`en`).
+pub fn is_language(language: &str, wanted: &str) -> bool {
+  let wanted = wanted.trim();
+
+  language.eq_ignore_ascii_case(wanted)
+    || language
+      .split('-')
+      .next()
+      .is_some_and(|primary| primary.eq_ignore_ascii_case(wanted))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{list_renditions, select_audio, select_subtitles};
+  use m3u8_rs::parse_master_playlist_res;
+  use protocol::channel::RenditionSelection;
+
+  const MASTER: &str = r#"#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac-hi",NAME="English",LANGUAGE="en",DEFAULT=YES,URI="audio/hi/en.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac-hi",NAME="日本語",LANGUAGE="ja",URI="audio/hi/ja.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac-lo",NAME="English",LANGUAGE="en",DEFAULT=YES,URI="audio/lo/en.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac-lo",NAME="日本語",LANGUAGE="ja",URI="audio/lo/ja.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="English",LANGUAGE="en-US",URI="subs/en.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="English (Forced)",LANGUAGE="en-US",FORCED=YES,URI="subs/en-forced.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="简体中文",LANGUAGE="zh-Hans",URI="subs/zh.m3u8"
+#EXT-X-MEDIA:TYPE=CLOSED-CAPTIONS,GROUP-ID="cc",NAME="CC1",INSTREAM-ID="CC1"
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080,AUDIO="aac-hi",SUBTITLES="subs",CLOSED-CAPTIONS="cc"
+1080p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=854x480,AUDIO="aac-lo",SUBTITLES="subs"
+480p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=400000,RESOLUTION=640x360
+360p.m3u8
+"#;
+
+  #[test]
+  fn test_list_renditions() {
+    let master = parse_master_playlist_res(MASTER.as_bytes()).unwrap();
+
+    let renditions = list_renditions(&master);
+
+    let audio: Vec<_> = renditions
+      .audio
+      .iter()
+      .map(|rendition| (rendition.name.as_str(), rendition.default))
+      .collect();
+    assert_eq!(audio, vec![("English", true), ("日本語", false)]);
+
+    let subtitles: Vec<_> = renditions
+      .subtitles
+      .iter()
+      .map(|rendition| (rendition.language.as_deref(), rendition.forced))
+      .collect();
+    assert_eq!(
+      subtitles,
+      vec![
+        (Some("en-US"), false),
+        (Some("en-US"), true),
+        (Some("zh-Hans"), false)
+      ]
+    );
+  }
+
+  #[test]
+  fn test_select_renditions() {
+    let master = parse_master_playlist_res(MASTER.as_bytes()).unwrap();
+
+    let select = |variant: usize, audio: Option<&str>| {
+      let selection = RenditionSelection {
+        audio: audio.map(str::to_string),
+        subtitles: vec![],
+      };
+
+      select_audio(&master, &master.variants[variant], &selection)
+        .and_then(|alternative| alternative.uri.clone())
+    };
+
+    // Without a selection, the variant is downloaded as it is
+    assert_eq!(select(0, None), None);
+    assert_eq!(select(0, Some("ja")).as_deref(), Some("audio/hi/ja.m3u8"));
+    assert_eq!(
+      select(1, Some("日本語")).as_deref(),
+      Some("audio/lo/ja.m3u8")
+    );
+    // Unknown languages fall back to the default rendition
+    assert_eq!(select(1, Some("fr")).as_deref(), Some("audio/lo/en.m3u8"));
+    // The audio of the variant is muxed into its video
+    assert_eq!(select(2, Some("ja")), None);
+
+    let selection = RenditionSelection {
+      audio: None,
+      subtitles: vec![
+        "zh".to_string(),
+        "English (Forced)".to_string(),
+        "zh-hans".to_string(),
+        "de".to_string(),
+      ],
+    };
+
+    let subtitles: Vec<_> = select_subtitles(&master, &master.variants[0], &selection)
+      .into_iter()
+      .map(|alternative| alternative.uri.clone().unwrap())
+      .collect();
+
+    assert_eq!(subtitles, vec!["subs/zh.m3u8", "subs/en-forced.m3u8"]);
+  }
+}
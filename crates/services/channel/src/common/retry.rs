@@ -0,0 +1,204 @@
+use configuration::RetryConfig;
+use reqwest::StatusCode;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Retries failed requests of a channel with exponential backoff.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+  max_attempts: u32,
+  initial_backoff: Duration,
+  max_backoff: Duration,
+  multiplier: f64,
+  jitter: f64,
+  retry_status_codes: Vec<StatusCode>,
+}
+
+impl RetryPolicy {
+  pub fn new(config: &RetryConfig) -> Self {
+    let retry_status_codes = config
+      .retry_status_codes
+      .iter()
+      .filter_map(|code| StatusCode::from_u16(*code).ok())
+      .collect();
+
+    Self {
+      max_attempts: config.max_attempts.max(1),
+      initial_backoff: Duration::from_millis(config.initial_backoff),
+      max_backoff: Duration::from_millis(config.max_backoff),
+      multiplier: config.multiplier.max(1.0),
+      jitter: config.jitter.clamp(0.0, 1.0),
+      retry_status_codes,
+    }
+  }
+
+  pub async fn run<T, F, Fut>(&self, name: &str, f: F) -> anyhow::Result<T>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+  {
+    self.run_with_notify(name, f, |_| {}).await
+  }
+
+  /// Runs `f` until it succeeds, fails with an error that is not worth retrying, or runs out of
+  /// attempts. `notify` is called with a description of every retry.
+  pub async fn run_with_notify<T, F, Fut, N>(
+    &self,
+    name: &str,
+    mut f: F,
+    mut notify: N,
+  ) -> anyhow::Result<T>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+    N: FnMut(&str),
+  {
+    let mut attempt = 1;
+
+    loop {
+      match f().await {
+        Ok(value) => return Ok(value),
+        Err(err) if attempt < self.max_attempts && self.is_retryable(&err) => {
+          let backoff = self.backoff(attempt);
+          let message = format!(
+            "Attempt {}/{} of {} failed: {:#}, retrying in {:?}",
+            attempt, self.max_attempts, name, err, backoff
+          );
+
+          log::warn!("{}", message);
+          notify(&message);
+
+          tokio::time::sleep(backoff).await;
+          attempt += 1;
+        }
+        Err(err) => return Err(err),
+      }
+    }
+  }
+
+  fn is_retryable(&self, err: &anyhow::Error) -> bool {
+    err
+      .chain()
+      .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+      .any(|e| match e.status() {
+        Some(status) => self.retry_status_codes.contains(&status),
+        None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+      })
+  }
+
+  fn backoff(&self, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1) as i32;
+    let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+    let backoff = backoff.min(self.max_backoff.as_secs_f64());
+
+    // Random factor between `1 - jitter` and `1 + jitter`
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    let factor = 1.0 + self.jitter * (random * 2.0 - 1.0);
+
+    Duration::from_secs_f64(backoff * factor)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::RetryPolicy;
+  use crate::common::test_server::serve_dir_with_routes;
+  use axum::http::StatusCode;
+  use axum::routing::get;
+  use configuration::RetryConfig;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
+  use std::time::Duration;
+
+  fn retry_config() -> RetryConfig {
+    RetryConfig {
+      max_attempts: 3,
+      initial_backoff: 10,
+      max_backoff: 30,
+      multiplier: 2.0,
+      jitter: 0.0,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_backoff_schedule() {
+    let policy = RetryPolicy::new(&RetryConfig {
+      initial_backoff: 100,
+      max_backoff: 500,
+      jitter: 0.0,
+      ..Default::default()
+    });
+
+    let schedule: Vec<u128> = (1..=5)
+      .map(|attempt| policy.backoff(attempt).as_millis())
+      .collect();
+
+    assert_eq!(schedule, vec![100, 200, 400, 500, 500]);
+
+    // Jitter deviates the backoff by a fraction of it at most
+    let policy = RetryPolicy::new(&RetryConfig {
+      initial_backoff: 1000,
+      jitter: 0.2,
+      ..Default::default()
+    });
+
+    for _ in 0..20 {
+      let backoff = policy.backoff(1).as_millis();
+      assert!((800..=1200).contains(&backoff), "{}", backoff);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_retryable_errors() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+
+    let routes = axum::Router::new()
+      .route(
+        "/unavailable",
+        get(move || {
+          counter.fetch_add(1, Ordering::SeqCst);
+          async { StatusCode::SERVICE_UNAVAILABLE }
+        }),
+      )
+      .route(
+        "/slow",
+        get(|| async {
+          tokio::time::sleep(Duration::from_secs(3)).await;
+          "slow"
+        }),
+      );
+    let (base_url, _dir) = serve_dir_with_routes(&[], routes).await;
+
+    let client = reqwest::Client::builder()
+      .timeout(Duration::from_millis(200))
+      .build()
+      .unwrap();
+    let policy = RetryPolicy::new(&retry_config());
+
+    let fetch = |path: &str| {
+      let request = client.get(format!("{}/{}", base_url, path));
+      async move { Ok(request.send().await?.error_for_status()?) }
+    };
+
+    // 5xx responses are retried until the attempts run out
+    assert!(policy
+      .run("unavailable", || fetch("unavailable"))
+      .await
+      .is_err());
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+    let is_retryable =
+      |result: anyhow::Result<reqwest::Response>| policy.is_retryable(&result.unwrap_err());
+
+    assert!(is_retryable(fetch("unavailable").await));
+    assert!(is_retryable(fetch("slow").await));
+    // 4xx responses are not
+    assert!(!is_retryable(fetch("missing").await));
+    // Neither are errors of something else than requests
+    assert!(!policy.is_retryable(&anyhow::anyhow!("Invalid playlist")));
+  }
+}
@@ -0,0 +1,485 @@
+use super::HttpClient;
+use futures::{StreamExt, TryStreamExt};
+use m3u8_rs::{ByteRange, MediaPlaylist};
+use protocol::{DownloadProgressExt, DownloadProgressStream};
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+const SEGMENT_CONCURRENCY: usize = 4;
+// Sources of the files of a segments directory, by file name
+const SOURCES_FILE: &str = "sources.json";
+
+// URL of a file to download, with its `(offset, length)` range, and its local path
+type FileDownload = (String, Option<(u64, u64)>, PathBuf);
+
+/// Downloads the keys, init sections (`#EXT-X-MAP`) and segments of `playlist` into
+/// `segments_dir` through the HTTP client of the channel, and returns a copy of the playlist that
+/// refers to the local files. Byte ranges (`#EXT-X-BYTERANGE`) are requested on their own, so the
+/// local files have no ranges.
+///
+/// Files that were completely downloaded by an earlier attempt from the same source are not
+/// downloaded again.
+pub async fn download_segments(
+  http_client: &HttpClient,
+  playlist: &MediaPlaylist,
+  segments_dir: &Path,
+  stream: &DownloadProgressStream,
+) -> anyhow::Result<MediaPlaylist> {
+  fs::create_dir_all(segments_dir).await?;
+
+  let mut local_playlist = playlist.clone();
+
+  // Keys and init sections, downloaded before the segments
+  let mut shared_downloads: Vec<FileDownload> = vec![];
+
+  // Keys are usually shared by many segments, download each of them only once
+  let mut key_files: HashMap<String, String> = HashMap::new();
+
+  for segment in &mut local_playlist.segments {
+    let Some(uri) = segment.key.as_mut().and_then(|key| key.uri.as_mut()) else {
+      continue;
+    };
+
+    // Leave keys of other schemes (e.g. `data:` or `skd:`) to ffmpeg
+    if !uri.starts_with("http") {
+      continue;
+    }
+
+    let file_name = match key_files.get(uri.as_str()) {
+      Some(file_name) => file_name.clone(),
+      None => {
+        let file_name = format!("{:03}.key", key_files.len());
+
+        shared_downloads.push((uri.clone(), None, segments_dir.join(&file_name)));
+        key_files.insert(uri.clone(), file_name.clone());
+
+        file_name
+      }
+    };
+
+    *uri = file_name;
+  }
+
+  // Init sections of fMP4 segments, which may be shared as well
+  let mut map_files: HashMap<(String, Option<(u64, u64)>), String> = HashMap::new();
+
+  for segment in &mut local_playlist.segments {
+    let Some(map) = segment.map.as_mut() else {
+      continue;
+    };
+
+    let range = map
+      .byte_range
+      .take()
+      .map(|range| (range.offset.unwrap_or(0), range.length));
+
+    let file_name = match map_files.get(&(map.uri.clone(), range)) {
+      Some(file_name) => file_name.clone(),
+      None => {
+        let file_name = format!("init-{:03}.mp4", map_files.len());
+
+        shared_downloads.push((map.uri.clone(), range, segments_dir.join(&file_name)));
+        map_files.insert((map.uri.clone(), range), file_name.clone());
+
+        file_name
+      }
+    };
+
+    map.uri = file_name;
+  }
+
+  // fMP4 segments are only playable after their init section, keep them apart from TS ones
+  let extension = if map_files.is_empty() { "ts" } else { "m4s" };
+
+  let mut downloads = Vec::with_capacity(local_playlist.segments.len());
+
+  let ranges = segment_ranges(playlist)?;
+
+  for (index, (segment, range)) in local_playlist.segments.iter_mut().zip(ranges).enumerate() {
+    let file_name = format!("{:05}.{}", index, extension);
+    let url = std::mem::replace(&mut segment.uri, file_name.clone());
+
+    segment.byte_range = None;
+    downloads.push((url, range, segments_dir.join(file_name)));
+  }
+
+  discard_stale_files(segments_dir, shared_downloads.iter().chain(&downloads)).await?;
+
+  for (url, range, path) in &shared_downloads {
+    download_file(http_client, url, *range, path, stream).await?;
+  }
+
+  let total_segments = downloads.len();
+  let downloaded_segments = AtomicUsize::new(0);
+  let downloaded_segments = &downloaded_segments;
+
+  futures::stream::iter(downloads)
+    .map(|(url, range, path)| async move {
+      download_file(http_client, &url, range, &path, stream).await?;
+
+      let downloaded = downloaded_segments.fetch_add(1, Ordering::SeqCst) + 1;
+
+      stream.segment_downloaded(&format!(
+        "Downloaded segment {}/{}",
+        downloaded, total_segments
+      ));
+
+      Ok::<_, anyhow::Error>(())
+    })
+    .buffer_unordered(SEGMENT_CONCURRENCY)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+  Ok(local_playlist)
+}
+
+/// `(offset, length)` byte ranges (`#EXT-X-BYTERANGE`) of the segments of `playlist`. A range
+/// without an offset follows the range of the previous segment, which is of the same resource.
+pub(super) fn segment_ranges(playlist: &MediaPlaylist) -> anyhow::Result<Vec<Option<(u64, u64)>>> {
+  let mut previous_range: Option<(&str, u64)> = None;
+  let mut ranges = Vec::with_capacity(playlist.segments.len());
+
+  for (index, segment) in playlist.segments.iter().enumerate() {
+    let range = match &segment.byte_range {
+      Some(ByteRange {
+        length,
+        offset: Some(offset),
+      }) => Some((*offset, *length)),
+      Some(ByteRange {
+        length,
+        offset: None,
+      }) => match previous_range {
+        Some((previous_url, end)) if previous_url == segment.uri => Some((end, *length)),
+        _ => anyhow::bail!(
+          "Byte range of segment {} has no offset and doesn't follow another range of {}",
+          index,
+          segment.uri
+        ),
+      },
+      None => None,
+    };
+
+    previous_range = range.map(|(offset, length)| (segment.uri.as_str(), offset + length));
+    ranges.push(range);
+  }
+
+  Ok(ranges)
+}
+
+// Removes the files left by an earlier attempt that were downloaded from other sources, then
+// records the sources of `downloads`. Files are named by their index, which points to other
+// segments once the playlist changes between attempts, e.g. when ads are filtered differently, the
+// media is trimmed differently or another line of the media is picked.
+async fn discard_stale_files(
+  segments_dir: &Path,
+  downloads: impl Iterator<Item = &FileDownload>,
+) -> anyhow::Result<()> {
+  let sources_path = segments_dir.join(SOURCES_FILE);
+
+  let previous_sources: HashMap<String, String> = match fs::read(&sources_path).await {
+    Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+    Err(_) => HashMap::new(),
+  };
+
+  let mut sources = HashMap::new();
+
+  for (url, range, path) in downloads {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let source = match range {
+      Some((offset, length)) => format!("{} {}@{}", url, length, offset),
+      None => url.clone(),
+    };
+
+    if previous_sources.get(file_name.as_ref()) != Some(&source) && fs::try_exists(path).await? {
+      log::info!("Discard {:?} downloaded from another source", path);
+      fs::remove_file(path).await?;
+    }
+
+    sources.insert(file_name.into_owned(), source);
+  }
+
+  fs::write(&sources_path, serde_json::to_vec_pretty(&sources)?).await?;
+
+  Ok(())
+}
+
+/// Downloads `url` into `path`, or only the `(offset, length)` range of it.
+pub(super) async fn download_file(
+  http_client: &HttpClient,
+  url: &str,
+  range: Option<(u64, u64)>,
+  path: &Path,
+  stream: &DownloadProgressStream,
+) -> anyhow::Result<()> {
+  if fs::try_exists(path).await? {
+    log::info!("Skip downloaded file {:?}", path);
+    return Ok(());
+  }
+
+  if let Some((offset, 0)) = range {
+    anyhow::bail!("Empty byte range at {} of {}", offset, url);
+  }
+
+  // Write to a temporary file first, so that an interrupted write is never taken as downloaded
+  let part_path = path.with_extension("part");
+
+  http_client
+    .retry_policy()
+    .run_with_notify(
+      url,
+      || write_response(http_client, url, range, &part_path),
+      |message| stream.retrying(message),
+    )
+    .await?;
+
+  fs::rename(&part_path, path).await?;
+
+  Ok(())
+}
+
+// Writes the response to `url`, or to the range of it, into `path` as it is received
+async fn write_response(
+  http_client: &HttpClient,
+  url: &str,
+  range: Option<(u64, u64)>,
+  path: &Path,
+) -> anyhow::Result<()> {
+  let mut request = http_client.media_client().get(url);
+
+  if let Some((offset, length)) = range {
+    let end = offset + length.saturating_sub(1);
+    request = request.header(RANGE, format!("bytes={}-{}", offset, end));
+  }
+
+  let mut res = http_client.send(request).await?.error_for_status()?;
+
+  // The part of the body that is kept, the server may ignore the range and send the whole file
+  let (mut skipped, mut remaining) = match range {
+    Some((offset, length)) if res.status() != StatusCode::PARTIAL_CONTENT => (offset, Some(length)),
+    _ => (0, None),
+  };
+
+  let mut file = fs::File::create(path).await?;
+
+  while let Some(chunk) = res.chunk().await? {
+    let start = skipped.min(chunk.len() as u64) as usize;
+    skipped -= start as u64;
+
+    let mut chunk = &chunk[start..];
+
+    if let Some(remaining) = remaining.as_mut() {
+      let end = (*remaining).min(chunk.len() as u64) as usize;
+      chunk = &chunk[..end];
+      *remaining -= end as u64;
+    }
+
+    file.write_all(chunk).await?;
+
+    if remaining == Some(0) {
+      break;
+    }
+  }
+
+  file.flush().await?;
+
+  if let (Some((offset, length)), Some(remaining)) = (range, remaining) {
+    if remaining > 0 {
+      let end = offset + length.saturating_sub(1);
+      anyhow::bail!("Range {}-{} is out of {}", offset, end, url);
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{download_file, download_segments};
+  use crate::common::test_server::{http_client, serve_dir, serve_dir_with_routes};
+  use axum::routing::get;
+  use m3u8_rs::{parse_media_playlist_res, MediaPlaylist};
+
+  fn parse_playlist(text: &str) -> MediaPlaylist {
+    parse_media_playlist_res(text.as_bytes()).unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_download_fmp4_byte_range_segments() {
+    // Init section and segments of a single fMP4 file, addressed by byte ranges
+    let content: Vec<u8> = (0..500).map(|i| (i % 251) as u8).collect();
+    let (base_url, dir) = serve_dir(&[("fmp4/stream.mp4", &content)]).await;
+
+    let playlist = parse_playlist(&format!(
+      "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:4
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-MAP:URI=\"{base_url}/fmp4/stream.mp4\",BYTERANGE=\"100@0\"
+#EXTINF:4.0,
+#EXT-X-BYTERANGE:200@100
+{base_url}/fmp4/stream.mp4
+#EXTINF:4.0,
+#EXT-X-BYTERANGE:150
+{base_url}/fmp4/stream.mp4
+#EXTINF:2.0,
+#EXT-X-BYTERANGE:50
+{base_url}/fmp4/stream.mp4
+#EXT-X-ENDLIST
+"
+    ));
+
+    let segments_dir = dir.join("out.segments");
+    let stream = stream::Stream::new(Ok);
+    let local_playlist = download_segments(&http_client(1), &playlist, &segments_dir, &stream)
+      .await
+      .unwrap();
+
+    let map = local_playlist.segments[0].map.as_ref().unwrap();
+
+    assert_eq!(map.uri, "init-000.mp4");
+    assert_eq!(map.byte_range, None);
+    assert_eq!(local_playlist.segments[0].uri, "00000.m4s");
+    assert!(local_playlist
+      .segments
+      .iter()
+      .all(|segment| segment.byte_range.is_none()));
+
+    let read = |name: &str| std::fs::read(segments_dir.join(name)).unwrap();
+
+    assert_eq!(read("init-000.mp4"), &content[..100]);
+    assert_eq!(read("00000.m4s"), &content[100..300]);
+    assert_eq!(read("00001.m4s"), &content[300..450]);
+    assert_eq!(read("00002.m4s"), &content[450..500]);
+
+    let mut playlist_bytes = vec![];
+    local_playlist.write_to(&mut playlist_bytes).unwrap();
+    let playlist_text = String::from_utf8(playlist_bytes).unwrap();
+
+    assert!(playlist_text.contains("#EXT-X-MAP:URI=\"init-000.mp4\""));
+    assert!(!playlist_text.contains("BYTERANGE"));
+  }
+
+  #[tokio::test]
+  async fn test_discard_segments_of_another_playlist() {
+    let (base_url, dir) = serve_dir(&[
+      ("0.ts", b"segment-0".as_slice()),
+      ("ad.ts", b"ad"),
+      ("1.ts", b"segment-1"),
+    ])
+    .await;
+
+    let playlist_of = |segments: &[&str]| {
+      let segments: String = segments
+        .iter()
+        .map(|segment| format!("#EXTINF:4.0,\n{}/{}\n", base_url, segment))
+        .collect();
+
+      parse_playlist(&format!(
+        "#EXTM3U\n#EXT-X-TARGETDURATION:4\n{}#EXT-X-ENDLIST\n",
+        segments
+      ))
+    };
+
+    let segments_dir = dir.join("out.segments");
+    let stream = stream::Stream::new(Ok);
+    let read = |name: &str| std::fs::read(segments_dir.join(name)).unwrap();
+
+    // An attempt that kept the ad, interrupted before the last segment
+    download_segments(
+      &http_client(1),
+      &playlist_of(&["0.ts", "ad.ts"]),
+      &segments_dir,
+      &stream,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(read("00001.ts"), b"ad");
+
+    // The next attempt filters the ad, the segment at its index is downloaded again
+    download_segments(
+      &http_client(1),
+      &playlist_of(&["0.ts", "1.ts"]),
+      &segments_dir,
+      &stream,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(read("00000.ts"), b"segment-0");
+    assert_eq!(read("00001.ts"), b"segment-1");
+  }
+
+  #[tokio::test]
+  async fn test_download_byte_range_without_previous_range() {
+    let (base_url, dir) = serve_dir(&[("norange/stream.ts", b"0123456789")]).await;
+
+    let playlist = parse_playlist(&format!(
+      "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXTINF:4.0,
+#EXT-X-BYTERANGE:5
+{base_url}/norange/stream.ts
+#EXT-X-ENDLIST
+"
+    ));
+
+    let stream = stream::Stream::new(Ok);
+    let result = download_segments(
+      &http_client(1),
+      &playlist,
+      &dir.join("out.segments"),
+      &stream,
+    )
+    .await;
+
+    assert!(result.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_download_range_ignored_by_server() {
+    let content: Vec<u8> = (0..100).collect();
+    let body = content.clone();
+
+    // Answers range requests with the whole file
+    let routes = axum::Router::new().route("/whole.ts", get(move || async move { body }));
+    let (base_url, dir) = serve_dir_with_routes(&[], routes).await;
+
+    let stream = stream::Stream::new(Ok);
+    let url = format!("{}/whole.ts", base_url);
+
+    let path = dir.join("range.ts");
+    download_file(&http_client(1), &url, Some((10, 20)), &path, &stream)
+      .await
+      .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), &content[10..30]);
+
+    let result = download_file(
+      &http_client(1),
+      &url,
+      Some((90, 20)),
+      &dir.join("out-of-file.ts"),
+      &stream,
+    )
+    .await;
+
+    assert!(result.is_err());
+
+    let result = download_file(
+      &http_client(1),
+      &url,
+      Some((10, 0)),
+      &dir.join("empty-range.ts"),
+      &stream,
+    )
+    .await;
+
+    assert!(result.is_err());
+  }
+}
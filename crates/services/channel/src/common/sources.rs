@@ -0,0 +1,546 @@
+use super::{fetch_playlist, segment_ranges, HttpClient, VariantPolicy};
+use chrono::{DateTime, Utc};
+use configuration::SourceSelectionConfig;
+use m3u8_rs::Playlist;
+use parking_lot::Mutex;
+use reqwest::header::RANGE;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const DEFAULT_PROBE_SEGMENTS: usize = 2;
+const DEFAULT_MAX_PROBED: usize = 4;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(20);
+// Hosts probed more recently than this are ranked by their history instead of probed again, e.g.
+// for the other episodes of a batch
+const PROBE_TTL: Duration = Duration::from_secs(30 * 60);
+// Weight of the latest probe in the averages of a host
+const RECENT_WEIGHT: f64 = 0.3;
+
+/// Playlist of a media on one of the sources serving it, e.g. a line of a unified channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceCandidate {
+  pub name: String,
+  pub url: String,
+}
+
+/// Speed of a source, measured by downloading the first segments of its media.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceMeasurement {
+  // Time until the playlist request is answered
+  pub latency: Duration,
+  pub bytes_per_second: f64,
+}
+
+// Probes of the sources served by a host, the averages favor the recent ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct HostStats {
+  probes: u32,
+  failures: u32,
+  // Share of the probes that succeeded
+  health: f64,
+  bytes_per_second: f64,
+  latency_ms: f64,
+  measured_at: Option<DateTime<Utc>>,
+}
+
+impl HostStats {
+  fn record(&mut self, measurement: Option<&SourceMeasurement>) {
+    let first_probe = self.probes == 0;
+    let first_success = self.probes == self.failures;
+
+    self.probes += 1;
+    self.measured_at = Some(Utc::now());
+
+    let success = if measurement.is_some() { 1.0 } else { 0.0 };
+    self.health = average(self.health, success, first_probe);
+
+    match measurement {
+      Some(measurement) => {
+        let latency_ms = measurement.latency.as_secs_f64() * 1000.0;
+
+        self.bytes_per_second = average(
+          self.bytes_per_second,
+          measurement.bytes_per_second,
+          first_success,
+        );
+        self.latency_ms = average(self.latency_ms, latency_ms, first_success);
+      }
+      None => self.failures += 1,
+    }
+  }
+
+  // Expected speed of the host, discounted by how often it failed
+  fn score(&self) -> f64 {
+    self.bytes_per_second * self.health
+  }
+
+  fn is_recent(&self) -> bool {
+    self.measured_at.is_some_and(|measured_at| {
+      (Utc::now() - measured_at)
+        .to_std()
+        .is_ok_and(|age| age < PROBE_TTL)
+    })
+  }
+}
+
+fn average(current: f64, latest: f64, first: bool) -> f64 {
+  if first {
+    latest
+  } else {
+    current * (1.0 - RECENT_WEIGHT) + latest * RECENT_WEIGHT
+  }
+}
+
+/// Probes of the hosts of sources, saved to a JSON file so that later downloads rank the
+/// sources by them.
+pub struct SourceHistory {
+  path: PathBuf,
+  hosts: Mutex<HashMap<String, HostStats>>,
+  // Saves one at a time, so that the last one writes the latest probes
+  saving: tokio::sync::Mutex<()>,
+}
+
+impl SourceHistory {
+  pub fn load(path: PathBuf) -> Self {
+    let hosts = match std::fs::read(&path) {
+      Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+        log::warn!("Ignoring invalid source history {:?}: {}", path, err);
+        HashMap::new()
+      }),
+      Err(_) => HashMap::new(),
+    };
+
+    Self {
+      path,
+      hosts: Mutex::new(hosts),
+      saving: tokio::sync::Mutex::new(()),
+    }
+  }
+
+  /// Sorts `sources` from the fastest host to the slowest one. Hosts that were never probed come
+  /// after the ones that worked and before the ones that always failed.
+  pub fn rank(&self, sources: &mut [SourceCandidate]) {
+    let hosts = self.hosts.lock();
+
+    let rank_of = |source: &SourceCandidate| match hosts.get(&host_of(&source.url)) {
+      Some(stats) if stats.score() > 0.0 => (0, stats.score()),
+      Some(_) => (2, 0.0),
+      None => (1, 0.0),
+    };
+
+    sources.sort_by(|a, b| {
+      let (a, b) = (rank_of(a), rank_of(b));
+
+      a.0
+        .cmp(&b.0)
+        .then(b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal))
+    });
+  }
+
+  /// Expected speed of the host of `source`, if it was probed within the TTL.
+  pub fn recent_score(&self, source: &SourceCandidate) -> Option<f64> {
+    self
+      .hosts
+      .lock()
+      .get(&host_of(&source.url))
+      .filter(|stats| stats.is_recent())
+      .map(HostStats::score)
+  }
+
+  pub fn record(&self, source: &SourceCandidate, measurement: Option<&SourceMeasurement>) {
+    self
+      .hosts
+      .lock()
+      .entry(host_of(&source.url))
+      .or_default()
+      .record(measurement);
+  }
+
+  pub async fn save(&self) {
+    let _saving = self.saving.lock().await;
+
+    let json = match serde_json::to_vec_pretty(&*self.hosts.lock()) {
+      Ok(json) => json,
+      Err(err) => {
+        log::warn!("Failed to serialize source history: {}", err);
+        return;
+      }
+    };
+
+    let path = self.path.clone();
+    let saved = tokio::task::spawn_blocking(move || write_replacing(&path, &json)).await;
+
+    match saved {
+      Ok(Ok(())) => {}
+      Ok(Err(err)) => log::warn!("Failed to save source history {:?}: {}", self.path, err),
+      Err(err) => log::warn!("Failed to save source history {:?}: {}", self.path, err),
+    }
+  }
+}
+
+// Writes a temporary file moved over `path`, so that the history is never left half written
+fn write_replacing(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+  if let Some(dir) = path.parent() {
+    std::fs::create_dir_all(dir)?;
+  }
+
+  let temp_path = path.with_extension("json.tmp");
+
+  std::fs::write(&temp_path, bytes)?;
+  std::fs::rename(&temp_path, path)
+}
+
+// Sources on the same host usually share their CDN
+fn host_of(url: &str) -> String {
+  url::Url::parse(url)
+    .map(|url| url.origin().ascii_serialization())
+    .unwrap_or_else(|_| url.to_string())
+}
+
+/// Picks the source of a media to download. The sources are ranked by their history, then the
+/// best ranked ones are probed one after the other, so that they don't share the bandwidth, and
+/// the fastest of them is picked. Hosts probed within the TTL are compared by their history
+/// instead. If none of them can be probed, the best ranked one is picked.
+pub async fn select_source(
+  http_client: &HttpClient,
+  variant_policy: &VariantPolicy,
+  history: &SourceHistory,
+  config: &SourceSelectionConfig,
+  mut sources: Vec<SourceCandidate>,
+) -> anyhow::Result<SourceCandidate> {
+  anyhow::ensure!(!sources.is_empty(), "No source of the media");
+
+  history.rank(&mut sources);
+
+  let probe_segments = config.probe_segments.unwrap_or(DEFAULT_PROBE_SEGMENTS);
+
+  if sources.len() == 1 || probe_segments == 0 {
+    return Ok(sources.remove(0));
+  }
+
+  let max_probed = config.max_probed.unwrap_or(DEFAULT_MAX_PROBED).max(1);
+  let mut fastest: Option<(usize, f64)> = None;
+  let mut probed = false;
+
+  for (index, source) in sources.iter().enumerate().take(max_probed) {
+    let bytes_per_second = match history.recent_score(source) {
+      Some(score) => {
+        log::debug!(
+          "Source {} ({}) was probed recently",
+          source.name,
+          source.url
+        );
+        Some(score).filter(|score| *score > 0.0)
+      }
+      None => {
+        probed = true;
+        probe_source(http_client, variant_policy, history, source, probe_segments).await
+      }
+    };
+
+    if let Some(bytes_per_second) = bytes_per_second {
+      let is_faster = fastest
+        .as_ref()
+        .is_none_or(|(_, fastest)| bytes_per_second > *fastest);
+
+      if is_faster {
+        fastest = Some((index, bytes_per_second));
+      }
+    }
+  }
+
+  if probed {
+    history.save().await;
+  }
+
+  let index = match fastest {
+    Some((index, _)) => index,
+    None => {
+      log::warn!("No source could be probed, picking the best ranked one");
+      0
+    }
+  };
+
+  Ok(sources.remove(index))
+}
+
+// Measures `source` and records it in the history, returning its speed if it's healthy
+async fn probe_source(
+  http_client: &HttpClient,
+  variant_policy: &VariantPolicy,
+  history: &SourceHistory,
+  source: &SourceCandidate,
+  probe_segments: usize,
+) -> Option<f64> {
+  let probe = measure_source(http_client, variant_policy, &source.url, probe_segments);
+
+  let measurement = match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+    Ok(Ok(measurement)) => {
+      log::info!(
+        "Source {} ({}) answered in {} ms at {:.0} KB/s",
+        source.name,
+        source.url,
+        measurement.latency.as_millis(),
+        measurement.bytes_per_second / 1024.0
+      );
+
+      Some(measurement)
+    }
+    Ok(Err(err)) => {
+      log::warn!(
+        "Source {} ({}) is unhealthy: {:#}",
+        source.name,
+        source.url,
+        err
+      );
+      None
+    }
+    Err(_) => {
+      log::warn!("Probe of source {} ({}) timed out", source.name, source.url);
+      None
+    }
+  };
+
+  history.record(source, measurement.as_ref());
+
+  measurement.map(|measurement| measurement.bytes_per_second)
+}
+
+/// Measures the latency of the playlist at `url` and the throughput of its first `segments`
+/// segments, in the variant that would be downloaded.
+pub async fn measure_source(
+  http_client: &HttpClient,
+  variant_policy: &VariantPolicy,
+  url: &str,
+  segments: usize,
+) -> anyhow::Result<SourceMeasurement> {
+  let requested_at = Instant::now();
+  let playlist = fetch_playlist(http_client, url).await?;
+  let latency = requested_at.elapsed();
+
+  let playlist = match playlist {
+    Playlist::MediaPlaylist(playlist) => playlist,
+    Playlist::MasterPlaylist(master_playlist) => {
+      let variants = master_playlist
+        .variants
+        .iter()
+        .filter(|variant| !variant.is_i_frame);
+
+      let variant = variant_policy
+        .select(variants)
+        .ok_or_else(|| anyhow::anyhow!("No variant to download in {}", url))?;
+
+      match fetch_playlist(http_client, &variant.uri).await? {
+        Playlist::MediaPlaylist(playlist) => playlist,
+        Playlist::MasterPlaylist(_) => {
+          anyhow::bail!("Variant {} is not a media playlist", variant.uri)
+        }
+      }
+    }
+  };
+
+  anyhow::ensure!(!playlist.segments.is_empty(), "No segment in {}", url);
+
+  let started_at = Instant::now();
+  let mut bytes = 0;
+
+  let ranges = segment_ranges(&playlist)?;
+
+  for (segment, range) in playlist.segments.iter().zip(ranges).take(segments.max(1)) {
+    let mut request = http_client.media_client().get(&segment.uri);
+
+    if let Some((offset, length)) = range {
+      let end = offset + length.saturating_sub(1);
+      request = request.header(RANGE, format!("bytes={}-{}", offset, end));
+    }
+
+    let res = http_client.send(request).await?.error_for_status()?;
+    bytes += res.bytes().await?.len();
+  }
+
+  anyhow::ensure!(bytes > 0, "Segments of {} are empty", url);
+
+  let elapsed = started_at.elapsed().as_secs_f64().max(0.001);
+
+  Ok(SourceMeasurement {
+    latency,
+    bytes_per_second: bytes as f64 / elapsed,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{select_source, SourceCandidate, SourceHistory};
+  use crate::common::test_server::{http_client, serve_dir};
+  use crate::common::{download_segments, fetch_playlist, VariantPolicy};
+  use configuration::{DownloadConfig, SourceSelectionConfig};
+  use m3u8_rs::Playlist;
+
+  const MEDIA: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXTINF:10.0,
+0.ts
+#EXTINF:10.0,
+1.ts
+#EXT-X-ENDLIST
+";
+
+  #[tokio::test]
+  async fn test_select_source() {
+    let segment = vec![0x47; 188 * 64];
+
+    let (healthy_url, healthy_dir) = serve_dir(&[
+      ("healthy/index.m3u8", MEDIA.as_bytes()),
+      ("healthy/0.ts", &segment),
+      ("healthy/1.ts", &segment),
+    ])
+    .await;
+    // Its playlist is served, but not its segments
+    let (broken_url, _broken_dir) = serve_dir(&[("broken/index.m3u8", MEDIA.as_bytes())]).await;
+
+    let sources = vec![
+      SourceCandidate {
+        name: "线路1".to_string(),
+        url: format!("{}/broken/index.m3u8", broken_url),
+      },
+      SourceCandidate {
+        name: "线路2".to_string(),
+        url: format!("{}/healthy/index.m3u8", healthy_url),
+      },
+    ];
+
+    let history_path = healthy_dir.join("source-history.json");
+    let variant_policy = VariantPolicy::new(&DownloadConfig::default());
+
+    let source = select_source(
+      &http_client(1),
+      &variant_policy,
+      &SourceHistory::load(history_path.clone()),
+      &SourceSelectionConfig::default(),
+      sources.clone(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(source.name, "线路2");
+
+    // Later downloads rank the healthy source first
+    let mut ranked = sources.clone();
+    SourceHistory::load(history_path.clone()).rank(&mut ranked);
+
+    assert_eq!(ranked[0].name, "线路2");
+
+    // Hosts probed within the TTL aren't probed again, e.g. for the next episode of a batch
+    let history = SourceHistory::load(history_path.clone());
+
+    let source = select_source(
+      &http_client(1),
+      &variant_policy,
+      &history,
+      &SourceSelectionConfig::default(),
+      sources.clone(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(source.name, "线路2");
+    assert!(history.hosts.lock().values().all(|stats| stats.probes == 1));
+    assert!(!history_path.with_extension("json.tmp").exists());
+
+    // Without probes, the best ranked source is picked
+    let config = SourceSelectionConfig {
+      probe_segments: Some(0),
+      ..Default::default()
+    };
+    let history = SourceHistory::load(healthy_dir.join("source-history.json"));
+
+    let source = select_source(&http_client(1), &variant_policy, &history, &config, sources)
+      .await
+      .unwrap();
+
+    assert_eq!(source.name, "线路2");
+  }
+
+  #[tokio::test]
+  async fn test_retry_with_another_line() {
+    // The segments of the second line aren't served yet
+    let (first_url, first_dir) = serve_dir(&[
+      ("index.m3u8", MEDIA.as_bytes()),
+      ("0.ts", b"line-1-segment-0"),
+      ("1.ts", b"line-1-segment-1"),
+    ])
+    .await;
+    let (second_url, second_dir) = serve_dir(&[("index.m3u8", MEDIA.as_bytes())]).await;
+
+    let sources = vec![
+      SourceCandidate {
+        name: "线路1".to_string(),
+        url: format!("{}/index.m3u8", first_url),
+      },
+      SourceCandidate {
+        name: "线路2".to_string(),
+        url: format!("{}/index.m3u8", second_url),
+      },
+    ];
+
+    let http_client = http_client(1);
+    let variant_policy = VariantPolicy::new(&DownloadConfig::default());
+    let segments_dir = first_dir.join("episode.segments");
+    let stream = stream::Stream::new(Ok);
+    let read = |name: &str| std::fs::read(segments_dir.join(name)).unwrap();
+
+    let download = |history_path| {
+      let sources = sources.clone();
+      let variant_policy = &variant_policy;
+      let http_client = &http_client;
+      let segments_dir = &segments_dir;
+      let stream = &stream;
+
+      async move {
+        let source = select_source(
+          http_client,
+          variant_policy,
+          &SourceHistory::load(history_path),
+          &SourceSelectionConfig::default(),
+          sources,
+        )
+        .await
+        .unwrap();
+
+        let Playlist::MediaPlaylist(playlist) =
+          fetch_playlist(http_client, &source.url).await.unwrap()
+        else {
+          panic!("{} is not a media playlist", source.url);
+        };
+
+        download_segments(http_client, &playlist, segments_dir, stream)
+          .await
+          .unwrap();
+
+        source.name
+      }
+    };
+
+    assert_eq!(download(first_dir.join("first.json")).await, "线路1");
+    assert_eq!(read("00000.ts"), b"line-1-segment-0");
+
+    // The first line went down before the retry, which downloads from the second one
+    for index in 0..2 {
+      let name = format!("{}.ts", index);
+
+      std::fs::remove_file(first_dir.join("www").join(&name)).unwrap();
+      std::fs::write(
+        second_dir.join("www").join(&name),
+        format!("line-2-segment-{}", index),
+      )
+      .unwrap();
+    }
+
+    assert_eq!(download(first_dir.join("second.json")).await, "线路2");
+    assert_eq!(read("00000.ts"), b"line-2-segment-0");
+    assert_eq!(read("00001.ts"), b"line-2-segment-1");
+  }
+}
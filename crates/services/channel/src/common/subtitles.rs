@@ -0,0 +1,503 @@
+use super::HttpClient;
+use configuration::SubtitleFormat;
+use futures::{StreamExt, TryStreamExt};
+use m3u8_rs::{AlternativeMedia, MediaPlaylist};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const SUBTITLE_SEGMENT_CONCURRENCY: usize = 4;
+
+// Timestamps of `X-TIMESTAMP-MAP` are MPEG-2 ones, a 90 kHz clock wrapping at 2^33
+const MPEGTS_CLOCK_PER_MS: i64 = 90;
+const MPEGTS_WRAP: i64 = 1 << 33;
+
+/// Downloads the WebVTT segments of subtitle renditions and saves each rendition next to
+/// `destination_path`, named the way media servers pick them up, e.g. `movie.en.srt` or
+/// `movie.en.forced.srt` for `movie.mp4`. Media of which only a part was `kept`, in seconds,
+/// keep the cues shown during that part, timed from its start.
+///
+/// Subtitles that fail to download are skipped, they aren't worth failing the media for.
+pub async fn save_subtitles(
+  http_client: &HttpClient,
+  subtitles: &[(AlternativeMedia, MediaPlaylist)],
+  destination_path: &Path,
+  format: SubtitleFormat,
+  kept: Option<Range<f64>>,
+) -> Vec<PathBuf> {
+  let mut saved_paths = vec![];
+
+  for (rendition, playlist) in subtitles {
+    let path = sidecar_path(destination_path, rendition, format, &saved_paths);
+
+    match save_rendition(http_client, playlist, &path, format, kept.clone()).await {
+      Ok(()) => {
+        log::info!("Saved subtitles {} to {:?}", rendition.name, path);
+        saved_paths.push(path);
+      }
+      Err(err) => log::warn!("Failed to save subtitles {}: {:#}", rendition.name, err),
+    }
+  }
+
+  saved_paths
+}
+
+async fn save_rendition(
+  http_client: &HttpClient,
+  playlist: &MediaPlaylist,
+  path: &Path,
+  format: SubtitleFormat,
+  kept: Option<Range<f64>>,
+) -> anyhow::Result<()> {
+  let urls: Vec<String> = playlist
+    .segments
+    .iter()
+    .map(|segment| segment.uri.clone())
+    .collect();
+
+  let segments: Vec<String> = futures::stream::iter(urls)
+    .map(|url| async move { fetch_text(http_client, &url).await })
+    .buffered(SUBTITLE_SEGMENT_CONCURRENCY)
+    .try_collect()
+    .await?;
+
+  let mut cues = merge_webvtt(&segments);
+
+  if let Some(kept) = kept {
+    cues = keep_cues(cues, kept);
+  }
+
+  if cues.is_empty() {
+    anyhow::bail!("No cues found in {} segments", segments.len());
+  }
+
+  let text = match format {
+    SubtitleFormat::Srt => to_srt(&cues),
+    SubtitleFormat::Vtt => to_webvtt(&cues),
+  };
+
+  fs::write(path, text).await?;
+
+  Ok(())
+}
+
+async fn fetch_text(http_client: &HttpClient, url: &str) -> anyhow::Result<String> {
+  http_client
+    .retry_policy()
+    .run(url, || async {
+      let request = http_client.media_client().get(url);
+      let res = http_client.send(request).await?.error_for_status()?;
+
+      Ok(res.text().await?)
+    })
+    .await
+}
+
+fn sidecar_path(
+  destination_path: &Path,
+  rendition: &AlternativeMedia,
+  format: SubtitleFormat,
+  taken: &[PathBuf],
+) -> PathBuf {
+  let stem = destination_path
+    .file_stem()
+    .unwrap_or_default()
+    .to_string_lossy();
+
+  let mut label: String = rendition
+    .language
+    .as_deref()
+    .unwrap_or(&rendition.name)
+    .chars()
+    .map(|c| {
+      if c.is_alphanumeric() || c == '-' {
+        c
+      } else {
+        '_'
+      }
+    })
+    .collect();
+
+  if rendition.forced {
+    label.push_str(".forced");
+  }
+
+  let extension = match format {
+    SubtitleFormat::Srt => "srt",
+    SubtitleFormat::Vtt => "vtt",
+  };
+
+  let mut path = destination_path.with_file_name(format!("{}.{}.{}", stem, label, extension));
+  let mut index = 2;
+
+  // Several renditions of the same language, e.g. SDH ones
+  while taken.contains(&path) {
+    path = destination_path.with_file_name(format!("{}.{}.{}.{}", stem, label, index, extension));
+    index += 1;
+  }
+
+  path
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cue {
+  start: i64,
+  end: i64,
+  settings: String,
+  text: String,
+}
+
+/// Merges the WebVTT segments of a subtitle playlist into a single list of cues, in milliseconds
+/// from the start of the media. Cues spanning several segments are repeated in each of them and
+/// kept once.
+fn merge_webvtt(segments: &[String]) -> Vec<Cue> {
+  let mut cues: Vec<Cue> = vec![];
+  let mut seen: HashSet<(i64, i64, String)> = HashSet::new();
+  // Offset of the first segment with a timestamp map, which the media starts at
+  let mut base_offset: Option<i64> = None;
+
+  for segment in segments {
+    let (timestamp_map, segment_cues) = parse_webvtt(segment);
+
+    let shift = match timestamp_map {
+      Some((mpegts, local)) => {
+        let base = *base_offset.get_or_insert(mpegts);
+        let mut mpegts = mpegts;
+
+        if mpegts < base {
+          mpegts += MPEGTS_WRAP;
+        }
+
+        (mpegts - base) / MPEGTS_CLOCK_PER_MS - local
+      }
+      None => 0,
+    };
+
+    for mut cue in segment_cues {
+      cue.start = (cue.start + shift).max(0);
+      cue.end = (cue.end + shift).max(0);
+
+      if seen.insert((cue.start, cue.end, cue.text.clone())) {
+        cues.push(cue);
+      }
+    }
+  }
+
+  cues.sort_by_key(|cue| cue.start);
+  cues
+}
+
+// Cues shown during `kept`, in seconds of the media, timed from its start
+fn keep_cues(cues: Vec<Cue>, kept: Range<f64>) -> Vec<Cue> {
+  let start = (kept.start * 1000.0).round() as i64;
+  let end = (kept.end * 1000.0).round() as i64;
+
+  cues
+    .into_iter()
+    .filter(|cue| cue.end > start && cue.start < end)
+    .map(|cue| Cue {
+      start: cue.start.max(start) - start,
+      end: cue.end.min(end) - start,
+      ..cue
+    })
+    .collect()
+}
+
+/// Parses a WebVTT file, returning its `X-TIMESTAMP-MAP` (MPEG-2 timestamp, local time in
+/// milliseconds) and its cues.
+fn parse_webvtt(text: &str) -> (Option<(i64, i64)>, Vec<Cue>) {
+  let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+  let mut timestamp_map = None;
+  let mut cues = vec![];
+
+  for (index, block) in text.split("\n\n").enumerate() {
+    let block = block.trim_matches('\n');
+
+    if index == 0 && block.starts_with("WEBVTT") {
+      timestamp_map = block
+        .lines()
+        .find_map(|line| line.strip_prefix("X-TIMESTAMP-MAP="))
+        .and_then(parse_timestamp_map);
+      continue;
+    }
+
+    let mut lines = block.lines();
+    // The identifier of a cue is optional
+    let Some(timing) = lines.by_ref().find(|line| line.contains("-->")) else {
+      continue;
+    };
+
+    let Some((start, rest)) = timing.split_once("-->") else {
+      continue;
+    };
+    let mut rest = rest.trim().splitn(2, char::is_whitespace);
+    let end = rest.next().unwrap_or_default();
+    let settings = rest.next().unwrap_or_default().trim().to_string();
+
+    let (Some(start), Some(end)) = (parse_timestamp(start.trim()), parse_timestamp(end)) else {
+      continue;
+    };
+
+    let text = lines.collect::<Vec<_>>().join("\n");
+
+    if !text.trim().is_empty() {
+      cues.push(Cue {
+        start,
+        end,
+        settings,
+        text,
+      });
+    }
+  }
+
+  (timestamp_map, cues)
+}
+
+// e.g. `MPEGTS:900000,LOCAL:00:00:00.000`
+fn parse_timestamp_map(value: &str) -> Option<(i64, i64)> {
+  let mut mpegts = None;
+  let mut local = None;
+
+  for part in value.split(',') {
+    match part.trim().split_once(':') {
+      Some(("MPEGTS", value)) => mpegts = value.parse().ok(),
+      Some(("LOCAL", value)) => local = parse_timestamp(value),
+      _ => {}
+    }
+  }
+
+  Some((mpegts?, local?))
+}
+
+// `hh:mm:ss.ttt` or `mm:ss.ttt`, in milliseconds
+fn parse_timestamp(value: &str) -> Option<i64> {
+  let (time, millis) = value.split_once('.')?;
+  let mut seconds = 0;
+
+  for part in time.split(':') {
+    seconds = seconds * 60 + part.parse::<i64>().ok()?;
+  }
+
+  Some(seconds * 1000 + millis.parse::<i64>().ok()?)
+}
+
+fn format_timestamp(millis: i64, separator: char) -> String {
+  format!(
+    "{:02}:{:02}:{:02}{}{:03}",
+    millis / 3_600_000,
+    millis / 60_000 % 60,
+    millis / 1000 % 60,
+    separator,
+    millis % 1000
+  )
+}
+
+fn to_webvtt(cues: &[Cue]) -> String {
+  let mut output = String::from("WEBVTT\n\n");
+
+  for cue in cues {
+    output.push_str(&format_timestamp(cue.start, '.'));
+    output.push_str(" --> ");
+    output.push_str(&format_timestamp(cue.end, '.'));
+
+    if !cue.settings.is_empty() {
+      output.push(' ');
+      output.push_str(&cue.settings);
+    }
+
+    output.push('\n');
+    output.push_str(&cue.text);
+    output.push_str("\n\n");
+  }
+
+  output
+}
+
+fn to_srt(cues: &[Cue]) -> String {
+  let mut output = String::new();
+
+  for (index, cue) in cues.iter().enumerate() {
+    output.push_str(&format!(
+      "{}\n{} --> {}\n{}\n\n",
+      index + 1,
+      format_timestamp(cue.start, ','),
+      format_timestamp(cue.end, ','),
+      strip_webvtt_tags(&cue.text)
+    ));
+  }
+
+  output
+}
+
+// SRT players only know about `<b>`, `<i>` and `<u>`, e.g. voice spans (`<v Bob>`) and
+// classes (`<c.yellow>`) of WebVTT are dropped
+fn strip_webvtt_tags(text: &str) -> String {
+  let mut output = String::with_capacity(text.len());
+  let mut rest = text;
+
+  while let Some(open) = rest.find('<') {
+    output.push_str(&rest[..open]);
+
+    let Some(close) = rest[open..].find('>') else {
+      break;
+    };
+
+    let tag = &rest[open..open + close + 1];
+    let name = tag
+      .trim_start_matches(['<', '/'])
+      .trim_end_matches('>')
+      .split(['.', ' '])
+      .next()
+      .unwrap_or_default();
+
+    if matches!(name, "b" | "i" | "u") {
+      output.push_str(tag);
+    }
+
+    rest = &rest[open + close + 1..];
+  }
+
+  output.push_str(rest);
+  output
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{keep_cues, merge_webvtt, save_subtitles, sidecar_path, to_srt, to_webvtt};
+  use crate::common::test_server::{http_client, serve_dir};
+  use configuration::SubtitleFormat;
+  use m3u8_rs::{parse_master_playlist_res, parse_media_playlist_res};
+  use std::path::{Path, PathBuf};
+
+  const SEGMENTS: [&str; 3] = [
+    "WEBVTT\r\nX-TIMESTAMP-MAP=MPEGTS:900000,LOCAL:00:00:00.000\r\n\r\n1\r\n00:00:01.000 --> 00:00:03.500 line:90%\r\n<v Alice>Hello</v>\r\n\r\n00:00:05.000 --> 00:00:07.000\r\nA cue across <i>segments</i>\r\n",
+    "WEBVTT\nX-TIMESTAMP-MAP=LOCAL:00:00:00.000,MPEGTS:900000\n\nNOTE repeated cue\n\n00:00:05.000 --> 00:00:07.000\nA cue across <i>segments</i>\n\n00:00:06.500 --> 00:00:08.000\nSecond <c.yellow>segment</c>\n",
+    // Cues relative to the segment, which starts 60s after the first one
+    "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:6300000,LOCAL:00:00:00.000\n\n00:02.250 --> 00:03.000\nThird segment\n",
+  ];
+
+  #[test]
+  fn test_merge_webvtt() {
+    let segments: Vec<String> = SEGMENTS.iter().map(|s| s.to_string()).collect();
+
+    let cues = merge_webvtt(&segments);
+
+    let timings: Vec<(i64, i64)> = cues.iter().map(|cue| (cue.start, cue.end)).collect();
+    assert_eq!(
+      timings,
+      vec![(1000, 3500), (5000, 7000), (6500, 8000), (62250, 63000)]
+    );
+
+    assert_eq!(
+      to_srt(&cues),
+      "1\n00:00:01,000 --> 00:00:03,500\nHello\n\n\
+       2\n00:00:05,000 --> 00:00:07,000\nA cue across <i>segments</i>\n\n\
+       3\n00:00:06,500 --> 00:00:08,000\nSecond segment\n\n\
+       4\n00:01:02,250 --> 00:01:03,000\nThird segment\n\n"
+    );
+
+    assert!(to_webvtt(&cues)
+      .starts_with("WEBVTT\n\n00:00:01.000 --> 00:00:03.500 line:90%\n<v Alice>Hello</v>\n\n"));
+
+    // Cues of a trimmed media are cut to the part of it that was kept
+    let kept: Vec<(i64, i64)> = keep_cues(cues, 6.0..62.5)
+      .iter()
+      .map(|cue| (cue.start, cue.end))
+      .collect();
+    assert_eq!(kept, vec![(0, 1000), (500, 2000), (56250, 56500)]);
+  }
+
+  #[test]
+  fn test_sidecar_path() {
+    let master = parse_master_playlist_res(
+      br#"#EXTM3U
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="English",LANGUAGE="en",URI="en.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="English (Forced)",LANGUAGE="en",FORCED=YES,URI="en-forced.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="Director's commentary",URI="commentary.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=800000,SUBTITLES="subs"
+480p.m3u8
+"#,
+    )
+    .unwrap();
+
+    let destination_path = Path::new("/downloads/123-1.mp4");
+    let mut taken: Vec<PathBuf> = vec![];
+
+    let mut path_of = |index: usize| {
+      let path = sidecar_path(
+        destination_path,
+        &master.alternatives[index],
+        SubtitleFormat::Srt,
+        &taken,
+      );
+      taken.push(path.clone());
+      path
+    };
+
+    assert_eq!(path_of(0), Path::new("/downloads/123-1.en.srt"));
+    assert_eq!(path_of(1), Path::new("/downloads/123-1.en.forced.srt"));
+    assert_eq!(
+      path_of(2),
+      Path::new("/downloads/123-1.Director_s_commentary.srt")
+    );
+    assert_eq!(path_of(0), Path::new("/downloads/123-1.en.2.srt"));
+  }
+
+  #[tokio::test]
+  async fn test_save_subtitles() {
+    let (base_url, dir) = serve_dir(&[
+      ("subs/0.vtt", SEGMENTS[0].as_bytes()),
+      ("subs/1.vtt", SEGMENTS[1].as_bytes()),
+    ])
+    .await;
+
+    let master = parse_master_playlist_res(
+      br#"#EXTM3U
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="English",LANGUAGE="en",URI="en.m3u8"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="Deutsch",LANGUAGE="de",URI="de.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=800000,SUBTITLES="subs"
+480p.m3u8
+"#,
+    )
+    .unwrap();
+
+    let playlist_of = |segments: &[&str]| {
+      let mut text = String::from("#EXTM3U\n#EXT-X-TARGETDURATION:6\n");
+
+      for segment in segments {
+        text.push_str(&format!("#EXTINF:6.0,\n{}/subs/{}\n", base_url, segment));
+      }
+
+      parse_media_playlist_res(text.as_bytes()).unwrap()
+    };
+
+    let subtitles = vec![
+      (
+        master.alternatives[0].clone(),
+        playlist_of(&["0.vtt", "1.vtt"]),
+      ),
+      // Not served, skipped
+      (
+        master.alternatives[1].clone(),
+        playlist_of(&["missing.vtt"]),
+      ),
+    ];
+
+    let destination_path = dir.join("movie.mp4");
+
+    let saved = save_subtitles(
+      &http_client(1),
+      &subtitles,
+      &destination_path,
+      SubtitleFormat::Vtt,
+      None,
+    )
+    .await;
+
+    assert_eq!(saved, vec![dir.join("movie.en.vtt")]);
+
+    let text = std::fs::read_to_string(&saved[0]).unwrap();
+    assert!(text.contains("00:00:06.500 --> 00:00:08.000\nSecond <c.yellow>segment</c>"));
+  }
+}
@@ -0,0 +1,261 @@
+use super::{run_ffmpeg, HttpClient};
+use protocol::channel::MediaTags;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+// Containers attaching cover art, others get the metadata only
+const COVER_EXTENSIONS: [&str; 5] = ["mp4", "m4v", "m4a", "mov", "mkv"];
+
+/// Tags written into a media by the ffmpeg pass producing it, along with its poster, which is
+/// downloaded beforehand so that it goes through the HTTP client of the channel.
+pub struct MediaTagging {
+  tags: MediaTags,
+  cover_path: Option<PathBuf>,
+}
+
+impl MediaTagging {
+  /// Downloads the poster of `tags` next to `destination_path`, if its container attaches cover
+  /// art. A poster that can't be downloaded is left out.
+  pub async fn prepare(
+    http_client: &HttpClient,
+    tags: &MediaTags,
+    destination_path: &Path,
+  ) -> Self {
+    let has_cover = destination_path
+      .extension()
+      .and_then(|extension| extension.to_str())
+      .is_some_and(|extension| COVER_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+
+    let cover_path = match &tags.poster_url {
+      Some(poster_url) if has_cover => {
+        let cover_path = destination_path.with_extension("poster");
+
+        match download_poster(http_client, poster_url, &cover_path).await {
+          Ok(()) => Some(cover_path),
+          Err(err) => {
+            log::warn!("Failed to download the poster {}: {:#}", poster_url, err);
+            None
+          }
+        }
+      }
+      _ => None,
+    };
+
+    Self {
+      tags: tags.clone(),
+      cover_path,
+    }
+  }
+
+  /// Poster attached as cover art, to be added as an input after those of the media. The media
+  /// are then mapped explicitly, after `map_args`.
+  pub fn cover_path(&self) -> Option<&Path> {
+    self.cover_path.as_deref()
+  }
+
+  /// Maps the cover read from input `cover_input` as the first stream of the output.
+  pub fn map_args(&self, cover_input: usize) -> Vec<OsString> {
+    match self.cover_path {
+      Some(_) => vec!["-map".into(), format!("{}:v:0", cover_input).into()],
+      None => vec![],
+    }
+  }
+
+  /// ffmpeg options following the codec ones of the output, which they override for the cover.
+  pub fn output_args(&self) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec![];
+
+    if self.cover_path.is_some() {
+      args.extend(["-c:0", "mjpeg", "-disposition:0", "attached_pic"].map(OsString::from));
+    }
+
+    for (key, value) in metadata_of(&self.tags) {
+      args.extend(["-metadata".into(), format!("{}={}", key, value).into()]);
+    }
+
+    args
+  }
+
+  pub async fn remove_cover(&self) {
+    if let Some(cover_path) = &self.cover_path {
+      fs::remove_file(cover_path).await.ok();
+    }
+  }
+
+  /// Writes the tags into the media file at `path`, for media saved as they are served instead
+  /// of produced by a remux. The video is left out of `audio_only` media, so that the cover is
+  /// their only one.
+  pub async fn write_into(&self, path: &Path, audio_only: bool) -> anyhow::Result<()> {
+    // ffmpeg can't write to its input, the output is moved in place once done
+    let temp_path = path.with_extension(format!(
+      "tagged.{}",
+      path.extension().unwrap_or_default().to_string_lossy()
+    ));
+
+    let mut args: Vec<OsString> = vec!["-i".into(), path.into()];
+
+    if let Some(cover_path) = &self.cover_path {
+      args.extend(["-i".into(), cover_path.into()]);
+    }
+
+    args.extend(self.map_args(1));
+    let streams = if audio_only { "0:a" } else { "0" };
+
+    args.extend(["-map", streams, "-c", "copy"].map(OsString::from));
+    args.extend(self.output_args());
+    args.extend(["-y".into(), temp_path.clone().into()]);
+
+    if let Err(err) = run_ffmpeg(args).await {
+      fs::remove_file(&temp_path).await.ok();
+      return Err(err);
+    }
+
+    fs::rename(&temp_path, path).await?;
+
+    Ok(())
+  }
+}
+
+async fn download_poster(http_client: &HttpClient, url: &str, path: &Path) -> anyhow::Result<()> {
+  let bytes = http_client
+    .retry_policy()
+    .run(url, || async {
+      let request = http_client.client().get(url);
+      let res = http_client.send(request).await?.error_for_status()?;
+
+      Ok(res.bytes().await?)
+    })
+    .await?;
+
+  fs::write(path, bytes).await?;
+
+  Ok(())
+}
+
+fn metadata_of(tags: &MediaTags) -> Vec<(&'static str, String)> {
+  let mut metadata = vec![("title", tags.title.clone())];
+
+  if let Some(year) = tags.year {
+    metadata.push(("date", year.to_string()));
+  }
+
+  if !tags.description.is_empty() {
+    metadata.push(("description", tags.description.clone()));
+    metadata.push(("comment", tags.description.clone()));
+  }
+
+  match &tags.episode {
+    Some(episode) => metadata.extend([
+      ("show", episode.show.clone()),
+      ("season_number", episode.season.to_string()),
+      ("episode_sort", episode.episode.to_string()),
+      (
+        "episode_id",
+        format!("S{:02}E{:02}", episode.season, episode.episode),
+      ),
+      // iTunes media types of TV shows and movies
+      ("media_type", "10".to_string()),
+    ]),
+    None => metadata.push(("media_type", "9".to_string())),
+  }
+
+  metadata
+}
+
+#[cfg(test)]
+mod tests {
+  use super::MediaTagging;
+  use crate::common::test_server::{http_client, serve_dir};
+  use protocol::channel::{EpisodeTags, MediaTags};
+  use std::ffi::OsString;
+
+  fn tags(poster_url: Option<String>) -> MediaTags {
+    MediaTags {
+      title: "庆余年 S02E03".to_string(),
+      year: Some(2024),
+      description: "范闲归来。".to_string(),
+      poster_url,
+      episode: Some(EpisodeTags {
+        show: "庆余年".to_string(),
+        season: 2,
+        episode: 3,
+      }),
+    }
+  }
+
+  fn strings(args: Vec<OsString>) -> Vec<String> {
+    args
+      .into_iter()
+      .map(|arg| arg.to_string_lossy().into_owned())
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn test_media_tagging() {
+    let (base_url, dir) = serve_dir(&[("tags/poster.jpg", b"jpeg".as_slice())]).await;
+    let destination_path = dir.join("9104-3.mp4");
+
+    let tagging = MediaTagging::prepare(
+      &http_client(1),
+      &tags(Some(format!("{}/tags/poster.jpg", base_url))),
+      &destination_path,
+    )
+    .await;
+
+    let cover_path = tagging.cover_path().unwrap();
+    assert_eq!(std::fs::read(cover_path).unwrap(), b"jpeg");
+    assert_eq!(strings(tagging.map_args(1)), ["-map", "1:v:0"]);
+
+    let args = strings(tagging.output_args());
+    let metadata: Vec<&str> = args
+      .windows(2)
+      .filter(|pair| pair[0] == "-metadata")
+      .map(|pair| pair[1].as_str())
+      .collect();
+
+    assert_eq!(
+      &args[..4],
+      ["-c:0", "mjpeg", "-disposition:0", "attached_pic"]
+    );
+    assert_eq!(
+      metadata,
+      vec![
+        "title=庆余年 S02E03",
+        "date=2024",
+        "description=范闲归来。",
+        "comment=范闲归来。",
+        "show=庆余年",
+        "season_number=2",
+        "episode_sort=3",
+        "episode_id=S02E03",
+        "media_type=10",
+      ]
+    );
+
+    tagging.remove_cover().await;
+    assert!(!destination_path.with_extension("poster").exists());
+
+    // MPEG-TS carries no cover art, its poster isn't downloaded
+    let tagging = MediaTagging::prepare(
+      &http_client(1),
+      &tags(Some(format!("{}/tags/poster.jpg", base_url))),
+      &dir.join("9104-3.ts"),
+    )
+    .await;
+
+    assert!(tagging.cover_path().is_none());
+    assert!(tagging.map_args(1).is_empty());
+    assert!(!strings(tagging.output_args()).contains(&"attached_pic".to_string()));
+
+    // Posters that can't be downloaded are left out
+    let tagging = MediaTagging::prepare(
+      &http_client(1),
+      &tags(Some(format!("{}/tags/missing.jpg", base_url))),
+      &destination_path,
+    )
+    .await;
+
+    assert!(tagging.cover_path().is_none());
+  }
+}
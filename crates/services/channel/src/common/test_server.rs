@@ -0,0 +1,85 @@
+use super::HttpClient;
+use configuration::{HttpClientConfig, RetryConfig};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tower_http::services::ServeDir;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary directory of a test, removed once dropped, even when the test fails.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+  fn new() -> Self {
+    let dir = std::env::temp_dir().join(format!(
+      "serve-{}-{}",
+      std::process::id(),
+      NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    std::fs::create_dir_all(&dir).unwrap();
+
+    Self(dir)
+  }
+}
+
+impl Deref for TestDir {
+  type Target = Path;
+
+  fn deref(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl Drop for TestDir {
+  fn drop(&mut self) {
+    std::fs::remove_dir_all(&self.0).ok();
+  }
+}
+
+/// Serves `files` from a temporary directory with range support, and returns the base URL of the
+/// server along with the directory. The served files are in the `www` sub directory, the rest of
+/// it is free for the outputs of a test.
+pub async fn serve_dir(files: &[(&str, &[u8])]) -> (String, TestDir) {
+  serve_dir_with_routes(files, axum::Router::new()).await
+}
+
+/// Same as `serve_dir`, with `routes` taking precedence over the files.
+pub async fn serve_dir_with_routes(
+  files: &[(&str, &[u8])],
+  routes: axum::Router,
+) -> (String, TestDir) {
+  let dir = TestDir::new();
+
+  for (name, content) in files {
+    let path = dir.join("www").join(name);
+
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+  }
+
+  let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let app = routes.fallback_service(ServeDir::new(dir.join("www")));
+
+  tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+  (format!("http://{}", addr), dir)
+}
+
+pub fn http_client(progressive_connections: usize) -> HttpClient {
+  let config = HttpClientConfig {
+    progressive_connections: Some(progressive_connections),
+    ..Default::default()
+  };
+
+  HttpClient::new(
+    "http://127.0.0.1",
+    http::Version::HTTP_11,
+    &config,
+    &RetryConfig::default(),
+    None,
+  )
+  .unwrap()
+}
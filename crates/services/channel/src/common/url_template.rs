@@ -0,0 +1,13 @@
+/// Renders the URL template of a configured channel endpoint. `{base_url}` is replaced with the
+/// base URL of the channel, and every `{name}` of `params` with its URL encoded value.
+pub fn render_url_template(template: &str, base_url: &str, params: &[(&str, &str)]) -> String {
+  let mut url = template.replace("{base_url}", base_url.trim_end_matches('/'));
+
+  for (name, value) in params {
+    let value: String = url::form_urlencoded::byte_serialize(value.as_bytes()).collect();
+
+    url = url.replace(&format!("{{{}}}", name), &value);
+  }
+
+  url
+}
@@ -0,0 +1,134 @@
+use configuration::{DownloadConfig, VariantPreference};
+
+/// A rendition of a media among which one is downloaded, e.g. a variant stream of an HLS master
+/// playlist or a representation of a DASH manifest.
+pub trait Variant {
+  fn bandwidth(&self) -> u64;
+
+  fn height(&self) -> Option<u32>;
+}
+
+/// Picks the variant to download according to the download settings of the channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VariantPolicy {
+  pub preference: VariantPreference,
+  pub max_height: Option<u32>,
+}
+
+impl VariantPolicy {
+  pub fn new(config: &DownloadConfig) -> Self {
+    Self {
+      preference: config.variant,
+      max_height: config.max_height,
+    }
+  }
+
+  pub fn select<'a, T: Variant>(&self, variants: impl IntoIterator<Item = &'a T>) -> Option<&'a T> {
+    let variants: Vec<&T> = variants.into_iter().collect();
+
+    let fits = |variant: &&T| match (self.max_height, variant.height()) {
+      (Some(max_height), Some(height)) => height <= max_height,
+      _ => true,
+    };
+
+    let candidates: Vec<&T> = if variants.iter().any(fits) {
+      variants.into_iter().filter(fits).collect()
+    } else {
+      // Everything is taller than the limit, the shortest is the closest
+      let min_height = variants.iter().filter_map(|variant| variant.height()).min();
+
+      variants
+        .into_iter()
+        .filter(|variant| variant.height() == min_height)
+        .collect()
+    };
+
+    match self.preference {
+      VariantPreference::Highest => candidates
+        .into_iter()
+        .max_by_key(|variant| variant.bandwidth()),
+      VariantPreference::Lowest => candidates
+        .into_iter()
+        .min_by_key(|variant| variant.bandwidth()),
+    }
+  }
+}
+
+impl Variant for m3u8_rs::VariantStream {
+  fn bandwidth(&self) -> u64 {
+    self.bandwidth
+  }
+
+  fn height(&self) -> Option<u32> {
+    self
+      .resolution
+      .map(|resolution| resolution.height.min(u32::MAX as u64) as u32)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Variant, VariantPolicy};
+  use configuration::VariantPreference;
+
+  struct TestVariant(u64, Option<u32>);
+
+  impl Variant for TestVariant {
+    fn bandwidth(&self) -> u64 {
+      self.0
+    }
+
+    fn height(&self) -> Option<u32> {
+      self.1
+    }
+  }
+
+  #[test]
+  fn test_select_variant() {
+    let variants = vec![
+      TestVariant(800_000, Some(480)),
+      TestVariant(5_000_000, Some(1080)),
+      TestVariant(2_500_000, Some(720)),
+      TestVariant(12_000_000, Some(2160)),
+    ];
+
+    let select = |preference, max_height| {
+      VariantPolicy {
+        preference,
+        max_height,
+      }
+      .select(&variants)
+      .map(|variant| variant.0)
+    };
+
+    assert_eq!(select(VariantPreference::Highest, None), Some(12_000_000));
+    assert_eq!(select(VariantPreference::Lowest, None), Some(800_000));
+    assert_eq!(
+      select(VariantPreference::Highest, Some(1080)),
+      Some(5_000_000)
+    );
+    assert_eq!(
+      select(VariantPreference::Highest, Some(720)),
+      Some(2_500_000)
+    );
+    assert_eq!(select(VariantPreference::Lowest, Some(720)), Some(800_000));
+    // Nothing fits, the shortest one is taken
+    assert_eq!(select(VariantPreference::Highest, Some(360)), Some(800_000));
+  }
+
+  #[test]
+  fn test_select_variant_without_height() {
+    let variants = vec![TestVariant(128_000, None), TestVariant(64_000, None)];
+
+    let policy = VariantPolicy {
+      preference: VariantPreference::Highest,
+      max_height: Some(720),
+    };
+
+    assert_eq!(
+      policy.select(&variants).map(|variant| variant.0),
+      Some(128_000)
+    );
+    assert!(policy.select(&Vec::<TestVariant>::new()).is_none());
+  }
+}
@@ -0,0 +1,468 @@
+use configuration::VerificationConfig;
+use protocol::{DownloadProgressExt, DownloadProgressStream};
+use serde::Deserialize;
+use std::future::Future;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::fs;
+use tokio::process::Command;
+
+const DEFAULT_DURATION_TOLERANCE: f64 = 10.0;
+const DEFAULT_MAX_GAP: f64 = 10.0;
+
+// Packets of long media are only checked for gaps in a few windows spread over them, reading all
+// of them takes as long as decoding the whole file
+const GAP_CHECK_WINDOWS: usize = 5;
+const GAP_CHECK_WINDOW_SECONDS: f64 = 30.0;
+
+/// Streams and timing of a media file, as probed by ffprobe.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MediaProbe {
+  pub duration: Option<f64>,
+  pub has_video: bool,
+  pub has_audio: bool,
+  // Longest gap between the packets of the video stream, or of the audio one without video, in
+  // the windows of it that were checked
+  pub max_gap: f64,
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+  #[serde(default)]
+  streams: Vec<ProbeStream>,
+  format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+  codec_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+  // ffprobe prints numbers of its JSON output as strings
+  duration: Option<String>,
+  start_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KeyframeOutput {
+  #[serde(default)]
+  packets: Vec<ProbePacket>,
+  format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbePacket {
+  pts_time: Option<String>,
+  flags: Option<String>,
+}
+
+/// Runs `download`, then verifies the media it saved at `destination_path`. A media failing the
+/// verification is downloaded again from scratch, as long as retries are left, its progress
+/// restarting from `total_segments`. Audio-only media don't `expect_video`. Returns the output of
+/// the verified download.
+pub async fn download_verified<F, Fut, T>(
+  download: F,
+  destination_path: &Path,
+  expected_duration: Option<f64>,
+  expect_video: bool,
+  config: &VerificationConfig,
+  total_segments: usize,
+  stream: &DownloadProgressStream,
+) -> anyhow::Result<T>
+where
+  F: Fn() -> Fut,
+  Fut: Future<Output = anyhow::Result<T>>,
+{
+  let mut retries = 0;
+
+  loop {
+    let output = download().await?;
+
+    if !config.enabled {
+      return Ok(output);
+    }
+
+    match verify_media(destination_path, expected_duration, expect_video, config).await {
+      Ok(()) => return Ok(output),
+      Err(err) if retries < config.retries => {
+        retries += 1;
+
+        log::warn!(
+          "{:#}, downloading it again ({}/{})",
+          err,
+          retries,
+          config.retries
+        );
+
+        fs::remove_file(destination_path).await?;
+        stream.retrying(&format!("{:#}", err));
+        stream.start(total_segments);
+      }
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+/// Probes the media at `path` and fails with its problems, if any.
+pub async fn verify_media(
+  path: &Path,
+  expected_duration: Option<f64>,
+  expect_video: bool,
+  config: &VerificationConfig,
+) -> anyhow::Result<()> {
+  let probe = probe_media(path).await?;
+  let problems = check_media(&probe, expected_duration, expect_video, config);
+
+  if !problems.is_empty() {
+    anyhow::bail!("Verification of {:?} failed: {}", path, problems.join(", "));
+  }
+
+  log::info!("Verified {:?}: {:?}", path, probe);
+
+  Ok(())
+}
+
+pub async fn probe_media(path: &Path) -> anyhow::Result<MediaProbe> {
+  let output = run_ffprobe(
+    &[
+      "-v",
+      "error",
+      "-show_entries",
+      "format=duration:stream=codec_type",
+      "-of",
+      "json",
+    ],
+    path,
+  )
+  .await?;
+
+  let mut probe = parse_probe_output(&output)?;
+
+  if probe.has_video || probe.has_audio {
+    let main_stream = if probe.has_video { "v:0" } else { "a:0" };
+
+    for interval in gap_check_intervals(probe.duration) {
+      let mut args = vec![
+        "-v",
+        "error",
+        "-select_streams",
+        main_stream,
+        "-show_entries",
+        "packet=pts_time",
+        "-of",
+        "csv=p=0",
+      ];
+
+      if let Some(interval) = &interval {
+        args.extend(["-read_intervals", interval]);
+      }
+
+      let packets = run_ffprobe(&args, path).await?;
+
+      probe.max_gap = probe.max_gap.max(max_packet_gap(&packets));
+    }
+  }
+
+  Ok(probe)
+}
+
+/// Times of the keyframes of the video of the media at `path` in its first `seconds`, from the
+/// start of the media as `-ss` counts them. `input_args` are the options of the input.
+pub async fn probe_keyframes(
+  path: &Path,
+  input_args: &[&str],
+  seconds: f64,
+) -> anyhow::Result<Vec<f64>> {
+  let interval = format!("%+{:.3}", seconds);
+  let mut args = vec!["-v", "error"];
+
+  args.extend(input_args);
+  args.extend([
+    "-select_streams",
+    "v:0",
+    "-read_intervals",
+    &interval,
+    "-show_entries",
+    "packet=pts_time,flags:format=start_time",
+    "-of",
+    "json",
+  ]);
+
+  let output = run_ffprobe(&args, path).await?;
+
+  parse_keyframes(&output)
+}
+
+async fn run_ffprobe(args: &[&str], path: &Path) -> anyhow::Result<String> {
+  let output = Command::new("ffprobe")
+    .args(args)
+    .arg(path)
+    .stdin(Stdio::null())
+    .output()
+    .await?;
+
+  if !output.status.success() {
+    anyhow::bail!(
+      "ffprobe failed with status: {:?} ({})",
+      output.status,
+      String::from_utf8_lossy(&output.stderr).trim()
+    );
+  }
+
+  Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn parse_probe_output(output: &str) -> anyhow::Result<MediaProbe> {
+  let output: ProbeOutput = serde_json::from_str(output)?;
+
+  let has_stream = |codec_type: &str| {
+    output
+      .streams
+      .iter()
+      .any(|stream| stream.codec_type.as_deref() == Some(codec_type))
+  };
+
+  Ok(MediaProbe {
+    duration: output
+      .format
+      .and_then(|format| format.duration)
+      .and_then(|duration| duration.parse().ok()),
+    has_video: has_stream("video"),
+    has_audio: has_stream("audio"),
+    max_gap: 0.0,
+  })
+}
+
+fn parse_keyframes(output: &str) -> anyhow::Result<Vec<f64>> {
+  let output: KeyframeOutput = serde_json::from_str(output)?;
+
+  let start_time = output
+    .format
+    .and_then(|format| format.start_time)
+    .and_then(|start_time| start_time.parse::<f64>().ok())
+    .unwrap_or_default();
+
+  let mut keyframes: Vec<f64> = output
+    .packets
+    .iter()
+    .filter(|packet| {
+      packet
+        .flags
+        .as_deref()
+        .is_some_and(|flags| flags.starts_with('K'))
+    })
+    .filter_map(|packet| packet.pts_time.as_deref()?.parse::<f64>().ok())
+    .map(|time| time - start_time)
+    .collect();
+
+  keyframes.sort_by(f64::total_cmp);
+
+  Ok(keyframes)
+}
+
+// `-read_intervals` of the windows checked for gaps, evenly spread from the start to the end of
+// the media. Media too short for them, or of unknown duration, are read whole.
+fn gap_check_intervals(duration: Option<f64>) -> Vec<Option<String>> {
+  let windows_duration = GAP_CHECK_WINDOWS as f64 * GAP_CHECK_WINDOW_SECONDS;
+
+  match duration {
+    Some(duration) if duration > windows_duration => {
+      let step = (duration - GAP_CHECK_WINDOW_SECONDS) / (GAP_CHECK_WINDOWS - 1) as f64;
+
+      (0..GAP_CHECK_WINDOWS)
+        .map(|index| {
+          Some(format!(
+            "{:.3}%+{:.0}",
+            index as f64 * step,
+            GAP_CHECK_WINDOW_SECONDS
+          ))
+        })
+        .collect()
+    }
+    _ => vec![None],
+  }
+}
+
+// Longest gap between the timestamps of `packets`, one per line. Packets are listed in decoding
+// order, which differs from the presentation one with B-frames.
+fn max_packet_gap(packets: &str) -> f64 {
+  let mut times: Vec<f64> = packets
+    .lines()
+    .filter_map(|line| line.trim().trim_end_matches(',').parse().ok())
+    .collect();
+
+  times.sort_by(f64::total_cmp);
+  times
+    .windows(2)
+    .map(|pair| pair[1] - pair[0])
+    .fold(0.0, f64::max)
+}
+
+/// Problems of a probed media, whose `expected_duration` is the one of its playlist if known.
+pub fn check_media(
+  probe: &MediaProbe,
+  expected_duration: Option<f64>,
+  expect_video: bool,
+  config: &VerificationConfig,
+) -> Vec<String> {
+  let mut problems = vec![];
+
+  if !probe.has_video && expect_video {
+    problems.push("no video stream".to_string());
+  }
+
+  if !probe.has_audio && config.require_audio.unwrap_or(true) {
+    problems.push("no audio stream".to_string());
+  }
+
+  let tolerance = config
+    .duration_tolerance
+    .unwrap_or(DEFAULT_DURATION_TOLERANCE);
+
+  match (probe.duration, expected_duration) {
+    (Some(duration), Some(expected)) if (duration - expected).abs() > tolerance => problems.push(
+      format!("duration {:.1}s instead of {:.1}s", duration, expected),
+    ),
+    (None, _) => problems.push("unknown duration".to_string()),
+    _ => {}
+  }
+
+  let max_gap = config.max_gap.unwrap_or(DEFAULT_MAX_GAP);
+
+  if probe.max_gap > max_gap {
+    problems.push(format!("gap of {:.1}s", probe.max_gap));
+  }
+
+  problems
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{check_media, gap_check_intervals, max_packet_gap, parse_keyframes};
+  use super::{parse_probe_output, MediaProbe};
+  use configuration::VerificationConfig;
+
+  #[test]
+  fn test_parse_probe_output() {
+    let output = r#"{
+      "programs": [],
+      "streams": [{ "codec_type": "video" }, { "codec_type": "audio" }],
+      "format": { "duration": "2533.280000" }
+    }"#;
+
+    assert_eq!(
+      parse_probe_output(output).unwrap(),
+      MediaProbe {
+        duration: Some(2533.28),
+        has_video: true,
+        has_audio: true,
+        max_gap: 0.0,
+      }
+    );
+
+    let probe =
+      parse_probe_output(r#"{ "streams": [{ "codec_type": "video" }], "format": {} }"#).unwrap();
+    assert!(!probe.has_audio);
+    assert_eq!(probe.duration, None);
+
+    assert_eq!(max_packet_gap("0.0\n0.08\n0.04\nN/A\n0.12,\n14.12\n"), 14.0);
+    assert_eq!(max_packet_gap(""), 0.0);
+  }
+
+  #[test]
+  fn test_parse_keyframes() {
+    let output = r#"{
+      "packets": [
+        { "pts_time": "1.400000", "flags": "K__" },
+        { "pts_time": "1.440000", "flags": "___" },
+        { "pts_time": "3.400000", "flags": "K__" },
+        { "pts_time": "N/A", "flags": "K__" }
+      ],
+      "format": { "start_time": "1.400000" }
+    }"#;
+
+    assert_eq!(parse_keyframes(output).unwrap(), vec![0.0, 2.0]);
+    assert!(parse_keyframes(r#"{ "format": {} }"#).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_gap_check_intervals() {
+    assert_eq!(gap_check_intervals(None), vec![None]);
+    assert_eq!(gap_check_intervals(Some(120.0)), vec![None]);
+
+    let intervals: Vec<String> = gap_check_intervals(Some(2430.0))
+      .into_iter()
+      .map(Option::unwrap)
+      .collect();
+
+    assert_eq!(
+      intervals,
+      vec![
+        "0.000%+30",
+        "600.000%+30",
+        "1200.000%+30",
+        "1800.000%+30",
+        "2400.000%+30"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_check_media() {
+    let config = VerificationConfig {
+      enabled: true,
+      ..Default::default()
+    };
+
+    let probe = MediaProbe {
+      duration: Some(600.5),
+      has_video: true,
+      has_audio: true,
+      max_gap: 0.04,
+    };
+
+    assert!(check_media(&probe, Some(596.0), true, &config).is_empty());
+    assert!(check_media(&probe, None, true, &config).is_empty());
+
+    // Truncated, without audio and with a hole
+    let probe = MediaProbe {
+      duration: Some(310.0),
+      has_video: true,
+      has_audio: false,
+      max_gap: 42.0,
+    };
+
+    assert_eq!(
+      check_media(&probe, Some(596.0), true, &config),
+      vec![
+        "no audio stream",
+        "duration 310.0s instead of 596.0s",
+        "gap of 42.0s"
+      ]
+    );
+
+    let config = VerificationConfig {
+      require_audio: Some(false),
+      duration_tolerance: Some(300.0),
+      max_gap: Some(60.0),
+      ..config
+    };
+
+    assert!(check_media(&probe, Some(596.0), true, &config).is_empty());
+
+    let audio_only = MediaProbe {
+      duration: Some(596.0),
+      has_video: false,
+      has_audio: true,
+      max_gap: 0.02,
+    };
+
+    assert!(check_media(&audio_only, Some(596.0), false, &config).is_empty());
+    assert_eq!(
+      check_media(&audio_only, Some(596.0), true, &config),
+      vec!["no video stream"]
+    );
+  }
+}
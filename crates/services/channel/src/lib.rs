@@ -1,12 +1,15 @@
 mod common;
 mod services;
 
+use common::SourceHistory;
 use configuration::Configuration;
 use protocol::channel::ChannelExt;
 use protocol::channel::DownloadMediaRequest;
+use protocol::channel::DIRECT_CHANNEL;
 use protocol::channel::{ChannelInfo, GetChannelsRequest, GetChannelsResponse};
 use protocol::channel::{GetMediaMetadataRequest, MediaMetadata};
 use protocol::channel::{GetMediaPlaylistRequest, MediaPlaylist};
+use protocol::channel::{GetMediaRenditionsRequest, MediaRenditions};
 use protocol::channel::{SearchMediaRequest, SearchMediaResponse};
 use protocol::tonic;
 use protocol::tonic::{async_trait, Request, Response, Status};
@@ -15,6 +18,7 @@ use services::{DownloadMediaOptions, MediaChannelExt};
 use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 pub struct ChannelService {
   destination_dir: PathBuf,
@@ -35,6 +39,8 @@ impl ChannelExt for ChannelService {
     let channels: Vec<ChannelInfo> = self
       .channels
       .iter()
+      // The direct channel has nothing to browse
+      .filter(|(channel_id, _)| channel_id.as_str() != DIRECT_CHANNEL)
       .map(|(channel_id, channel)| ChannelInfo {
         id: channel_id.clone(),
         name: channel.display_name().to_string(),
@@ -57,20 +63,61 @@ impl ChannelExt for ChannelService {
       request.number
     );
 
-    let file_name = format!("{}-{}.mp4", request.media_id, request.number.unwrap_or(1));
+    if let Some(clip) = &request.clip {
+      clip.validate().map_err(Status::invalid_argument)?;
+    }
+
+    if let Some(recording) = &request.recording {
+      recording.validate().map_err(Status::invalid_argument)?;
+    }
+
+    if let Some(trim) = &request.trim {
+      trim.validate().map_err(Status::invalid_argument)?;
+    }
+
+    let mut file_name = format!(
+      "{}-{}",
+      file_name_of_media_id(&request.media_id),
+      request.number.unwrap_or(1)
+    );
+
+    // Clips don't overwrite the whole media, their bounds are named in milliseconds
+    if let Some(clip) = &request.clip {
+      let end = clip
+        .end
+        .map_or("end".to_string(), |end| millis(end).to_string());
+      file_name.push_str(&format!("-clip-{}-{}", millis(clip.start), end));
+    }
+
+    // Each recording of a live stream is a media of its own
+    if request.recording.is_some() {
+      let started_at = chrono::Local::now().format("%Y%m%d-%H%M%S");
+      file_name.push_str(&format!("-live-{}", started_at));
+    }
+
+    file_name.push_str(".mp4");
 
     let options = DownloadMediaOptions {
       media_id: request.media_id,
       number: request.number,
       destination_path: self.destination_dir.join(file_name),
+      renditions: request.renditions,
+      container: request.container,
+      clip: request.clip,
+      audio_only: request.audio_only.is_some(),
+      recording: request.recording,
+      trim: request.trim,
+      tags: request.tags,
     };
 
-    let channel = self.get_channel_by_id(&request.channel)?;
+    let channel = self
+      .get_channel_by_id(&request.channel)
+      .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
     let download_progress_receiver = channel
       .download_media(options)
       .await
-      .map_err(|e| Status::internal(format!("Error occurred during download media: {}", e)))?;
+      .map_err(|e| Status::internal(format!("Error occurred during download media: {:#}", e)))?;
 
     Ok(Response::new(download_progress_receiver))
   }
@@ -82,12 +129,14 @@ impl ChannelExt for ChannelService {
     let request = request.into_inner();
     log::info!("Getting media metadata of {}", request.media_id);
 
-    let channel = self.get_channel_by_id(&request.channel)?;
+    let channel = self
+      .get_channel_by_id(&request.channel)
+      .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
     let metadata = channel
       .get_media_metadata(&request.media_id)
       .await
-      .map_err(|e| Status::internal(format!("Failed to get media metadata: {}", e)))?;
+      .map_err(|e| Status::internal(format!("Failed to get media metadata: {:#}", e)))?;
 
     Ok(Response::new(metadata))
   }
@@ -100,12 +149,14 @@ impl ChannelExt for ChannelService {
     log::info!("Searching media metadata of {}", request.keyword);
 
     let channel_name = request.channel.as_ref().unwrap_or(&self.default_channel);
-    let channel = self.get_channel_by_id(channel_name)?;
+    let channel = self
+      .get_channel_by_id(channel_name)
+      .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
     let search_result = channel
       .search_media(&request)
       .await
-      .map_err(|e| Status::internal(format!("Failed to search media: {}", e)))?;
+      .map_err(|e| Status::internal(format!("Failed to search media: {:#}", e)))?;
 
     Ok(Response::new(search_result))
   }
@@ -117,23 +168,53 @@ impl ChannelExt for ChannelService {
     let request = request.into_inner();
     log::info!("Getting media playlist of {}", request.media_id);
 
-    let channel = self.get_channel_by_id(&request.channel)?;
+    let channel = self
+      .get_channel_by_id(&request.channel)
+      .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
-    let playlist = channel
+    let mut playlist = channel
       .get_media_playlist(&request.media_id)
       .await
-      .map_err(|e| Status::internal(format!("Failed to get media playlist: {}", e)))?;
+      .map_err(|e| Status::internal(format!("Failed to get media playlist: {:#}", e)))?;
+
+    if request.probe {
+      channel.probe_media_playlist(&mut playlist).await;
+    }
 
     Ok(Response::new(playlist))
   }
+
+  async fn get_media_renditions(
+    &self,
+    request: Request<GetMediaRenditionsRequest>,
+  ) -> tonic::Result<Response<MediaRenditions>> {
+    let request = request.into_inner();
+    log::info!(
+      "Getting media renditions of {} ({:?})",
+      request.media_id,
+      request.number
+    );
+
+    let channel = self
+      .get_channel_by_id(&request.channel)
+      .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+    let renditions = channel
+      .get_media_renditions(&request.media_id, request.number)
+      .await
+      .map_err(|e| Status::internal(format!("Failed to get media renditions: {:#}", e)))?;
+
+    Ok(Response::new(renditions))
+  }
 }
 
 impl ChannelService {
-  fn get_channel_by_id(&self, channel_id: &str) -> tonic::Result<&dyn MediaChannelExt> {
+  // The error is an invalid argument of the request
+  fn get_channel_by_id(&self, channel_id: &str) -> anyhow::Result<&dyn MediaChannelExt> {
     let channel = self
       .channels
       .get(channel_id)
-      .ok_or_else(|| Status::invalid_argument(format!("No channel '{}' found.", channel_id)))?;
+      .ok_or_else(|| anyhow::anyhow!("No channel '{}' found.", channel_id))?;
 
     log::info!(
       "Found channel with id '{}', name '{}'",
@@ -147,12 +228,28 @@ impl ChannelService {
 
 impl ChannelService {
   pub fn new(config: &Configuration) -> Self {
+    use self::services::direct::DirectMediaService;
+    use self::services::html::HtmlMediaService;
+    use self::services::json::JsonMediaService;
     use self::services::unified::UnifiedMediaService;
 
     let mut channels = HashMap::new();
 
+    let destination_dir = destination_dir();
+    // Shared by the channels, since their sources are often on the same CDNs
+    let source_history = Arc::new(SourceHistory::load(
+      destination_dir.join(SOURCE_HISTORY_FILE),
+    ));
+
     for (channel_id, config) in &config.channel.unified_channels {
-      let unified_channel = UnifiedMediaService::new(channel_id, config);
+      let unified_channel =
+        match UnifiedMediaService::new(channel_id, config, source_history.clone()) {
+          Ok(channel) => channel,
+          Err(err) => {
+            log::error!("Failed to create unified channel {}: {}", channel_id, err);
+            continue;
+          }
+        };
 
       log::info!(
         "Adding new unified channel {} with base URL {} ... ",
@@ -163,15 +260,88 @@ impl ChannelService {
       channels.insert(channel_id.to_string(), Box::new(unified_channel) as Box<_>);
     }
 
+    for (channel_id, config) in &config.channel.json_channels {
+      let json_channel = match JsonMediaService::new(channel_id, config) {
+        Ok(channel) => channel,
+        Err(err) => {
+          log::error!("Failed to create JSON channel {}: {:#}", channel_id, err);
+          continue;
+        }
+      };
+
+      log::info!(
+        "Adding new JSON channel {} with base URL {} ... ",
+        channel_id,
+        config.base_url,
+      );
+
+      channels.insert(channel_id.to_string(), Box::new(json_channel) as Box<_>);
+    }
+
+    for (channel_id, config) in &config.channel.html_channels {
+      let html_channel = match HtmlMediaService::new(channel_id, config) {
+        Ok(channel) => channel,
+        Err(err) => {
+          log::error!("Failed to create HTML channel {}: {:#}", channel_id, err);
+          continue;
+        }
+      };
+
+      log::info!(
+        "Adding new HTML channel {} with base URL {} ... ",
+        channel_id,
+        config.base_url,
+      );
+
+      channels.insert(channel_id.to_string(), Box::new(html_channel) as Box<_>);
+    }
+
+    match DirectMediaService::new(&config.channel.direct) {
+      Ok(direct_channel) => {
+        channels.insert(
+          DIRECT_CHANNEL.to_string(),
+          Box::new(direct_channel) as Box<_>,
+        );
+      }
+      Err(err) => log::error!("Failed to create direct channel: {:#}", err),
+    }
+
     Self {
       channels,
       default_channel: config.channel.default.clone(),
-      destination_dir: destination_dir(),
+      destination_dir,
     }
   }
 }
 
+// Media IDs of the direct channel are URLs, which can't be used as file names
+fn file_name_of_media_id(media_id: &str) -> String {
+  let is_safe = media_id.len() <= 64
+    && media_id
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+  if is_safe {
+    return media_id.to_string();
+  }
+
+  format!("{:016x}", fnv1a(media_id.as_bytes()))
+}
+
+// 64-bit FNV-1a, unlike the hasher of std it is the same on every Rust release, so that later
+// attempts find the files of earlier ones
+fn fnv1a(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+  })
+}
+
+fn millis(seconds: f64) -> u64 {
+  (seconds * 1000.0).round() as u64
+}
+
 const DOWNLOAD_DESTINATION_DIR: &str = "/downloads";
+const SOURCE_HISTORY_FILE: &str = ".source-history.json";
 
 fn destination_dir() -> PathBuf {
   let preset_destination_dir = PathBuf::from(DOWNLOAD_DESTINATION_DIR);
@@ -186,3 +356,20 @@ fn destination_dir() -> PathBuf {
 
   base_dir.join("downloads")
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{file_name_of_media_id, millis};
+
+  #[test]
+  fn test_file_name_of_media_id() {
+    assert_eq!(file_name_of_media_id("9104"), "9104");
+    // Pinned, so that a change of the hash is noticed before downloads are named differently
+    assert_eq!(
+      file_name_of_media_id("https://cdn.example.com/movies/9104/index.m3u8"),
+      "9dac1cd8545f83cd"
+    );
+    assert_eq!(millis(90.5), 90500);
+    assert_eq!(millis(0.1 + 0.2), 300);
+  }
+}
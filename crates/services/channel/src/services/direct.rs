@@ -0,0 +1,163 @@
+use crate::common::{download_media, get_media_renditions, HttpClient};
+use crate::services::DownloadMediaOptions;
+use crate::services::MediaChannelExt;
+use configuration::{DirectChannelConfig, DownloadConfig};
+use protocol::channel::{MediaKind, MediaMetadata, MediaRenditions, DIRECT_CHANNEL};
+use protocol::channel::{MediaPlaylist, MediaPlaylistItem};
+use protocol::channel::{SearchMediaRequest, SearchMediaResponse};
+use protocol::DownloadProgressReceiver;
+use url::Url;
+
+// Cookies are seeded for the base URL, which the direct channel doesn't have
+const PLACEHOLDER_BASE_URL: &str = "http://localhost";
+
+/// Built-in channel downloading stream URLs given by users. There's no catalog, the ID of a
+/// media is its URL.
+pub struct DirectMediaService {
+  http_client: HttpClient,
+  download_config: DownloadConfig,
+}
+
+#[async_trait::async_trait]
+impl MediaChannelExt for DirectMediaService {
+  fn display_name(&self) -> &str {
+    "Direct URL"
+  }
+
+  fn base_url(&self) -> &str {
+    ""
+  }
+
+  async fn download_media(
+    &self,
+    options: DownloadMediaOptions,
+  ) -> anyhow::Result<DownloadProgressReceiver> {
+    let url = parse_media_url(&options.media_id)?;
+
+    let download_opts = crate::common::DownloadMediaOptions {
+      download_url: url.as_str(),
+      destination_path: &options.destination_path,
+      http_client: &self.http_client,
+      download_config: &self.download_config,
+      renditions: &options.renditions,
+      container: options.container,
+      clip: options.clip,
+      audio_only: options.audio_only,
+      recording: options.recording.as_ref(),
+      trim: options.trim,
+      tags: options.tags.as_ref(),
+    };
+
+    download_media(download_opts).await
+  }
+
+  async fn get_media_metadata(&self, media_id: &str) -> anyhow::Result<MediaMetadata> {
+    let url = parse_media_url(media_id)?;
+
+    // Without a catalog, the name of the file is the best guess
+    let name = url
+      .path_segments()
+      .and_then(|mut segments| segments.next_back())
+      .filter(|name| !name.is_empty())
+      .unwrap_or(url.host_str().unwrap_or_default())
+      .to_string();
+
+    Ok(MediaMetadata {
+      channel: DIRECT_CHANNEL.to_string(),
+      id: media_id.to_string(),
+      name,
+      poster_url: String::new(),
+      release_year: 0,
+      description: String::new(),
+      kind: MediaKind::Other,
+      cast: vec![],
+      directors: vec![],
+      region: String::new(),
+      language: String::new(),
+      remarks: String::new(),
+      total_episodes: None,
+      updated_at: None,
+      score: None,
+    })
+  }
+
+  async fn search_media(
+    &self,
+    request: &SearchMediaRequest,
+  ) -> anyhow::Result<SearchMediaResponse> {
+    Ok(SearchMediaResponse {
+      items: vec![],
+      total: 0,
+      page: request.page,
+      page_size: request.page_size,
+    })
+  }
+
+  async fn get_media_playlist(&self, media_id: &str) -> anyhow::Result<MediaPlaylist> {
+    let url = parse_media_url(media_id)?;
+
+    Ok(MediaPlaylist {
+      channel: DIRECT_CHANNEL.to_string(),
+      media_id: media_id.to_string(),
+      items: vec![MediaPlaylistItem {
+        number: 1,
+        text: String::new(),
+        url: url.to_string(),
+        probe: None,
+      }],
+    })
+  }
+
+  fn http_client(&self) -> &HttpClient {
+    &self.http_client
+  }
+
+  fn download_config(&self) -> &DownloadConfig {
+    &self.download_config
+  }
+
+  async fn get_media_renditions(
+    &self,
+    media_id: &str,
+    _number: Option<u32>,
+  ) -> anyhow::Result<MediaRenditions> {
+    let url = parse_media_url(media_id)?;
+
+    get_media_renditions(&self.http_client, url.as_str()).await
+  }
+}
+
+fn parse_media_url(media_id: &str) -> anyhow::Result<Url> {
+  let url = Url::parse(media_id).map_err(|e| anyhow::anyhow!("Invalid URL {}: {}", media_id, e))?;
+
+  if !matches!(url.scheme(), "http" | "https") {
+    anyhow::bail!(
+      "Unsupported URL {}, only HTTP(S) URLs can be downloaded",
+      media_id
+    );
+  }
+
+  Ok(url)
+}
+
+impl DirectMediaService {
+  pub fn new(config: &DirectChannelConfig) -> anyhow::Result<Self> {
+    let http_config = configuration::HttpClientConfig {
+      cookies: vec![],
+      ..config.http.clone()
+    };
+
+    let http_client = HttpClient::new(
+      PLACEHOLDER_BASE_URL,
+      http::Version::HTTP_11,
+      &http_config,
+      &config.retry,
+      config.rate_limit.as_ref(),
+    )?;
+
+    Ok(Self {
+      http_client,
+      download_config: config.download.clone(),
+    })
+  }
+}
@@ -0,0 +1,764 @@
+use crate::common::{get_media_renditions, render_url_template};
+use crate::common::{Category, CategoryMapper, HttpClient};
+use crate::services::DownloadMediaOptions;
+use crate::services::MediaChannelExt;
+use anyhow::Context;
+use configuration::{DownloadConfig, HtmlChannelConfig, HtmlFieldConfig};
+use protocol::channel::{MediaKind, MediaMetadata, MediaRenditions};
+use protocol::channel::{MediaPlaylist, MediaPlaylistItem};
+use protocol::channel::{SearchMediaRequest, SearchMediaResponse};
+use protocol::DownloadProgressReceiver;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use url::Url;
+
+const DEFAULT_M3U8_PATTERN: &str = r#"(https?:[^"'\s<>]+?\.m3u8[^"'\s<>]*)"#;
+
+// Player pages usually embed the video in one or two levels of frames
+const MAX_FRAME_DEPTH: usize = 3;
+
+/// Channel scraped from the HTML pages of a site, configured with CSS selectors.
+pub struct HtmlMediaService {
+  channel_id: String,
+  display_name: String,
+  base_url: String,
+  http_client: HttpClient,
+  category_mapper: CategoryMapper,
+  download_config: DownloadConfig,
+  search: SearchPage,
+  detail: DetailPage,
+  playlist: PlaylistPage,
+  player: PlayerPage,
+}
+
+struct SearchPage {
+  url: String,
+  items: Selector,
+  id: Field,
+  name: Field,
+  poster_url: Option<Field>,
+  total: Option<Field>,
+}
+
+struct DetailPage {
+  url: String,
+  name: Field,
+  poster_url: Option<Field>,
+  release_year: Option<Field>,
+  description: Option<Field>,
+  category: Option<Field>,
+  cast: Option<Field>,
+  directors: Option<Field>,
+  region: Option<Field>,
+  language: Option<Field>,
+  remarks: Option<Field>,
+}
+
+struct PlaylistPage {
+  url: Option<String>,
+  episodes: Selector,
+}
+
+struct PlayerPage {
+  pattern: Regex,
+  frame: Option<Field>,
+}
+
+enum PlayerSource {
+  Media(Url),
+  Frame(Url),
+}
+
+struct Field {
+  selector: Selector,
+  attribute: Option<String>,
+  pattern: Option<Regex>,
+}
+
+#[async_trait::async_trait]
+impl MediaChannelExt for HtmlMediaService {
+  fn display_name(&self) -> &str {
+    &self.display_name
+  }
+
+  fn base_url(&self) -> &str {
+    &self.base_url
+  }
+
+  async fn download_media(
+    &self,
+    options: DownloadMediaOptions,
+  ) -> anyhow::Result<DownloadProgressReceiver> {
+    let media_url = self
+      .get_media_url(&options.media_id, options.number)
+      .await?;
+
+    let download_opts = crate::common::DownloadMediaOptions {
+      download_url: &media_url,
+      destination_path: &options.destination_path,
+      http_client: &self.http_client,
+      download_config: &self.download_config,
+      renditions: &options.renditions,
+      container: options.container,
+      clip: options.clip,
+      audio_only: options.audio_only,
+      recording: options.recording.as_ref(),
+      trim: options.trim,
+      tags: options.tags.as_ref(),
+    };
+
+    let progress = crate::common::download_media(download_opts).await?;
+
+    Ok(progress)
+  }
+
+  async fn get_media_metadata(&self, media_id: &str) -> anyhow::Result<MediaMetadata> {
+    log::info!("Getting video detail of {:?}", media_id);
+
+    let url = self.render_url(&self.detail.url, &[("media_id", media_id)]);
+    let (page_url, html) = self.request_page(&url).await?;
+
+    self.parse_detail_page(media_id, &html, &page_url)
+  }
+
+  async fn search_media(
+    &self,
+    request: &SearchMediaRequest,
+  ) -> anyhow::Result<SearchMediaResponse> {
+    let url = self.render_url(
+      &self.search.url,
+      &[
+        ("keyword", &request.keyword),
+        ("page", &request.page.to_string()),
+      ],
+    );
+
+    log::info!("Search media with: {}", url);
+
+    let (page_url, html) = self.request_page(&url).await?;
+
+    let (items, total) = self.parse_search_page(&html, &page_url);
+
+    let page_size = if request.page_size > 0 {
+      request.page_size
+    } else {
+      items.len() as u32
+    };
+
+    Ok(SearchMediaResponse {
+      total: total.unwrap_or(items.len() as u32),
+      items,
+      page: request.page,
+      page_size,
+    })
+  }
+
+  async fn get_media_playlist(&self, media_id: &str) -> anyhow::Result<MediaPlaylist> {
+    let url = self.playlist.url.as_ref().unwrap_or(&self.detail.url);
+    let url = self.render_url(url, &[("media_id", media_id)]);
+
+    let (page_url, html) = self.request_page(&url).await?;
+
+    Ok(MediaPlaylist {
+      channel: self.channel_id.clone(),
+      media_id: media_id.to_string(),
+      items: self.parse_playlist_page(&html, &page_url),
+    })
+  }
+
+  fn http_client(&self) -> &HttpClient {
+    &self.http_client
+  }
+
+  fn download_config(&self) -> &DownloadConfig {
+    &self.download_config
+  }
+
+  // Items link to player pages, the playlists of which are probed
+  async fn media_url_of_item(&self, url: String) -> anyhow::Result<String> {
+    Ok(self.find_m3u8_url(&url).await?.to_string())
+  }
+
+  async fn get_media_renditions(
+    &self,
+    media_id: &str,
+    number: Option<u32>,
+  ) -> anyhow::Result<MediaRenditions> {
+    let media_url = self.get_media_url(media_id, number).await?;
+
+    get_media_renditions(&self.http_client, &media_url).await
+  }
+}
+
+impl HtmlMediaService {
+  async fn get_media_url(&self, media_id: &str, number: Option<u32>) -> anyhow::Result<String> {
+    let playlist = self.get_media_playlist(media_id).await?;
+
+    let item = playlist
+      .items
+      .iter()
+      .find(|item| item.number == number.unwrap_or(1))
+      .ok_or_else(|| anyhow::anyhow!("Invalid number {:?} of media {}", number, media_id))?;
+
+    let m3u8_url = self.find_m3u8_url(&item.url).await?;
+
+    Ok(m3u8_url.to_string())
+  }
+
+  /// Returns the URL of the page after redirects, which relative links are resolved against.
+  async fn request_page(&self, url: &str) -> anyhow::Result<(Url, String)> {
+    let client = self.http_client.client();
+
+    self
+      .http_client
+      .retry_policy()
+      .run(url, || async {
+        let request = client.get(url).version(self.http_client.http_version());
+
+        let res = self
+          .http_client
+          .send(request)
+          .await
+          .context("Failed to send request")?
+          .error_for_status()
+          .context("Request failed")?;
+
+        let page_url = res.url().clone();
+        let html = res.text().await.context("Failed to get text response")?;
+
+        Ok((page_url, html))
+      })
+      .await
+  }
+
+  /// Follows the player page of an episode, and its frames, until the m3u8 URL is found.
+  async fn find_m3u8_url(&self, player_url: &str) -> anyhow::Result<Url> {
+    let mut url = Url::parse(player_url).context("Invalid player page URL")?;
+
+    for _ in 0..MAX_FRAME_DEPTH {
+      // Some sites link the episodes to the media directly
+      if url.path().ends_with(".m3u8") {
+        return Ok(url);
+      }
+
+      let (page_url, html) = self.request_page(url.as_str()).await?;
+
+      match self.parse_player_page(&html, &page_url)? {
+        PlayerSource::Media(media_url) => return Ok(media_url),
+        PlayerSource::Frame(frame_url) => {
+          log::info!("Following player frame {}", frame_url);
+          url = frame_url;
+        }
+      }
+    }
+
+    anyhow::bail!("No m3u8 URL found following player page {}", player_url)
+  }
+
+  fn render_url(&self, template: &str, params: &[(&str, &str)]) -> String {
+    render_url_template(template, &self.base_url, params)
+  }
+
+  fn parse_search_page(&self, html: &str, page_url: &Url) -> (Vec<MediaMetadata>, Option<u32>) {
+    let html = Html::parse_document(html);
+    let search = &self.search;
+
+    let items = html
+      .select(&search.items)
+      .filter_map(|item| {
+        let id = search.id.value(item)?;
+        let name = search.name.value(item)?;
+
+        Some(MediaMetadata {
+          // Search results don't show categories, the kind is known from the detail page
+          kind: MediaKind::Other,
+          channel: self.channel_id.clone(),
+          id,
+          name,
+          poster_url: url_field(&search.poster_url, item, page_url),
+          release_year: 0,
+          description: String::new(),
+          cast: vec![],
+          directors: vec![],
+          region: String::new(),
+          language: String::new(),
+          remarks: String::new(),
+          total_episodes: None,
+          updated_at: None,
+          score: None,
+        })
+      })
+      .collect();
+
+    let total = search
+      .total
+      .as_ref()
+      .and_then(|total| total.value(html.root_element()))
+      .and_then(|total| parse_leading_number(&total));
+
+    (items, total)
+  }
+
+  fn parse_detail_page(
+    &self,
+    media_id: &str,
+    html: &str,
+    page_url: &Url,
+  ) -> anyhow::Result<MediaMetadata> {
+    let html = Html::parse_document(html);
+    let root = html.root_element();
+    let detail = &self.detail;
+
+    let name = detail
+      .name
+      .value(root)
+      .ok_or_else(|| anyhow::anyhow!("No media name found in detail page {}", page_url))?;
+
+    let category = text_field(&detail.category, root);
+
+    Ok(MediaMetadata {
+      kind: self.category_mapper.media_kind([Category {
+        id: None,
+        name: &category,
+      }]),
+      channel: self.channel_id.clone(),
+      id: media_id.to_string(),
+      name,
+      poster_url: url_field(&detail.poster_url, root, page_url),
+      release_year: parse_leading_number(&text_field(&detail.release_year, root)).unwrap_or(0),
+      description: text_field(&detail.description, root),
+      cast: list_field(&detail.cast, root),
+      directors: list_field(&detail.directors, root),
+      region: text_field(&detail.region, root),
+      language: text_field(&detail.language, root),
+      remarks: text_field(&detail.remarks, root),
+      total_episodes: None,
+      updated_at: None,
+      score: None,
+    })
+  }
+
+  fn parse_playlist_page(&self, html: &str, page_url: &Url) -> Vec<MediaPlaylistItem> {
+    let html = Html::parse_document(html);
+
+    html
+      .select(&self.playlist.episodes)
+      .filter_map(|link| {
+        let href = link.value().attr("href")?;
+
+        Some((element_text(link), page_url.join(href).ok()?))
+      })
+      .enumerate()
+      .map(|(index, (text, url))| MediaPlaylistItem {
+        number: index as u32 + 1,
+        text,
+        url: url.to_string(),
+        probe: None,
+      })
+      .collect()
+  }
+
+  fn parse_player_page(&self, html: &str, page_url: &Url) -> anyhow::Result<PlayerSource> {
+    // URLs in inline scripts are usually JSON encoded
+    let unescaped = html.replace("\\/", "/");
+
+    if let Some(captures) = self.player.pattern.captures(&unescaped) {
+      let url = captures.get(1).or(captures.get(0)).unwrap().as_str();
+
+      return Ok(PlayerSource::Media(page_url.join(url)?));
+    }
+
+    let frame_url = self.player.frame.as_ref().and_then(|frame| {
+      let html = Html::parse_document(html);
+
+      frame.value(html.root_element())
+    });
+
+    match frame_url {
+      Some(frame_url) => Ok(PlayerSource::Frame(page_url.join(&frame_url)?)),
+      None => anyhow::bail!("No m3u8 URL found in player page {}", page_url),
+    }
+  }
+}
+
+impl Field {
+  fn new(config: &HtmlFieldConfig) -> anyhow::Result<Self> {
+    let pattern = config
+      .pattern
+      .as_deref()
+      .map(Regex::new)
+      .transpose()
+      .with_context(|| format!("Invalid pattern of field {:?}", config.selector))?;
+
+    Ok(Self {
+      selector: parse_selector(&config.selector)?,
+      attribute: config.attribute.clone(),
+      pattern,
+    })
+  }
+
+  /// Returns the first non-empty value of the elements matched in `element`.
+  fn value(&self, element: ElementRef) -> Option<String> {
+    self.values(element).into_iter().next()
+  }
+
+  fn values(&self, element: ElementRef) -> Vec<String> {
+    element
+      .select(&self.selector)
+      .filter_map(|element| {
+        let value = match &self.attribute {
+          Some(attribute) => element.value().attr(attribute)?.trim().to_string(),
+          None => element_text(element),
+        };
+
+        let value = match &self.pattern {
+          Some(pattern) => {
+            let captures = pattern.captures(&value)?;
+
+            captures
+              .get(1)
+              .or(captures.get(0))?
+              .as_str()
+              .trim()
+              .to_string()
+          }
+          None => value,
+        };
+
+        Some(value).filter(|value| !value.is_empty())
+      })
+      .collect()
+  }
+}
+
+// Text of an element, with whitespace between and inside the text nodes collapsed
+fn element_text(element: ElementRef) -> String {
+  element
+    .text()
+    .flat_map(|text| text.split_whitespace())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+fn text_field(field: &Option<Field>, element: ElementRef) -> String {
+  field
+    .as_ref()
+    .and_then(|field| field.value(element))
+    .unwrap_or_default()
+}
+
+fn list_field(field: &Option<Field>, element: ElementRef) -> Vec<String> {
+  field
+    .as_ref()
+    .map(|field| field.values(element))
+    .unwrap_or_default()
+}
+
+fn url_field(field: &Option<Field>, element: ElementRef, page_url: &Url) -> String {
+  field
+    .as_ref()
+    .and_then(|field| field.value(element))
+    .and_then(|url| page_url.join(&url).ok())
+    .map(|url| url.to_string())
+    .unwrap_or_default()
+}
+
+fn parse_leading_number(value: &str) -> Option<u32> {
+  let digits: String = value
+    .chars()
+    .skip_while(|c| !c.is_ascii_digit())
+    .take_while(|c| c.is_ascii_digit())
+    .collect();
+
+  digits.parse().ok()
+}
+
+fn parse_selector(selector: &str) -> anyhow::Result<Selector> {
+  Selector::parse(selector).map_err(|e| anyhow::anyhow!("Invalid selector {:?}: {}", selector, e))
+}
+
+fn optional_field(config: &Option<HtmlFieldConfig>) -> anyhow::Result<Option<Field>> {
+  config.as_ref().map(Field::new).transpose()
+}
+
+impl HtmlMediaService {
+  pub fn new(channel_id: &str, config: &HtmlChannelConfig) -> anyhow::Result<Self> {
+    let http_version = if config.http_version.unwrap_or(2) == 1 {
+      http::Version::HTTP_11
+    } else {
+      http::Version::HTTP_2
+    };
+
+    let http_client = HttpClient::new(
+      &config.base_url,
+      http_version,
+      &config.http,
+      &config.retry,
+      config.rate_limit.as_ref(),
+    )?;
+
+    let search = SearchPage {
+      url: config.search.url.clone(),
+      items: parse_selector(&config.search.items)?,
+      id: Field::new(&config.search.id)?,
+      name: Field::new(&config.search.name)?,
+      poster_url: optional_field(&config.search.poster_url)?,
+      total: optional_field(&config.search.total)?,
+    };
+
+    let detail = DetailPage {
+      url: config.detail.url.clone(),
+      name: Field::new(&config.detail.name)?,
+      poster_url: optional_field(&config.detail.poster_url)?,
+      release_year: optional_field(&config.detail.release_year)?,
+      description: optional_field(&config.detail.description)?,
+      category: optional_field(&config.detail.category)?,
+      cast: optional_field(&config.detail.cast)?,
+      directors: optional_field(&config.detail.directors)?,
+      region: optional_field(&config.detail.region)?,
+      language: optional_field(&config.detail.language)?,
+      remarks: optional_field(&config.detail.remarks)?,
+    };
+
+    let playlist = PlaylistPage {
+      url: config.playlist.url.clone(),
+      episodes: parse_selector(&config.playlist.episodes)?,
+    };
+
+    let player_config = config.player.as_ref();
+
+    let player = PlayerPage {
+      pattern: Regex::new(
+        player_config
+          .and_then(|player| player.pattern.as_deref())
+          .unwrap_or(DEFAULT_M3U8_PATTERN),
+      )
+      .context("Invalid m3u8 pattern of player page")?,
+      frame: player_config
+        .map(|player| optional_field(&player.frame))
+        .transpose()?
+        .flatten(),
+    };
+
+    Ok(Self {
+      channel_id: channel_id.to_owned(),
+      display_name: config.name.clone(),
+      base_url: config.base_url.clone(),
+      http_client,
+      category_mapper: CategoryMapper::new(&config.categories)?,
+      download_config: config.download.clone(),
+      search,
+      detail,
+      playlist,
+      player,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{HtmlMediaService, PlayerSource};
+  use protocol::channel::MediaKind;
+  use serde_json::json;
+  use url::Url;
+
+  // Pages of a site using a common MacCMS template
+  const SEARCH_PAGE: &str = r#"
+    <html><body>
+      <div class="module-search-item">
+        <div class="video-cover"><img class="lazy" data-src="/upload/vod/1024.jpg"></div>
+        <div class="video-info">
+          <h3><a href="/voddetail/1024.html" title="示例剧集">示例剧集</a></h3>
+        </div>
+      </div>
+      <div class="module-search-item">
+        <div class="video-cover"><img class="lazy" data-src="https://img.example.com/2048.jpg"></div>
+        <div class="video-info">
+          <h3><a href="/voddetail/2048.html">示例 电影</a></h3>
+        </div>
+      </div>
+      <div class="module-search-item">
+        <h3><a href="/topic/1.html">专题</a></h3>
+      </div>
+      <div class="page-info">共 <strong>37</strong> 条结果</div>
+    </body></html>
+  "#;
+
+  const DETAIL_PAGE: &str = r#"
+    <html><body>
+      <div class="module-info">
+        <div class="module-info-poster"><img data-src="/upload/vod/1024.jpg"></div>
+        <h1 class="page-title">示例剧集</h1>
+        <div class="module-info-tag">
+          <a href="/vodshow/13-----------2023.html">2023</a>
+          <a href="/vodshow/13-大陆----------.html">大陆</a>
+          <a href="/vodtype/13.html">国产剧</a>
+        </div>
+        <div class="module-info-item director"><a>导演甲</a></div>
+        <div class="module-info-item actor"><a>演员甲</a> / <a>演员乙</a></div>
+        <div class="module-info-item remarks">更新至 第2集</div>
+        <div class="module-info-introduction-content">
+          <p>剧情
+            简介</p>
+        </div>
+      </div>
+      <div class="module-play-list">
+        <a href="/vodplay/1024-1-1.html"><span>第01集</span></a>
+        <a href="/vodplay/1024-1-2.html"><span>第02集</span></a>
+      </div>
+      <div class="module-play-list">
+        <a href="/vodplay/1024-2-1.html"><span>第01集</span></a>
+      </div>
+    </body></html>
+  "#;
+
+  fn channel() -> HtmlMediaService {
+    let field = |selector: &str| json!({ "selector": selector });
+
+    let config = serde_json::from_value(json!({
+      "name": "Example",
+      "url": "https://www.example.com",
+      "http-version": 1,
+      "categories": [{ "pattern": "剧$", "kind": "tv" }],
+      "search": {
+        "url": "{base_url}/vodsearch/{keyword}----------{page}---.html",
+        "items": ".module-search-item",
+        "id": { "selector": "h3 a", "attribute": "href", "pattern": r"/voddetail/(\d+)\.html" },
+        "name": field("h3 a"),
+        "poster-url": { "selector": "img", "attribute": "data-src" },
+        "total": field(".page-info strong")
+      },
+      "detail": {
+        "url": "{base_url}/voddetail/{media_id}.html",
+        "name": field(".page-title"),
+        "poster-url": { "selector": ".module-info-poster img", "attribute": "data-src" },
+        "release-year": field(".module-info-tag a:nth-child(1)"),
+        "region": field(".module-info-tag a:nth-child(2)"),
+        "category": field(".module-info-tag a:nth-child(3)"),
+        "directors": field(".director a"),
+        "cast": field(".actor a"),
+        "remarks": field(".remarks"),
+        "description": field(".module-info-introduction-content")
+      },
+      "playlist": {
+        "episodes": ".module-play-list:nth-of-type(2) a"
+      },
+      "player": {
+        "frame": { "selector": "iframe", "attribute": "src" }
+      }
+    }))
+    .unwrap();
+
+    HtmlMediaService::new("example", &config).unwrap()
+  }
+
+  #[test]
+  fn test_render_url() {
+    let channel = channel();
+
+    assert_eq!(
+      channel.render_url(&channel.search.url, &[("keyword", "示例"), ("page", "1")]),
+      "https://www.example.com/vodsearch/%E7%A4%BA%E4%BE%8B----------1---.html"
+    );
+  }
+
+  #[test]
+  fn test_parse_search_page() {
+    let channel = channel();
+    let page_url = Url::parse("https://www.example.com/vodsearch/abc.html").unwrap();
+
+    let (items, total) = channel.parse_search_page(SEARCH_PAGE, &page_url);
+
+    assert_eq!(total, Some(37));
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].id, "1024");
+    assert_eq!(items[0].name, "示例剧集");
+    assert_eq!(
+      items[0].poster_url,
+      "https://www.example.com/upload/vod/1024.jpg"
+    );
+    assert_eq!(items[1].id, "2048");
+    assert_eq!(items[1].name, "示例 电影");
+    assert_eq!(items[1].poster_url, "https://img.example.com/2048.jpg");
+  }
+
+  #[test]
+  fn test_parse_detail_page() {
+    let channel = channel();
+    let page_url = Url::parse("https://www.example.com/voddetail/1024.html").unwrap();
+
+    let metadata = channel
+      .parse_detail_page("1024", DETAIL_PAGE, &page_url)
+      .unwrap();
+
+    assert_eq!(metadata.id, "1024");
+    assert_eq!(metadata.channel, "example");
+    assert_eq!(metadata.name, "示例剧集");
+    assert_eq!(metadata.kind, MediaKind::TV);
+    assert_eq!(metadata.release_year, 2023);
+    assert_eq!(metadata.region, "大陆");
+    assert_eq!(metadata.directors, vec!["导演甲"]);
+    assert_eq!(metadata.cast, vec!["演员甲", "演员乙"]);
+    assert_eq!(metadata.remarks, "更新至 第2集");
+    assert_eq!(metadata.description, "剧情 简介");
+    assert_eq!(
+      metadata.poster_url,
+      "https://www.example.com/upload/vod/1024.jpg"
+    );
+
+    assert!(channel
+      .parse_detail_page("1024", "<html></html>", &page_url)
+      .is_err());
+  }
+
+  #[test]
+  fn test_parse_playlist_page() {
+    let channel = channel();
+    let page_url = Url::parse("https://www.example.com/voddetail/1024.html").unwrap();
+
+    let items = channel.parse_playlist_page(DETAIL_PAGE, &page_url);
+
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].number, 1);
+    assert_eq!(items[0].text, "第01集");
+    assert_eq!(
+      items[0].url,
+      "https://www.example.com/vodplay/1024-1-1.html"
+    );
+    assert_eq!(items[1].number, 2);
+    assert_eq!(
+      items[1].url,
+      "https://www.example.com/vodplay/1024-1-2.html"
+    );
+  }
+
+  #[test]
+  fn test_parse_player_page() {
+    let channel = channel();
+    let page_url = Url::parse("https://www.example.com/vodplay/1024-1-1.html").unwrap();
+
+    let script_page = r#"<script>var player_aaaa={"flag":"play","url":"https:\/\/cdn.example.com\/20230714\/abc\/index.m3u8","from":"example"}</script>"#;
+
+    match channel.parse_player_page(script_page, &page_url).unwrap() {
+      PlayerSource::Media(url) => assert_eq!(
+        url.as_str(),
+        "https://cdn.example.com/20230714/abc/index.m3u8"
+      ),
+      PlayerSource::Frame(_) => panic!("Expected the m3u8 URL"),
+    }
+
+    let frame_page =
+      r#"<div class="player"><iframe src="/player/?url=abc" allowfullscreen></iframe></div>"#;
+
+    match channel.parse_player_page(frame_page, &page_url).unwrap() {
+      PlayerSource::Frame(url) => {
+        assert_eq!(url.as_str(), "https://www.example.com/player/?url=abc")
+      }
+      PlayerSource::Media(_) => panic!("Expected the player frame"),
+    }
+
+    assert!(channel
+      .parse_player_page("<html></html>", &page_url)
+      .is_err());
+  }
+}
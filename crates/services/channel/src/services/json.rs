@@ -0,0 +1,639 @@
+mod path;
+
+use self::path::JsonPath;
+use crate::common::{get_media_renditions, render_url_template, split_names};
+use crate::common::{Category, CategoryMapper, HttpClient};
+use crate::services::DownloadMediaOptions;
+use crate::services::MediaChannelExt;
+use anyhow::Context;
+use configuration::{DownloadConfig, JsonPlaylistConfig};
+use configuration::{JsonChannelConfig, JsonEndpointConfig, JsonMetadataMappingConfig};
+use protocol::channel::{MediaMetadata, MediaRenditions};
+use protocol::channel::{MediaPlaylist, MediaPlaylistItem};
+use protocol::channel::{SearchMediaRequest, SearchMediaResponse};
+use protocol::DownloadProgressReceiver;
+use serde_json::Value;
+
+/// Channel whose API is described by its configuration: URL templates of the endpoints and paths
+/// of the fields in the JSON responses.
+pub struct JsonMediaService {
+  channel_id: String,
+  display_name: String,
+  base_url: String,
+  http_client: HttpClient,
+  category_mapper: CategoryMapper,
+  download_config: DownloadConfig,
+  search: Endpoint,
+  detail: Endpoint,
+  playlist: PlaylistMapping,
+  fields: MetadataMapping,
+}
+
+struct Endpoint {
+  url: String,
+  items: JsonPath,
+  total: Option<JsonPath>,
+}
+
+struct PlaylistMapping {
+  url: Option<String>,
+  items: JsonPath,
+  text: JsonPath,
+  media_url: JsonPath,
+  number: Option<JsonPath>,
+}
+
+struct MetadataMapping {
+  id: JsonPath,
+  name: JsonPath,
+  poster_url: Option<JsonPath>,
+  release_year: Option<JsonPath>,
+  description: Option<JsonPath>,
+  category_id: Option<JsonPath>,
+  category_name: Option<JsonPath>,
+  cast: Option<JsonPath>,
+  directors: Option<JsonPath>,
+  region: Option<JsonPath>,
+  language: Option<JsonPath>,
+  remarks: Option<JsonPath>,
+  total_episodes: Option<JsonPath>,
+  updated_at: Option<JsonPath>,
+  score: Option<JsonPath>,
+}
+
+#[async_trait::async_trait]
+impl MediaChannelExt for JsonMediaService {
+  fn display_name(&self) -> &str {
+    &self.display_name
+  }
+
+  fn base_url(&self) -> &str {
+    &self.base_url
+  }
+
+  async fn download_media(
+    &self,
+    options: DownloadMediaOptions,
+  ) -> anyhow::Result<DownloadProgressReceiver> {
+    let media_url = self
+      .get_media_url(&options.media_id, options.number)
+      .await?;
+
+    let download_opts = crate::common::DownloadMediaOptions {
+      download_url: &media_url,
+      destination_path: &options.destination_path,
+      http_client: &self.http_client,
+      download_config: &self.download_config,
+      renditions: &options.renditions,
+      container: options.container,
+      clip: options.clip,
+      audio_only: options.audio_only,
+      recording: options.recording.as_ref(),
+      trim: options.trim,
+      tags: options.tags.as_ref(),
+    };
+
+    let progress = crate::common::download_media(download_opts).await?;
+
+    Ok(progress)
+  }
+
+  async fn get_media_metadata(&self, media_id: &str) -> anyhow::Result<MediaMetadata> {
+    let response = self.get_media_detail(media_id).await?;
+
+    let item = select_items(&self.detail.items, &response)
+      .into_iter()
+      .next()
+      .ok_or_else(|| anyhow::anyhow!("Invalid media ID: {:?}", media_id))?;
+
+    self.to_media_metadata(item)
+  }
+
+  async fn search_media(
+    &self,
+    request: &SearchMediaRequest,
+  ) -> anyhow::Result<SearchMediaResponse> {
+    let url = self.render_url(
+      &self.search.url,
+      &[
+        ("keyword", &request.keyword),
+        ("page", &request.page.to_string()),
+        ("page_size", &request.page_size.to_string()),
+      ],
+    );
+
+    log::info!("Search media with: {}", url);
+
+    let response = self.request_json(&url).await?;
+
+    let items: Vec<MediaMetadata> = select_items(&self.search.items, &response)
+      .into_iter()
+      .filter_map(|item| match self.to_media_metadata(item) {
+        Ok(metadata) => Some(metadata),
+        Err(err) => {
+          log::warn!(
+            "Skip invalid search result of {}: {:#}",
+            self.channel_id,
+            err
+          );
+          None
+        }
+      })
+      .collect();
+
+    let total = self
+      .search
+      .total
+      .as_ref()
+      .and_then(|total| total.first(&response))
+      .and_then(value_to_u32)
+      .unwrap_or(items.len() as u32);
+
+    let page_size = if request.page_size > 0 {
+      request.page_size
+    } else {
+      items.len() as u32
+    };
+
+    Ok(SearchMediaResponse {
+      items,
+      total,
+      page: request.page,
+      page_size,
+    })
+  }
+
+  async fn get_media_playlist(&self, media_id: &str) -> anyhow::Result<MediaPlaylist> {
+    let response = match &self.playlist.url {
+      Some(url) => {
+        let url = self.render_url(url, &[("media_id", media_id)]);
+
+        self.request_json(&url).await?
+      }
+      None => self.get_media_detail(media_id).await?,
+    };
+
+    let items = select_items(&self.playlist.items, &response)
+      .into_iter()
+      .enumerate()
+      .map(|(index, item)| self.to_media_playlist_item(index, item))
+      .collect();
+
+    Ok(MediaPlaylist {
+      channel: self.channel_id.clone(),
+      media_id: media_id.to_string(),
+      items,
+    })
+  }
+
+  fn http_client(&self) -> &HttpClient {
+    &self.http_client
+  }
+
+  fn download_config(&self) -> &DownloadConfig {
+    &self.download_config
+  }
+
+  async fn get_media_renditions(
+    &self,
+    media_id: &str,
+    number: Option<u32>,
+  ) -> anyhow::Result<MediaRenditions> {
+    let media_url = self.get_media_url(media_id, number).await?;
+
+    get_media_renditions(&self.http_client, &media_url).await
+  }
+}
+
+impl JsonMediaService {
+  async fn get_media_url(&self, media_id: &str, number: Option<u32>) -> anyhow::Result<String> {
+    let playlist = self.get_media_playlist(media_id).await?;
+
+    let item = playlist
+      .items
+      .iter()
+      .find(|item| item.number == number.unwrap_or(1))
+      .ok_or_else(|| anyhow::anyhow!("Invalid number {:?} of media {}", number, media_id))?;
+
+    Ok(item.url.clone())
+  }
+
+  async fn get_media_detail(&self, media_id: &str) -> anyhow::Result<Value> {
+    log::info!("Getting video detail of {:?}", media_id);
+
+    let url = self.render_url(&self.detail.url, &[("media_id", media_id)]);
+
+    self.request_json(&url).await
+  }
+
+  async fn request_json(&self, url: &str) -> anyhow::Result<Value> {
+    let client = self.http_client.client();
+
+    self
+      .http_client
+      .retry_policy()
+      .run(url, || async {
+        let request = client.get(url).version(self.http_client.http_version());
+
+        let res = self
+          .http_client
+          .send(request)
+          .await
+          .context("Failed to send request")?
+          .error_for_status()
+          .context("Request failed")?;
+
+        res.json().await.context("Failed to parse JSON response")
+      })
+      .await
+  }
+
+  fn render_url(&self, template: &str, params: &[(&str, &str)]) -> String {
+    render_url_template(template, &self.base_url, params)
+  }
+
+  fn to_media_metadata(&self, item: &Value) -> anyhow::Result<MediaMetadata> {
+    let fields = &self.fields;
+
+    let id = fields
+      .id
+      .first(item)
+      .and_then(value_to_string)
+      .ok_or_else(|| anyhow::anyhow!("No media ID found in {}", item))?;
+
+    let name = fields
+      .name
+      .first(item)
+      .and_then(value_to_string)
+      .ok_or_else(|| anyhow::anyhow!("No media name found in {}", item))?;
+
+    let category_id = field(&fields.category_id, item).and_then(value_to_u32);
+    let category_name = string_field(&fields.category_name, item);
+
+    let kind = self.category_mapper.media_kind([Category {
+      id: category_id,
+      name: &category_name,
+    }]);
+
+    Ok(MediaMetadata {
+      kind,
+      channel: self.channel_id.clone(),
+      id,
+      name,
+      release_year: field(&fields.release_year, item)
+        .and_then(value_to_year)
+        .unwrap_or(0),
+      poster_url: string_field(&fields.poster_url, item),
+      description: string_field(&fields.description, item),
+      cast: names_field(&fields.cast, item),
+      directors: names_field(&fields.directors, item),
+      region: string_field(&fields.region, item),
+      language: string_field(&fields.language, item),
+      remarks: string_field(&fields.remarks, item),
+      total_episodes: field(&fields.total_episodes, item)
+        .and_then(value_to_u32)
+        .filter(|total| *total > 0),
+      updated_at: field(&fields.updated_at, item)
+        .and_then(value_to_string)
+        .filter(|updated_at| !updated_at.is_empty()),
+      score: field(&fields.score, item)
+        .and_then(value_to_f32)
+        .filter(|score| *score > 0.0),
+    })
+  }
+
+  fn to_media_playlist_item(&self, index: usize, item: &Value) -> MediaPlaylistItem {
+    let number = field(&self.playlist.number, item)
+      .and_then(value_to_u32)
+      .unwrap_or(index as u32 + 1);
+
+    MediaPlaylistItem {
+      number,
+      text: self
+        .playlist
+        .text
+        .first(item)
+        .and_then(value_to_string)
+        .unwrap_or_default(),
+      url: self
+        .playlist
+        .media_url
+        .first(item)
+        .and_then(value_to_string)
+        .unwrap_or_default(),
+      probe: None,
+    }
+  }
+}
+
+// Arrays matched by a path are flattened, so that both `$.list` and `$.list[*]` select the items
+fn select_items<'a>(path: &JsonPath, value: &'a Value) -> Vec<&'a Value> {
+  path
+    .select(value)
+    .into_iter()
+    .flat_map(|value| match value {
+      Value::Array(items) => items.iter().collect(),
+      value => vec![value],
+    })
+    .collect()
+}
+
+fn field<'a>(path: &Option<JsonPath>, item: &'a Value) -> Option<&'a Value> {
+  path.as_ref().and_then(|path| path.first(item))
+}
+
+fn string_field(path: &Option<JsonPath>, item: &Value) -> String {
+  field(path, item)
+    .and_then(value_to_string)
+    .unwrap_or_default()
+}
+
+fn names_field(path: &Option<JsonPath>, item: &Value) -> Vec<String> {
+  let Some(path) = path else {
+    return vec![];
+  };
+
+  path
+    .select(item)
+    .into_iter()
+    .flat_map(|value| match value {
+      Value::Array(names) => names.iter().filter_map(value_to_string).collect(),
+      value => value_to_string(value)
+        .map(|names| split_names(&names))
+        .unwrap_or_default(),
+    })
+    .filter(|name| !name.is_empty())
+    .collect()
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+  match value {
+    Value::String(value) => Some(value.trim().to_string()),
+    Value::Number(value) => Some(value.to_string()),
+    Value::Bool(value) => Some(value.to_string()),
+    _ => None,
+  }
+}
+
+fn value_to_u32(value: &Value) -> Option<u32> {
+  match value {
+    Value::Number(value) => value.as_u64().and_then(|value| value.try_into().ok()),
+    Value::String(value) => value.trim().parse().ok(),
+    _ => None,
+  }
+}
+
+fn value_to_f32(value: &Value) -> Option<f32> {
+  match value {
+    Value::Number(value) => value.as_f64().map(|value| value as f32),
+    Value::String(value) => value.trim().parse().ok(),
+    _ => None,
+  }
+}
+
+// Release years are often given as dates, e.g. `2023-05-01`
+fn value_to_year(value: &Value) -> Option<u32> {
+  let value = value_to_string(value)?;
+  let year: String = value.chars().take_while(|c| c.is_ascii_digit()).collect();
+
+  year.parse().ok()
+}
+
+fn optional_path(path: &Option<String>) -> anyhow::Result<Option<JsonPath>> {
+  path.as_deref().map(JsonPath::parse).transpose()
+}
+
+impl Endpoint {
+  fn new(config: &JsonEndpointConfig) -> anyhow::Result<Self> {
+    Ok(Self {
+      url: config.url.clone(),
+      items: JsonPath::parse(config.items.as_deref().unwrap_or("$"))?,
+      total: optional_path(&config.total)?,
+    })
+  }
+}
+
+impl PlaylistMapping {
+  fn new(config: &JsonPlaylistConfig) -> anyhow::Result<Self> {
+    Ok(Self {
+      url: config.url.clone(),
+      items: JsonPath::parse(&config.items)?,
+      text: JsonPath::parse(&config.text)?,
+      media_url: JsonPath::parse(&config.media_url)?,
+      number: optional_path(&config.number)?,
+    })
+  }
+}
+
+impl MetadataMapping {
+  fn new(config: &JsonMetadataMappingConfig) -> anyhow::Result<Self> {
+    Ok(Self {
+      id: JsonPath::parse(&config.id)?,
+      name: JsonPath::parse(&config.name)?,
+      poster_url: optional_path(&config.poster_url)?,
+      release_year: optional_path(&config.release_year)?,
+      description: optional_path(&config.description)?,
+      category_id: optional_path(&config.category_id)?,
+      category_name: optional_path(&config.category_name)?,
+      cast: optional_path(&config.cast)?,
+      directors: optional_path(&config.directors)?,
+      region: optional_path(&config.region)?,
+      language: optional_path(&config.language)?,
+      remarks: optional_path(&config.remarks)?,
+      total_episodes: optional_path(&config.total_episodes)?,
+      updated_at: optional_path(&config.updated_at)?,
+      score: optional_path(&config.score)?,
+    })
+  }
+}
+
+impl JsonMediaService {
+  pub fn new(channel_id: &str, config: &JsonChannelConfig) -> anyhow::Result<Self> {
+    let http_version = if config.http_version.unwrap_or(2) == 1 {
+      http::Version::HTTP_11
+    } else {
+      http::Version::HTTP_2
+    };
+
+    let http_client = HttpClient::new(
+      &config.base_url,
+      http_version,
+      &config.http,
+      &config.retry,
+      config.rate_limit.as_ref(),
+    )?;
+
+    Ok(Self {
+      channel_id: channel_id.to_owned(),
+      display_name: config.name.clone(),
+      base_url: config.base_url.clone(),
+      http_client,
+      category_mapper: CategoryMapper::new(&config.categories)?,
+      download_config: config.download.clone(),
+      search: Endpoint::new(&config.search).context("Invalid search endpoint")?,
+      detail: Endpoint::new(&config.detail).context("Invalid detail endpoint")?,
+      playlist: PlaylistMapping::new(&config.playlist).context("Invalid playlist")?,
+      fields: MetadataMapping::new(&config.fields).context("Invalid field mappings")?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{select_items, JsonMediaService};
+  use protocol::channel::MediaKind;
+  use serde_json::json;
+
+  fn channel() -> JsonMediaService {
+    let config = serde_json::from_value(json!({
+      "name": "Example",
+      "url": "https://api.example.com/",
+      "http-version": 1,
+      "categories": [{ "name": "Series", "kind": "tv" }],
+      "search": {
+        "url": "{base_url}/v1/search?q={keyword}&page={page}",
+        "items": "$.data.results",
+        "total": "$.data.count"
+      },
+      "detail": {
+        "url": "{base_url}/v1/videos/{media_id}",
+        "items": "$.data"
+      },
+      "playlist": {
+        "items": "$.data.sources[0].episodes[*]",
+        "text": "title",
+        "media-url": "$['stream']['hls']",
+        "number": "$.index"
+      },
+      "fields": {
+        "id": "$.id",
+        "name": "$.title",
+        "poster-url": "$.images.poster",
+        "release-year": "$.released_at",
+        "description": "$.summary",
+        "category-name": "$.genre.name",
+        "cast": "$.credits.actors[*].name",
+        "directors": "$.credits.director",
+        "region": "$.country",
+        "total-episodes": "$.episode_count",
+        "score": "$.rating"
+      }
+    }))
+    .unwrap();
+
+    JsonMediaService::new("example", &config).unwrap()
+  }
+
+  fn detail_response() -> serde_json::Value {
+    json!({
+      "code": 0,
+      "data": {
+        "id": 1024,
+        "title": " 示例剧集 ",
+        "images": { "poster": "https://img.example.com/1024.jpg" },
+        "released_at": "2023-07-14",
+        "summary": "<p>剧情简介</p>",
+        "genre": { "id": 2, "name": "Series" },
+        "credits": {
+          "actors": [{ "name": "演员甲" }, { "name": "演员乙" }],
+          "director": "导演甲,导演乙"
+        },
+        "country": "中国大陆",
+        "episode_count": "24",
+        "rating": 8.6,
+        "sources": [{
+          "episodes": [
+            { "index": 1, "title": "第1集", "stream": { "hls": "https://cdn.example.com/1.m3u8" } },
+            { "index": 2, "title": "第2集", "stream": { "hls": "https://cdn.example.com/2.m3u8" } }
+          ]
+        }]
+      }
+    })
+  }
+
+  #[test]
+  fn test_render_url() {
+    let channel = channel();
+
+    assert_eq!(
+      channel.render_url(
+        &channel.search.url,
+        &[("keyword", "示例 剧集&"), ("page", "2")]
+      ),
+      "https://api.example.com/v1/search?q=%E7%A4%BA%E4%BE%8B+%E5%89%A7%E9%9B%86%26&page=2"
+    );
+    assert_eq!(
+      channel.render_url(&channel.detail.url, &[("media_id", "1024")]),
+      "https://api.example.com/v1/videos/1024"
+    );
+  }
+
+  #[test]
+  fn test_to_media_metadata() {
+    let channel = channel();
+    let response = detail_response();
+    let item = select_items(&channel.detail.items, &response)[0];
+
+    let metadata = channel.to_media_metadata(item).unwrap();
+
+    assert_eq!(metadata.channel, "example");
+    assert_eq!(metadata.id, "1024");
+    assert_eq!(metadata.name, "示例剧集");
+    assert_eq!(metadata.kind, MediaKind::TV);
+    assert_eq!(metadata.release_year, 2023);
+    assert_eq!(metadata.poster_url, "https://img.example.com/1024.jpg");
+    assert_eq!(metadata.description, "<p>剧情简介</p>");
+    assert_eq!(metadata.cast, vec!["演员甲", "演员乙"]);
+    assert_eq!(metadata.directors, vec!["导演甲", "导演乙"]);
+    assert_eq!(metadata.region, "中国大陆");
+    assert_eq!(metadata.language, "");
+    assert_eq!(metadata.total_episodes, Some(24));
+    assert_eq!(metadata.updated_at, None);
+    assert_eq!(metadata.score, Some(8.6));
+  }
+
+  #[test]
+  fn test_to_media_metadata_without_required_fields() {
+    let channel = channel();
+
+    assert!(channel
+      .to_media_metadata(&json!({ "title": "示例" }))
+      .is_err());
+    assert!(channel.to_media_metadata(&json!({ "id": 1 })).is_err());
+  }
+
+  #[test]
+  fn test_to_media_playlist_items() {
+    let channel = channel();
+    let response = detail_response();
+
+    let items: Vec<_> = select_items(&channel.playlist.items, &response)
+      .into_iter()
+      .enumerate()
+      .map(|(index, item)| channel.to_media_playlist_item(index, item))
+      .collect();
+
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1].number, 2);
+    assert_eq!(items[1].text, "第2集");
+    assert_eq!(items[1].url, "https://cdn.example.com/2.m3u8");
+  }
+
+  #[test]
+  fn test_search_items() {
+    let channel = channel();
+    let response = json!({
+      "data": {
+        "count": 31,
+        "results": [{ "id": "a1", "title": "甲" }, { "id": "b2", "title": "乙" }]
+      }
+    });
+
+    let ids: Vec<_> = select_items(&channel.search.items, &response)
+      .into_iter()
+      .map(|item| channel.to_media_metadata(item).unwrap().id)
+      .collect();
+
+    assert_eq!(ids, vec!["a1", "b2"]);
+  }
+}
@@ -0,0 +1,170 @@
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+  Key(String),
+  Index(usize),
+  Wildcard,
+}
+
+/// Subset of JSONPath supported by JSON channels: `$`, `.key`, `['key']`, `[0]`, `.*` and `[*]`.
+/// The leading `$` may be omitted, e.g. `data.list[*].title`.
+#[derive(Debug, Clone)]
+pub struct JsonPath {
+  segments: Vec<Segment>,
+}
+
+impl JsonPath {
+  pub fn parse(path: &str) -> anyhow::Result<Self> {
+    let invalid = |reason: &str| anyhow::anyhow!("Invalid path {:?}: {}", path, reason);
+
+    let trimmed = path.trim();
+
+    // Paths without `$` start with a key
+    let normalized = match trimmed.strip_prefix('$') {
+      Some(rest) => rest.to_string(),
+      None if trimmed.starts_with(['.', '[']) || trimmed.is_empty() => trimmed.to_string(),
+      None => format!(".{}", trimmed),
+    };
+
+    let mut segments = vec![];
+    let mut rest = normalized.as_str();
+
+    while !rest.is_empty() {
+      if let Some(after_dot) = rest.strip_prefix('.') {
+        let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+        let key = &after_dot[..end];
+
+        if key.is_empty() {
+          return Err(invalid("empty key"));
+        }
+
+        segments.push(if key == "*" {
+          Segment::Wildcard
+        } else {
+          Segment::Key(key.to_string())
+        });
+
+        rest = &after_dot[end..];
+      } else if let Some(after_bracket) = rest.strip_prefix('[') {
+        let end = after_bracket
+          .find(']')
+          .ok_or_else(|| invalid("unclosed `[`"))?;
+        let selector = after_bracket[..end].trim();
+
+        let segment = if selector == "*" {
+          Segment::Wildcard
+        } else if let Some(key) = quoted(selector) {
+          Segment::Key(key.to_string())
+        } else {
+          Segment::Index(selector.parse().map_err(|_| invalid("invalid index"))?)
+        };
+
+        segments.push(segment);
+        rest = &after_bracket[end + 1..];
+      } else {
+        return Err(invalid("expected `.` or `[`"));
+      }
+    }
+
+    Ok(Self { segments })
+  }
+
+  /// Returns all values matched by the path.
+  pub fn select<'a>(&self, value: &'a Value) -> Vec<&'a Value> {
+    let mut values = vec![value];
+
+    for segment in &self.segments {
+      values = values
+        .into_iter()
+        .flat_map(|value| -> Vec<&'a Value> {
+          match (segment, value) {
+            (Segment::Key(key), Value::Object(object)) => object.get(key).into_iter().collect(),
+            (Segment::Index(index), Value::Array(array)) => array.get(*index).into_iter().collect(),
+            (Segment::Wildcard, Value::Array(array)) => array.iter().collect(),
+            (Segment::Wildcard, Value::Object(object)) => object.values().collect(),
+            _ => vec![],
+          }
+        })
+        .collect();
+    }
+
+    values
+  }
+
+  /// Returns the first value matched by the path, ignoring `null`.
+  pub fn first<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+    self
+      .select(value)
+      .into_iter()
+      .find(|value| !value.is_null())
+  }
+}
+
+fn quoted(selector: &str) -> Option<&str> {
+  ['\'', '"'].iter().find_map(|quote| {
+    selector
+      .strip_prefix(*quote)
+      .and_then(|selector| selector.strip_suffix(*quote))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::JsonPath;
+  use serde_json::json;
+
+  #[test]
+  fn test_select() {
+    let value = json!({
+      "data": {
+        "list": [
+          { "title": "甲", "tags": ["a", "b"] },
+          { "title": "乙", "tags": ["c"] }
+        ],
+        "page size": 20
+      }
+    });
+
+    let test_cases = [
+      ("$", vec![value.clone()]),
+      ("$.data.list[0].title", vec![json!("甲")]),
+      ("data.list[1].title", vec![json!("乙")]),
+      ("$.data.list[*].title", vec![json!("甲"), json!("乙")]),
+      (
+        "$.data.list.*.tags[*]",
+        vec![json!("a"), json!("b"), json!("c")],
+      ),
+      ("$['data'][\"page size\"]", vec![json!(20)]),
+      ("$.data.list[2].title", vec![]),
+      ("$.data.missing", vec![]),
+      ("$.data.list.title", vec![]),
+    ];
+
+    for (path, expected) in test_cases {
+      let path = JsonPath::parse(path).unwrap();
+      let selected: Vec<_> = path.select(&value).into_iter().cloned().collect();
+
+      assert_eq!(selected, expected, "Unexpected values of {:?}", path);
+    }
+  }
+
+  #[test]
+  fn test_first_skips_null() {
+    let value = json!({ "list": [{ "url": null }, { "url": "b" }] });
+    let path = JsonPath::parse("$.list[*].url").unwrap();
+
+    assert_eq!(path.first(&value), Some(&json!("b")));
+  }
+
+  #[test]
+  fn test_invalid_paths() {
+    for path in ["$.", "$.list[", "$.list[x]", "$list", "$..title"] {
+      assert!(
+        JsonPath::parse(path).is_err(),
+        "{:?} should be invalid",
+        path
+      );
+    }
+  }
+}
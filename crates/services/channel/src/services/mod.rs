@@ -1,6 +1,13 @@
+pub mod direct;
+pub mod html;
+pub mod json;
 pub mod unified;
 
+use crate::common::{probe_playlist_items, HttpClient, VariantPolicy};
+use configuration::DownloadConfig;
 use protocol::channel::MediaMetadata;
+use protocol::channel::{LiveRecording, MediaClip, MediaContainer, MediaTags, MediaTrim};
+use protocol::channel::{MediaRenditions, RenditionSelection};
 use protocol::channel::{SearchMediaRequest, SearchMediaResponse};
 use protocol::media::MediaPlaylist;
 use protocol::DownloadProgressReceiver;
@@ -10,6 +17,14 @@ pub struct DownloadMediaOptions {
   pub media_id: String,
   pub number: Option<u32>,
   pub destination_path: PathBuf,
+  pub renditions: RenditionSelection,
+  pub container: Option<MediaContainer>,
+  pub clip: Option<MediaClip>,
+  // Only the audio of the media is needed
+  pub audio_only: bool,
+  pub recording: Option<LiveRecording>,
+  pub trim: Option<MediaTrim>,
+  pub tags: Option<MediaTags>,
 }
 
 #[async_trait::async_trait]
@@ -24,4 +39,30 @@ pub trait MediaChannelExt: Send + Sync {
   async fn search_media(&self, request: &SearchMediaRequest)
     -> anyhow::Result<SearchMediaResponse>;
   async fn get_media_playlist(&self, media_id: &str) -> anyhow::Result<MediaPlaylist>;
+  fn http_client(&self) -> &HttpClient;
+  fn download_config(&self) -> &DownloadConfig;
+
+  // Probes the media of each item of `playlist`
+  async fn probe_media_playlist(&self, playlist: &mut MediaPlaylist) {
+    let variant_policy = VariantPolicy::new(self.download_config());
+
+    probe_playlist_items(
+      self.http_client(),
+      &variant_policy,
+      &mut playlist.items,
+      |url| self.media_url_of_item(url),
+    )
+    .await
+  }
+
+  // URL of the media of a playlist item, which is that of the item unless it links to a page
+  async fn media_url_of_item(&self, url: String) -> anyhow::Result<String> {
+    Ok(url)
+  }
+
+  async fn get_media_renditions(
+    &self,
+    media_id: &str,
+    number: Option<u32>,
+  ) -> anyhow::Result<MediaRenditions>;
 }
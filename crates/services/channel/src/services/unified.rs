@@ -1,24 +1,34 @@
 mod api;
+mod xml;
 
 use self::api::{
-  parse_root_type, Detail, ListRequest, Response as UnifiedAPIResponse, TypeItem, UnifiedAPI,
+  category_lineage, Detail, ListRequest, Response as UnifiedAPIResponse, TypeItem, UnifiedAPI,
 };
+use crate::common::{get_media_renditions, VariantPolicy};
+use crate::common::{select_source, SourceCandidate, SourceHistory};
+use crate::common::{CategoryMapper, HttpClient};
 use crate::services::DownloadMediaOptions;
 use crate::services::MediaChannelExt;
-use configuration::UnifiedItemConfig;
+use configuration::{DownloadConfig, UnifiedItemConfig};
 use parking_lot::Mutex;
 use protocol::channel::MediaKind;
 use protocol::channel::MediaMetadata;
+use protocol::channel::MediaRenditions;
 use protocol::channel::{MediaPlaylist, MediaPlaylistItem};
 use protocol::channel::{SearchMediaRequest, SearchMediaResponse};
 use protocol::DownloadProgressReceiver;
+use std::sync::Arc;
 
 pub struct UnifiedMediaService {
   channel_id: String,
   display_name: String,
   api: UnifiedAPI,
+  http_client: HttpClient,
   base_url: String,
   types: Mutex<Vec<TypeItem>>,
+  category_mapper: CategoryMapper,
+  download_config: DownloadConfig,
+  source_history: Arc<SourceHistory>,
 }
 
 #[async_trait::async_trait]
@@ -35,35 +45,38 @@ impl MediaChannelExt for UnifiedMediaService {
     &self,
     options: DownloadMediaOptions,
   ) -> anyhow::Result<DownloadProgressReceiver> {
-    let detail = self.get_media_detail(&options.media_id).await?;
+    let sources = self
+      .get_media_sources(&options.media_id, options.number)
+      .await?;
 
-    let mut play_url_list = detail.play_url.split('#');
+    let source = select_source(
+      &self.http_client,
+      &VariantPolicy::new(&self.download_config),
+      &self.source_history,
+      &self.download_config.source_selection,
+      sources,
+    )
+    .await?;
 
-    let number: usize = options.number.unwrap_or(1).try_into()?;
+    log::info!("Downloading from source {} ({})", source.name, source.url);
 
-    let url_of_number = play_url_list.nth(number - 1).ok_or_else(|| {
-      anyhow::anyhow!(
-        "Invalid number {:?} of media {}",
-        options.number,
-        options.media_id
-      )
-    })?;
-
-    let m3u8_url = url_of_number.split('$').nth(1).ok_or_else(|| {
-      anyhow::anyhow!(
-        "Invalid url format found: {} ({}#{:?})",
-        url_of_number,
-        options.media_id,
-        options.number,
-      )
-    })?;
+    let m3u8_url = source.url;
 
     let download_opts = crate::common::DownloadMediaOptions {
-      download_url: m3u8_url,
+      download_url: &m3u8_url,
       destination_path: &options.destination_path,
+      http_client: &self.http_client,
+      download_config: &self.download_config,
+      renditions: &options.renditions,
+      container: options.container,
+      clip: options.clip,
+      audio_only: options.audio_only,
+      recording: options.recording.as_ref(),
+      trim: options.trim,
+      tags: options.tags.as_ref(),
     };
 
-    let progress = crate::common::download_media_using_ffmpeg(download_opts).await?;
+    let progress = crate::common::download_media(download_opts).await?;
 
     Ok(progress)
   }
@@ -73,19 +86,7 @@ impl MediaChannelExt for UnifiedMediaService {
 
     self.ensure_types().await.ok();
 
-    let release_year = detail.year.parse().unwrap_or(0);
-
-    let kind = self.parse_media_kind(detail.type_id);
-
-    Ok(MediaMetadata {
-      kind,
-      channel: self.channel_id.clone(),
-      id: detail.id.to_string(),
-      name: detail.name,
-      release_year,
-      poster_url: detail.picture,
-      description: detail.description,
-    })
+    Ok(self.to_media_metadata(&detail))
   }
 
   async fn search_media(
@@ -115,20 +116,7 @@ impl MediaChannelExt for UnifiedMediaService {
     let items = items_with_detail
       .list
       .iter()
-      .map(|detail| {
-        let release_year = detail.year.parse().unwrap_or(0);
-        let kind = self.parse_media_kind(detail.type_id);
-
-        MediaMetadata {
-          kind,
-          channel: self.channel_id.clone(),
-          id: detail.id.to_string(),
-          name: detail.name.clone(),
-          release_year,
-          poster_url: detail.picture.clone(),
-          description: detail.description.clone(),
-        }
-      })
+      .map(|detail| self.to_media_metadata(detail))
       .collect();
 
     let page_size: u32 = search_result.limit.into();
@@ -144,19 +132,22 @@ impl MediaChannelExt for UnifiedMediaService {
   async fn get_media_playlist(&self, media_id: &str) -> anyhow::Result<crate::MediaPlaylist> {
     let detail = self.get_media_detail(media_id).await?;
 
-    let playlist: Vec<MediaPlaylistItem> = detail
-      .play_url
-      .split('#')
+    // Items are listed as on the first source, the others are matched by their text
+    let items = detail
+      .sources()
+      .into_iter()
+      .next()
+      .map(|source| source.items)
+      .unwrap_or_default();
+
+    let playlist: Vec<MediaPlaylistItem> = items
+      .into_iter()
       .enumerate()
-      .map(|(index, url)| {
-        let number = index + 1;
-        let name_and_url = url.split('$').collect::<Vec<_>>();
-
-        MediaPlaylistItem {
-          number: number as u32,
-          text: name_and_url.first().unwrap_or(&"").to_string(),
-          url: name_and_url.get(1).unwrap_or(&"").to_string(),
-        }
+      .map(|(index, (text, url))| MediaPlaylistItem {
+        number: index as u32 + 1,
+        text,
+        url,
+        probe: None,
       })
       .collect();
 
@@ -166,9 +157,66 @@ impl MediaChannelExt for UnifiedMediaService {
       items: playlist,
     })
   }
+
+  fn http_client(&self) -> &HttpClient {
+    &self.http_client
+  }
+
+  fn download_config(&self) -> &DownloadConfig {
+    &self.download_config
+  }
+
+  async fn get_media_renditions(
+    &self,
+    media_id: &str,
+    number: Option<u32>,
+  ) -> anyhow::Result<MediaRenditions> {
+    let m3u8_url = self.get_media_url(media_id, number).await?;
+
+    get_media_renditions(&self.http_client, &m3u8_url).await
+  }
 }
 
 impl UnifiedMediaService {
+  // The renditions are those of the source downloaded without probes
+  async fn get_media_url(&self, media_id: &str, number: Option<u32>) -> anyhow::Result<String> {
+    let mut sources = self.get_media_sources(media_id, number).await?;
+
+    self.source_history.rank(&mut sources);
+
+    Ok(sources.remove(0).url)
+  }
+
+  // Sources with a URL for item `number` of the media
+  async fn get_media_sources(
+    &self,
+    media_id: &str,
+    number: Option<u32>,
+  ) -> anyhow::Result<Vec<SourceCandidate>> {
+    let detail = self.get_media_detail(media_id).await?;
+
+    let index: usize = number.unwrap_or(1).try_into()?;
+
+    let sources: Vec<SourceCandidate> = match index.checked_sub(1) {
+      Some(index) => detail
+        .item_sources(index)
+        .into_iter()
+        .map(|(name, url)| SourceCandidate { name, url })
+        .collect(),
+      None => vec![],
+    };
+
+    if sources.is_empty() {
+      anyhow::bail!(
+        "No source of number {:?} of media {} found",
+        number,
+        media_id
+      );
+    }
+
+    Ok(sources)
+  }
+
   async fn get_media_detail(&self, id: &str) -> anyhow::Result<Detail> {
     log::info!("Getting video detail of {:?}", id);
 
@@ -193,10 +241,32 @@ impl UnifiedMediaService {
     Ok(details)
   }
 
-  fn parse_media_kind(&self, type_id: u32) -> MediaKind {
+  fn to_media_metadata(&self, detail: &Detail) -> MediaMetadata {
+    MediaMetadata {
+      kind: self.parse_media_kind(detail.type_id, &detail.type_name),
+      channel: self.channel_id.clone(),
+      id: detail.id.to_string(),
+      name: detail.name.clone(),
+      release_year: detail.year.parse().unwrap_or(0),
+      poster_url: detail.picture.clone(),
+      description: detail.description.clone(),
+      cast: detail.cast(),
+      directors: detail.directors(),
+      region: detail.area.trim().to_string(),
+      language: detail.lang.trim().to_string(),
+      remarks: detail.remarks.trim().to_string(),
+      total_episodes: detail.total_episodes(),
+      updated_at: detail.updated_at(),
+      score: detail.score(),
+    }
+  }
+
+  fn parse_media_kind(&self, type_id: u32, type_name: &str) -> MediaKind {
     let types = self.types.lock();
 
-    parse_root_type(&types, type_id)
+    let lineage = category_lineage(&types, type_id, type_name);
+
+    self.category_mapper.media_kind(lineage)
   }
 
   async fn ensure_types(&self) -> anyhow::Result<()> {
@@ -212,7 +282,8 @@ impl UnifiedMediaService {
         })
         .await?;
 
-      *self.types.lock() = res.class.unwrap();
+      // Mirrors may leave out the class, the categories are then named by the media
+      *self.types.lock() = res.class.unwrap_or_default();
     }
 
     Ok(())
@@ -220,21 +291,37 @@ impl UnifiedMediaService {
 }
 
 impl UnifiedMediaService {
-  pub fn new(channel_id: &str, config: &UnifiedItemConfig) -> Self {
-    let http_version = config.http_version.unwrap_or(2);
-
-    let api = if http_version == 1 {
-      UnifiedAPI::new_with_http_1(&config.base_url)
+  pub fn new(
+    channel_id: &str,
+    config: &UnifiedItemConfig,
+    source_history: Arc<SourceHistory>,
+  ) -> anyhow::Result<Self> {
+    let http_version = if config.http_version.unwrap_or(2) == 1 {
+      http::Version::HTTP_11
     } else {
-      UnifiedAPI::new(&config.base_url)
+      http::Version::HTTP_2
     };
 
-    Self {
+    let http_client = HttpClient::new(
+      &config.base_url,
+      http_version,
+      &config.http,
+      &config.retry,
+      config.rate_limit.as_ref(),
+    )?;
+
+    let api = UnifiedAPI::new(&config.base_url, config.format, http_client.clone());
+
+    Ok(Self {
       channel_id: channel_id.to_owned(),
       display_name: config.name.clone(),
       base_url: config.base_url.clone(),
       api,
+      http_client,
       types: Mutex::new(vec![]),
-    }
+      category_mapper: CategoryMapper::new(&config.categories)?,
+      download_config: config.download.clone(),
+      source_history,
+    })
   }
 }
@@ -1,5 +1,7 @@
-use reqwest::Client;
-use protocol::channel::MediaKind;
+use crate::common::{split_names, Category, HttpClient};
+use super::xml;
+use anyhow::Context;
+use configuration::ApiFormat;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,7 +28,10 @@ pub struct ListItem {
   pub type_name: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Separator of the sources in `vod_play_from` and `vod_play_url`
+pub const SOURCE_SEPARATOR: &str = "$$$";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Detail {
   #[serde(rename = "vod_id")]
   pub id: u32,
@@ -40,8 +45,164 @@ pub struct Detail {
   pub picture: String,
   #[serde(rename = "vod_content")]
   pub description: String,
+  #[serde(rename = "vod_play_from", default, deserialize_with = "lenient_string")]
+  pub play_from: String,
   #[serde(rename = "vod_play_url")]
   pub play_url: String,
+  #[serde(rename = "vod_actor", default, deserialize_with = "lenient_string")]
+  pub actor: String,
+  #[serde(rename = "vod_director", default, deserialize_with = "lenient_string")]
+  pub director: String,
+  #[serde(rename = "vod_area", default, deserialize_with = "lenient_string")]
+  pub area: String,
+  #[serde(rename = "vod_lang", default, deserialize_with = "lenient_string")]
+  pub lang: String,
+  #[serde(rename = "vod_remarks", default, deserialize_with = "lenient_string")]
+  pub remarks: String,
+  #[serde(rename = "vod_total", default, deserialize_with = "lenient_string")]
+  pub total: String,
+  #[serde(rename = "vod_serial", default, deserialize_with = "lenient_string")]
+  pub serial: String,
+  #[serde(rename = "vod_time", default, deserialize_with = "lenient_string")]
+  pub time: String,
+  #[serde(rename = "vod_score", default, deserialize_with = "lenient_string")]
+  pub score: String,
+}
+
+/// Playlist of a media on one of its sources (lines), in the `name$url#name$url` format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaySource {
+  pub name: String,
+  // Text and URL of each item
+  pub items: Vec<(String, String)>,
+}
+
+impl Detail {
+  pub fn sources(&self) -> Vec<PlaySource> {
+    let mut names = self.play_from.split(SOURCE_SEPARATOR);
+
+    self
+      .play_url
+      .split(SOURCE_SEPARATOR)
+      .enumerate()
+      .map(|(index, play_url)| {
+        let name = names
+          .next()
+          .map(|name| name.trim())
+          .filter(|name| !name.is_empty())
+          .map_or_else(|| (index + 1).to_string(), |name| name.to_string());
+
+        let items = play_url
+          .split('#')
+          .map(|item| {
+            let name_and_url = item.split('$').collect::<Vec<_>>();
+
+            (
+              name_and_url.first().unwrap_or(&"").to_string(),
+              name_and_url.get(1).unwrap_or(&"").to_string(),
+            )
+          })
+          .collect();
+
+        PlaySource { name, items }
+      })
+      .collect()
+  }
+
+  /// Name and URL of item `index` on each source. Items are numbered as on the first source, the
+  /// others are matched by their text, since lines may miss or reorder some of them. Lines with
+  /// untitled items are matched by position, as long as they list as many items.
+  pub fn item_sources(&self, index: usize) -> Vec<(String, String)> {
+    let sources = self.sources();
+
+    let Some(reference) = sources.first() else {
+      return vec![];
+    };
+    let Some((text, _)) = reference.items.get(index) else {
+      return vec![];
+    };
+
+    let text = text.trim().to_string();
+    let count = reference.items.len();
+
+    sources
+      .into_iter()
+      .filter_map(|source| {
+        let url = match text.is_empty() {
+          false => source
+            .items
+            .into_iter()
+            .find(|(item_text, _)| item_text.trim() == text)
+            .map(|(_, url)| url),
+          true if source.items.len() == count => {
+            source.items.into_iter().nth(index).map(|(_, url)| url)
+          }
+          true => None,
+        }?;
+
+        (!url.is_empty()).then_some((source.name, url))
+      })
+      .collect()
+  }
+
+  pub fn cast(&self) -> Vec<String> {
+    split_names(&self.actor)
+  }
+
+  pub fn directors(&self) -> Vec<String> {
+    split_names(&self.director)
+  }
+
+  pub fn total_episodes(&self) -> Option<u32> {
+    // Some channels only fill the serial, which is the total of finished media
+    [&self.total, &self.serial]
+      .iter()
+      .filter_map(|value| value.trim().parse::<u32>().ok())
+      .find(|total| *total > 0)
+  }
+
+  pub fn score(&self) -> Option<f32> {
+    self
+      .score
+      .trim()
+      .parse::<f32>()
+      .ok()
+      .filter(|score| *score > 0.0)
+  }
+
+  pub fn updated_at(&self) -> Option<String> {
+    let time = self.time.trim();
+
+    if time.is_empty() {
+      None
+    } else {
+      Some(time.to_string())
+    }
+  }
+}
+
+// Fields beyond the basic ones are filled inconsistently by channels, they may be strings,
+// numbers or null.
+fn lenient_string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+  D: serde::Deserializer<'de>,
+{
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Lenient {
+    String(String),
+    Integer(i64),
+    Float(f64),
+  }
+
+  let value = Option::<Lenient>::deserialize(deserializer)?;
+
+  Ok(match value {
+    Some(Lenient::String(value)) => value,
+    Some(Lenient::Integer(value)) => value.to_string(),
+    Some(Lenient::Float(value)) => value.to_string(),
+    None => String::new(),
+  })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,16 +231,12 @@ pub type DetailResponse = Response<Detail>;
 
 pub struct UnifiedAPI {
   base_url: String,
-  http_version: http::Version,
+  format: ApiFormat,
+  http_client: HttpClient,
 }
 
-const REQUEST_USER_AGENT: &str = 
-      "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/127.0.0.0 Safari/537.36";
-
 impl UnifiedAPI {
   pub async fn list(&self, request: &ListRequest) -> anyhow::Result<ListResponse> {
-    let client = self.request_client()?;
-
     let query = &[
       ("ac", "list"),
       ("pg", &request.page.to_string()),
@@ -87,28 +244,13 @@ impl UnifiedAPI {
       ("t", &request.type_id.unwrap_or_default().to_string()),
     ];
 
-    let res = client
-      .get(&self.base_url)
-      .query(query)
-      .header("User-Agent", REQUEST_USER_AGENT)
-      .version(self.http_version)
-      .send()
-      .await
-      .map_err(|e| anyhow::anyhow!("Failed to send request: {}", e))?;
-
-    let status = res.status();
-
-    if !status.is_success() {
-      anyhow::bail!("Request failed with status: {}", status);
-    }
-
-    let text = res
-      .text()
-      .await
-      .map_err(|e| anyhow::anyhow!("Failed to get text response: {}", e))?;
+    let text = self.request_text(query).await?;
 
-    let res: ListResponse = serde_json::from_str(&text)
-      .map_err(|e| anyhow::anyhow!("Failed to decode json response: {} ({})", e, text))?;
+    let res: ListResponse = match self.format {
+      ApiFormat::Json => serde_json::from_str(&text)
+        .map_err(|e| anyhow::anyhow!("Failed to decode json response: {} ({})", e, text))?,
+      ApiFormat::Xml => xml::decode_list_response(&text)?,
+    };
 
     if res.code != 1 {
       anyhow::bail!("Request failed with response: {:#?}", res);
@@ -120,33 +262,18 @@ impl UnifiedAPI {
   }
 
   pub async fn get_details<T: AsRef<str>>(&self, ids: &[T]) -> anyhow::Result<DetailResponse> {
-    let client = self.request_client()?;
-
     let ids = ids.iter().map(|s| s.as_ref()).collect::<Vec<_>>().join(",");
     log::info!("Getting details with ids: {}", ids);
 
-    let res = client
-      .get(&self.base_url)
-      .query(&[("ac", "detail"), ("ids", &ids)])
-      .header("User-Agent", REQUEST_USER_AGENT)
-      .version(self.http_version)
-      .send()
-      .await
-      .map_err(|e| anyhow::anyhow!("Failed to send request: {}", e))?;
-
-    let status = res.status();
-
-    if !status.is_success() {
-      anyhow::bail!("Request failed with status: {}", status);
-    }
-
-    let text = res
-      .text()
-      .await
-      .map_err(|e| anyhow::anyhow!("Failed to get text response: {}", e))?;
+    let text = self
+      .request_text(&[("ac", "detail"), ("ids", &ids)])
+      .await?;
 
-    let res: DetailResponse = serde_json::from_str(&text)
-      .map_err(|e| anyhow::anyhow!("Failed to decode json response: {} ({})", e, text))?;
+    let res: DetailResponse = match self.format {
+      ApiFormat::Json => serde_json::from_str(&text)
+        .map_err(|e| anyhow::anyhow!("Failed to decode json response: {} ({})", e, text))?,
+      ApiFormat::Xml => xml::decode_detail_response(&text)?,
+    };
 
     if res.code != 1 {
       anyhow::bail!("Request failed with response: {:#?}", res);
@@ -155,64 +282,374 @@ impl UnifiedAPI {
     Ok(res)
   }
 
-  fn request_client(&self) -> anyhow::Result<Client> {
-    if self.http_version == http::Version::HTTP_2 {
-      Client::builder()
-      .http2_prior_knowledge()
-        .use_rustls_tls()
-        .build()
-        .map_err(|e| anyhow::anyhow!("Failed to create new request client: {}", e))
-    } else {
-      Ok(Client::new())
-    }
+  async fn request_text(&self, query: &[(&str, &str)]) -> anyhow::Result<String> {
+    let client = self.http_client.client();
+
+    self
+      .http_client
+      .retry_policy()
+      .run(&self.base_url, || async {
+        let request = client
+          .get(&self.base_url)
+          .query(query)
+          .version(self.http_client.http_version());
+
+        let res = self
+          .http_client
+          .send(request)
+          .await
+          .context("Failed to send request")?
+          .error_for_status()
+          .context("Request failed")?;
+
+        res.text().await.context("Failed to get text response")
+      })
+      .await
   }
 }
 
 impl UnifiedAPI {
-  pub fn new(base_url: &str) -> Self {
+  pub fn new(base_url: &str, format: ApiFormat, http_client: HttpClient) -> Self {
     Self {
       base_url: base_url.to_owned(),
-      http_version: http::Version::HTTP_2,
+      format,
+      http_client,
     }
   }
+}
 
-  pub fn new_with_http_1(base_url: &str) -> Self {
-    Self {
-      base_url: base_url.to_owned(),
-      http_version: http::Version::HTTP_11
+/// Lists the category `type_id` and its ancestors, from the category itself up to the root.
+/// Channels that don't list the category in `class` still have the name given by the media.
+pub fn category_lineage<'a>(
+  class: &'a [TypeItem],
+  type_id: u32,
+  type_name: &'a str,
+) -> Vec<Category<'a>> {
+  let mut lineage = vec![];
+  let mut current = Some(type_id);
+
+  while let Some(id) = current {
+    // Guard against cycles of malformed category trees
+    if lineage.len() > class.len() {
+      break;
     }
-  }
-}
 
-pub fn parse_root_type(class: &[TypeItem], type_id: u32) -> MediaKind {
-  if let Some(c) = class.iter().find(|c| c.type_id == type_id) {
+    let Some(item) = class.iter().find(|item| item.type_id == id) else {
+      break;
+    };
 
-    let type_pid = c.type_pid.unwrap_or(0);
-    if type_pid != 0 {
-      return parse_root_type(class, type_pid);
-    }
+    lineage.push(Category {
+      id: Some(item.type_id),
+      name: &item.type_name,
+    });
 
-    match c.type_name.as_str() {
-      "电影" => return MediaKind::Movie,
-      "电视剧" => return MediaKind::TV,
-      "综艺" => return MediaKind::Variety,
-      "动漫" => return  MediaKind::Anime,
-      _ => return MediaKind::Other,
-    }
+    current = item.type_pid.filter(|pid| *pid != 0);
+  }
+
+  if lineage.is_empty() {
+    lineage.push(Category {
+      id: Some(type_id),
+      name: type_name,
+    });
   }
 
-  MediaKind::Other
+  lineage
 }
 
 impl From<StringOrNumber> for u32 {
   fn from(val: StringOrNumber) -> Self {
     match val {
-      StringOrNumber::Number(value) => {
-        value
-      }
-      StringOrNumber::String(value) => {
-        value.parse().unwrap()
-      }
+      StringOrNumber::Number(value) => value,
+      StringOrNumber::String(value) => value.parse().unwrap(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{category_lineage, Detail, PlaySource, TypeItem};
+  use crate::common::CategoryMapper;
+  use configuration::CategoryMappingConfig;
+  use protocol::channel::MediaKind;
+
+  // Root categories with sub categories, as served by most MacCMS channels
+  const NESTED_CLASS: &str = r#"[
+    {"type_id": 1, "type_pid": 0, "type_name": "电影"},
+    {"type_id": 2, "type_pid": 0, "type_name": "连续剧"},
+    {"type_id": 3, "type_pid": 0, "type_name": "综艺"},
+    {"type_id": 4, "type_pid": 0, "type_name": "动漫"},
+    {"type_id": 6, "type_pid": 1, "type_name": "动作片"},
+    {"type_id": 7, "type_pid": 1, "type_name": "喜剧片"},
+    {"type_id": 13, "type_pid": 2, "type_name": "国产剧"},
+    {"type_id": 14, "type_pid": 2, "type_name": "香港剧"},
+    {"type_id": 20, "type_pid": 1, "type_name": "记录片"},
+    {"type_id": 25, "type_pid": 3, "type_name": "大陆综艺"},
+    {"type_id": 29, "type_pid": 4, "type_name": "国产动漫"},
+    {"type_id": 46, "type_pid": 2, "type_name": "短剧"}
+  ]"#;
+
+  // Root categories only, without `type_pid`
+  const FLAT_CLASS: &str = r#"[
+    {"type_id": 1, "type_name": "电影片"},
+    {"type_id": 2, "type_name": "电视剧"},
+    {"type_id": 3, "type_name": "综艺片"},
+    {"type_id": 4, "type_name": "动漫片"},
+    {"type_id": 5, "type_name": "纪录片"},
+    {"type_id": 36, "type_name": "短剧大全"}
+  ]"#;
+
+  // Categories no default name matches
+  const CUSTOM_CLASS: &str = r#"[
+    {"type_id": 1, "type_pid": 0, "type_name": "影视"},
+    {"type_id": 2, "type_pid": 1, "type_name": "欧美剧"},
+    {"type_id": 3, "type_pid": 1, "type_name": "港台剧"},
+    {"type_id": 4, "type_pid": 1, "type_name": "院线大片"},
+    {"type_id": 40, "type_pid": 0, "type_name": "探索发现"},
+    {"type_id": 41, "type_pid": 0, "type_name": "体育赛事"},
+    {"type_id": 42, "type_pid": 41, "type_name": "足球联赛"}
+  ]"#;
+
+  fn parse_class(class: &str) -> Vec<TypeItem> {
+    serde_json::from_str(class).unwrap()
+  }
+
+  fn mapping(
+    type_id: Option<u32>,
+    name: Option<&str>,
+    pattern: Option<&str>,
+    kind: &str,
+  ) -> CategoryMappingConfig {
+    CategoryMappingConfig {
+      type_id,
+      name: name.map(|name| name.to_string()),
+      pattern: pattern.map(|pattern| pattern.to_string()),
+      kind: kind.to_string(),
     }
   }
+
+  fn assert_kinds(
+    mapper: &CategoryMapper,
+    class: &[TypeItem],
+    test_cases: &[(u32, &str, MediaKind)],
+  ) {
+    for (type_id, type_name, expected) in test_cases {
+      let lineage = category_lineage(class, *type_id, type_name);
+
+      assert_eq!(
+        mapper.media_kind(lineage),
+        *expected,
+        "Unexpected kind of category {} ({})",
+        type_id,
+        type_name
+      );
+    }
+  }
+
+  #[test]
+  fn test_default_kinds_of_nested_categories() {
+    let class = parse_class(NESTED_CLASS);
+    let mapper = CategoryMapper::new(&[]).unwrap();
+
+    assert_kinds(
+      &mapper,
+      &class,
+      &[
+        (1, "电影", MediaKind::Movie),
+        (6, "动作片", MediaKind::Movie),
+        (7, "喜剧片", MediaKind::Movie),
+        (20, "记录片", MediaKind::Documentary),
+        (2, "连续剧", MediaKind::TV),
+        (13, "国产剧", MediaKind::TV),
+        (14, "香港剧", MediaKind::TV),
+        (46, "短剧", MediaKind::ShortDrama),
+        (25, "大陆综艺", MediaKind::Variety),
+        (29, "国产动漫", MediaKind::Anime),
+      ],
+    );
+  }
+
+  #[test]
+  fn test_default_kinds_of_flat_categories() {
+    let class = parse_class(FLAT_CLASS);
+    let mapper = CategoryMapper::new(&[]).unwrap();
+
+    assert_kinds(
+      &mapper,
+      &class,
+      &[
+        (1, "电影片", MediaKind::Movie),
+        (2, "电视剧", MediaKind::TV),
+        (3, "综艺片", MediaKind::Variety),
+        (4, "动漫片", MediaKind::Anime),
+        (5, "纪录片", MediaKind::Documentary),
+        (36, "短剧大全", MediaKind::ShortDrama),
+        // Sub categories missing from the class only have their own name
+        (12, "国产剧", MediaKind::Other),
+        (22, "动画片", MediaKind::Anime),
+      ],
+    );
+  }
+
+  #[test]
+  fn test_configured_kinds() {
+    let class = parse_class(CUSTOM_CLASS);
+    let mapper = CategoryMapper::new(&[
+      mapping(Some(4), None, None, "movie"),
+      mapping(None, None, Some("剧$"), "tv"),
+      mapping(Some(40), None, None, "documentary"),
+      mapping(None, Some("体育赛事"), None, "variety"),
+    ])
+    .unwrap();
+
+    assert_kinds(
+      &mapper,
+      &class,
+      &[
+        (1, "影视", MediaKind::Other),
+        (2, "欧美剧", MediaKind::TV),
+        (3, "港台剧", MediaKind::TV),
+        (4, "院线大片", MediaKind::Movie),
+        (40, "探索发现", MediaKind::Documentary),
+        (42, "足球联赛", MediaKind::Variety),
+      ],
+    );
+  }
+
+  #[test]
+  fn test_configured_kinds_take_precedence() {
+    let class = parse_class(NESTED_CLASS);
+    let mapper = CategoryMapper::new(&[
+      mapping(Some(46), None, None, "tv"),
+      mapping(None, Some("动漫"), None, "other"),
+    ])
+    .unwrap();
+
+    assert_kinds(
+      &mapper,
+      &class,
+      &[
+        (46, "短剧", MediaKind::TV),
+        (29, "国产动漫", MediaKind::Other),
+        (13, "国产剧", MediaKind::TV),
+      ],
+    );
+  }
+
+  #[test]
+  fn test_configured_ancestors_take_precedence_over_default_names() {
+    let class = parse_class(NESTED_CLASS);
+    let mapper = CategoryMapper::new(&[
+      mapping(Some(2), None, None, "tv"),
+      mapping(None, Some("大陆综艺"), None, "documentary"),
+    ])
+    .unwrap();
+
+    assert_kinds(
+      &mapper,
+      &class,
+      &[
+        // 短剧 is a default name, but its parent 连续剧 is configured
+        (46, "短剧", MediaKind::TV),
+        // The most specific configured category wins
+        (25, "大陆综艺", MediaKind::Documentary),
+        // Lineages without any configured category fall back to the default names
+        (29, "国产动漫", MediaKind::Anime),
+      ],
+    );
+  }
+
+  #[test]
+  fn test_invalid_mappings() {
+    assert!(CategoryMapper::new(&[mapping(None, None, None, "tv")]).is_err());
+    assert!(CategoryMapper::new(&[mapping(Some(1), Some("电影"), None, "movie")]).is_err());
+    assert!(CategoryMapper::new(&[mapping(None, None, Some("(剧"), "tv")]).is_err());
+    assert!(CategoryMapper::new(&[mapping(Some(1), None, None, "cartoon")]).is_err());
+  }
+
+  #[test]
+  fn test_cyclic_categories() {
+    let class = parse_class(
+      r#"[
+        {"type_id": 1, "type_pid": 2, "type_name": "国产剧"},
+        {"type_id": 2, "type_pid": 1, "type_name": "港台剧"}
+      ]"#,
+    );
+    let mapper = CategoryMapper::new(&[]).unwrap();
+
+    assert_kinds(&mapper, &class, &[(1, "国产剧", MediaKind::Other)]);
+  }
+
+  #[test]
+  fn test_sources_of_detail() {
+    let detail: Detail = serde_json::from_str(
+      r#"{
+        "vod_id": 1, "vod_name": "示例剧集", "type_id": 13, "type_name": "国产剧",
+        "vod_year": "2023", "vod_pic": "", "vod_content": "",
+        "vod_play_from": "line1$$$line2",
+        "vod_play_url": "第01集$https://a.example.com/1.m3u8#第02集$https://a.example.com/2.m3u8$$$第01集$https://b.example.com/1.m3u8"
+      }"#,
+    )
+    .unwrap();
+
+    let item = |text: &str, url: &str| (text.to_string(), url.to_string());
+
+    assert_eq!(
+      detail.sources(),
+      vec![
+        PlaySource {
+          name: "line1".to_string(),
+          items: vec![
+            item("第01集", "https://a.example.com/1.m3u8"),
+            item("第02集", "https://a.example.com/2.m3u8"),
+          ],
+        },
+        PlaySource {
+          name: "line2".to_string(),
+          items: vec![item("第01集", "https://b.example.com/1.m3u8")],
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_item_sources() {
+    let detail = |play_url: &str| -> Detail {
+      serde_json::from_value(serde_json::json!({
+        "vod_id": 1, "vod_name": "示例剧集", "type_id": 13, "type_name": "国产剧",
+        "vod_year": "2023", "vod_pic": "", "vod_content": "",
+        "vod_play_from": "line1$$$line2",
+        "vod_play_url": play_url,
+      }))
+      .unwrap()
+    };
+    let source = |name: &str, url: &str| (name.to_string(), url.to_string());
+
+    // The second line misses the first episode, its items are matched by their text
+    let detail_with_gap = detail(
+      "第01集$https://a.example.com/1.m3u8#第02集$https://a.example.com/2.m3u8$$$第02集$https://b.example.com/2.m3u8",
+    );
+
+    assert_eq!(
+      detail_with_gap.item_sources(0),
+      vec![source("line1", "https://a.example.com/1.m3u8")]
+    );
+    assert_eq!(
+      detail_with_gap.item_sources(1),
+      vec![
+        source("line1", "https://a.example.com/2.m3u8"),
+        source("line2", "https://b.example.com/2.m3u8"),
+      ]
+    );
+    assert!(detail_with_gap.item_sources(2).is_empty());
+
+    // Untitled items are only paired between lines listing as many of them
+    let untitled = detail(
+      "$https://a.example.com/1.m3u8#$https://a.example.com/2.m3u8$$$$https://b.example.com/2.m3u8",
+    );
+
+    assert_eq!(
+      untitled.item_sources(0),
+      vec![source("line1", "https://a.example.com/1.m3u8")]
+    );
+  }
 }
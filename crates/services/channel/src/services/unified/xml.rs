@@ -0,0 +1,322 @@
+use super::api::{Detail, ListItem, Response, StringOrNumber, TypeItem, SOURCE_SEPARATOR};
+use roxmltree::{Document, Node};
+
+// Decoder of the XML flavor of the MacCMS collection API (`/api.php/provide/vod/at/xml`):
+//
+// <rss version="5.1">
+//   <list page="1" pagecount="10" pagesize="20" recordcount="200">
+//     <video><id>1</id><tid>2</tid><name>...</name><type>...</type>...</video>
+//   </list>
+//   <class><ty id="1">电影</ty></class>
+// </rss>
+
+pub fn decode_list_response(text: &str) -> anyhow::Result<Response<ListItem>> {
+  decode_response(text, |video| {
+    Ok(ListItem {
+      id: parse_number(video, "id")?,
+      name: child_text(video, "name"),
+      type_id: parse_number(video, "tid")?,
+      type_name: child_text(video, "type"),
+    })
+  })
+}
+
+pub fn decode_detail_response(text: &str) -> anyhow::Result<Response<Detail>> {
+  decode_response(text, |video| {
+    let (play_from, play_url) = play_sources(video);
+
+    Ok(Detail {
+      id: parse_number(video, "id")?,
+      name: child_text(video, "name"),
+      type_id: parse_number(video, "tid")?,
+      type_name: child_text(video, "type"),
+      year: child_text(video, "year"),
+      picture: child_text(video, "pic"),
+      description: child_text(video, "des"),
+      play_from,
+      play_url,
+      actor: child_text(video, "actor"),
+      director: child_text(video, "director"),
+      area: child_text(video, "area"),
+      lang: child_text(video, "lang"),
+      // `note` and `state` hold `vod_remarks` and `vod_serial`, whose unknown value is 0
+      remarks: child_text(video, "note"),
+      serial: Some(child_text(video, "state"))
+        .filter(|state| state.parse::<u32>().is_ok_and(|serial| serial > 0))
+        .unwrap_or_default(),
+      time: child_text(video, "last"),
+      // The XML flavor has no total of episodes nor score
+      ..Default::default()
+    })
+  })
+}
+
+fn decode_response<T>(
+  text: &str,
+  decode_video: impl Fn(Node) -> anyhow::Result<T>,
+) -> anyhow::Result<Response<T>> {
+  let document = Document::parse(text)
+    .map_err(|e| anyhow::anyhow!("Failed to decode xml response: {} ({})", e, text))?;
+
+  let root = document.root_element();
+
+  let list = child(root, "list")
+    .ok_or_else(|| anyhow::anyhow!("No list found in xml response ({})", text))?;
+
+  let videos = children(list, "video")
+    .map(decode_video)
+    .collect::<anyhow::Result<Vec<_>>>()?;
+
+  let class = child(root, "class").map(|class| {
+    children(class, "ty")
+      .filter_map(|ty| {
+        Some(TypeItem {
+          type_id: ty.attribute("id")?.trim().parse().ok()?,
+          type_pid: None,
+          type_name: node_text(ty),
+        })
+      })
+      .collect()
+  });
+
+  let attribute = |name: &str| -> u32 {
+    list
+      .attribute(name)
+      .and_then(|value| value.trim().parse().ok())
+      .unwrap_or_default()
+  };
+
+  // The XML flavor has no status code, a response with a list is successful
+  Ok(Response {
+    code: 1,
+    msg: String::new(),
+    page: StringOrNumber::Number(attribute("page")),
+    page_count: attribute("pagecount"),
+    total: attribute("recordcount"),
+    limit: StringOrNumber::Number(attribute("pagesize")),
+    list: videos,
+    class,
+  })
+}
+
+// Each `dd` is the playlist of a source, in the `name$url#name$url` format of `vod_play_url`.
+// Keep the m3u8 sources, since other sources are usually web players, joined like the sources
+// of `vod_play_from` and `vod_play_url`.
+fn play_sources(video: Node) -> (String, String) {
+  let Some(dl) = child(video, "dl") else {
+    return Default::default();
+  };
+
+  let sources: Vec<_> = children(dl, "dd").collect();
+
+  let mut m3u8_sources: Vec<_> = sources
+    .iter()
+    .filter(|dd| {
+      dd.attribute("flag")
+        .is_some_and(|flag| flag.to_lowercase().contains("m3u8"))
+    })
+    .collect();
+
+  if m3u8_sources.is_empty() {
+    m3u8_sources.extend(sources.first());
+  }
+
+  let play_from = m3u8_sources
+    .iter()
+    .map(|dd| dd.attribute("flag").unwrap_or_default())
+    .collect::<Vec<_>>()
+    .join(SOURCE_SEPARATOR);
+
+  let play_url = m3u8_sources
+    .iter()
+    .map(|dd| node_text(**dd))
+    .collect::<Vec<_>>()
+    .join(SOURCE_SEPARATOR);
+
+  (play_from, play_url)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+  node.children().find(|child| child.has_tag_name(name))
+}
+
+fn children<'a, 'input: 'a>(
+  node: Node<'a, 'input>,
+  name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+  node
+    .children()
+    .filter(move |child| child.has_tag_name(name))
+}
+
+// Text of a node, including CDATA sections
+fn node_text(node: Node) -> String {
+  node
+    .children()
+    .filter(|child| child.is_text())
+    .filter_map(|child| child.text())
+    .collect::<String>()
+    .trim()
+    .to_string()
+}
+
+fn child_text(node: Node, name: &str) -> String {
+  child(node, name).map(node_text).unwrap_or_default()
+}
+
+fn parse_number(node: Node, name: &str) -> anyhow::Result<u32> {
+  let text = child_text(node, name);
+
+  text
+    .parse()
+    .map_err(|e| anyhow::anyhow!("Invalid {} {:?} of video: {}", name, text, e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode_detail_response, decode_list_response};
+
+  const LIST_RESPONSE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="5.1">
+  <list page="2" pagecount="120" pagesize="20" recordcount="2388">
+    <video>
+      <last>2023-07-14 21:03:12</last>
+      <id>1024</id>
+      <tid>13</tid>
+      <name><![CDATA[示例剧集]]></name>
+      <type>国产剧</type>
+      <dt>m3u8</dt>
+      <note><![CDATA[更新至第2集]]></note>
+    </video>
+    <video>
+      <last>2023-07-13 08:00:00</last>
+      <id>2048</id>
+      <tid>6</tid>
+      <name><![CDATA[示例电影]]></name>
+      <type>动作片</type>
+      <dt>m3u8</dt>
+      <note><![CDATA[HD]]></note>
+    </video>
+  </list>
+  <class>
+    <ty id="1">电影</ty>
+    <ty id="2">连续剧</ty>
+    <ty id="6">动作片</ty>
+    <ty id="13">国产剧</ty>
+  </class>
+</rss>"#;
+
+  const DETAIL_RESPONSE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="5.1">
+  <list page="1" pagecount="1" pagesize="20" recordcount="1">
+    <video>
+      <last>2023-07-14 21:03:12</last>
+      <id>1024</id>
+      <tid>13</tid>
+      <name><![CDATA[示例剧集]]></name>
+      <type>国产剧</type>
+      <pic>https://img.example.com/1024.jpg</pic>
+      <lang>国语</lang>
+      <area>大陆</area>
+      <year>2023</year>
+      <state>24</state>
+      <note><![CDATA[更新至第2集]]></note>
+      <actor><![CDATA[演员甲,演员乙]]></actor>
+      <director><![CDATA[导演甲]]></director>
+      <dl>
+        <dd flag="example"><![CDATA[第01集$https://play.example.com/share/abc]]></dd>
+        <dd flag="examplem3u8"><![CDATA[第01集$https://cdn.example.com/1/index.m3u8#第02集$https://cdn.example.com/2/index.m3u8]]></dd>
+      </dl>
+      <des><![CDATA[<p>剧情简介 &amp; 更多</p>]]></des>
+    </video>
+  </list>
+</rss>"#;
+
+  #[test]
+  fn test_decode_list_response() {
+    let res = decode_list_response(LIST_RESPONSE).unwrap();
+
+    assert_eq!(res.code, 1);
+    assert_eq!(u32::from(res.page), 2);
+    assert_eq!(res.page_count, 120);
+    assert_eq!(res.total, 2388);
+    assert_eq!(u32::from(res.limit), 20);
+
+    assert_eq!(res.list.len(), 2);
+    assert_eq!(res.list[0].id, 1024);
+    assert_eq!(res.list[0].name, "示例剧集");
+    assert_eq!(res.list[0].type_id, 13);
+    assert_eq!(res.list[0].type_name, "国产剧");
+    assert_eq!(res.list[1].id, 2048);
+
+    let class = res.class.unwrap();
+
+    assert_eq!(class.len(), 4);
+    assert_eq!(class[3].type_id, 13);
+    assert_eq!(class[3].type_pid, None);
+    assert_eq!(class[3].type_name, "国产剧");
+  }
+
+  #[test]
+  fn test_decode_detail_response() {
+    let res = decode_detail_response(DETAIL_RESPONSE).unwrap();
+
+    assert_eq!(res.total, 1);
+    assert!(res.class.is_none());
+
+    let detail = &res.list[0];
+
+    assert_eq!(detail.id, 1024);
+    assert_eq!(detail.name, "示例剧集");
+    assert_eq!(detail.year, "2023");
+    assert_eq!(detail.picture, "https://img.example.com/1024.jpg");
+    assert_eq!(detail.description, "<p>剧情简介 &amp; 更多</p>");
+    assert_eq!(detail.play_from, "examplem3u8");
+    assert_eq!(
+      detail.play_url,
+      "第01集$https://cdn.example.com/1/index.m3u8#第02集$https://cdn.example.com/2/index.m3u8"
+    );
+    assert_eq!(detail.cast(), vec!["演员甲", "演员乙"]);
+    assert_eq!(detail.directors(), vec!["导演甲"]);
+    assert_eq!(detail.area, "大陆");
+    assert_eq!(detail.lang, "国语");
+    assert_eq!(detail.remarks, "更新至第2集");
+    assert_eq!(detail.total_episodes(), Some(24));
+    assert_eq!(detail.updated_at().as_deref(), Some("2023-07-14 21:03:12"));
+    assert_eq!(detail.score(), None);
+  }
+
+  // Mirrors filtering the list by category leave out the class
+  const LIST_WITHOUT_CLASS_RESPONSE: &str = r#"<?xml version="1.0" encoding="utf-8"?><rss version="5.1"><list page="1" pagecount="1" pagesize="20" recordcount="1"><video><last>2024-03-02 10:12:45</last><id>38271</id><tid>13</tid><name><![CDATA[繁花]]></name><type>国产剧</type><pic>https://img.example.com/upload/vod/20231228-1/fanhua.jpg</pic><lang>国语</lang><area>大陆</area><year>2023</year><state>0</state><note><![CDATA[已完结]]></note><actor><![CDATA[胡歌,马伊琍,唐嫣]]></actor><director><![CDATA[王家卫]]></director><dl><dd flag="ffm3u8"><![CDATA[第01集$https://cdn.example.com/20231227/1/index.m3u8#第02集$https://cdn.example.com/20231227/2/index.m3u8]]></dd></dl><des><![CDATA[<p>九十年代初的上海。</p>]]></des></video></list></rss>"#;
+
+  #[test]
+  fn test_decode_response_without_class() {
+    let res = decode_list_response(LIST_WITHOUT_CLASS_RESPONSE).unwrap();
+
+    assert!(res.class.is_none());
+    assert_eq!(res.list[0].id, 38271);
+    assert_eq!(res.list[0].type_name, "国产剧");
+
+    let res = decode_detail_response(LIST_WITHOUT_CLASS_RESPONSE).unwrap();
+    let detail = &res.list[0];
+
+    assert!(res.class.is_none());
+    assert_eq!(detail.remarks, "已完结");
+    // Neither the unknown serial nor the missing total and score are made up
+    assert_eq!(detail.serial, "");
+    assert_eq!(detail.total, "");
+    assert_eq!(detail.total_episodes(), None);
+    assert_eq!(detail.score(), None);
+    assert_eq!(detail.sources()[0].items.len(), 2);
+  }
+
+  #[test]
+  fn test_decode_invalid_response() {
+    assert!(decode_list_response("<html><body>404</body></html>").is_err());
+    assert!(decode_list_response("{\"code\": 1}").is_err());
+    assert!(decode_detail_response(
+      "<rss><list><video><id>abc</id><tid>1</tid></video></list></rss>"
+    )
+    .is_err());
+  }
+}
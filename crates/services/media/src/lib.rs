@@ -1,25 +1,35 @@
+mod nfo;
+mod post_processing;
 mod utils;
 
 // use models::ConnectionPool;
+use configuration::{PostProcessingConfig, SeriesConfig};
+use post_processing::{audio_profile, run_profile, PostProcessingProfile, PostProcessor};
+use protocol::channel::DIRECT_CHANNEL;
+use protocol::channel::{AudioFormat, LiveRecording, MediaKind, MediaTags, MediaTrim};
 use protocol::media::BatchDownloadMediaRequest;
 use protocol::media::DownloadMediaRequest;
+use protocol::media::DownloadUrlRequest;
 use protocol::media::MediaExt;
 use protocol::media::{GetMediaMetadataRequest, MediaMetadata};
 use protocol::media::{GetMediaPlaylistRequest, MediaPlaylist};
+use protocol::media::{GetMediaRenditionsRequest, MediaRenditions};
 use protocol::media::{SearchMediaRequest, SearchMediaResponse};
-use protocol::tonic::{self, async_trait, Request, Response};
+use protocol::tonic::{self, async_trait, Request, Response, Status};
 use rpc_client::RpcClient;
 use std::ops::AddAssign;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use task_manager::TaskManager;
-use utils::rename_file;
+use utils::{rename_file, rename_sidecar_files};
 
 pub struct MediaService {
   media_dir: PathBuf,
   rpc_client: RpcClient,
   task_manager: Arc<TaskManager>,
+  post_processor: PostProcessor,
+  series: Vec<SeriesConfig>,
   // connection_pool: ConnectionPool,
 }
 
@@ -29,28 +39,95 @@ impl MediaExt for MediaService {
     &self,
     request: Request<DownloadMediaRequest>,
   ) -> tonic::Result<Response<protocol::Empty>> {
-    let request = request.into_inner();
+    let mut request = request.into_inner();
     log::info!(
       "Downloading media {} (#{:?})",
       request.media_id,
       request.number
     );
 
+    if let Some(clip) = &request.clip {
+      clip.validate().map_err(Status::invalid_argument)?;
+    }
+
+    if let Some(trim) = &request.trim {
+      trim.validate().map_err(Status::invalid_argument)?;
+    }
+
+    // Clips are cut where they are asked for
+    if request.trim.is_none() && request.clip.is_none() {
+      request.trim = self.series_trim(&request.channel, &request.media_id);
+    }
+
+    if let Some(recording) = &request.recording {
+      recording.validate().map_err(Status::invalid_argument)?;
+    }
+
+    let mut channel_client = self.rpc_client.channel.clone();
+    let metadata = channel_client
+      .get_media_metadata(GetMediaMetadataRequest {
+        channel: request.channel.clone(),
+        media_id: request.media_id.clone(),
+      })
+      .await?
+      .into_inner();
+
+    let profile = self
+      .post_processing_profile(
+        request.post_processing.as_deref(),
+        Some(metadata.kind),
+        request.audio_only,
+      )
+      .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+
+    if request.tags.is_none() {
+      request.tags = Some(Self::media_tags(
+        &metadata,
+        None,
+        request.number.unwrap_or(1),
+      ));
+    }
+
     let task_id = self.task_manager.create_task(
       request.channel.clone(),
       request.media_id.clone(),
       request.media_id.clone(),
       request.number,
     );
+    let start_delay = self.schedule_recording(&task_id, request.recording.as_ref());
 
     tokio::spawn({
       let channel_client = self.rpc_client.channel.clone();
+      let media_dir = self.media_dir.clone();
       let task_manager = self.task_manager.clone();
       async move {
-        if let Err(err) =
-          Self::download_media_with_tracking(channel_client, request.clone(), &task_manager, &task_id)
-            .await
-        {
+        if let Some(delay) = start_delay {
+          tokio::time::sleep(delay).await;
+        }
+
+        if let Err(err) = Self::download_media_with_tracking(
+          channel_client,
+          request.clone(),
+          profile.as_ref(),
+          &task_manager,
+          &task_id,
+        )
+        .await
+        .and_then(|local_path| {
+          // Media are left in the downloads, audio is filed into its library
+          if request.audio_only.is_none() {
+            return Ok(());
+          }
+
+          Self::file_into_library(
+            metadata,
+            &media_dir,
+            true,
+            None,
+            request.number.unwrap_or(1),
+            &local_path,
+          )
+        }) {
           log::info!(
             "Failed to download media {}(#{:?}): {}",
             request.media_id,
@@ -113,6 +190,22 @@ impl MediaExt for MediaService {
     Ok(Response::new(res))
   }
 
+  async fn get_media_renditions(
+    &self,
+    request: Request<GetMediaRenditionsRequest>,
+  ) -> tonic::Result<Response<MediaRenditions>> {
+    let request = request.into_inner();
+
+    let mut channel_client = self.rpc_client.channel.clone();
+
+    let res = channel_client
+      .get_media_renditions(request.clone())
+      .await?
+      .into_inner();
+
+    Ok(Response::new(res))
+  }
+
   async fn batch_download_media(
     &self,
     request: Request<BatchDownloadMediaRequest>,
@@ -125,6 +218,14 @@ impl MediaExt for MediaService {
       request.count,
     );
 
+    if let Some(trim) = &request.trim {
+      trim.validate().map_err(Status::invalid_argument)?;
+    }
+
+    let trim = request
+      .trim
+      .or_else(|| self.series_trim(&request.channel, &request.media_id));
+
     let mut channel_client = self.rpc_client.channel.clone();
     let metadata = channel_client
       .get_media_metadata(GetMediaMetadataRequest {
@@ -134,6 +235,14 @@ impl MediaExt for MediaService {
       .await?
       .into_inner();
 
+    let profile = self
+      .post_processing_profile(
+        request.post_processing.as_deref(),
+        Some(metadata.kind),
+        request.audio_only,
+      )
+      .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+
     let task_ids: Vec<String> = (0..request.count)
       .map(|idx| {
         let ep = request.start_number + (idx as u32);
@@ -162,17 +271,29 @@ impl MediaExt for MediaService {
               channel: batch_request.channel.clone(),
               media_id: batch_request.media_id.clone(),
               number: Some(start_number),
+              renditions: batch_request.renditions.clone(),
+              post_processing: batch_request.post_processing.clone(),
+              container: batch_request.container,
+              clip: None,
+              audio_only: batch_request.audio_only,
+              recording: None,
+              trim,
+              tags: Some(Self::media_tags(&metadata, None, start_number)),
             },
+            profile.as_ref(),
             &task_manager,
             task_id,
           )
           .await
           .and_then(|local_path| {
-            if metadata.is_movie() {
-              Self::rename_movie_file(metadata.clone(), &media_dir, &local_path)
-            } else {
-              Self::rename_media_file(metadata.clone(), &media_dir, start_number, &local_path)
-            }
+            Self::file_into_library(
+              metadata.clone(),
+              &media_dir,
+              batch_request.audio_only.is_some(),
+              None,
+              start_number,
+              &local_path,
+            )
           }) {
             log::info!(
               "Failed to download media {}(#{:?}): {}",
@@ -201,12 +322,129 @@ impl MediaExt for MediaService {
 
     Ok(Response::new(protocol::Empty {}))
   }
+
+  async fn download_url(
+    &self,
+    request: Request<DownloadUrlRequest>,
+  ) -> tonic::Result<Response<protocol::Empty>> {
+    let request = request.into_inner();
+    log::info!("Downloading {} as {}", request.url, request.title);
+
+    let title = request.title.trim().to_string();
+
+    if title.is_empty() {
+      return Err(Status::invalid_argument("Title of the media is required"));
+    }
+
+    let is_http_url =
+      url::Url::parse(&request.url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+
+    if !is_http_url {
+      return Err(Status::invalid_argument(format!(
+        "Invalid URL {}, only HTTP(S) URLs can be downloaded",
+        request.url
+      )));
+    }
+
+    if let Some(recording) = &request.recording {
+      recording.validate().map_err(Status::invalid_argument)?;
+    }
+
+    let profile = self
+      .post_processing_profile(
+        request.post_processing.as_deref(),
+        Some(request.kind),
+        request.audio_only,
+      )
+      .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+
+    let task_id = self.task_manager.create_task(
+      DIRECT_CHANNEL.to_string(),
+      request.url.clone(),
+      title.clone(),
+      request.episode,
+    );
+    let start_delay = self.schedule_recording(&task_id, request.recording.as_ref());
+
+    let metadata = MediaMetadata {
+      channel: DIRECT_CHANNEL.to_string(),
+      id: request.url.clone(),
+      name: title,
+      poster_url: String::new(),
+      release_year: request.release_year.unwrap_or(0),
+      description: String::new(),
+      kind: request.kind,
+      cast: vec![],
+      directors: vec![],
+      region: String::new(),
+      language: String::new(),
+      remarks: String::new(),
+      total_episodes: None,
+      updated_at: None,
+      score: None,
+    };
+
+    tokio::spawn({
+      let channel_client = self.rpc_client.channel.clone();
+      let media_dir = self.media_dir.clone();
+      let task_manager = self.task_manager.clone();
+
+      async move {
+        if let Some(delay) = start_delay {
+          tokio::time::sleep(delay).await;
+        }
+
+        let download_request = DownloadMediaRequest {
+          channel: DIRECT_CHANNEL.to_string(),
+          media_id: request.url.clone(),
+          number: None,
+          renditions: request.renditions.clone(),
+          post_processing: request.post_processing.clone(),
+          container: request.container,
+          clip: None,
+          audio_only: request.audio_only,
+          recording: request.recording.clone(),
+          trim: None,
+          tags: Some(Self::media_tags(
+            &metadata,
+            request.season,
+            request.episode.unwrap_or(1),
+          )),
+        };
+
+        if let Err(err) = Self::download_media_with_tracking(
+          channel_client,
+          download_request,
+          profile.as_ref(),
+          &task_manager,
+          &task_id,
+        )
+        .await
+        .and_then(|local_path| {
+          Self::file_into_library(
+            metadata,
+            &media_dir,
+            request.audio_only.is_some(),
+            request.season,
+            request.episode.unwrap_or(1),
+            &local_path,
+          )
+        }) {
+          log::info!("Failed to download {}: {}", request.url, err);
+          task_manager.task_failed(&task_id, &err.to_string());
+        }
+      }
+    });
+
+    Ok(Response::new(protocol::Empty {}))
+  }
 }
 
 impl MediaService {
   async fn download_media_with_tracking(
     mut channel_client: protocol::channel::ChannelClient<tonic::transport::Channel>,
     request: DownloadMediaRequest,
+    profile: Option<&PostProcessingProfile>,
     task_manager: &TaskManager,
     task_id: &str,
   ) -> anyhow::Result<PathBuf> {
@@ -222,8 +460,20 @@ impl MediaService {
       match evt {
         protocol::DownloadProgressItem::Done { local_path, .. } => {
           log::info!("Download done: {}", local_path);
+
+          let mut local_path = PathBuf::from(local_path);
+
+          if let Some(profile) = profile {
+            task_manager.task_transforming(task_id);
+
+            local_path = run_profile(profile, &local_path, |fraction| {
+              task_manager.task_transforming_progress(task_id, fraction)
+            })
+            .await?;
+          }
+
           task_manager.task_completed(task_id);
-          return Ok(PathBuf::from(local_path));
+          return Ok(local_path);
         }
         protocol::DownloadProgressItem::Started {
           total_segments_of_media,
@@ -237,12 +487,50 @@ impl MediaService {
           log::info!("Downloading ... {}/{}", finished, total.unwrap_or(0));
           task_manager.task_segment_downloaded(task_id);
         }
+        protocol::DownloadProgressItem::BytesDownloaded {
+          downloaded_bytes,
+          total_bytes,
+          ..
+        } => {
+          task_manager.task_bytes_downloaded(task_id, downloaded_bytes, total_bytes);
+        }
+        protocol::DownloadProgressItem::Retrying { message, .. } => {
+          log::warn!("Retrying: {}", message);
+          task_manager.task_retrying(task_id);
+        }
+        protocol::DownloadProgressItem::AdsRemoved {
+          removed_segments,
+          removed_seconds,
+          ..
+        } => {
+          log::info!(
+            "Removed {} ad segments ({:.1}s)",
+            removed_segments,
+            removed_seconds
+          );
+          task_manager.task_ads_removed(task_id, removed_seconds);
+        }
+        protocol::DownloadProgressItem::Recording {
+          recorded_seconds,
+          recorded_bytes,
+          limit_seconds,
+          ..
+        } => {
+          log::info!(
+            "Recording ... {:.0}/{:.0}s ({} bytes)",
+            recorded_seconds,
+            limit_seconds,
+            recorded_bytes
+          );
+          task_manager.task_recording(task_id, recorded_seconds, recorded_bytes, limit_seconds);
+        }
         protocol::DownloadProgressItem::TransformingVideo { .. } => {
           log::info!("Transforming video...");
           task_manager.task_transforming(task_id);
         }
-        _ => {
-          log::info!("Event {:#?}", evt);
+        // e.g. the downloaded media failed its verification
+        protocol::DownloadProgressItem::Failed { reason, .. } => {
+          anyhow::bail!(reason);
         }
       };
     }
@@ -250,9 +538,90 @@ impl MediaService {
     anyhow::bail!("No done event received")
   }
 
-  fn rename_movie_file(
+  // Trim configured for the episodes of a show, if any
+  fn series_trim(&self, channel: &str, media_id: &str) -> Option<MediaTrim> {
+    self
+      .series
+      .iter()
+      .find(|series| series.channel == channel && series.media_id == media_id)
+      .map(SeriesConfig::trim)
+      .filter(|trim| trim.intro > 0.0 || trim.outro > 0.0)
+  }
+
+  // Recordings scheduled for later stay pending until then, returning how long they wait
+  fn schedule_recording(
+    &self,
+    task_id: &str,
+    recording: Option<&LiveRecording>,
+  ) -> Option<std::time::Duration> {
+    let recording = recording?;
+    let delay = recording.start_delay()?;
+
+    if let Some(start_at) = recording.start_at() {
+      log::info!("Recording of task {} scheduled at {}", task_id, start_at);
+      self.task_manager.task_scheduled(task_id, start_at);
+    }
+
+    Some(delay)
+  }
+
+  // Tags of episodes name their show, whose season is parsed from the media name unless it's given
+  fn media_tags(
+    metadata: &MediaMetadata,
+    season_number: Option<u8>,
+    episode_number: u32,
+  ) -> MediaTags {
+    if metadata.is_movie() {
+      return MediaTags::movie(metadata);
+    }
+
+    let (show, season_number) = match season_number {
+      Some(season_number) => (metadata.name.clone(), season_number),
+      None => Self::parse_season_number_from_media_name(&metadata.name)
+        .unwrap_or((metadata.name.clone(), 1)),
+    };
+
+    MediaTags::episode(metadata, show, season_number, episode_number)
+  }
+
+  // Libraries of movies and TV shows. Audio is filed into a library of its own, which holds both.
+  fn library_dirs(media_dir: &Path, audio_only: bool) -> (PathBuf, PathBuf) {
+    if audio_only {
+      let audio_dir = media_dir.join("audio");
+      return (audio_dir.clone(), audio_dir);
+    }
+
+    (media_dir.join("movies"), media_dir.join("tv_shows"))
+  }
+
+  // Renames a download into the library of its kind, the season is parsed from the media name
+  // unless it's given
+  fn file_into_library(
     metadata: MediaMetadata,
     media_dir: &Path,
+    audio_only: bool,
+    season_number: Option<u8>,
+    episode_number: u32,
+    local_path: &Path,
+  ) -> anyhow::Result<()> {
+    let (movies_dir, shows_dir) = Self::library_dirs(media_dir, audio_only);
+
+    if metadata.is_movie() {
+      Self::rename_movie_file(metadata, &movies_dir, local_path)
+    } else {
+      Self::rename_media_file(
+        metadata,
+        &shows_dir,
+        season_number,
+        episode_number,
+        local_path,
+      )
+    }
+  }
+
+  fn rename_movie_file(
+    metadata: MediaMetadata,
+    movies_dir: &Path,
     local_path: &Path,
   ) -> anyhow::Result<()> {
     let ext = local_path
@@ -263,7 +632,7 @@ impl MediaService {
 
     let file_name = format!("{} ({}).{}", metadata.name, metadata.release_year, ext);
 
-    let new_local_path = media_dir.join("movies").join(file_name);
+    let new_local_path = movies_dir.join(file_name);
 
     log::info!(
       "Rename file from {} to {}",
@@ -271,14 +640,19 @@ impl MediaService {
       new_local_path.to_string_lossy().to_string()
     );
 
-    rename_file(local_path, new_local_path)?;
+    rename_file(local_path, &new_local_path)?;
+    rename_sidecar_files(local_path, &new_local_path)?;
+
+    nfo::write_movie_nfo(&metadata, &new_local_path.with_extension("nfo"))?;
 
     Ok(())
   }
 
+  // The season is parsed from the media name unless it's given
   fn rename_media_file(
     metadata: MediaMetadata,
-    media_dir: &Path,
+    shows_dir: &Path,
+    season_number: Option<u8>,
     episode_number: u32,
     local_path: &Path,
   ) -> anyhow::Result<()> {
@@ -288,25 +662,25 @@ impl MediaService {
       .to_string_lossy()
       .to_string();
 
-    let (media_name, season_number) = Self::parse_season_number_from_media_name(&metadata.name)
-      .unwrap_or((
+    let (media_name, season_number) = match season_number {
+      Some(season_number) => (metadata.name.clone(), season_number),
+      None => Self::parse_season_number_from_media_name(&metadata.name).unwrap_or((
         metadata.name.clone(),
         1, // 默认第一季
-      ));
+      )),
+    };
 
     let base_dir_name = media_name;
     let season_string = format!("{:02}", season_number);
     let season_dir_name = format!("Season {}", season_string);
+    let episode_string = Self::format_episode_number(episode_number, metadata.total_episodes);
     let file_name = format!(
       "{} S{}E{}.{}",
-      metadata.name, season_string, episode_number, ext
+      metadata.name, season_string, episode_string, ext
     );
 
-    let new_local_path = media_dir
-      .join("tv_shows")
-      .join(base_dir_name)
-      .join(season_dir_name)
-      .join(file_name);
+    let show_dir = shows_dir.join(base_dir_name);
+    let new_local_path = show_dir.join(season_dir_name).join(file_name);
 
     log::info!(
       "Rename file from {} to {}",
@@ -314,11 +688,30 @@ impl MediaService {
       new_local_path.to_string_lossy().to_string()
     );
 
-    rename_file(local_path, new_local_path)?;
+    rename_file(local_path, &new_local_path)?;
+    rename_sidecar_files(local_path, &new_local_path)?;
+
+    nfo::write_tv_show_nfo(&metadata, &show_dir.join("tvshow.nfo"))?;
+    nfo::write_episode_nfo(
+      &metadata,
+      season_number,
+      episode_number,
+      &new_local_path.with_extension("nfo"),
+    )?;
 
     Ok(())
   }
 
+  // Pad episode numbers to the width of the total, so that files sort naturally
+  fn format_episode_number(episode_number: u32, total_episodes: Option<u32>) -> String {
+    let width = total_episodes
+      .map(|total| total.to_string().len())
+      .unwrap_or(0)
+      .max(2);
+
+    format!("{:0width$}", episode_number, width = width)
+  }
+
   // 如果 名字以 Xxx 第二季 第三季 第四季 之类的格式结尾，则解析出季数
   fn parse_season_number_from_media_name(media_name: &str) -> anyhow::Result<(String, u8)> {
     let pattern =
@@ -361,11 +754,41 @@ impl MediaService {
 }
 
 impl MediaService {
-  pub fn new(rpc_client: &RpcClient, task_manager: Arc<TaskManager>) -> Self {
+  // Audio-only downloads are encoded into their format instead of being post-processed. Errors
+  // are invalid arguments of the request.
+  fn post_processing_profile(
+    &self,
+    requested: Option<&str>,
+    kind: Option<MediaKind>,
+    audio_only: Option<AudioFormat>,
+  ) -> anyhow::Result<Option<PostProcessingProfile>> {
+    if let Some(format) = audio_only {
+      // The audio profile replaces the post-processing of the media
+      anyhow::ensure!(
+        requested.is_none_or(|name| name.trim().is_empty()),
+        "Audio-only downloads can't be post-processed with a profile"
+      );
+
+      return Ok(Some(audio_profile(format)));
+    }
+
+    self.post_processor.profile(requested, kind)
+  }
+}
+
+impl MediaService {
+  pub fn new(
+    rpc_client: &RpcClient,
+    task_manager: Arc<TaskManager>,
+    post_processing: &PostProcessingConfig,
+    series: &[SeriesConfig],
+  ) -> Self {
     Self {
       media_dir: media_dir(),
       rpc_client: rpc_client.clone(),
       task_manager,
+      post_processor: PostProcessor::new(post_processing),
+      series: series.to_vec(),
       // connection_pool: connection_pool.clone(),
     }
   }
@@ -381,7 +804,10 @@ fn media_dir() -> PathBuf {
 
 #[cfg(test)]
 mod tests {
+  use super::utils::rename_sidecar_files;
   use super::MediaService;
+  use protocol::channel::MediaKind;
+  use protocol::media::MediaMetadata;
 
   #[test]
   fn test_parse_season_number_from_media_name() {
@@ -417,4 +843,97 @@ mod tests {
       }
     }
   }
+
+  #[test]
+  fn test_format_episode_number() {
+    assert_eq!(MediaService::format_episode_number(3, None), "03");
+    assert_eq!(MediaService::format_episode_number(3, Some(12)), "03");
+    assert_eq!(MediaService::format_episode_number(12, Some(40)), "12");
+    assert_eq!(MediaService::format_episode_number(7, Some(120)), "007");
+    assert_eq!(
+      MediaService::format_episode_number(1024, Some(1100)),
+      "1024"
+    );
+  }
+
+  #[test]
+  fn test_rename_sidecar_files() {
+    let dir = std::env::temp_dir().join(format!("sidecars-{}", std::process::id()));
+    let downloads = dir.join("downloads");
+    std::fs::create_dir_all(&downloads).unwrap();
+
+    for name in [
+      "123-1.en.srt",
+      "123-1.en.forced.srt",
+      "123-1.ja.vtt",
+      "123-10.en.srt",
+    ] {
+      std::fs::write(downloads.join(name), name).unwrap();
+    }
+
+    let movie = dir.join("movies").join("Movie (2020).mp4");
+
+    rename_sidecar_files(&downloads.join("123-1.mp4"), &movie).unwrap();
+
+    let mut renamed: Vec<String> = std::fs::read_dir(dir.join("movies"))
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+      .collect();
+    renamed.sort();
+
+    assert_eq!(
+      renamed,
+      vec![
+        "Movie (2020).en.forced.srt",
+        "Movie (2020).en.srt",
+        "Movie (2020).ja.vtt"
+      ]
+    );
+    // Sidecars of other media are left alone
+    assert!(downloads.join("123-10.en.srt").exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_file_audio_into_library() {
+    let dir = std::env::temp_dir().join(format!("library-{}", std::process::id()));
+    let downloads = dir.join("downloads");
+    std::fs::create_dir_all(&downloads).unwrap();
+
+    let metadata = MediaMetadata {
+      channel: "unified".to_string(),
+      id: "9104".to_string(),
+      name: "庆余年 第二季".to_string(),
+      poster_url: String::new(),
+      release_year: 2024,
+      description: String::new(),
+      kind: MediaKind::TV,
+      cast: vec![],
+      directors: vec![],
+      region: String::new(),
+      language: String::new(),
+      remarks: String::new(),
+      total_episodes: Some(36),
+      updated_at: None,
+      score: None,
+    };
+
+    let local_path = downloads.join("9104-3.m4a");
+    std::fs::write(&local_path, b"audio").unwrap();
+
+    MediaService::file_into_library(metadata, &dir, true, None, 3, &local_path).unwrap();
+
+    let show_dir = dir.join("audio").join("庆余年");
+
+    assert_eq!(
+      std::fs::read(show_dir.join("Season 02").join("庆余年 第二季 S02E03.m4a")).unwrap(),
+      b"audio"
+    );
+    assert!(show_dir.join("tvshow.nfo").exists());
+    assert!(!local_path.exists());
+    assert!(!dir.join("tv_shows").exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
 }
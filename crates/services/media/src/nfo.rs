@@ -0,0 +1,215 @@
+use protocol::media::MediaMetadata;
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::LazyLock;
+
+// NFO files follow the format read by Kodi, Jellyfin and Emby, see
+// https://kodi.wiki/view/NFO_files
+
+pub fn write_movie_nfo(metadata: &MediaMetadata, path: &Path) -> io::Result<()> {
+  write_nfo(path, &movie_nfo(metadata))
+}
+
+pub fn write_tv_show_nfo(metadata: &MediaMetadata, path: &Path) -> io::Result<()> {
+  write_nfo(path, &tv_show_nfo(metadata))
+}
+
+pub fn write_episode_nfo(
+  metadata: &MediaMetadata,
+  season_number: u8,
+  episode_number: u32,
+  path: &Path,
+) -> io::Result<()> {
+  write_nfo(path, &episode_nfo(metadata, season_number, episode_number))
+}
+
+fn movie_nfo(metadata: &MediaMetadata) -> String {
+  let mut nfo = String::from("<movie>\n");
+
+  write_common_elements(&mut nfo, metadata);
+  nfo.push_str("</movie>\n");
+
+  nfo
+}
+
+fn tv_show_nfo(metadata: &MediaMetadata) -> String {
+  let mut nfo = String::from("<tvshow>\n");
+
+  write_common_elements(&mut nfo, metadata);
+  nfo.push_str("</tvshow>\n");
+
+  nfo
+}
+
+fn episode_nfo(metadata: &MediaMetadata, season_number: u8, episode_number: u32) -> String {
+  let mut nfo = String::from("<episodedetails>\n");
+
+  push_element(&mut nfo, "title", &format!("第{}集", episode_number));
+  push_element(&mut nfo, "showtitle", &metadata.name);
+  push_element(&mut nfo, "season", &season_number.to_string());
+  push_element(&mut nfo, "episode", &episode_number.to_string());
+  nfo.push_str("</episodedetails>\n");
+
+  nfo
+}
+
+fn write_common_elements(nfo: &mut String, metadata: &MediaMetadata) {
+  push_element(nfo, "title", &metadata.name);
+
+  if metadata.release_year > 0 {
+    push_element(nfo, "year", &metadata.release_year.to_string());
+  }
+
+  push_element(nfo, "plot", &strip_html(&metadata.description));
+  push_element(nfo, "thumb", &metadata.poster_url);
+  push_element(nfo, "country", &metadata.region);
+  push_element(nfo, "language", &metadata.language);
+  push_element(nfo, "tagline", &metadata.remarks);
+
+  if let Some(score) = metadata.score {
+    push_element(nfo, "rating", &score.to_string());
+  }
+
+  for director in &metadata.directors {
+    push_element(nfo, "director", director);
+  }
+
+  for actor in &metadata.cast {
+    nfo.push_str("  <actor>\n  ");
+    push_element(nfo, "name", actor);
+    nfo.push_str("  </actor>\n");
+  }
+}
+
+fn push_element(nfo: &mut String, name: &str, value: &str) {
+  if value.is_empty() {
+    return;
+  }
+
+  nfo.push_str(&format!("  <{}>{}</{}>\n", name, escape_xml(value), name));
+}
+
+fn write_nfo(path: &Path, nfo: &str) -> io::Result<()> {
+  fs::create_dir_all(path.parent().unwrap())?;
+
+  let content = format!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n{}",
+    nfo
+  );
+
+  fs::write(path, content)
+}
+
+fn escape_xml(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&apos;")
+}
+
+// Descriptions of channels are HTML fragments, their entities are decoded before being escaped
+// again
+static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+fn strip_html(value: &str) -> String {
+  HTML_TAG
+    .replace_all(value, "")
+    .replace("&nbsp;", " ")
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&amp;", "&")
+    .trim()
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{episode_nfo, movie_nfo, tv_show_nfo};
+  use protocol::channel::MediaKind;
+  use protocol::media::MediaMetadata;
+
+  fn metadata(kind: MediaKind) -> MediaMetadata {
+    MediaMetadata {
+      channel: "unified".to_string(),
+      id: "9104".to_string(),
+      name: "Tom & Jerry <Remastered>".to_string(),
+      poster_url: "https://img.example.com/p.jpg?w=1&h=2".to_string(),
+      release_year: 2021,
+      description: "<p>Cat &amp; mouse,&nbsp;\"again\"</p>".to_string(),
+      kind,
+      cast: vec!["汤姆".to_string(), "O'Brien".to_string()],
+      directors: vec!["Hanna".to_string()],
+      region: "美国".to_string(),
+      language: "英语".to_string(),
+      remarks: "".to_string(),
+      total_episodes: Some(12),
+      updated_at: None,
+      score: Some(8.5),
+    }
+  }
+
+  #[test]
+  fn test_movie_nfo() {
+    assert_eq!(
+      movie_nfo(&metadata(MediaKind::Movie)),
+      r#"<movie>
+  <title>Tom &amp; Jerry &lt;Remastered&gt;</title>
+  <year>2021</year>
+  <plot>Cat &amp; mouse, &quot;again&quot;</plot>
+  <thumb>https://img.example.com/p.jpg?w=1&amp;h=2</thumb>
+  <country>美国</country>
+  <language>英语</language>
+  <rating>8.5</rating>
+  <director>Hanna</director>
+  <actor>
+    <name>汤姆</name>
+  </actor>
+  <actor>
+    <name>O&apos;Brien</name>
+  </actor>
+</movie>
+"#
+    );
+  }
+
+  #[test]
+  fn test_tv_show_nfo() {
+    let metadata = MediaMetadata {
+      release_year: 0,
+      score: None,
+      cast: vec![],
+      remarks: "更新至12集".to_string(),
+      ..metadata(MediaKind::TV)
+    };
+
+    assert_eq!(
+      tv_show_nfo(&metadata),
+      r#"<tvshow>
+  <title>Tom &amp; Jerry &lt;Remastered&gt;</title>
+  <plot>Cat &amp; mouse, &quot;again&quot;</plot>
+  <thumb>https://img.example.com/p.jpg?w=1&amp;h=2</thumb>
+  <country>美国</country>
+  <language>英语</language>
+  <tagline>更新至12集</tagline>
+  <director>Hanna</director>
+</tvshow>
+"#
+    );
+    assert_eq!(
+      episode_nfo(&metadata, 2, 7),
+      r#"<episodedetails>
+  <title>第7集</title>
+  <showtitle>Tom &amp; Jerry &lt;Remastered&gt;</showtitle>
+  <season>2</season>
+  <episode>7</episode>
+</episodedetails>
+"#
+    );
+  }
+}
@@ -0,0 +1,372 @@
+use configuration::{PostProcessingConfig, PostProcessingProfileConfig};
+use protocol::channel::{AudioFormat, MediaKind};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+// Name of the profile skipping the post-processing of a media kind or the default one
+const NO_PROFILE: &str = "none";
+
+/// Named ffmpeg settings run on downloaded media, e.g. to archive them as H.265.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostProcessingProfile {
+  pub name: String,
+  pub args: Vec<String>,
+  pub extension: Option<String>,
+  // Codec of the sources whose audio is copied instead of encoded, e.g. `aac` into M4A
+  pub copied_audio_codec: Option<String>,
+}
+
+/// Picks the post-processing profile of downloads, among the built-in and configured ones.
+pub struct PostProcessor {
+  profiles: HashMap<String, PostProcessingProfile>,
+  kinds: Vec<(MediaKind, String)>,
+  default: Option<String>,
+}
+
+impl PostProcessor {
+  pub fn new(config: &PostProcessingConfig) -> Self {
+    let mut profiles = builtin_profiles();
+
+    for (name, profile) in &config.profiles {
+      profiles.insert(name.clone(), to_profile(name, profile));
+    }
+
+    let is_known = |name: &str| {
+      let is_known = name == NO_PROFILE || profiles.contains_key(name);
+
+      if !is_known {
+        log::error!("Unknown post-processing profile '{}', ignoring it", name);
+      }
+
+      is_known
+    };
+
+    let mut kinds = vec![];
+
+    for (kind, name) in &config.kinds {
+      match kind.parse::<MediaKind>() {
+        Ok(kind) if is_known(name) => kinds.push((kind, name.clone())),
+        Ok(_) => {}
+        Err(err) => log::error!("Invalid post-processing kind: {}", err),
+      }
+    }
+
+    let default = config.default.clone().filter(|name| is_known(name));
+
+    Self {
+      profiles,
+      kinds,
+      default,
+    }
+  }
+
+  /// Profile of a download: the one of the request, else the one of the media kind, else the
+  /// default one. `none` skips the post-processing.
+  pub fn profile(
+    &self,
+    requested: Option<&str>,
+    kind: Option<MediaKind>,
+  ) -> anyhow::Result<Option<PostProcessingProfile>> {
+    let name = requested
+      .map(str::trim)
+      .filter(|name| !name.is_empty())
+      .or_else(|| {
+        self
+          .kinds
+          .iter()
+          .find(|(k, _)| Some(*k) == kind)
+          .map(|(_, name)| name.as_str())
+      })
+      .or(self.default.as_deref());
+
+    match name {
+      None | Some(NO_PROFILE) => Ok(None),
+      Some(name) => match self.profiles.get(name) {
+        Some(profile) => Ok(Some(profile.clone())),
+        None => anyhow::bail!("Unknown post-processing profile '{}'", name),
+      },
+    }
+  }
+}
+
+fn builtin_profiles() -> HashMap<String, PostProcessingProfile> {
+  let profile = |name: &str, args: &[&str], extension: &str| {
+    (
+      name.to_string(),
+      PostProcessingProfile {
+        name: name.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        extension: Some(extension.to_string()),
+        copied_audio_codec: None,
+      },
+    )
+  };
+
+  HashMap::from([
+    // Smaller files of the same quality for the library, keeping every stream. `V` leaves out
+    // the cover art, which is copied with the rest.
+    profile(
+      "h265-archive",
+      &[
+        "-map", "0", "-c", "copy", "-c:V", "libx265", "-crf", "26", "-preset", "slow", "-tag:V",
+        "hvc1",
+      ],
+      "mkv",
+    ),
+    // Plays anywhere, at most 720p
+    profile(
+      "mobile-720p",
+      &[
+        "-map",
+        "0:V:0",
+        "-map",
+        "0:a:0?",
+        "-vf",
+        "scale=-2:'min(720,ih)'",
+        "-c:v",
+        "libx264",
+        "-crf",
+        "23",
+        "-preset",
+        "veryfast",
+        "-c:a",
+        "aac",
+        "-b:a",
+        "128k",
+        "-movflags",
+        "+faststart",
+      ],
+      "mp4",
+    ),
+    profile("remux-mkv", &["-map", "0", "-c", "copy"], "mkv"),
+  ])
+}
+
+/// Profile extracting the audio of a media into `format`, run on audio-only downloads instead of
+/// their post-processing. The only video of those is their cover art, which M4A keeps.
+pub fn audio_profile(format: AudioFormat) -> PostProcessingProfile {
+  let (name, args, extension, copied_audio_codec): (_, &[&str], _, _) = match format {
+    AudioFormat::M4a => (
+      "audio-m4a",
+      &[
+        "-map",
+        "0:a:0",
+        "-map",
+        "0:v:0?",
+        "-c:v",
+        "copy",
+        "-disposition:v:0",
+        "attached_pic",
+        "-c:a",
+        "aac",
+        "-b:a",
+        "192k",
+        "-movflags",
+        "+faststart",
+      ],
+      "m4a",
+      Some("aac"),
+    ),
+    AudioFormat::Opus => (
+      "audio-opus",
+      &["-map", "0:a:0", "-vn", "-c:a", "libopus", "-b:a", "96k"],
+      "opus",
+      Some("opus"),
+    ),
+  };
+
+  PostProcessingProfile {
+    name: name.to_string(),
+    args: args.iter().map(|arg| arg.to_string()).collect(),
+    extension: Some(extension.to_string()),
+    copied_audio_codec: copied_audio_codec.map(str::to_string),
+  }
+}
+
+fn to_profile(name: &str, config: &PostProcessingProfileConfig) -> PostProcessingProfile {
+  PostProcessingProfile {
+    name: name.to_string(),
+    args: config.args.clone(),
+    extension: config
+      .extension
+      .as_ref()
+      .map(|extension| extension.trim_start_matches('.').to_string()),
+    copied_audio_codec: None,
+  }
+}
+
+/// Runs `profile` on the media at `input_path`, calling `on_progress` with the processed fraction
+/// of it (0.0 - 1.0). The input is replaced by the output, whose path is returned.
+pub async fn run_profile<F>(
+  profile: &PostProcessingProfile,
+  input_path: &Path,
+  on_progress: F,
+) -> anyhow::Result<PathBuf>
+where
+  F: Fn(f64),
+{
+  let output_path = match &profile.extension {
+    Some(extension) => input_path.with_extension(extension),
+    None => input_path.to_path_buf(),
+  };
+
+  // ffmpeg can't write to its input, the output is moved in place once done
+  let temp_path = output_path.with_extension(format!(
+    "{}.{}",
+    profile.name,
+    output_path
+      .extension()
+      .unwrap_or_default()
+      .to_string_lossy()
+  ));
+
+  log::info!(
+    "Post-processing {:?} with profile {}",
+    input_path,
+    profile.name
+  );
+
+  let mut args: Vec<OsString> = vec!["-hide_banner".into(), "-y".into(), "-i".into()];
+  args.push(input_path.into());
+  args.extend(profile.args.iter().map(OsString::from));
+
+  // Encoding the audio again into its own codec only loses quality, the last `-c:a` wins
+  if let Some(copied_audio_codec) = &profile.copied_audio_codec {
+    match ffmpeg::probe_codec(input_path, &[], "a:0").await {
+      Ok(Some(codec)) if codec == *copied_audio_codec => {
+        args.extend(["-c:a", "copy"].map(OsString::from));
+      }
+      Ok(_) => {}
+      Err(err) => log::warn!(
+        "Failed to probe the audio codec of {:?}: {:#}",
+        input_path,
+        err
+      ),
+    }
+  }
+
+  args.push(temp_path.clone().into());
+
+  let mut last_percent = 0;
+
+  let result = ffmpeg::run(args, |progress| {
+    let Some(fraction) = progress.fraction() else {
+      return;
+    };
+
+    let percent = (fraction * 100.0) as u32;
+
+    if percent != last_percent {
+      last_percent = percent;
+      on_progress(fraction);
+    }
+  })
+  .await;
+
+  if let Err(err) = result {
+    tokio::fs::remove_file(&temp_path).await.ok();
+
+    anyhow::bail!(
+      "Post-processing with profile {} failed: {:#}",
+      profile.name,
+      err
+    );
+  }
+
+  tokio::fs::rename(&temp_path, &output_path).await?;
+
+  if output_path != input_path {
+    tokio::fs::remove_file(input_path).await?;
+  }
+
+  Ok(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{audio_profile, PostProcessor};
+  use configuration::{PostProcessingConfig, PostProcessingProfileConfig};
+  use protocol::channel::{AudioFormat, MediaKind};
+  use std::collections::HashMap;
+
+  #[test]
+  fn test_select_profile() {
+    let config = PostProcessingConfig {
+      profiles: HashMap::from([(
+        "remux-mp4".to_string(),
+        PostProcessingProfileConfig {
+          args: vec!["-c".to_string(), "copy".to_string()],
+          extension: Some(".mp4".to_string()),
+        },
+      )]),
+      kinds: HashMap::from([
+        ("movie".to_string(), "h265-archive".to_string()),
+        ("anime".to_string(), "none".to_string()),
+        ("tv".to_string(), "missing".to_string()),
+        ("cartoon".to_string(), "remux-mkv".to_string()),
+      ]),
+      default: Some("remux-mp4".to_string()),
+    };
+
+    let post_processor = PostProcessor::new(&config);
+
+    let name_of = |requested: Option<&str>, kind: Option<MediaKind>| {
+      post_processor
+        .profile(requested, kind)
+        .unwrap()
+        .map(|profile| profile.name)
+    };
+
+    assert_eq!(
+      name_of(None, Some(MediaKind::Movie)).as_deref(),
+      Some("h265-archive")
+    );
+    assert_eq!(name_of(None, Some(MediaKind::Anime)), None);
+    // Kinds with unknown profiles fall back to the default one
+    assert_eq!(
+      name_of(None, Some(MediaKind::TV)).as_deref(),
+      Some("remux-mp4")
+    );
+    assert_eq!(name_of(None, None).as_deref(), Some("remux-mp4"));
+    assert_eq!(
+      name_of(Some("mobile-720p"), Some(MediaKind::Movie)).as_deref(),
+      Some("mobile-720p")
+    );
+    assert_eq!(name_of(Some("none"), Some(MediaKind::Movie)), None);
+    assert_eq!(
+      post_processor
+        .profile(None, None)
+        .unwrap()
+        .unwrap()
+        .extension
+        .as_deref(),
+      Some("mp4")
+    );
+    assert!(post_processor.profile(Some("unknown"), None).is_err());
+
+    let post_processor = PostProcessor::new(&PostProcessingConfig::default());
+
+    assert!(post_processor
+      .profile(None, Some(MediaKind::Movie))
+      .unwrap()
+      .is_none());
+  }
+
+  #[test]
+  fn test_audio_profile() {
+    let profile = audio_profile(AudioFormat::Opus);
+
+    assert_eq!(profile.extension.as_deref(), Some("opus"));
+    assert!(profile.args.contains(&"libopus".to_string()));
+    assert!(profile.args.contains(&"-vn".to_string()));
+
+    // M4A keeps the cover art
+    let profile = audio_profile(AudioFormat::M4a);
+
+    assert_eq!(profile.extension.as_deref(), Some("m4a"));
+    assert!(profile.args.contains(&"attached_pic".to_string()));
+    // AAC sources are copied instead of encoded again
+    assert_eq!(profile.copied_audio_codec.as_deref(), Some("aac"));
+  }
+}
@@ -10,3 +10,43 @@ pub fn rename_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result
 
   Ok(())
 }
+
+// Extensions of the files saved next to a downloaded media, e.g. `123-1.en.srt` of `123-1.mp4`
+const SIDECAR_EXTENSIONS: [&str; 2] = ["srt", "vtt"];
+
+/// Moves the sidecar files of the media at `from` next to the media at `to`, keeping what follows
+/// the name of the media, e.g. `123-1.en.forced.srt` -> `Movie (2020).en.forced.srt`.
+pub fn rename_sidecar_files(from: &Path, to: &Path) -> io::Result<()> {
+  let (Some(from_dir), Some(from_stem), Some(to_stem)) = (
+    from.parent(),
+    from.file_stem().and_then(|stem| stem.to_str()),
+    to.file_stem().and_then(|stem| stem.to_str()),
+  ) else {
+    return Ok(());
+  };
+
+  let prefix = format!("{}.", from_stem);
+
+  for entry in fs::read_dir(from_dir)? {
+    let path = entry?.path();
+
+    let is_sidecar = path
+      .extension()
+      .and_then(|extension| extension.to_str())
+      .is_some_and(|extension| SIDECAR_EXTENSIONS.contains(&extension));
+
+    let Some(suffix) = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .and_then(|name| name.strip_prefix(&prefix))
+    else {
+      continue;
+    };
+
+    if is_sidecar {
+      rename_file(&path, to.with_file_name(format!("{}.{}", to_stem, suffix)))?;
+    }
+  }
+
+  Ok(())
+}
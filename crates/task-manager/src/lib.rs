@@ -27,6 +27,14 @@ pub struct DownloadTask {
   pub progress: u8,
   pub total_segments: Option<usize>,
   pub downloaded_segments: usize,
+  pub total_bytes: Option<u64>,
+  pub downloaded_bytes: u64,
+  pub retries: u32,
+  pub removed_ad_seconds: f64,
+  // Seconds of live streams recorded so far
+  pub recorded_seconds: f64,
+  // Pending recordings wait until then to start
+  pub scheduled_at: Option<DateTime<Utc>>,
   pub error_message: Option<String>,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
@@ -72,6 +80,12 @@ impl TaskManager {
       progress: 0,
       total_segments: None,
       downloaded_segments: 0,
+      total_bytes: None,
+      downloaded_bytes: 0,
+      retries: 0,
+      removed_ad_seconds: 0.0,
+      recorded_seconds: 0.0,
+      scheduled_at: None,
       error_message: None,
       created_at: now,
       updated_at: now,
@@ -89,10 +103,19 @@ impl TaskManager {
     id
   }
 
+  pub fn task_scheduled(&self, task_id: &str, scheduled_at: DateTime<Utc>) {
+    self.update_task(task_id, |task| {
+      task.scheduled_at = Some(scheduled_at);
+    });
+  }
+
+  // A download started again, e.g. after failing its verification, restarts its progress
   pub fn task_started(&self, task_id: &str, total_segments: usize) {
     self.update_task(task_id, |task| {
       task.status = TaskStatus::Downloading;
       task.total_segments = Some(total_segments);
+      task.downloaded_segments = 0;
+      task.progress = 0;
     });
   }
 
@@ -107,10 +130,55 @@ impl TaskManager {
     });
   }
 
+  pub fn task_bytes_downloaded(&self, task_id: &str, downloaded: u64, total: Option<u64>) {
+    self.update_task(task_id, |task| {
+      task.downloaded_bytes = downloaded;
+      task.total_bytes = total;
+      if let Some(total) = total.filter(|total| *total > 0) {
+        task.progress = ((downloaded as f64 / total as f64) * 100.0).min(99.0) as u8;
+      }
+    });
+  }
+
+  pub fn task_recording(
+    &self,
+    task_id: &str,
+    recorded_seconds: f64,
+    recorded_bytes: u64,
+    limit_seconds: f64,
+  ) {
+    self.update_task(task_id, |task| {
+      task.recorded_seconds = recorded_seconds;
+      task.downloaded_bytes = recorded_bytes;
+      if limit_seconds > 0.0 {
+        task.progress = ((recorded_seconds / limit_seconds) * 100.0).min(99.0) as u8;
+      }
+    });
+  }
+
+  pub fn task_retrying(&self, task_id: &str) {
+    self.update_task(task_id, |task| {
+      task.retries += 1;
+    });
+  }
+
+  pub fn task_ads_removed(&self, task_id: &str, removed_seconds: f64) {
+    self.update_task(task_id, |task| {
+      task.removed_ad_seconds += removed_seconds;
+    });
+  }
+
+  // The progress of a transforming task is the one of the post-processing
   pub fn task_transforming(&self, task_id: &str) {
     self.update_task(task_id, |task| {
       task.status = TaskStatus::Transforming;
-      task.progress = 99;
+      task.progress = 0;
+    });
+  }
+
+  pub fn task_transforming_progress(&self, task_id: &str, fraction: f64) {
+    self.update_task(task_id, |task| {
+      task.progress = (fraction * 100.0).clamp(0.0, 99.0) as u8;
     });
   }
 
@@ -133,7 +201,7 @@ impl TaskManager {
 
     let tasks = self.tasks.read();
     let mut list: Vec<DownloadTask> = tasks.values().cloned().collect();
-    list.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    list.sort_by_key(|task| std::cmp::Reverse(task.created_at));
     list
   }
 
@@ -114,17 +114,13 @@ impl App {
 
   fn handke_key_event(&mut self, event: KeyEvent) -> anyhow::Result<()> {
     match event.code {
-      KeyCode::Char('q') => {
-        if !self.state.editing {
-          self.actions_tx.send(Action::Quit)?;
-        }
+      KeyCode::Char('q') if !self.state.editing => {
+        self.actions_tx.send(Action::Quit)?;
       }
-      KeyCode::Char('s') => {
-        if self.state.currently_view != CurrentlyView::Search {
-          self.state.currently_view = CurrentlyView::Search;
+      KeyCode::Char('s') if self.state.currently_view != CurrentlyView::Search => {
+        self.state.currently_view = CurrentlyView::Search;
 
-          self.actions_tx.send(Action::EnterSearchView)?;
-        }
+        self.actions_tx.send(Action::EnterSearchView)?;
       }
       _ => {}
     };
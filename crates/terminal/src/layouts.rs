@@ -1 +0,0 @@
-pub mod status_bar;
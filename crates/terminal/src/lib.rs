@@ -3,7 +3,6 @@ mod api;
 mod app;
 mod component;
 mod components;
-mod layouts;
 mod state;
 mod terminal;
 mod views;
@@ -7,7 +7,6 @@ use self::search_input::SearchInput;
 use crate::api::{SearchMediaOptions, API};
 use crate::component::Component;
 use crossterm::event::{KeyCode, KeyEvent};
-use protocol::channel::SearchMediaResponse;
 use ratatui::layout::{Constraint, Layout, Rect};
 use ratatui::text::Text;
 use ratatui::widgets::{Block, Paragraph};
@@ -19,7 +18,7 @@ enum SearchState {
   Pending,
   Input,
   Searching,
-  Completed(SearchMediaResponse),
+  Completed,
   Error(String),
 }
 
@@ -27,10 +26,6 @@ impl SearchState {
   fn is_pending(&self) -> bool {
     matches!(self, Self::Pending)
   }
-
-  fn is_completed(&self) -> bool {
-    matches!(self, Self::Completed(_)) || matches!(self, Self::Error(_))
-  }
 }
 
 pub struct Search {
@@ -117,7 +112,7 @@ impl Component for Search {
 
       match action {
         SearchAction::Completed(res) => {
-          self.state = SearchState::Completed(res.clone());
+          self.state = SearchState::Completed;
           self.content = Some(Box::new(completed::SearchCompeted::new(res)));
         }
         SearchAction::Cancelled => {
@@ -161,7 +156,7 @@ impl Component for Search {
       SearchState::Searching => {
         self.render_searching(frame, layout_chunks[1]);
       }
-      SearchState::Completed(_) => {
+      SearchState::Completed => {
         if let Some(content) = self.content.as_mut() {
           content.render(frame, layout_chunks[1]);
         }
@@ -17,10 +17,7 @@ pub async fn create_testing_channel() -> (Channel, IncomingServer) {
         if let Some(client) = client {
           Ok(client)
         } else {
-          Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Client already taken",
-          ))
+          Err(std::io::Error::other("Client already taken"))
         }
       }
     }))
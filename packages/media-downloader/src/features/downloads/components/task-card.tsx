@@ -15,6 +15,45 @@ function formatTime(dateStr: string) {
   return `${h}:${m}:${s}`
 }
 
+function formatBytes(bytes: number) {
+  const units = ['B', 'KB', 'MB', 'GB']
+  let value = bytes
+  let unit = 0
+
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024
+    unit += 1
+  }
+
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`
+}
+
+function formatDuration(seconds: number) {
+  const h = Math.floor(seconds / 3600)
+  const m = String(Math.floor((seconds % 3600) / 60)).padStart(2, '0')
+  const s = String(Math.floor(seconds % 60)).padStart(2, '0')
+  return `${h}:${m}:${s}`
+}
+
+function formatDownloaded(task: DownloadTask) {
+  // Live streams are recorded for a time rather than to their end
+  if (task.recorded_seconds > 0) {
+    return `Recorded ${formatDuration(task.recorded_seconds)} (${formatBytes(
+      task.downloaded_bytes,
+    )})`
+  }
+
+  // Progressive downloads have no segments, their progress is in bytes
+  if (task.downloaded_bytes > 0 || task.total_bytes !== null) {
+    const total =
+      task.total_bytes === null ? '?' : formatBytes(task.total_bytes)
+
+    return `${formatBytes(task.downloaded_bytes)} / ${total}`
+  }
+
+  return `${task.downloaded_segments} / ${task.total_segments ?? '?'} segments`
+}
+
 export const TaskCard: React.FC<TaskCardProps> = ({ task }) => {
   const episodeLabel = task.episode_number
     ? ` - Episode ${task.episode_number}`
@@ -40,11 +79,34 @@ export const TaskCard: React.FC<TaskCardProps> = ({ task }) => {
         </div>
       </div>
 
+      {task.status === 'Pending' && task.scheduled_at && (
+        <p className="mt-2 text-xs text-slate-500 dark:text-slate-400">
+          Recording scheduled at {new Date(task.scheduled_at).toLocaleString()}
+        </p>
+      )}
+
       {task.status === 'Downloading' && (
         <div className="mt-3">
           <div className="flex items-center justify-between mb-1">
             <span className="text-xs text-slate-500 dark:text-slate-400">
-              {task.downloaded_segments} / {task.total_segments ?? '?'} segments
+              {formatDownloaded(task)}
+              {task.retries > 0 && ` (${task.retries} retries)`}
+              {task.removed_ad_seconds > 0 &&
+                ` (${Math.round(task.removed_ad_seconds)}s of ads removed)`}
+            </span>
+            <span className="text-xs font-medium text-blue-600 dark:text-blue-400">
+              {task.progress}%
+            </span>
+          </div>
+          <TaskProgressBar progress={task.progress} />
+        </div>
+      )}
+
+      {task.status === 'Transforming' && (
+        <div className="mt-3">
+          <div className="flex items-center justify-between mb-1">
+            <span className="text-xs text-slate-500 dark:text-slate-400">
+              Post-processing
             </span>
             <span className="text-xs font-medium text-blue-600 dark:text-blue-400">
               {task.progress}%
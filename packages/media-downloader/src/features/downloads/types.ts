@@ -15,6 +15,14 @@ export interface DownloadTask {
   progress: number
   total_segments: number | null
   downloaded_segments: number
+  total_bytes: number | null
+  downloaded_bytes: number
+  retries: number
+  removed_ad_seconds: number
+  // Seconds of live streams recorded so far
+  recorded_seconds: number
+  // Pending recordings start then
+  scheduled_at: string | null
   error_message: string | null
   created_at: string
   updated_at: string
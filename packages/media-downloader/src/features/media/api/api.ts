@@ -1,6 +1,14 @@
 import { APIClient } from '@/common/api-client'
 import { ListResponse } from '@/common/types'
-import { MediaMetadata, MediaPlaylistItem } from '@/features/media/types'
+import {
+  LiveRecording,
+  MediaClip,
+  MediaMetadata,
+  MediaPlaylistItem,
+  MediaRenditions,
+  MediaTrim,
+  RenditionSelection,
+} from '@/features/media/types'
 
 export interface SearchMediaOptions {
   keyword: string
@@ -16,11 +24,49 @@ export interface MediaPlaylistResponse {
   items: MediaPlaylistItem[]
 }
 
+export interface DownloadOptions {
+  channel: string
+  media_id: string
+  number?: number
+  renditions?: RenditionSelection
+  post_processing?: string
+  container?: 'mp4' | 'mkv' | 'ts'
+  // Only this part of the media is downloaded
+  clip?: MediaClip
+  // Only the audio is kept, filed into the audio library
+  audio_only?: 'm4a' | 'opus'
+  // The media is a live stream, recorded until these limits
+  recording?: LiveRecording
+  // Instead of the trim configured for the series
+  trim?: MediaTrim
+}
+
 export interface BatchDownloadOptions {
   channel: string
   media_id: string
   start_number: number
   count: number
+  renditions?: RenditionSelection
+  // Post-processing profile, e.g. `h265-archive`, instead of the one of the media kind
+  post_processing?: string
+  // Container of the output, instead of the one of the channel
+  container?: 'mp4' | 'mkv' | 'ts'
+  audio_only?: 'm4a' | 'opus'
+  trim?: MediaTrim
+}
+
+export interface DownloadUrlOptions {
+  url: string
+  title: string
+  kind: string
+  release_year?: number
+  season?: number
+  episode?: number
+  renditions?: RenditionSelection
+  post_processing?: string
+  container?: 'mp4' | 'mkv' | 'ts'
+  audio_only?: 'm4a' | 'opus'
+  recording?: LiveRecording
 }
 
 class MediaAPI extends APIClient {
@@ -41,9 +87,29 @@ class MediaAPI extends APIClient {
     return res
   }
 
-  public async getPlaylist(channel: string, id: string) {
+  // Probing takes a request or more per item
+  public async getPlaylist(channel: string, id: string, probe = false) {
     const res = await this.request<MediaPlaylistResponse>({
       url: `/channels/${channel}/media/${id}/playlist`,
+      params: probe ? { probe } : undefined,
+    })
+
+    return res
+  }
+
+  public async getRenditions(channel: string, id: string, number: number) {
+    const res = await this.request<MediaRenditions>({
+      url: `/channels/${channel}/media/${id}/playlist/${number}/renditions`,
+    })
+
+    return res
+  }
+
+  public async download(options: DownloadOptions) {
+    const res = await this.request<string>({
+      url: '/media/download',
+      data: options,
+      method: 'POST',
     })
 
     return res
@@ -58,6 +124,16 @@ class MediaAPI extends APIClient {
 
     return res
   }
+
+  public async downloadUrl(options: DownloadUrlOptions) {
+    const res = await this.request<string>({
+      url: '/media/download_url',
+      data: options,
+      method: 'POST',
+    })
+
+    return res
+  }
 }
 
 export { MediaAPI }
@@ -26,7 +26,34 @@ const Metadata: React.FC<MetadataProps> = ({ metadata }) => {
           <span className="px-3 py-1 bg-blue-100 dark:bg-blue-900 text-blue-700 dark:text-blue-300 rounded-full text-sm font-medium">
             {metadata.release_year}
           </span>
+          {metadata.remarks && (
+            <span className="px-3 py-1 bg-slate-100 dark:bg-slate-800 text-slate-600 dark:text-slate-300 rounded-full text-sm">
+              {metadata.remarks}
+            </span>
+          )}
+          {metadata.score !== null && (
+            <span className="text-sm font-medium text-amber-600 dark:text-amber-400">
+              {metadata.score.toFixed(1)}
+            </span>
+          )}
         </div>
+        <dl className="grid grid-cols-[auto_1fr] gap-x-4 gap-y-1 mb-4 text-sm">
+          {[
+            ['Director', metadata.directors.join(' / ')],
+            ['Cast', metadata.cast.join(' / ')],
+            ['Region', metadata.region],
+            ['Language', metadata.language],
+            ['Episodes', metadata.total_episodes?.toString() ?? ''],
+            ['Updated', metadata.updated_at ?? ''],
+          ]
+            .filter(([, value]) => value)
+            .map(([label, value]) => (
+              <React.Fragment key={label}>
+                <dt className="text-slate-500 dark:text-slate-400">{label}</dt>
+                <dd className="text-slate-700 dark:text-slate-300">{value}</dd>
+              </React.Fragment>
+            ))}
+        </dl>
         <p className="text-slate-600 dark:text-slate-400 leading-relaxed" dangerouslySetInnerHTML={{ __html: metadata.description }} />
       </div>
     </div>
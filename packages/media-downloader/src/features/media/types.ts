@@ -6,10 +6,69 @@ export interface MediaMetadata {
   release_year: number
   description: string
   kind: string
+  cast: string[]
+  directors: string[]
+  region: string
+  language: string
+  remarks: string
+  total_episodes: number | null
+  updated_at: string | null
+  score: number | null
+}
+
+// What the playlist of an item tells about its media, without downloading it
+export interface MediaItemProbe {
+  reachable: boolean
+  duration: number | null
+  segments: number | null
+  resolutions: string[]
+  error: string | null
 }
 
 export interface MediaPlaylistItem {
   number: number
   text: string
   url: string
+  // Set when the playlist was probed
+  probe?: MediaItemProbe | null
+}
+
+export interface MediaRendition {
+  group_id: string
+  name: string
+  language: string | null
+  default: boolean
+  forced: boolean
+}
+
+export interface MediaRenditions {
+  audio: MediaRendition[]
+  subtitles: MediaRendition[]
+}
+
+// Matched against the language or the name of the renditions
+export interface RenditionSelection {
+  audio?: string
+  subtitles?: string[]
+}
+
+// Seconds from the start of the media, clips without an end run to its end
+export interface MediaClip {
+  start: number
+  end?: number
+}
+
+// Seconds cut from the start and the end of each episode, e.g. its opening and ending
+export interface MediaTrim {
+  intro?: number
+  outro?: number
+}
+
+// Limits of the recording of a live stream, times are RFC 3339
+export interface LiveRecording {
+  // Seconds to record
+  duration?: number
+  until?: string
+  // The recording is scheduled for then
+  start_at?: string
 }